@@ -57,23 +57,28 @@ use clap::{App, Arg, ArgMatches};
 use fs2::FileExt;
 
 use tikv::config::{MetricConfig, TiKvConfig};
-use tikv::util::{self, panic_hook, rocksdb as rocksdb_util};
-use tikv::util::collections::HashMap;
+use tikv::util::{self, escape, panic_hook, rocksdb as rocksdb_util};
+use tikv::util::collections::{HashMap, HashSet};
 use tikv::util::logger::{self, StderrLogger};
 use tikv::util::file_log::RotatingFileLogger;
 use tikv::util::transport::SendCh;
-use tikv::util::worker::FutureWorker;
-use tikv::storage::DEFAULT_ROCKSDB_SUB_DIR;
+use tikv::util::worker::{FutureWorker, Worker};
+use tikv::storage::{MaxTsObserver, MaxTsSyncRunner, CF_DEFAULT, CF_WRITE, DEFAULT_ROCKSDB_SUB_DIR,
+                    MAX_TS_OBSERVER_PRIORITY};
+use tikv::storage::ttl::{TTLCompactionFilter, TTL_COMPACTION_FILTER_NAME};
+use tikv::storage::mvcc::compaction_filter::{self, GcContext, WriteCompactionFilterFactory,
+                                             WRITE_COMPACTION_FILTER_FACTORY_NAME};
 use tikv::server::{create_raft_storage, Node, Server, DEFAULT_CLUSTER_ID};
 use tikv::server::transport::ServerRaftStoreRouter;
 use tikv::server::resolve;
-use tikv::raftstore::store::{self, Engines, SnapManager};
+use tikv::raftstore::store::{self, unsafe_recovery, Engines, LocalReader, Msg, SnapManager};
 use tikv::raftstore::coprocessor::CoprocessorHost;
 use tikv::pd::{PdClient, RpcClient};
 use tikv::util::time::Monitor;
 use tikv::util::rocksdb::metrics_flusher::{MetricsFlusher, DEFAULT_FLUSHER_INTERVAL};
 
 const RESERVED_OPEN_FDS: u64 = 1000;
+const UNSAFE_RECOVERY_TIMEOUT_SECS: u64 = 60;
 
 // A workaround for checking if log is initialized.
 static LOG_INITIALIZED: AtomicBool = ATOMIC_BOOL_INIT;
@@ -144,7 +149,63 @@ fn check_system_config(config: &TiKvConfig) {
     }
 }
 
-fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
+/// Drops the peers on `failed_stores` from the regions on the store that lost the quorum,
+/// then logs the ranges that no surviving peer in the cluster covers.
+fn unsafe_recover(ch: &SendCh<Msg>, pd_client: &RpcClient, failed_stores: HashSet<u64>) {
+    let timeout = Duration::from_secs(UNSAFE_RECOVERY_TIMEOUT_SECS);
+    let reports = unsafe_recovery::report(ch, timeout)
+        .unwrap_or_else(|e| fatal!("failed to report peers for unsafe recovery: {:?}", e));
+    for report in reports {
+        info!("unsafe recovery report {:?}", report);
+    }
+
+    let res = unsafe_recovery::force_remove_peers(ch, failed_stores.clone(), timeout)
+        .unwrap_or_else(|e| fatal!("failed to force remove peers: {:?}", e));
+    for (region_id, r) in res {
+        match r {
+            Ok(region) => info!(
+                "[region {}] force removed failed peers, now {:?}",
+                region_id,
+                region
+            ),
+            Err(e) => error!("[region {}] failed to force remove peers: {:?}", region_id, e),
+        }
+    }
+
+    // PD still knows the regions whose peers are all on the failed stores, skip them to find
+    // the ranges that are lost.
+    let mut regions = vec![];
+    let mut key = vec![];
+    loop {
+        let region = pd_client
+            .get_region(&key)
+            .unwrap_or_else(|e| fatal!("failed to get region of {}: {:?}", escape(&key), e));
+        key = region.get_end_key().to_vec();
+        if region
+            .get_peers()
+            .iter()
+            .any(|p| !failed_stores.contains(&p.get_store_id()))
+        {
+            regions.push(region);
+        }
+        if key.is_empty() {
+            break;
+        }
+    }
+    for (start_key, end_key) in unsafe_recovery::find_lost_ranges(&regions) {
+        warn!(
+            "range [{}, {}) is lost, it needs to be recreated as an empty region",
+            escape(&start_key),
+            escape(&end_key)
+        );
+    }
+}
+
+fn run_raft_server(
+    pd_client: RpcClient,
+    cfg: &TiKvConfig,
+    failed_stores: Option<HashSet<u64>>,
+) {
     let store_path = Path::new(&cfg.storage.data_dir);
     let lock_path = store_path.join(Path::new("LOCK"));
     let db_path = store_path.join(Path::new(DEFAULT_ROCKSDB_SUB_DIR));
@@ -166,17 +227,51 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
         .unwrap_or_else(|e| fatal!("failed to create event loop: {:?}", e));
     let store_sendch = SendCh::new(event_loop.channel(), "raftstore");
     let (significant_msg_sender, significant_msg_receiver) = mpsc::channel();
-    let raft_router = ServerRaftStoreRouter::new(store_sendch.clone(), significant_msg_sender);
+    let local_reader = LocalReader::new();
+    let raft_router = ServerRaftStoreRouter::new(
+        store_sendch.clone(),
+        significant_msg_sender,
+        local_reader.clone(),
+    );
 
     // Create kv engine, storage.
     let kv_db_opts = cfg.rocksdb.build_opt();
-    let kv_cfs_opts = cfg.rocksdb.build_cf_opts();
+    let mut kv_cfs_opts = cfg.rocksdb.build_cf_opts();
+    if cfg.storage.enable_ttl {
+        // Expired raw values are dropped in compaction. The lock and write CFs hold MVCC data
+        // of transactions, the raw values put in them only expire on read.
+        for cf_opts in kv_cfs_opts.iter_mut().filter(|x| x.cf() == CF_DEFAULT) {
+            cf_opts
+                .set_compaction_filter(TTL_COMPACTION_FILTER_NAME, Box::new(TTLCompactionFilter))
+                .unwrap_or_else(|e| fatal!("failed to set ttl compaction filter: {:?}", e));
+        }
+    }
+    let gc_ctx = GcContext::new();
+    if cfg.storage.enable_compaction_filter_gc {
+        // Obsolete MVCC versions are dropped in compaction.
+        for cf_opts in kv_cfs_opts.iter_mut().filter(|x| x.cf() == CF_WRITE) {
+            cf_opts
+                .set_compaction_filter_factory(
+                    WRITE_COMPACTION_FILTER_FACTORY_NAME,
+                    Box::new(WriteCompactionFilterFactory::new(gc_ctx.clone())),
+                )
+                .unwrap_or_else(|e| fatal!("failed to set write compaction filter: {:?}", e));
+        }
+    }
     let kv_engine = Arc::new(
         rocksdb_util::new_engine_opt(db_path.to_str().unwrap(), kv_db_opts, kv_cfs_opts)
             .unwrap_or_else(|s| fatal!("failed to create kv engine: {:?}", s)),
     );
+    let mut gc_worker = Worker::new("gc-compaction-filter");
+    if cfg.storage.enable_compaction_filter_gc {
+        gc_worker
+            .start(compaction_filter::Runner::new(kv_engine.clone()))
+            .unwrap_or_else(|e| fatal!("failed to start gc compaction filter worker: {:?}", e));
+        gc_ctx.init(&kv_engine, gc_worker.scheduler());
+    }
     let mut storage = create_raft_storage(raft_router.clone(), kv_engine.clone(), &cfg.storage)
         .unwrap_or_else(|e| fatal!("failed to create raft stroage: {:?}", e));
+    storage.set_gc_context(gc_ctx);
 
     // Create raft engine.
     let raft_db_opts = cfg.raftdb.build_opt();
@@ -193,6 +288,7 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
     // Create pd client and pd work, snapshot manager, server.
     let pd_client = Arc::new(pd_client);
     let pd_worker = FutureWorker::new("pd worker");
+    storage.set_pd_scheduler(pd_worker.scheduler());
     let (mut worker, resolver) = resolve::new_resolver(pd_client.clone())
         .unwrap_or_else(|e| fatal!("failed to start address resolver: {:?}", e));
     let snap_mgr = SnapManager::new(
@@ -213,11 +309,25 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
     ).unwrap_or_else(|e| fatal!("failed to create server: {:?}", e));
     let trans = server.transport();
 
+    // Start max ts sync worker.
+    let cm = storage.get_concurrency_manager();
+    let mut max_ts_worker = FutureWorker::new("max-ts-sync");
+    let max_ts_runner =
+        MaxTsSyncRunner::new(pd_client.clone(), cm.clone(), max_ts_worker.scheduler());
+    max_ts_worker
+        .start(max_ts_runner)
+        .unwrap_or_else(|e| fatal!("failed to start max ts sync worker: {:?}", e));
+
     // Create node.
-    let mut node = Node::new(&mut event_loop, &cfg.server, &cfg.raft_store, pd_client);
+    let mut node = Node::new(&mut event_loop, &cfg.server, &cfg.raft_store, pd_client.clone());
 
     // Create CoprocessorHost.
-    let coprocessor_host = CoprocessorHost::new(cfg.coprocessor.clone(), node.get_sendch());
+    let mut coprocessor_host = CoprocessorHost::new(cfg.coprocessor.clone(), node.get_sendch());
+    // A new leader syncs the max ts with PD before it serves async-commit prewrites.
+    coprocessor_host.registry.register_observer(
+        MAX_TS_OBSERVER_PRIORITY,
+        Box::new(MaxTsObserver::new(cm.clone(), max_ts_worker.scheduler())),
+    );
 
     node.start(
         event_loop,
@@ -227,6 +337,8 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
         significant_msg_receiver,
         pd_worker,
         coprocessor_host,
+        local_reader,
+        cm,
     ).unwrap_or_else(|e| fatal!("failed to start node: {:?}", e));
     initial_metric(&cfg.metric, Some(node.id()));
 
@@ -250,6 +362,10 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
     server
         .start(&cfg.server)
         .unwrap_or_else(|e| fatal!("failed to start server: {:?}", e));
+    if let Some(failed_stores) = failed_stores {
+        info!("start unsafe recovery for failed stores {:?}", failed_stores);
+        unsafe_recover(&node.get_sendch(), &pd_client, failed_stores);
+    }
     signal_handler::handle_signal(engines, &cfg.rocksdb.backup_dir);
 
     // Stop.
@@ -264,6 +380,12 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
     if let Some(Err(e)) = worker.stop().map(|j| j.join()) {
         info!("ignore failure when stopping resolver: {:?}", e);
     }
+    if let Some(Err(e)) = gc_worker.stop().map(|j| j.join()) {
+        info!("ignore failure when stopping gc compaction filter worker: {:?}", e);
+    }
+    if let Some(Err(e)) = max_ts_worker.stop().map(|j| j.join()) {
+        info!("ignore failure when stopping max ts sync worker: {:?}", e);
+    }
 }
 
 fn overwrite_config_with_cmd_args(config: &mut TiKvConfig, matches: &ArgMatches) {
@@ -428,6 +550,23 @@ fn main() {
                      `zone=cn,disk=ssd`",
                 ),
         )
+        .arg(
+            Arg::with_name("unsafe-recover-failed-stores")
+                .long("unsafe-recover-failed-stores")
+                .takes_value(true)
+                .value_name("STORE_ID")
+                .multiple(true)
+                .use_delimiter(true)
+                .require_delimiter(true)
+                .value_delimiter(",")
+                .help("Recovers the regions that lost the quorum on the failed stores")
+                .long_help(
+                    "Removes the peers on the failed stores from the regions that lost the \
+                     quorum after the server starts, and logs the lost ranges. The writes \
+                     committed only on the failed stores are lost. Uses `,` to separate \
+                     multiple stores",
+                ),
+        )
         .arg(
             Arg::with_name("print-sample-config")
                 .long("print-sample-config")
@@ -493,6 +632,13 @@ fn main() {
     config.server.cluster_id = cluster_id;
     info!("connect to PD cluster {}", cluster_id);
 
+    let failed_stores = matches.values_of("unsafe-recover-failed-stores").map(|ids| {
+        ids.map(|id| {
+            id.parse()
+                .unwrap_or_else(|e| fatal!("invalid failed store {:?}: {}", id, e))
+        }).collect()
+    });
+
     let _m = Monitor::default();
-    run_raft_server(pd_client, &config);
+    run_raft_server(pd_client, &config, failed_stores);
 }
@@ -0,0 +1,309 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::mem;
+use std::sync::mpsc::Sender;
+
+use kvproto::metapb::Region;
+use kvproto::raft_cmdpb::{CmdType, Request};
+use kvproto::raft_serverpb::{PeerState, RaftApplyState, RegionLocalState};
+
+use raftstore::{Error, Result};
+use raftstore::store::{keys, Iterable, Peekable, Resolver};
+use raftstore::store::engine::Snapshot;
+use storage::{Key, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+use storage::mvcc::{Lock, LockType, Write, WriteType};
+use storage::types::split_encoded_key_on_ts;
+use util::collections::HashMap;
+use util::escape;
+
+use super::{Event, OpType, Row};
+
+/// `Delegate` assembles the changes of a region for a subscription.
+pub struct Delegate {
+    region: Region,
+    // The index of the last command seen by the subscription.
+    applied_index: u64,
+    checkpoint_ts: u64,
+    sink: Sender<Event>,
+    resolver: Resolver,
+    resolved_ts: u64,
+    // encoded key -> (start ts, value) of the prewritten puts.
+    prewrites: HashMap<Vec<u8>, (u64, Vec<u8>)>,
+    // The rows committed after the resolved ts.
+    rows: Vec<Row>,
+}
+
+impl Delegate {
+    /// Creates the delegate with an incremental scan of the changes committed after
+    /// `checkpoint_ts` in the snapshot.
+    pub fn new(
+        snap: &Snapshot,
+        region_id: u64,
+        checkpoint_ts: u64,
+        sink: Sender<Event>,
+    ) -> Result<Delegate> {
+        let state_key = keys::region_state_key(region_id);
+        let region_state: RegionLocalState = match snap.get_msg_cf(CF_RAFT, &state_key)? {
+            Some(state) => state,
+            None => return Err(Error::RegionNotFound(region_id)),
+        };
+        if region_state.get_state() != PeerState::Normal {
+            return Err(box_err!(
+                "region {} is in state {:?}",
+                region_id,
+                region_state.get_state()
+            ));
+        }
+        let apply_state: RaftApplyState =
+            match snap.get_msg_cf(CF_RAFT, &keys::apply_state_key(region_id))? {
+                Some(state) => state,
+                None => return Err(box_err!("region {} has no apply state", region_id)),
+            };
+
+        let mut delegate = Delegate {
+            region: region_state.get_region().clone(),
+            applied_index: apply_state.get_applied_index(),
+            checkpoint_ts: checkpoint_ts,
+            sink: sink,
+            resolver: Resolver::default(),
+            resolved_ts: checkpoint_ts,
+            prewrites: HashMap::default(),
+            rows: vec![],
+        };
+        delegate.scan(snap)?;
+        Ok(delegate)
+    }
+
+    fn scan(&mut self, snap: &Snapshot) -> Result<()> {
+        let start_key = keys::enc_start_key(&self.region);
+        let end_key = keys::enc_end_key(&self.region);
+        snap.scan_cf(CF_LOCK, &start_key, &end_key, false, &mut |key, value| {
+            let key = keys::origin_key(key);
+            let lock = Lock::parse(value).map_err(|e| box_err!("{:?}", e))?;
+            let default_value = if lock.lock_type == LockType::Put && lock.short_value.is_none() {
+                get_default_value(snap, key, lock.ts)?
+            } else {
+                None
+            };
+            self.on_lock_put(key, value, default_value);
+            Ok(true)
+        })?;
+        snap.scan_cf(CF_WRITE, &start_key, &end_key, false, &mut |key, value| {
+            let key = keys::origin_key(key);
+            let (user_key, commit_ts) =
+                split_encoded_key_on_ts(key).map_err(|e| box_err!("{:?}", e))?;
+            if commit_ts <= self.checkpoint_ts {
+                return Ok(true);
+            }
+            let write = Write::parse(value).map_err(|e| box_err!("{:?}", e))?;
+            let default_value = if write.write_type == WriteType::Put && write.short_value.is_none()
+            {
+                get_default_value(snap, user_key, write.start_ts)?
+            } else {
+                None
+            };
+            if let Some(row) = new_row(user_key, commit_ts, write, default_value) {
+                self.rows.push(row);
+            }
+            Ok(true)
+        })?;
+        Ok(())
+    }
+
+    /// Handles a write command applied at `index` in `region`, returns false if the
+    /// subscription is stopped.
+    pub fn on_applied_cmd(&mut self, region: &Region, index: u64, requests: &[Request]) -> bool {
+        if index <= self.applied_index {
+            // It's included in the incremental scan.
+            return true;
+        }
+        if region.get_region_epoch().get_version() !=
+            self.region.get_region_epoch().get_version()
+        {
+            self.stop(format!("region {} is split or merged", region.get_id()));
+            return false;
+        }
+        self.applied_index = index;
+
+        // encoded key with start ts -> value, the values written by the command.
+        let mut values = HashMap::default();
+        for req in requests {
+            if req.get_cmd_type() == CmdType::Put {
+                let put = req.get_put();
+                if put.get_cf().is_empty() || put.get_cf() == CF_DEFAULT {
+                    values.insert(put.get_key(), put.get_value());
+                }
+            }
+        }
+        // The prewritten value is taken by the commit before the lock is deleted.
+        for req in requests.iter().filter(|r| r.get_cmd_type() == CmdType::Put) {
+            let put = req.get_put();
+            if put.get_cf() == CF_WRITE {
+                self.on_write_put(put.get_key(), put.get_value(), &values);
+            }
+        }
+        for req in requests {
+            match req.get_cmd_type() {
+                CmdType::Delete if req.get_delete().get_cf() == CF_LOCK => {
+                    let key = req.get_delete().get_key();
+                    self.resolver.untrack_lock(key);
+                    self.prewrites.remove(key);
+                }
+                CmdType::DeleteRange if req.get_delete_range().get_cf() == CF_LOCK => {
+                    let delete_range = req.get_delete_range();
+                    let (start_key, end_key) =
+                        (delete_range.get_start_key(), delete_range.get_end_key());
+                    self.resolver.untrack_range(start_key, end_key);
+                    self.prewrites.retain(|key, _| {
+                        let key = key.as_slice();
+                        key < start_key || (!end_key.is_empty() && key >= end_key)
+                    });
+                }
+                _ => {}
+            }
+        }
+        for req in requests.iter().filter(|r| r.get_cmd_type() == CmdType::Put) {
+            let put = req.get_put();
+            if put.get_cf() == CF_LOCK {
+                let default_value = Lock::parse(put.get_value()).ok().and_then(|lock| {
+                    let key = Key::from_encoded(put.get_key().to_vec()).append_ts(lock.ts);
+                    values.get(key.encoded().as_slice()).map(|v| v.to_vec())
+                });
+                self.on_lock_put(put.get_key(), put.get_value(), default_value);
+            }
+        }
+        self.flush()
+    }
+
+    fn on_lock_put(&mut self, key: &[u8], value: &[u8], default_value: Option<Vec<u8>>) {
+        self.resolver.on_lock_put(key, value);
+        let lock = match Lock::parse(value) {
+            Ok(lock) => lock,
+            Err(_) => return,
+        };
+        if lock.lock_type != LockType::Put {
+            self.prewrites.remove(key);
+            return;
+        }
+        match lock.short_value.or(default_value) {
+            Some(v) => {
+                self.prewrites.insert(key.to_vec(), (lock.ts, v));
+            }
+            None => error!(
+                "[region {}] the value of lock {} at {} is missing",
+                self.region.get_id(),
+                escape(key),
+                lock.ts
+            ),
+        }
+    }
+
+    fn on_write_put(&mut self, key: &[u8], value: &[u8], values: &HashMap<&[u8], &[u8]>) {
+        let (user_key, commit_ts) = match split_encoded_key_on_ts(key) {
+            Ok(res) => res,
+            Err(_) => return,
+        };
+        if commit_ts <= self.checkpoint_ts {
+            // It has been sent before the checkpoint.
+            return;
+        }
+        let write = match Write::parse(value) {
+            Ok(write) => write,
+            Err(_) => return,
+        };
+        let default_value = match self.prewrites.get(user_key) {
+            Some(&(start_ts, ref v)) if start_ts == write.start_ts => Some(v.clone()),
+            // The transaction is committed in one phase.
+            _ => {
+                let key = Key::from_encoded(user_key.to_vec()).append_ts(write.start_ts);
+                values.get(key.encoded().as_slice()).map(|v| v.to_vec())
+            }
+        };
+        if let Some(row) = new_row(user_key, commit_ts, write, default_value) {
+            self.rows.push(row);
+        }
+    }
+
+    /// Advances the resolved ts with the ts the leader resolves the region at, returns false
+    /// if the subscription is stopped.
+    pub fn on_resolved_ts(&mut self, ts: u64) -> bool {
+        self.resolver.advance_ts(ts);
+        self.flush()
+    }
+
+    /// Sends the rows committed at or before the resolved ts, returns false if the sink
+    /// is disconnected.
+    pub fn flush(&mut self) -> bool {
+        let resolved_ts = self.resolver.resolved_ts();
+        if resolved_ts <= self.resolved_ts {
+            return true;
+        }
+        self.resolved_ts = resolved_ts;
+        let rows = mem::replace(&mut self.rows, vec![]);
+        let (mut ready, pending): (Vec<_>, Vec<_>) =
+            rows.into_iter().partition(|r| r.commit_ts <= resolved_ts);
+        self.rows = pending;
+        if !ready.is_empty() {
+            ready.sort_by_key(|r| r.commit_ts);
+            if self.sink.send(Event::Rows(ready)).is_err() {
+                return false;
+            }
+        }
+        self.sink.send(Event::ResolvedTs(resolved_ts)).is_ok()
+    }
+
+    pub fn stop(&self, reason: String) {
+        info!("[region {}] stop cdc: {}", self.region.get_id(), reason);
+        let _ = self.sink.send(Event::Error(reason));
+    }
+}
+
+fn get_default_value(snap: &Snapshot, key: &[u8], start_ts: u64) -> Result<Option<Vec<u8>>> {
+    let key = Key::from_encoded(key.to_vec()).append_ts(start_ts);
+    let value = snap.get_value_cf(CF_DEFAULT, &keys::data_key(key.encoded()))?;
+    Ok(value.map(|v| v.to_vec()))
+}
+
+fn new_row(key: &[u8], commit_ts: u64, write: Write, value: Option<Vec<u8>>) -> Option<Row> {
+    let op_type = match write.write_type {
+        WriteType::Put => OpType::Put,
+        WriteType::Delete => OpType::Delete,
+        WriteType::Lock | WriteType::Rollback => return None,
+    };
+    let raw_key = match Key::from_encoded(key.to_vec()).raw() {
+        Ok(raw_key) => raw_key,
+        Err(e) => {
+            error!("failed to decode key {}: {:?}", escape(key), e);
+            return None;
+        }
+    };
+    let value = if op_type == OpType::Put {
+        match write.short_value.or(value) {
+            Some(v) => v,
+            None => {
+                error!("the value of {} at {} is missing", escape(key), commit_ts);
+                return None;
+            }
+        }
+    } else {
+        vec![]
+    };
+    Some(Row {
+        key: raw_key,
+        value: value,
+        start_ts: write.start_ts,
+        commit_ts: commit_ts,
+        op_type: op_type,
+    })
+}
@@ -0,0 +1,430 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::Sender;
+
+use kvproto::metapb::Region;
+use kvproto::raft_cmdpb::Request;
+use rocksdb::DB;
+
+use raftstore::store::engine::Snapshot;
+use util::collections::{HashMap, HashSet};
+use util::worker::Runnable;
+
+use super::Event;
+use super::delegate::Delegate;
+
+pub enum Task {
+    /// Subscribes the changes of the region committed after `checkpoint_ts`.
+    Register {
+        region_id: u64,
+        checkpoint_ts: u64,
+        sink: Sender<Event>,
+    },
+    /// Stops all the subscriptions of the region.
+    Deregister { region_id: u64 },
+    /// A write command is applied.
+    Apply {
+        region: Region,
+        index: u64,
+        requests: Vec<Request>,
+    },
+    /// The leader advances the resolved ts of the region.
+    ResolvedTs { region_id: u64, ts: u64 },
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::Register {
+                region_id,
+                checkpoint_ts,
+                ..
+            } => write!(
+                f,
+                "register cdc of region {} from {}",
+                region_id,
+                checkpoint_ts
+            ),
+            Task::Deregister { region_id } => write!(f, "deregister cdc of region {}", region_id),
+            Task::Apply {
+                ref region, index, ..
+            } => write!(f, "apply {} of region {}", index, region.get_id()),
+            Task::ResolvedTs { region_id, ts } => {
+                write!(f, "advance resolved ts of region {} to {}", region_id, ts)
+            }
+        }
+    }
+}
+
+pub struct Endpoint {
+    db: Arc<DB>,
+    // The regions being subscribed, shared with `CdcObserver`.
+    regions: Arc<RwLock<HashSet<u64>>>,
+    delegates: HashMap<u64, Vec<Delegate>>,
+}
+
+impl Endpoint {
+    pub fn new(db: Arc<DB>, regions: Arc<RwLock<HashSet<u64>>>) -> Endpoint {
+        Endpoint {
+            db: db,
+            regions: regions,
+            delegates: HashMap::default(),
+        }
+    }
+
+    fn on_register(&mut self, region_id: u64, checkpoint_ts: u64, sink: Sender<Event>) {
+        // The region must be observed before taking the snapshot, so the commands applied
+        // after the snapshot are all delivered.
+        self.regions.write().unwrap().insert(region_id);
+        let snap = Snapshot::new(self.db.clone());
+        match Delegate::new(&snap, region_id, checkpoint_ts, sink.clone()) {
+            Ok(mut delegate) => {
+                info!(
+                    "[region {}] register cdc from {}",
+                    region_id,
+                    checkpoint_ts
+                );
+                if delegate.flush() {
+                    self.delegates
+                        .entry(region_id)
+                        .or_insert_with(Vec::new)
+                        .push(delegate);
+                }
+            }
+            Err(e) => {
+                error!("[region {}] failed to register cdc: {:?}", region_id, e);
+                let _ = sink.send(Event::Error(format!("{:?}", e)));
+            }
+        }
+        self.unobserve_if_empty(region_id);
+    }
+
+    fn on_deregister(&mut self, region_id: u64) {
+        if let Some(delegates) = self.delegates.remove(&region_id) {
+            for delegate in delegates {
+                delegate.stop("deregistered".to_owned());
+            }
+        }
+        self.unobserve_if_empty(region_id);
+    }
+
+    fn on_apply(&mut self, region: Region, index: u64, requests: Vec<Request>) {
+        let region_id = region.get_id();
+        if let Some(delegates) = self.delegates.get_mut(&region_id) {
+            delegates.retain(|d| d.on_applied_cmd(&region, index, &requests));
+        }
+        self.unobserve_if_empty(region_id);
+    }
+
+    fn on_resolved_ts(&mut self, region_id: u64, ts: u64) {
+        if let Some(delegates) = self.delegates.get_mut(&region_id) {
+            delegates.retain(|d| d.on_resolved_ts(ts));
+        }
+        self.unobserve_if_empty(region_id);
+    }
+
+    fn unobserve_if_empty(&mut self, region_id: u64) {
+        let empty = self.delegates
+            .get(&region_id)
+            .map_or(true, |delegates| delegates.is_empty());
+        if empty {
+            self.delegates.remove(&region_id);
+            self.regions.write().unwrap().remove(&region_id);
+        }
+    }
+}
+
+impl Runnable<Task> for Endpoint {
+    fn run(&mut self, task: Task) {
+        match task {
+            Task::Register {
+                region_id,
+                checkpoint_ts,
+                sink,
+            } => self.on_register(region_id, checkpoint_ts, sink),
+            Task::Deregister { region_id } => self.on_deregister(region_id),
+            Task::Apply {
+                region,
+                index,
+                requests,
+            } => self.on_apply(region, index, requests),
+            Task::ResolvedTs { region_id, ts } => self.on_resolved_ts(region_id, ts),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        for (_, delegates) in self.delegates.drain() {
+            for delegate in delegates {
+                delegate.stop("cdc is shutting down".to_owned());
+            }
+        }
+        self.regions.write().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver};
+
+    use kvproto::metapb::Peer;
+    use kvproto::raft_cmdpb::{CmdType, DeleteRequest, PutRequest};
+    use kvproto::raft_serverpb::{RaftApplyState, RegionLocalState};
+    use rocksdb::Writable;
+    use tempdir::TempDir;
+
+    use cdc::{OpType, Row};
+    use raftstore::store::{keys, Mutable};
+    use storage::{make_key, ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+    use storage::mvcc::{Lock, LockType, Write, WriteType};
+    use util::rocksdb::{get_cf_handle, new_engine};
+    use util::HandyRwLock;
+    use super::*;
+
+    fn lock_key(k: &[u8]) -> Vec<u8> {
+        make_key(k).encoded().to_owned()
+    }
+
+    fn ts_key(k: &[u8], ts: u64) -> Vec<u8> {
+        make_key(k).append_ts(ts).encoded().to_owned()
+    }
+
+    fn lock_value(ts: u64, short_value: Option<&[u8]>) -> Vec<u8> {
+        let short_value = short_value.map(|v| v.to_vec());
+        Lock::new(LockType::Put, b"k1".to_vec(), ts, 0, short_value).to_bytes()
+    }
+
+    fn write_value(write_type: WriteType, start_ts: u64) -> Vec<u8> {
+        Write::new(write_type, start_ts, None).to_bytes()
+    }
+
+    fn put(cf: &str, key: Vec<u8>, value: Vec<u8>) -> Request {
+        let mut put = PutRequest::new();
+        put.set_cf(cf.to_owned());
+        put.set_key(key);
+        put.set_value(value);
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Put);
+        req.set_put(put);
+        req
+    }
+
+    fn delete(cf: &str, key: Vec<u8>) -> Request {
+        let mut delete = DeleteRequest::new();
+        delete.set_cf(cf.to_owned());
+        delete.set_key(key);
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Delete);
+        req.set_delete(delete);
+        req
+    }
+
+    fn row(k: &[u8], v: &[u8], start_ts: u64, commit_ts: u64, op_type: OpType) -> Row {
+        Row {
+            key: k.to_vec(),
+            value: v.to_vec(),
+            start_ts: start_ts,
+            commit_ts: commit_ts,
+            op_type: op_type,
+        }
+    }
+
+    fn must_recv(rx: &Receiver<Event>) -> Event {
+        rx.try_recv().unwrap()
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let path = TempDir::new("test-cdc-endpoint").unwrap();
+        let db = Arc::new(new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
+        let mut region = Region::new();
+        region.set_id(1);
+        region.mut_peers().push(Peer::new());
+        region.mut_region_epoch().set_version(1);
+        let mut region_state = RegionLocalState::new();
+        region_state.set_region(region.clone());
+        let mut apply_state = RaftApplyState::new();
+        apply_state.set_applied_index(10);
+        let raft_cf = get_cf_handle(&db, CF_RAFT).unwrap();
+        db.put_msg_cf(raft_cf, &keys::region_state_key(1), &region_state)
+            .unwrap();
+        db.put_msg_cf(raft_cf, &keys::apply_state_key(1), &apply_state)
+            .unwrap();
+
+        // k1 is committed at 3 and 5, k2 is prewritten at 6 with a long value.
+        let (default_cf, lock_cf, write_cf) = (
+            get_cf_handle(&db, CF_DEFAULT).unwrap(),
+            get_cf_handle(&db, CF_LOCK).unwrap(),
+            get_cf_handle(&db, CF_WRITE).unwrap(),
+        );
+        let data_key = |k: Vec<u8>| keys::data_key(&k);
+        let put_default = |k, v: &[u8]| db.put_cf(default_cf, &data_key(k), v).unwrap();
+        put_default(ts_key(b"k1", 2), b"v1");
+        put_default(ts_key(b"k1", 4), b"v2");
+        put_default(ts_key(b"k2", 6), b"v3");
+        let put_write = |k, v: &[u8]| db.put_cf(write_cf, &data_key(k), v).unwrap();
+        put_write(ts_key(b"k1", 3), &write_value(WriteType::Put, 2));
+        put_write(ts_key(b"k1", 5), &write_value(WriteType::Put, 4));
+        db.put_cf(lock_cf, &data_key(lock_key(b"k2")), &lock_value(6, None))
+            .unwrap();
+
+        let regions = Arc::new(RwLock::new(HashSet::default()));
+        let mut endpoint = Endpoint::new(db.clone(), regions.clone());
+
+        // The rows committed after the checkpoint are scanned, they are sent after the
+        // resolved ts is advanced, which is blocked by the lock at 6.
+        let (tx, rx) = mpsc::channel();
+        endpoint.run(Task::Register {
+            region_id: 1,
+            checkpoint_ts: 3,
+            sink: tx,
+        });
+        assert!(regions.rl().contains(&1));
+        assert!(rx.try_recv().is_err());
+        endpoint.run(Task::ResolvedTs {
+            region_id: 1,
+            ts: 7,
+        });
+        assert_eq!(
+            must_recv(&rx),
+            Event::Rows(vec![row(b"k1", b"v2", 4, 5, OpType::Put)])
+        );
+        assert_eq!(must_recv(&rx), Event::ResolvedTs(5));
+        assert!(rx.try_recv().is_err());
+
+        // The commands included in the snapshot are skipped.
+        let requests = vec![put(CF_WRITE, ts_key(b"k3", 8), write_value(WriteType::Put, 7))];
+        endpoint.run(Task::Apply {
+            region: region.clone(),
+            index: 10,
+            requests: requests,
+        });
+        assert!(rx.try_recv().is_err());
+
+        // A transaction is committed in one phase at 8, it's blocked by the lock at 6.
+        let requests = vec![
+            put(CF_DEFAULT, ts_key(b"k3", 7), b"v4".to_vec()),
+            put(CF_WRITE, ts_key(b"k3", 8), write_value(WriteType::Put, 7)),
+        ];
+        endpoint.run(Task::Apply {
+            region: region.clone(),
+            index: 11,
+            requests: requests,
+        });
+        assert!(rx.try_recv().is_err());
+
+        // k2 is committed at 9, the value is taken from the scanned lock.
+        let requests = vec![
+            delete(CF_LOCK, lock_key(b"k2")),
+            put(CF_WRITE, ts_key(b"k2", 9), write_value(WriteType::Put, 6)),
+        ];
+        endpoint.run(Task::Apply {
+            region: region.clone(),
+            index: 12,
+            requests: requests,
+        });
+        // No lock blocks the resolved ts now, it's advanced to the ts of the last advance.
+        assert_eq!(must_recv(&rx), Event::ResolvedTs(7));
+        endpoint.run(Task::ResolvedTs {
+            region_id: 1,
+            ts: 9,
+        });
+        assert_eq!(
+            must_recv(&rx),
+            Event::Rows(vec![
+                row(b"k3", b"v4", 7, 8, OpType::Put),
+                row(b"k2", b"v3", 6, 9, OpType::Put),
+            ])
+        );
+        assert_eq!(must_recv(&rx), Event::ResolvedTs(9));
+
+        // k1 is prewritten at 10 with a short value and deleted at 11.
+        let requests = vec![put(CF_LOCK, lock_key(b"k1"), lock_value(10, Some(b"v5")))];
+        endpoint.run(Task::Apply {
+            region: region.clone(),
+            index: 13,
+            requests: requests,
+        });
+        assert!(rx.try_recv().is_err());
+        let requests = vec![
+            put(CF_WRITE, ts_key(b"k1", 11), write_value(WriteType::Delete, 10)),
+            delete(CF_LOCK, lock_key(b"k1")),
+        ];
+        endpoint.run(Task::Apply {
+            region: region.clone(),
+            index: 14,
+            requests: requests,
+        });
+        endpoint.run(Task::ResolvedTs {
+            region_id: 1,
+            ts: 11,
+        });
+        assert_eq!(
+            must_recv(&rx),
+            Event::Rows(vec![row(b"k1", b"", 10, 11, OpType::Delete)])
+        );
+        assert_eq!(must_recv(&rx), Event::ResolvedTs(11));
+
+        // The subscription is stopped after split.
+        let mut new_region = region.clone();
+        new_region.mut_region_epoch().set_version(2);
+        endpoint.run(Task::Apply {
+            region: new_region,
+            index: 15,
+            requests: vec![],
+        });
+        match must_recv(&rx) {
+            Event::Error(_) => {}
+            e => panic!("expect error, got {:?}", e),
+        }
+        assert!(!regions.rl().contains(&1));
+
+        // Registering a missing region fails.
+        let (tx, rx) = mpsc::channel();
+        endpoint.run(Task::Register {
+            region_id: 2,
+            checkpoint_ts: 0,
+            sink: tx,
+        });
+        match must_recv(&rx) {
+            Event::Error(_) => {}
+            e => panic!("expect error, got {:?}", e),
+        }
+        assert!(regions.rl().is_empty());
+
+        // The subscription is stopped if the receiver is dropped.
+        db.delete_cf(lock_cf, &data_key(lock_key(b"k2"))).unwrap();
+        let (tx, rx) = mpsc::channel();
+        endpoint.run(Task::Register {
+            region_id: 1,
+            checkpoint_ts: 11,
+            sink: tx,
+        });
+        assert!(regions.rl().contains(&1));
+        drop(rx);
+        let requests = vec![put(CF_WRITE, ts_key(b"k3", 13), write_value(WriteType::Put, 12))];
+        endpoint.run(Task::Apply {
+            region: region.clone(),
+            index: 16,
+            requests: requests,
+        });
+        assert!(regions.rl().contains(&1));
+        endpoint.run(Task::ResolvedTs {
+            region_id: 1,
+            ts: 13,
+        });
+        assert!(regions.rl().is_empty());
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Change data capture, streams the committed changes of a region.
+//!
+//! A subscription starts from an incremental scan of the changes committed after a
+//! checkpoint ts, then follows the applied commands delivered by `CdcObserver`. The
+//! changes are sent in commit ts order, each batch is followed by a resolved ts, no
+//! change committed at or before it will be sent later. The resolved ts is advanced
+//! with the ts the leader resolves the region at, so the region should be subscribed
+//! on its leader.
+//!
+//! kvproto has no change data service yet, so it's only an internal API: register
+//! `CdcObserver` to the coprocessor host, run `Endpoint` on a worker, and subscribe a
+//! region with `Task::Register`.
+
+mod delegate;
+mod endpoint;
+mod observer;
+
+pub use self::endpoint::{Endpoint, Task};
+pub use self::observer::{CdcObserver, CDC_OBSERVER_PRIORITY};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpType {
+    Put,
+    Delete,
+}
+
+/// A committed change of a row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub start_ts: u64,
+    pub commit_ts: u64,
+    pub op_type: OpType,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// The rows sorted by commit ts.
+    Rows(Vec<Row>),
+    /// All the rows committed at or before the ts have been sent.
+    ResolvedTs(u64),
+    /// The subscription is stopped, it should be started again from the last resolved ts.
+    Error(String),
+}
@@ -0,0 +1,176 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, RwLock};
+
+use kvproto::raft_cmdpb::Request;
+
+use raftstore::coprocessor::{Coprocessor, ObserverContext, RegionChangeEvent, RegionObserver};
+use util::collections::HashSet;
+use util::worker::Scheduler;
+
+use super::Task;
+
+pub const CDC_OBSERVER_PRIORITY: u32 = 500;
+
+/// Forwards the write commands applied to the subscribed regions and their resolved ts to
+/// the `Endpoint`.
+pub struct CdcObserver {
+    regions: Arc<RwLock<HashSet<u64>>>,
+    scheduler: Scheduler<Task>,
+}
+
+impl CdcObserver {
+    pub fn new(regions: Arc<RwLock<HashSet<u64>>>, scheduler: Scheduler<Task>) -> CdcObserver {
+        CdcObserver {
+            regions: regions,
+            scheduler: scheduler,
+        }
+    }
+
+    fn is_observed(&self, region_id: u64) -> bool {
+        self.regions.read().unwrap().contains(&region_id)
+    }
+}
+
+impl Coprocessor for CdcObserver {}
+
+impl RegionObserver for CdcObserver {
+    fn observes_applied_cmds(&self) -> bool {
+        true
+    }
+
+    fn post_apply_query(&self, ctx: &mut ObserverContext, index: u64, requests: &[Request]) {
+        let region = ctx.region();
+        if !self.is_observed(region.get_id()) {
+            return;
+        }
+        let task = Task::Apply {
+            region: region.clone(),
+            index: index,
+            requests: requests.to_vec(),
+        };
+        if let Err(e) = self.scheduler.schedule(task) {
+            warn!(
+                "[region {}] failed to schedule cdc apply task: {:?}",
+                region.get_id(),
+                e
+            );
+        }
+    }
+
+    fn on_advance_resolved_ts(&self, ctx: &mut ObserverContext, ts: u64) {
+        let region_id = ctx.region().get_id();
+        if !self.is_observed(region_id) {
+            return;
+        }
+        let task = Task::ResolvedTs {
+            region_id: region_id,
+            ts: ts,
+        };
+        if let Err(e) = self.scheduler.schedule(task) {
+            warn!(
+                "[region {}] failed to schedule cdc resolved ts task: {:?}",
+                region_id,
+                e
+            );
+        }
+    }
+
+    fn on_region_changed(&self, ctx: &mut ObserverContext, event: RegionChangeEvent) {
+        let region_id = ctx.region().get_id();
+        if event == RegionChangeEvent::ChangePeer || !self.is_observed(region_id) {
+            return;
+        }
+        // The subscriptions can't follow the range of the region any more.
+        if let Err(e) = self.scheduler.schedule(Task::Deregister { region_id: region_id }) {
+            warn!(
+                "[region {}] failed to schedule cdc deregister task: {:?}",
+                region_id,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Sender};
+    use std::time::Duration;
+
+    use kvproto::metapb::Region;
+    use util::worker::{Runnable, Worker};
+    use util::HandyRwLock;
+    use super::*;
+
+    struct ForwardRunner {
+        ch: Sender<(u64, u64, usize)>,
+    }
+
+    impl Runnable<Task> for ForwardRunner {
+        fn run(&mut self, task: Task) {
+            let res = match task {
+                Task::Apply {
+                    region,
+                    index,
+                    requests,
+                } => (region.get_id(), index, requests.len()),
+                Task::Deregister { region_id } => (region_id, 0, 0),
+                Task::ResolvedTs { region_id, ts } => (region_id, ts, 0),
+                Task::Register { .. } => unreachable!(),
+            };
+            self.ch.send(res).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_cdc_observer() {
+        let mut worker = Worker::new("test-cdc-observer");
+        let (tx, rx) = mpsc::channel();
+        worker.start(ForwardRunner { ch: tx }).unwrap();
+        let regions = Arc::new(RwLock::new(HashSet::default()));
+        let observer = CdcObserver::new(regions.clone(), worker.scheduler());
+
+        let mut region = Region::new();
+        region.set_id(1);
+        let requests = vec![Request::new(), Request::new()];
+        let mut ctx = ObserverContext::new(&region);
+        assert!(observer.observes_applied_cmds());
+        observer.post_apply_query(&mut ctx, 10, &requests);
+        observer.on_advance_resolved_ts(&mut ctx, 10);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        regions.wl().insert(1);
+        observer.post_apply_query(&mut ctx, 11, &requests);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(3)).unwrap(),
+            (1, 11, 2)
+        );
+
+        observer.on_advance_resolved_ts(&mut ctx, 12);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(3)).unwrap(),
+            (1, 12, 0)
+        );
+
+        observer.on_region_changed(&mut ctx, RegionChangeEvent::ChangePeer);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        observer.on_region_changed(&mut ctx, RegionChangeEvent::Destroy);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(3)).unwrap(),
+            (1, 0, 0)
+        );
+
+        worker.stop().unwrap().join().unwrap();
+    }
+}
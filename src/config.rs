@@ -675,6 +675,10 @@ impl Default for TiKvConfig {
 impl TiKvConfig {
     pub fn validate(&mut self) -> Result<(), Box<Error>> {
         self.storage.validate()?;
+        // kvproto has no TTL fields yet, so the raw kv RPCs can only put values living forever.
+        if self.storage.enable_ttl {
+            return Err("storage.enable-ttl is not supported by the raw kv RPCs yet".into());
+        }
         if self.rocksdb.backup_dir.is_empty() && self.storage.data_dir != DEFAULT_DATA_DIR {
             self.rocksdb.backup_dir = format!(
                 "{}",
@@ -688,6 +692,18 @@ impl TiKvConfig {
         } else {
             config::canonicalize_path(&self.raft_store.raftdb_path)?
         };
+        self.raft_store.import_dir = if self.raft_store.import_dir.is_empty() {
+            config::canonicalize_sub_path(&self.storage.data_dir, "import")?
+        } else {
+            config::canonicalize_path(&self.raft_store.import_dir)?
+        };
+
+        let spill_dir = if self.server.end_point_aggr_spill_dir.is_empty() {
+            config::canonicalize_sub_path(&self.storage.data_dir, "aggr-spill")?
+        } else {
+            config::canonicalize_path(&self.server.end_point_aggr_spill_dir)?
+        };
+        self.server.end_point_aggr_spill_dir = spill_dir;
 
         let kv_db_path =
             config::canonicalize_sub_path(&self.storage.data_dir, DEFAULT_ROCKSDB_SUB_DIR)?;
@@ -30,6 +30,7 @@ use super::mysql::{types, Duration, Time};
 pub const ID_LEN: usize = 8;
 pub const PREFIX_LEN: usize = TABLE_PREFIX_LEN + ID_LEN /*table_id*/ + SEP_LEN;
 pub const RECORD_ROW_KEY_LEN: usize = PREFIX_LEN + ID_LEN;
+pub const TABLE_PREFIX_KEY_LEN: usize = TABLE_PREFIX_LEN + ID_LEN;
 pub const TABLE_PREFIX: &'static [u8] = b"t";
 pub const RECORD_PREFIX_SEP: &'static [u8] = b"_r";
 pub const INDEX_PREFIX_SEP: &'static [u8] = b"_i";
@@ -83,6 +84,23 @@ pub fn encode_row(row: Vec<Datum>, col_ids: &[i64]) -> Result<Vec<u8>> {
     datum::encode_value(&values)
 }
 
+/// `encode_table_prefix` encodes the table id into the prefix shared by all keys of the table.
+pub fn encode_table_prefix(table_id: i64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(TABLE_PREFIX_KEY_LEN);
+    key.write_all(TABLE_PREFIX).unwrap();
+    key.encode_i64(table_id).unwrap();
+    key
+}
+
+/// `decode_table_id` decodes the table id of a table key.
+pub fn decode_table_id(key: &[u8]) -> Result<i64> {
+    if !key.starts_with(TABLE_PREFIX) || key.len() < TABLE_PREFIX_KEY_LEN {
+        return Err(invalid_type!("table key expected, but got {}", escape(key)));
+    }
+    let mut remaining = &key[TABLE_PREFIX.len()..];
+    remaining.decode_i64()
+}
+
 /// `encode_row_key` encodes the table id and record handle into a byte array.
 pub fn encode_row_key(table_id: i64, encoded_handle: &[u8]) -> Vec<u8> {
     let mut key = Vec::with_capacity(RECORD_ROW_KEY_LEN);
@@ -388,6 +406,21 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_table_prefix() {
+        let tests = vec![i64::MIN, i64::MAX, -1, 0, 2, 3, 1024];
+        for &t in &tests {
+            let prefix = encode_table_prefix(t);
+            assert_eq!(prefix.len(), TABLE_PREFIX_KEY_LEN);
+            assert_eq!(t, decode_table_id(&prefix).unwrap());
+            let k = encode_row_key(t, &[]);
+            assert!(k.starts_with(&prefix));
+            assert_eq!(t, decode_table_id(&k).unwrap());
+        }
+        assert!(decode_table_id(b"t").is_err());
+        assert!(decode_table_id(b"m12345678").is_err());
+    }
+
     #[test]
     fn test_index_key_codec() {
         let tests = vec![Datum::U64(1), Datum::Bytes(b"123".to_vec()), Datum::I64(-1)];
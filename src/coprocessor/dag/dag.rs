@@ -11,32 +11,41 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::mem;
 use std::rc::Rc;
 
 use tipb::executor::{ExecType, Executor};
 use tipb::schema::ColumnInfo;
-use tipb::select::{DAGRequest, SelectResponse};
+use tipb::select::{Chunk, DAGRequest, SelectResponse};
+use tipb::expression::{Expr, ExprType};
 use kvproto::coprocessor::{KeyRange, Response};
 use protobuf::{Message as PbMsg, RepeatedField};
 
-use coprocessor::codec::mysql;
-use coprocessor::codec::datum::{Datum, DatumEncoder};
+use coprocessor::codec::{mysql, table};
+use coprocessor::codec::datum::{self, Datum, DatumEncoder};
 use coprocessor::select::xeval::EvalContext;
 use coprocessor::{Error, Result};
-use coprocessor::endpoint::{get_chunk, get_pk, to_pb_error, ReqContext};
-use storage::{Snapshot, SnapshotStore, Statistics};
+use coprocessor::endpoint::{get_chunk, get_pk, prefix_next, to_pb_error, ReqContext};
+use storage::{Key, Snapshot, SnapshotStore, Statistics};
+use util::codec::number::{NumberDecoder, NumberEncoder};
 
-use super::executor::{AggregationExecutor, Executor as DAGExecutor, IndexScanExecutor,
-                      LimitExecutor, Row, SelectionExecutor, TableScanExecutor, TopNExecutor};
+use super::executor::{AggregationExecutor, Batch, BatchAggregationExecutor, BatchExecutor,
+                      BatchLimitExecutor, BatchSelectionExecutor, BatchTableScanExecutor,
+                      BatchTopNExecutor, Executor as DAGExecutor, ExprColumnRefVisitor,
+                      IndexLookupExecutor, IndexScanExecutor, LimitExecutor,
+                      ProjectionExecutor, Row, SelectionExecutor, SpillConfig,
+                      StreamAggExecutor, TableScanExecutor, TopNExecutor};
 
 pub struct DAGContext<'s> {
     columns: Rc<Vec<ColumnInfo>>,
     has_aggr: bool,
+    has_projection: bool,
     req: DAGRequest,
     ranges: Vec<KeyRange>,
     snap: &'s Snapshot,
     eval_ctx: Rc<EvalContext>,
     req_ctx: &'s ReqContext,
+    aggr_spill_cfg: SpillConfig,
 }
 
 impl<'s> DAGContext<'s> {
@@ -46,6 +55,7 @@ impl<'s> DAGContext<'s> {
         snap: &'s Snapshot,
         eval_ctx: Rc<EvalContext>,
         req_ctx: &'s ReqContext,
+        aggr_spill_cfg: SpillConfig,
     ) -> DAGContext<'s> {
         DAGContext {
             req: req,
@@ -53,46 +63,120 @@ impl<'s> DAGContext<'s> {
             ranges: ranges,
             snap: snap,
             has_aggr: false,
+            has_projection: false,
             eval_ctx: eval_ctx,
             req_ctx: req_ctx,
+            aggr_spill_cfg: aggr_spill_cfg,
         }
     }
 
-    pub fn handle_request(mut self, statistics: &'s mut Statistics) -> Result<Response> {
+    pub fn handle_request(self, statistics: &'s mut Statistics) -> Result<Response> {
+        // The handling is never paused without streaming.
+        self.handle(statistics, None).map(Option::unwrap)
+    }
+
+    /// Handles the request by streaming, a response is sent by `on_resp` with the key of its
+    /// last row once it has `batch_row_limit` rows. If `on_resp` returns false, the handling
+    /// is paused and `None` is returned, the request can be resumed after the key. The rest
+    /// rows are in the returned response.
+    ///
+    /// Only the rows that can be resumed by their keys are streamed, the others are all in
+    /// the returned response.
+    pub fn handle_streaming_request(
+        self,
+        statistics: &'s mut Statistics,
+        batch_row_limit: usize,
+        on_resp: &mut FnMut(Response, Vec<u8>) -> Result<bool>,
+    ) -> Result<Option<Response>> {
+        self.handle(statistics, Some((batch_row_limit, on_resp)))
+    }
+
+    fn handle(
+        mut self,
+        statistics: &'s mut Statistics,
+        stream: Option<(usize, &mut FnMut(Response, Vec<u8>) -> Result<bool>)>,
+    ) -> Result<Option<Response>> {
         self.validate_dag()?;
-        let mut exec = self.build_dag(statistics)?;
-        let mut chunks = vec![];
+        let table_id = self.resumable_table_id();
+        let writer = ResponseWriter {
+            req_ctx: self.req_ctx.clone(),
+            chunks: vec![],
+            rows: 0,
+            table_id: table_id.unwrap_or_default(),
+            stream: if table_id.is_some() { stream } else { None },
+        };
+        if self.can_execute_in_batches() {
+            self.handle_in_batches(statistics, writer)
+        } else {
+            self.handle_in_rows(statistics, writer)
+        }
+    }
+
+    fn handle_in_rows(
+        &'s self,
+        statistics: &'s mut Statistics,
+        writer: ResponseWriter,
+    ) -> Result<Option<Response>> {
+        // The rows looked up by the handles of an index are counted separately, since
+        // `statistics` is held by the index scan.
+        let mut lookup_statistics = Statistics::default();
+        let res = {
+            let exec = self.build_dag(statistics, &mut lookup_statistics);
+            exec.and_then(|mut exec| self.write_rows(&mut *exec, writer))
+        };
+        statistics.add(&lookup_statistics);
+        res
+    }
+
+    fn write_rows<'a>(
+        &self,
+        exec: &mut (DAGExecutor + 'a),
+        mut writer: ResponseWriter,
+    ) -> Result<Option<Response>> {
         loop {
             match exec.next() {
                 Ok(Some(row)) => {
-                    self.req_ctx.check_if_outdated()?;
-                    let chunk = get_chunk(&mut chunks);
-                    if self.has_aggr {
-                        chunk.mut_rows_data().extend_from_slice(&row.data.value);
+                    writer.check_if_outdated()?;
+                    let goes_on = if self.has_aggr || self.has_projection {
+                        writer.write_row(row.handle, &row.data.value)?
                     } else {
                         let value =
                             inflate_cols(&row, &self.columns, self.req.get_output_offsets())?;
-                        chunk.mut_rows_data().extend_from_slice(&value);
+                        writer.write_row(row.handle, &value)?
+                    };
+                    if !goes_on {
+                        return Ok(None);
                     }
                 }
-                Ok(None) => {
-                    let mut resp = Response::new();
-                    let mut sel_resp = SelectResponse::new();
-                    sel_resp.set_chunks(RepeatedField::from_vec(chunks));
-                    let data = box_try!(sel_resp.write_to_bytes());
-                    resp.set_data(data);
-                    return Ok(resp);
+                Ok(None) => return writer.finish().map(Some),
+                Err(e) => return build_error_response(e).map(Some),
+            }
+        }
+    }
+
+    fn handle_in_batches(
+        &'s self,
+        statistics: &'s mut Statistics,
+        mut writer: ResponseWriter,
+    ) -> Result<Option<Response>> {
+        let mut exec = self.build_batch_dag(statistics)?;
+        loop {
+            match exec.next_batch() {
+                Ok(Some(batch)) => {
+                    writer.check_if_outdated()?;
+                    for i in 0..batch.len() {
+                        let value = if self.has_aggr {
+                            box_try!(datum::encode_value(&batch.row(i)))
+                        } else {
+                            inflate_batch_cols(&batch, i, self.req.get_output_offsets())?
+                        };
+                        if !writer.write_row(batch.handles[i], &value)? {
+                            return Ok(None);
+                        }
+                    }
                 }
-                Err(e) => if let Error::Other(_) = e {
-                    let mut resp = Response::new();
-                    let mut sel_resp = SelectResponse::new();
-                    sel_resp.set_error(to_pb_error(&e));
-                    resp.set_data(box_try!(sel_resp.write_to_bytes()));
-                    resp.set_other_error(format!("{}", e));
-                    return Ok(resp);
-                } else {
-                    return Err(e);
-                },
+                Ok(None) => return writer.finish().map(Some),
+                Err(e) => return build_error_response(e).map(Some),
             }
         }
     }
@@ -108,7 +192,14 @@ impl<'s> DAGContext<'s> {
                 self.columns = Rc::new(first.get_tbl_scan().get_columns().to_vec());
             }
             ExecType::TypeIndexScan => {
-                self.columns = Rc::new(first.get_idx_scan().get_columns().to_vec());
+                let columns = match lookup_table_id(&self.req) {
+                    Some(table_id) => {
+                        self.check_rows_in_region(table_id)?;
+                        execs[1].get_tbl_scan().get_columns()
+                    }
+                    None => first.get_idx_scan().get_columns(),
+                };
+                self.columns = Rc::new(columns.to_vec());
             }
             _ => {
                 return Err(box_err!(
@@ -125,9 +216,61 @@ impl<'s> DAGContext<'s> {
         {
             self.has_aggr = true;
         }
+        // The output of projection is not in the columns of the scan, so only limit can
+        // follow it.
+        if let Some(pos) = execs
+            .iter()
+            .position(|exec| exec.get_tp() == ExecType::TypeProjection)
+        {
+            if execs[..pos]
+                .iter()
+                .any(|exec| exec.get_tp() == ExecType::TypeAggregation)
+            {
+                return Err(box_err!("projection after aggregation is not supported"));
+            }
+            if let Some(exec) = execs[pos + 1..]
+                .iter()
+                .find(|exec| exec.get_tp() != ExecType::TypeLimit)
+            {
+                return Err(box_err!(
+                    "{:?} after projection is not supported",
+                    exec.get_tp()
+                ));
+            }
+            // The rows are written as the projection outputs them.
+            let exprs_len = execs[pos].get_projection().get_exprs().len();
+            let offsets = self.req.get_output_offsets();
+            if !offsets.iter().map(|o| *o as usize).eq(0..exprs_len) {
+                return Err(box_err!(
+                    "output offsets {:?} after projection of {} exprs are not supported",
+                    offsets,
+                    exprs_len
+                ));
+            }
+            self.has_projection = true;
+        }
         Ok(())
     }
 
+    // The rows are looked up in the snapshot of the index's region, so all the rows of
+    // the table should be in the region. Otherwise the plan is not supported, it's not a
+    // region error, or the client would retry it on the same region forever.
+    fn check_rows_in_region(&self, table_id: i64) -> Result<()> {
+        let (start, end) = table_row_range(table_id);
+        let start = Key::from_raw(&start);
+        let end = Key::from_raw(&end);
+        let lower_ok = self.snap.lower_bound().map_or(true, |b| b <= start.encoded().as_slice());
+        let upper_ok = self.snap.upper_bound().map_or(true, |b| end.encoded().as_slice() <= b);
+        if lower_ok && upper_ok {
+            return Ok(());
+        }
+        Err(box_err!(
+            "looking up the rows of table {} out of region {} is not supported",
+            table_id,
+            self.req_ctx.region_id
+        ))
+    }
+
     // seperate first exec build action from `build_dag`
     // since it will generte mutable conflict when putting together
     fn build_first(
@@ -159,9 +302,33 @@ impl<'s> DAGContext<'s> {
         }
     }
 
-    fn build_dag(&'s self, statistics: &'s mut Statistics) -> Result<Box<DAGExecutor + 's>> {
-        let mut execs = self.req.get_executors().to_vec().into_iter();
-        let mut src = self.build_first(execs.next().unwrap(), statistics);
+    fn build_dag(
+        &'s self,
+        statistics: &'s mut Statistics,
+        lookup_statistics: &'s mut Statistics,
+    ) -> Result<Box<DAGExecutor + 's>> {
+        let mut execs = self.req.get_executors().to_vec().into_iter().peekable();
+        let first = execs.next().unwrap();
+        // The rows of an index scan are ordered by the index columns.
+        let mut ordered = first.get_tp() == ExecType::TypeIndexScan;
+        let mut src = self.build_first(first, statistics);
+        if ordered && execs.peek().map(|exec| exec.get_tp()) == Some(ExecType::TypeTableScan) {
+            let exec = execs.next().unwrap();
+            let store = SnapshotStore::new(
+                self.snap,
+                self.req.get_start_ts(),
+                self.req_ctx.isolation_level,
+                self.req_ctx.fill_cache,
+            );
+            src = Box::new(IndexLookupExecutor::new(
+                exec.get_tbl_scan(),
+                store,
+                lookup_statistics,
+                src,
+            ));
+            // The group by items refer to the columns of the table now.
+            ordered = false;
+        }
         for mut exec in execs {
             let curr: Box<DAGExecutor> = match exec.get_tp() {
                 ExecType::TypeTableScan | ExecType::TypeIndexScan => {
@@ -173,19 +340,153 @@ impl<'s> DAGContext<'s> {
                     self.columns.clone(),
                     src,
                 )?),
-                ExecType::TypeAggregation => Box::new(AggregationExecutor::new(
+                ExecType::TypeAggregation => {
+                    let stream =
+                        ordered && is_prefix_columns(exec.get_aggregation().get_group_by());
+                    ordered = false;
+                    if stream {
+                        Box::new(StreamAggExecutor::new(
+                            exec.take_aggregation(),
+                            self.eval_ctx.clone(),
+                            self.columns.clone(),
+                            src,
+                        )?)
+                    } else {
+                        Box::new(AggregationExecutor::new(
+                            exec.take_aggregation(),
+                            self.eval_ctx.clone(),
+                            self.columns.clone(),
+                            src,
+                            self.aggr_spill_cfg.clone(),
+                        )?)
+                    }
+                }
+                ExecType::TypeTopN => {
+                    ordered = false;
+                    Box::new(TopNExecutor::new(
+                        exec.take_topN(),
+                        self.eval_ctx.clone(),
+                        self.columns.clone(),
+                        src,
+                    )?)
+                }
+                ExecType::TypeLimit => Box::new(LimitExecutor::new(exec.take_limit(), src)),
+                ExecType::TypeProjection => Box::new(ProjectionExecutor::new(
+                    exec.take_projection(),
+                    self.eval_ctx.clone(),
+                    self.columns.clone(),
+                    src,
+                )?),
+            };
+            src = curr;
+        }
+        Ok(src)
+    }
+
+    // The rows can be resumed by their keys if they are the rows of a table in scan order,
+    // and the rows after a key are the same no matter how many rows are before it.
+    fn resumable_table_id(&self) -> Option<i64> {
+        let execs = self.req.get_executors();
+        if execs[0].get_tp() != ExecType::TypeTableScan {
+            return None;
+        }
+        if execs.iter().any(|exec| match exec.get_tp() {
+            ExecType::TypeAggregation | ExecType::TypeTopN | ExecType::TypeLimit => true,
+            _ => false,
+        }) {
+            return None;
+        }
+        Some(execs[0].get_tbl_scan().get_table_id())
+    }
+
+    // A DAG is executed in batches if it starts with a table scan and all the executors
+    // support batches.
+    fn can_execute_in_batches(&self) -> bool {
+        let execs = self.req.get_executors();
+        if execs[0].get_tp() != ExecType::TypeTableScan {
+            return false;
+        }
+        execs.iter().enumerate().skip(1).all(|(i, exec)| {
+            match exec.get_tp() {
+                ExecType::TypeSelection | ExecType::TypeTopN | ExecType::TypeLimit => true,
+                // The output of aggregation is not in the columns of the scan.
+                ExecType::TypeAggregation => i == execs.len() - 1,
+                _ => false,
+            }
+        })
+    }
+
+    // offsets of the columns used by the executors and the output.
+    fn used_cols_offset(&self) -> Result<Vec<usize>> {
+        let mut visitor = ExprColumnRefVisitor::new(self.columns.len());
+        for exec in self.req.get_executors() {
+            match exec.get_tp() {
+                ExecType::TypeSelection => {
+                    visitor.batch_visit(exec.get_selection().get_conditions())?;
+                }
+                ExecType::TypeAggregation => {
+                    visitor.batch_visit(exec.get_aggregation().get_group_by())?;
+                    visitor.batch_visit(exec.get_aggregation().get_agg_func())?;
+                }
+                ExecType::TypeTopN => for by_item in exec.get_topN().get_order_by() {
+                    visitor.visit(by_item.get_expr())?;
+                },
+                _ => {}
+            }
+        }
+        let mut offsets = visitor.column_offsets();
+        if !self.has_aggr {
+            offsets.extend(self.req.get_output_offsets().iter().map(|o| *o as usize));
+            offsets.sort();
+            offsets.dedup();
+        }
+        Ok(offsets)
+    }
+
+    fn build_batch_dag(
+        &'s self,
+        statistics: &'s mut Statistics,
+    ) -> Result<Box<BatchExecutor + 's>> {
+        let mut execs = self.req.get_executors().to_vec().into_iter();
+        let first = execs.next().unwrap();
+        let store = SnapshotStore::new(
+            self.snap,
+            self.req.get_start_ts(),
+            self.req_ctx.isolation_level,
+            self.req_ctx.fill_cache,
+        );
+        let mut src: Box<BatchExecutor + 's> = Box::new(BatchTableScanExecutor::new(
+            first.get_tbl_scan(),
+            self.ranges.clone(),
+            store,
+            statistics,
+            self.eval_ctx.clone(),
+            self.columns.clone(),
+            self.used_cols_offset()?,
+        ));
+        for mut exec in execs {
+            let curr: Box<BatchExecutor> = match exec.get_tp() {
+                ExecType::TypeSelection => Box::new(BatchSelectionExecutor::new(
+                    exec.take_selection(),
+                    self.eval_ctx.clone(),
+                    self.columns.clone(),
+                    src,
+                )?),
+                ExecType::TypeAggregation => Box::new(BatchAggregationExecutor::new(
                     exec.take_aggregation(),
                     self.eval_ctx.clone(),
                     self.columns.clone(),
                     src,
+                    self.aggr_spill_cfg.clone(),
                 )?),
-                ExecType::TypeTopN => Box::new(TopNExecutor::new(
+                ExecType::TypeTopN => Box::new(BatchTopNExecutor::new(
                     exec.take_topN(),
                     self.eval_ctx.clone(),
                     self.columns.clone(),
                     src,
                 )?),
-                ExecType::TypeLimit => Box::new(LimitExecutor::new(exec.take_limit(), src)),
+                ExecType::TypeLimit => Box::new(BatchLimitExecutor::new(exec.take_limit(), src)),
+                _ => unreachable!(),
             };
             src = curr;
         }
@@ -193,6 +494,113 @@ impl<'s> DAGContext<'s> {
     }
 }
 
+/// Returns the table whose rows are looked up by the handles of the index scan, if the index
+/// scan is followed by a table scan.
+pub fn lookup_table_id(req: &DAGRequest) -> Option<i64> {
+    let execs = req.get_executors();
+    match (execs.get(0), execs.get(1)) {
+        (Some(first), Some(second))
+            if first.get_tp() == ExecType::TypeIndexScan &&
+                second.get_tp() == ExecType::TypeTableScan =>
+        {
+            Some(second.get_tbl_scan().get_table_id())
+        }
+        _ => None,
+    }
+}
+
+/// Returns the raw key range of all the rows of the table.
+pub fn table_row_range(table_id: i64) -> (Vec<u8>, Vec<u8>) {
+    let prefix = table::encode_row_key(table_id, &[]);
+    let end = prefix_next(&prefix);
+    (prefix, end)
+}
+
+// Checks whether the group by items are exactly the leading columns, so the rows ordered
+// by the columns are ordered by the groups.
+fn is_prefix_columns(group_by: &[Expr]) -> bool {
+    if group_by.is_empty() {
+        return false;
+    }
+    let mut offsets = Vec::with_capacity(group_by.len());
+    for expr in group_by {
+        if expr.get_tp() != ExprType::ColumnRef {
+            return false;
+        }
+        match expr.get_val().decode_i64() {
+            Ok(offset) => offsets.push(offset),
+            Err(_) => return false,
+        }
+    }
+    offsets.sort();
+    offsets.iter().enumerate().all(|(i, o)| *o == i as i64)
+}
+
+// Collects the output rows into responses.
+struct ResponseWriter<'a> {
+    req_ctx: ReqContext,
+    chunks: Vec<Chunk>,
+    rows: usize,
+    // The table of the rows, it's set if they are streamed.
+    table_id: i64,
+    // The row limit and the callback of a streaming request whose rows can be resumed.
+    stream: Option<(usize, &'a mut FnMut(Response, Vec<u8>) -> Result<bool>)>,
+}
+
+impl<'a> ResponseWriter<'a> {
+    fn check_if_outdated(&self) -> Result<()> {
+        self.req_ctx.check_if_outdated()
+    }
+
+    // Returns false if the handling should be paused after the row.
+    fn write_row(&mut self, handle: i64, value: &[u8]) -> Result<bool> {
+        get_chunk(&mut self.chunks)
+            .mut_rows_data()
+            .extend_from_slice(value);
+        self.rows += 1;
+        match self.stream {
+            Some((limit, _)) if self.rows >= limit => {}
+            _ => return Ok(true),
+        }
+        let mut buf = Vec::with_capacity(8);
+        // can't panic
+        buf.encode_i64(handle).unwrap();
+        let last_key = table::encode_row_key(self.table_id, &buf);
+        let resp = build_response(mem::replace(&mut self.chunks, vec![]))?;
+        self.rows = 0;
+        match self.stream {
+            Some((_, ref mut on_resp)) => on_resp(resp, last_key),
+            None => unreachable!(),
+        }
+    }
+
+    fn finish(self) -> Result<Response> {
+        build_response(self.chunks)
+    }
+}
+
+fn build_response(chunks: Vec<Chunk>) -> Result<Response> {
+    let mut resp = Response::new();
+    let mut sel_resp = SelectResponse::new();
+    sel_resp.set_chunks(RepeatedField::from_vec(chunks));
+    let data = box_try!(sel_resp.write_to_bytes());
+    resp.set_data(data);
+    Ok(resp)
+}
+
+fn build_error_response(e: Error) -> Result<Response> {
+    if let Error::Other(_) = e {
+        let mut resp = Response::new();
+        let mut sel_resp = SelectResponse::new();
+        sel_resp.set_error(to_pb_error(&e));
+        resp.set_data(box_try!(sel_resp.write_to_bytes()));
+        resp.set_other_error(format!("{}", e));
+        Ok(resp)
+    } else {
+        Err(e)
+    }
+}
+
 #[inline]
 fn inflate_cols(row: &Row, cols: &[ColumnInfo], output_offsets: &[u32]) -> Result<Vec<u8>> {
     let data = &row.data;
@@ -220,3 +628,17 @@ fn inflate_cols(row: &Row, cols: &[ColumnInfo], output_offsets: &[u32]) -> Resul
     }
     Ok(values)
 }
+
+/// Encodes the output columns of a row in the batch, the same as `inflate_cols`.
+#[inline]
+fn inflate_batch_cols(batch: &Batch, idx: usize, output_offsets: &[u32]) -> Result<Vec<u8>> {
+    let mut values = Vec::with_capacity(output_offsets.len());
+    for offset in output_offsets {
+        let col = match batch.columns.get(*offset as usize) {
+            Some(col) => col,
+            None => return Err(box_err!("output offset {} overflow", offset)),
+        };
+        values.push(box_try!(table::flatten(col.datum_at(idx))));
+    }
+    Ok(box_try!(datum::encode_value(&values)))
+}
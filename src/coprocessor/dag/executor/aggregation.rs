@@ -28,14 +28,16 @@ use coprocessor::metrics::*;
 use coprocessor::Result;
 
 use super::{inflate_with_col_for_dag, Executor, ExprColumnRefVisitor, Row};
+use super::spill::{AggrSpiller, SpillConfig, SpillMerger};
 
-struct AggrFuncExpr {
+pub struct AggrFuncExpr {
     args: Vec<Expression>,
-    tp: ExprType,
+    pub tp: ExprType,
+    pub distinct: bool,
 }
 
 impl AggrFuncExpr {
-    fn batch_build(ctx: &EvalContext, expr: Vec<Expr>) -> Result<Vec<AggrFuncExpr>> {
+    pub fn batch_build(ctx: &EvalContext, expr: Vec<Expr>) -> Result<Vec<AggrFuncExpr>> {
         expr.into_iter()
             .map(|v| AggrFuncExpr::build(ctx, v))
             .collect()
@@ -46,8 +48,15 @@ impl AggrFuncExpr {
             ctx,
             expr.take_children().into_vec()
         ));
-        let tp = expr.get_tp();
-        Ok(AggrFuncExpr { args: args, tp: tp })
+        Ok(AggrFuncExpr {
+            args: args,
+            tp: expr.get_tp(),
+            distinct: expr.get_has_distinct(),
+        })
+    }
+
+    pub fn build_func(&self) -> Result<Box<AggrFunc>> {
+        aggregate::build_aggr_func(self.tp, self.distinct)
     }
 
     fn eval_args(&self, ctx: &EvalContext, row: &[Datum]) -> Result<Vec<Datum>> {
@@ -57,7 +66,7 @@ impl AggrFuncExpr {
 }
 
 impl AggrFunc {
-    fn update_with_expr(
+    pub fn update_with_expr(
         &mut self,
         ctx: &EvalContext,
         expr: &AggrFuncExpr,
@@ -80,6 +89,11 @@ pub struct AggregationExecutor<'a> {
     cols: Rc<Vec<ColumnInfo>>,
     related_cols_offset: Vec<usize>, // offset of related columns
     src: Box<Executor + 'a>,
+    // the estimated memory taken by the groups in memory.
+    mem_usage: usize,
+    memory_quota: usize,
+    spiller: AggrSpiller,
+    merger: Option<SpillMerger>,
 }
 
 impl<'a> AggregationExecutor<'a> {
@@ -88,6 +102,7 @@ impl<'a> AggregationExecutor<'a> {
         ctx: Rc<EvalContext>,
         columns: Rc<Vec<ColumnInfo>>,
         src: Box<Executor + 'a>,
+        spill_cfg: SpillConfig,
     ) -> Result<AggregationExecutor<'a>> {
         // collect all cols used in aggregation
         let mut visitor = ExprColumnRefVisitor::new(columns.len());
@@ -109,6 +124,10 @@ impl<'a> AggregationExecutor<'a> {
             cols: columns,
             related_cols_offset: visitor.column_offsets(),
             src: src,
+            mem_usage: 0,
+            memory_quota: spill_cfg.memory_quota,
+            spiller: AggrSpiller::new(spill_cfg.dir),
+            merger: None,
         })
     }
 
@@ -140,40 +159,58 @@ impl<'a> AggregationExecutor<'a> {
                 Entry::Vacant(e) => {
                     let mut aggrs = Vec::with_capacity(self.aggr_func.len());
                     for expr in &self.aggr_func {
-                        let mut aggr = aggregate::build_aggr_func(expr.tp)?;
+                        let mut aggr = expr.build_func()?;
                         aggr.update_with_expr(&self.ctx, expr, &cols)?;
                         aggrs.push(aggr);
                     }
+                    let aggrs_size: usize = aggrs.iter().map(|aggr| aggr.mem_size()).sum();
+                    self.mem_usage += group_key.len() + aggrs_size;
                     self.group_keys.push(group_key);
                     e.insert(aggrs);
                 }
                 Entry::Occupied(e) => {
                     let aggrs = e.into_mut();
                     for (expr, aggr) in self.aggr_func.iter().zip(aggrs) {
+                        // The context may grow with the rows, e.g. the max string.
+                        let size = aggr.mem_size();
                         aggr.update_with_expr(&self.ctx, expr, &cols)?;
+                        self.mem_usage = self.mem_usage + aggr.mem_size() - size;
                     }
                 }
             }
+            if self.mem_usage > self.memory_quota {
+                self.spill()?;
+            }
+        }
+        if !self.spiller.is_empty() {
+            // The groups in memory are spilled too, then all the groups are merged from
+            // the files.
+            self.spill()?;
+            let spiller = self.spiller.take();
+            let funcs = self.aggr_func
+                .iter()
+                .map(|expr| (expr.tp, expr.distinct))
+                .collect();
+            self.merger = Some(spiller.merge(self.ctx.clone(), funcs)?);
         }
         Ok(())
     }
-}
 
-impl<'a> Executor for AggregationExecutor<'a> {
-    fn next(&mut self) -> Result<Option<Row>> {
-        if !self.executed {
-            self.aggregate()?;
-            self.executed = true;
+    fn spill(&mut self) -> Result<()> {
+        let mut groups = Vec::with_capacity(self.group_keys.len());
+        for key in self.group_keys.drain(..) {
+            let aggrs = self.group_key_aggrs.remove(&key).unwrap();
+            groups.push((Rc::try_unwrap(key).unwrap(), aggrs));
         }
+        self.mem_usage = 0;
+        COPR_AGGR_SPILL_COUNTER.inc();
+        self.spiller.spill(groups)
+    }
 
-        if self.cursor >= self.group_keys.len() {
-            return Ok(None);
-        }
+    fn build_row(&self, group_key: &[u8], aggrs: &mut [Box<AggrFunc>]) -> Result<Row> {
         // calc all aggr func
         let mut aggr_cols = Vec::with_capacity(2 * self.aggr_func.len());
-        let group_key = &self.group_keys[self.cursor];
-        let mut aggrs = self.group_key_aggrs.remove(group_key).unwrap();
-        for aggr in &mut aggrs {
+        for aggr in aggrs {
             aggr.calc(&mut aggr_cols)?;
         }
         // construct row data
@@ -183,20 +220,44 @@ impl<'a> Executor for AggregationExecutor<'a> {
         if !self.group_by.is_empty() {
             value.extend_from_slice(group_key);
         }
-        self.cursor += 1;
-        Ok(Some(Row {
+        Ok(Row {
             handle: 0,
             data: RowColsDict::new(map![], value),
-        }))
+        })
+    }
+}
+
+impl<'a> Executor for AggregationExecutor<'a> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        if !self.executed {
+            self.aggregate()?;
+            self.executed = true;
+        }
+
+        let group = match self.merger {
+            Some(ref mut merger) => merger.next()?,
+            None if self.cursor < self.group_keys.len() => {
+                let group_key = self.group_keys[self.cursor].clone();
+                let aggrs = self.group_key_aggrs.remove(&group_key).unwrap();
+                self.cursor += 1;
+                Some(((*group_key).clone(), aggrs))
+            }
+            None => None,
+        };
+        match group {
+            Some((group_key, mut aggrs)) => self.build_row(&group_key, &mut aggrs).map(Some),
+            None => Ok(None),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::i64;
+    use std::{i64, usize};
 
     use kvproto::kvrpcpb::IsolationLevel;
     use protobuf::RepeatedField;
+    use tempdir::TempDir;
     use tipb::executor::TableScan;
     use tipb::expression::{Expr, ExprType};
 
@@ -293,74 +354,80 @@ mod test {
         let mut table_scan = TableScan::new();
         table_scan.set_table_id(tid);
         table_scan.set_columns(RepeatedField::from_vec(cis.clone()));
-        // init TableScan Exectutor
-        let key_ranges = vec![get_range(tid, i64::MIN, i64::MAX)];
-        let (snapshot, start_ts) = test_store.get_snapshot();
-        let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+        // the groups are spilled to disk when the memory quota is exceeded, they are
+        // merged in the order of the group keys, which is the same as the order above.
+        let spill_dir = TempDir::new("test-aggr-spill").unwrap();
+        for quota in vec![usize::MAX, 1] {
+            // init TableScan Exectutor
+            let key_ranges = vec![get_range(tid, i64::MIN, i64::MAX)];
+            let (snapshot, start_ts) = test_store.get_snapshot();
+            let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
 
-        let mut statistics = Statistics::default();
-        let ts_ect = TableScanExecutor::new(&table_scan, key_ranges, store, &mut statistics);
+            let mut statistics = Statistics::default();
+            let ts_ect = TableScanExecutor::new(&table_scan, key_ranges, store, &mut statistics);
 
-        // init aggregation meta
-        let mut aggregation = Aggregation::default();
-        let group_by_cols = vec![1, 2];
-        let group_by = build_group_by(&group_by_cols);
-        aggregation.set_group_by(RepeatedField::from_vec(group_by));
-        let aggr_funcs = vec![(ExprType::Avg, 0), (ExprType::Count, 2)];
-        let aggr_funcs = build_aggr_func(&aggr_funcs);
-        aggregation.set_agg_func(RepeatedField::from_vec(aggr_funcs));
-        // init Aggregation Executor
-        let mut aggr_ect = AggregationExecutor::new(
-            aggregation,
-            Rc::new(EvalContext::default()),
-            Rc::new(cis),
-            Box::new(ts_ect),
-        ).unwrap();
-        let expect_row_cnt = 4;
-        let mut row_data = Vec::with_capacity(expect_row_cnt);
-        while let Some(row) = aggr_ect.next().unwrap() {
-            row_data.push(row.data);
-        }
-        assert_eq!(row_data.len(), expect_row_cnt);
-        let expect_row_data = vec![
-            (
-                3 as u64,
-                Decimal::from(7),
-                3 as u64,
-                b"a".as_ref(),
-                Decimal::from(7),
-            ),
-            (
-                2 as u64,
-                Decimal::from(9),
-                2 as u64,
-                b"b".as_ref(),
-                Decimal::from(8),
-            ),
-            (
-                1 as u64,
-                Decimal::from(5),
-                1 as u64,
-                b"f".as_ref(),
-                Decimal::from(5),
-            ),
-            (
-                1 as u64,
-                Decimal::from(7),
-                1 as u64,
-                b"f".as_ref(),
-                Decimal::from(6),
-            ),
-        ];
-        let expect_col_cnt = 5;
-        for (row, expect_cols) in row_data.into_iter().zip(expect_row_data) {
-            let ds = row.value.as_slice().decode().unwrap();
-            assert_eq!(ds.len(), expect_col_cnt);
-            assert_eq!(ds[0], Datum::from(expect_cols.0));
-            assert_eq!(ds[1], Datum::from(expect_cols.1));
-            assert_eq!(ds[2], Datum::from(expect_cols.2));
-            assert_eq!(ds[3], Datum::from(expect_cols.3));
-            assert_eq!(ds[4], Datum::from(expect_cols.4));
+            // init aggregation meta
+            let mut aggregation = Aggregation::default();
+            let group_by_cols = vec![1, 2];
+            let group_by = build_group_by(&group_by_cols);
+            aggregation.set_group_by(RepeatedField::from_vec(group_by));
+            let aggr_funcs = vec![(ExprType::Avg, 0), (ExprType::Count, 2)];
+            let aggr_funcs = build_aggr_func(&aggr_funcs);
+            aggregation.set_agg_func(RepeatedField::from_vec(aggr_funcs));
+            // init Aggregation Executor
+            let mut aggr_ect = AggregationExecutor::new(
+                aggregation,
+                Rc::new(EvalContext::default()),
+                Rc::new(cis.clone()),
+                Box::new(ts_ect),
+                SpillConfig::new(quota, spill_dir.path().to_path_buf()),
+            ).unwrap();
+            let expect_row_cnt = 4;
+            let mut row_data = Vec::with_capacity(expect_row_cnt);
+            while let Some(row) = aggr_ect.next().unwrap() {
+                row_data.push(row.data);
+            }
+            assert_eq!(row_data.len(), expect_row_cnt);
+            let expect_row_data = vec![
+                (
+                    3 as u64,
+                    Decimal::from(7),
+                    3 as u64,
+                    b"a".as_ref(),
+                    Decimal::from(7),
+                ),
+                (
+                    2 as u64,
+                    Decimal::from(9),
+                    2 as u64,
+                    b"b".as_ref(),
+                    Decimal::from(8),
+                ),
+                (
+                    1 as u64,
+                    Decimal::from(5),
+                    1 as u64,
+                    b"f".as_ref(),
+                    Decimal::from(5),
+                ),
+                (
+                    1 as u64,
+                    Decimal::from(7),
+                    1 as u64,
+                    b"f".as_ref(),
+                    Decimal::from(6),
+                ),
+            ];
+            let expect_col_cnt = 5;
+            for (row, expect_cols) in row_data.into_iter().zip(expect_row_data) {
+                let ds = row.value.as_slice().decode().unwrap();
+                assert_eq!(ds.len(), expect_col_cnt);
+                assert_eq!(ds[0], Datum::from(expect_cols.0));
+                assert_eq!(ds[1], Datum::from(expect_cols.1));
+                assert_eq!(ds[2], Datum::from(expect_cols.2));
+                assert_eq!(ds[3], Datum::from(expect_cols.3));
+                assert_eq!(ds[4], Datum::from(expect_cols.4));
+            }
         }
     }
 }
@@ -0,0 +1,227 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::rc::Rc;
+use std::vec::IntoIter;
+
+use tipb::executor::Aggregation;
+use tipb::expression::{Expr, ExprType};
+use tipb::schema::ColumnInfo;
+use util::collections::{HashMap, HashMapEntry as Entry};
+
+use coprocessor::codec::datum::{self, Datum, DatumDecoder};
+use coprocessor::dag::expr::Expression;
+use coprocessor::metrics::*;
+use coprocessor::select::aggregate::{self, AggrFunc};
+use coprocessor::select::xeval::EvalContext;
+use coprocessor::Result;
+
+use super::{Batch, BatchExecutor, BATCH_SIZE};
+use super::super::ExprColumnRefVisitor;
+use super::super::spill::{AggrSpiller, SpillConfig, SpillMerger};
+
+struct AggrFuncExpr {
+    args: Vec<Expression>,
+    tp: ExprType,
+    distinct: bool,
+}
+
+impl AggrFuncExpr {
+    fn build(ctx: &EvalContext, mut expr: Expr) -> Result<AggrFuncExpr> {
+        let args = box_try!(Expression::batch_build(
+            ctx,
+            expr.take_children().into_vec()
+        ));
+        Ok(AggrFuncExpr {
+            args: args,
+            tp: expr.get_tp(),
+            distinct: expr.get_has_distinct(),
+        })
+    }
+}
+
+/// The group by values and the aggregate functions of a group.
+type Group = (Vec<Datum>, Vec<Box<AggrFunc>>);
+
+/// Aggregates the batches by hash, the output rows are the results of the aggregate
+/// functions followed by the group by values, in the order the groups are seen. If the
+/// groups take more memory than the quota, they are spilled to disk and the output rows
+/// are in the order of the encoded group by values.
+pub struct BatchAggregationExecutor<'a> {
+    group_by: Vec<Expression>,
+    aggr_func: Vec<AggrFuncExpr>,
+    groups: Option<IntoIter<Group>>,
+    ctx: Rc<EvalContext>,
+    src: Box<BatchExecutor + 'a>,
+    memory_quota: usize,
+    spiller: AggrSpiller,
+    merger: Option<SpillMerger>,
+}
+
+impl<'a> BatchAggregationExecutor<'a> {
+    pub fn new(
+        mut meta: Aggregation,
+        ctx: Rc<EvalContext>,
+        columns: Rc<Vec<ColumnInfo>>,
+        src: Box<BatchExecutor + 'a>,
+        spill_cfg: SpillConfig,
+    ) -> Result<BatchAggregationExecutor<'a>> {
+        let mut visitor = ExprColumnRefVisitor::new(columns.len());
+        let group_by = meta.take_group_by().into_vec();
+        visitor.batch_visit(&group_by)?;
+        let aggr_func = meta.take_agg_func().into_vec();
+        visitor.batch_visit(&aggr_func)?;
+        COPR_EXECUTOR_COUNT
+            .with_label_values(&["aggregation"])
+            .inc();
+        Ok(BatchAggregationExecutor {
+            group_by: box_try!(Expression::batch_build(ctx.as_ref(), group_by)),
+            aggr_func: aggr_func
+                .into_iter()
+                .map(|expr| AggrFuncExpr::build(ctx.as_ref(), expr))
+                .collect::<Result<_>>()?,
+            groups: None,
+            ctx: ctx,
+            src: src,
+            memory_quota: spill_cfg.memory_quota,
+            spiller: AggrSpiller::new(spill_cfg.dir),
+            merger: None,
+        })
+    }
+
+    fn new_aggrs(&self) -> Result<Vec<Box<AggrFunc>>> {
+        self.aggr_func
+            .iter()
+            .map(|expr| aggregate::build_aggr_func(expr.tp, expr.distinct))
+            .collect()
+    }
+
+    fn aggregate(&mut self) -> Result<Vec<Group>> {
+        let mut groups: Vec<Group> = vec![];
+        // encoded group by values -> index of the group
+        let mut group_idx = HashMap::default();
+        let mut mem_usage = 0;
+        while let Some(batch) = self.src.next_batch()? {
+            let (columns, rows) = (&batch.columns, batch.len());
+            let mut group_by = Vec::with_capacity(self.group_by.len());
+            for expr in &self.group_by {
+                group_by.push(box_try!(expr.eval_vector(&self.ctx, columns, rows)));
+            }
+            let mut args = Vec::with_capacity(self.aggr_func.len());
+            for expr in &self.aggr_func {
+                let mut vals = Vec::with_capacity(expr.args.len());
+                for arg in &expr.args {
+                    vals.push(box_try!(arg.eval_vector(&self.ctx, columns, rows)));
+                }
+                args.push(vals);
+            }
+
+            for i in 0..rows {
+                let idx = if group_by.is_empty() {
+                    if groups.is_empty() {
+                        groups.push((vec![], self.new_aggrs()?));
+                    }
+                    0
+                } else {
+                    let vals: Vec<_> = group_by.iter().map(|v| v.datum_at(i)).collect();
+                    let key = box_try!(datum::encode_value(&vals));
+                    match group_idx.entry(key) {
+                        Entry::Occupied(e) => *e.get(),
+                        Entry::Vacant(e) => {
+                            let aggrs = self.new_aggrs()?;
+                            let aggrs_size: usize = aggrs.iter().map(|aggr| aggr.mem_size()).sum();
+                            mem_usage += 2 * e.key().len() + aggrs_size;
+                            groups.push((vals, aggrs));
+                            *e.insert(groups.len() - 1)
+                        }
+                    }
+                };
+                for (aggr, vals) in groups[idx].1.iter_mut().zip(&args) {
+                    let vals = vals.iter().map(|v| v.datum_at(i)).collect();
+                    // The context may grow with the rows, e.g. the max string.
+                    let size = aggr.mem_size();
+                    aggr.update(&self.ctx, vals)?;
+                    mem_usage = mem_usage + aggr.mem_size() - size;
+                }
+            }
+            if mem_usage > self.memory_quota {
+                self.spill(&mut group_idx, &mut groups)?;
+                mem_usage = 0;
+            }
+        }
+        if self.spiller.is_empty() {
+            return Ok(groups);
+        }
+        // The groups in memory are spilled too, then all the groups are merged from the
+        // files.
+        self.spill(&mut group_idx, &mut groups)?;
+        let spiller = self.spiller.take();
+        let funcs = self.aggr_func
+            .iter()
+            .map(|expr| (expr.tp, expr.distinct))
+            .collect();
+        self.merger = Some(spiller.merge(self.ctx.clone(), funcs)?);
+        Ok(vec![])
+    }
+
+    fn spill(
+        &mut self,
+        group_idx: &mut HashMap<Vec<u8>, usize>,
+        groups: &mut Vec<Group>,
+    ) -> Result<()> {
+        let mut aggrs: Vec<_> = groups.drain(..).map(|(_, aggrs)| Some(aggrs)).collect();
+        let spilled = group_idx
+            .drain()
+            .map(|(key, idx)| (key, aggrs[idx].take().unwrap()))
+            .collect();
+        COPR_AGGR_SPILL_COUNTER.inc();
+        self.spiller.spill(spilled)
+    }
+
+    fn next_group(&mut self) -> Result<Option<Group>> {
+        if let Some(ref mut merger) = self.merger {
+            return match merger.next()? {
+                Some((key, aggrs)) => Ok(Some((box_try!(key.as_slice().decode()), aggrs))),
+                None => Ok(None),
+            };
+        }
+        Ok(self.groups.as_mut().unwrap().next())
+    }
+}
+
+impl<'a> BatchExecutor for BatchAggregationExecutor<'a> {
+    fn next_batch(&mut self) -> Result<Option<Batch>> {
+        if self.groups.is_none() {
+            let groups = self.aggregate()?;
+            self.groups = Some(groups.into_iter());
+        }
+        let mut rows = vec![];
+        while rows.len() < BATCH_SIZE {
+            let (group_by, mut aggrs) = match self.next_group()? {
+                Some(group) => group,
+                None => break,
+            };
+            let mut row = Vec::with_capacity(2 * aggrs.len() + group_by.len());
+            for aggr in &mut aggrs {
+                aggr.calc(&mut row)?;
+            }
+            row.extend(group_by);
+            rows.push(row);
+        }
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        let cols_len = rows[0].len();
+        Ok(Some(Batch::from_rows(vec![0; rows.len()], rows, cols_len)))
+    }
+}
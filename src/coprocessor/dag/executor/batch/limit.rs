@@ -0,0 +1,54 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tipb::executor::Limit;
+
+use coprocessor::Result;
+use coprocessor::metrics::*;
+
+use super::{Batch, BatchExecutor};
+
+pub struct BatchLimitExecutor<'a> {
+    limit: u64,
+    cursor: u64,
+    src: Box<BatchExecutor + 'a>,
+}
+
+impl<'a> BatchLimitExecutor<'a> {
+    pub fn new(limit: Limit, src: Box<BatchExecutor + 'a>) -> BatchLimitExecutor {
+        COPR_EXECUTOR_COUNT.with_label_values(&["limit"]).inc();
+        BatchLimitExecutor {
+            limit: limit.get_limit(),
+            cursor: 0,
+            src: src,
+        }
+    }
+}
+
+impl<'a> BatchExecutor for BatchLimitExecutor<'a> {
+    fn next_batch(&mut self) -> Result<Option<Batch>> {
+        if self.cursor >= self.limit {
+            return Ok(None);
+        }
+        let mut batch = match self.src.next_batch()? {
+            Some(batch) => batch,
+            None => return Ok(None),
+        };
+        let left = self.limit - self.cursor;
+        if batch.len() as u64 > left {
+            batch.truncate(left as usize);
+        }
+        self.cursor += batch.len() as u64;
+        Ok(Some(batch))
+    }
+}
@@ -0,0 +1,408 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Executors passing batches of rows in columns between each other, the expressions are
+//! evaluated over whole columns.
+//!
+//! Only the DAGs starting with a table scan are executed in batches, the others are executed
+//! by the executors in rows.
+
+use coprocessor::codec::datum::Datum;
+use coprocessor::dag::expr::VectorValue;
+use coprocessor::Result;
+
+mod table_scan;
+mod selection;
+mod topn;
+mod limit;
+mod aggregation;
+
+pub use self::table_scan::BatchTableScanExecutor;
+pub use self::selection::BatchSelectionExecutor;
+pub use self::topn::BatchTopNExecutor;
+pub use self::limit::BatchLimitExecutor;
+pub use self::aggregation::BatchAggregationExecutor;
+
+/// The max number of rows in a batch.
+pub const BATCH_SIZE: usize = 1024;
+
+/// A batch of rows, the columns are in the order of the columns of the scan.
+#[derive(Debug)]
+pub struct Batch {
+    pub handles: Vec<i64>,
+    pub columns: Vec<VectorValue>,
+}
+
+impl Batch {
+    pub fn from_rows(handles: Vec<i64>, rows: Vec<Vec<Datum>>, cols_len: usize) -> Batch {
+        let mut columns: Vec<_> = (0..cols_len)
+            .map(|_| Vec::with_capacity(rows.len()))
+            .collect();
+        for row in rows {
+            for (col, datum) in columns.iter_mut().zip(row) {
+                col.push(datum);
+            }
+        }
+        Batch {
+            handles: handles,
+            columns: columns.into_iter().map(VectorValue::from_datums).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    pub fn row(&self, idx: usize) -> Vec<Datum> {
+        self.columns.iter().map(|c| c.datum_at(idx)).collect()
+    }
+
+    /// Keeps the rows whose positions are marked in `keep`.
+    pub fn retain(&mut self, keep: &[bool]) {
+        let mut i = 0;
+        self.handles.retain(|_| {
+            i += 1;
+            keep[i - 1]
+        });
+        for col in &mut self.columns {
+            col.retain(keep);
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.handles.truncate(len);
+        for col in &mut self.columns {
+            col.truncate(len);
+        }
+    }
+}
+
+pub trait BatchExecutor {
+    /// Returns the next batch, which is never empty, or `None` if there are no more rows.
+    fn next_batch(&mut self) -> Result<Option<Batch>>;
+}
+
+#[cfg(test)]
+mod test {
+    use std::{i64, usize};
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    use kvproto::kvrpcpb::IsolationLevel;
+    use protobuf::RepeatedField;
+    use tempdir::TempDir;
+    use tipb::executor::{Aggregation, Limit, Selection, TableScan, TopN};
+    use tipb::expression::{ByItem, Expr, ExprType, ScalarFuncSig};
+    use tipb::schema::ColumnInfo;
+
+    use coprocessor::codec::datum::{self, Datum};
+    use coprocessor::codec::mysql::types;
+    use coprocessor::select::xeval::EvalContext;
+    use storage::{SnapshotStore, Statistics};
+    use util::codec::number::NumberEncoder;
+
+    use super::*;
+    use super::super::{AggregationExecutor, Executor, LimitExecutor, SelectionExecutor,
+                       SpillConfig, TableScanExecutor, TopNExecutor};
+    use super::super::topn::test::gen_table_data;
+    use super::super::scanner::test::{get_range, new_col_info, TestStore};
+
+    const TABLE_ID: i64 = 1;
+
+    fn col_expr(offset: i64) -> Expr {
+        let mut expr = Expr::new();
+        expr.set_tp(ExprType::ColumnRef);
+        expr.mut_val().encode_i64(offset).unwrap();
+        expr
+    }
+
+    fn i64_expr(v: i64) -> Expr {
+        let mut expr = Expr::new();
+        expr.set_tp(ExprType::Int64);
+        expr.mut_val().encode_i64(v).unwrap();
+        expr
+    }
+
+    fn fn_expr(sig: ScalarFuncSig, children: Vec<Expr>) -> Expr {
+        let mut expr = Expr::new();
+        expr.set_tp(ExprType::ScalarFunc);
+        expr.set_sig(sig);
+        expr.set_children(RepeatedField::from_vec(children));
+        expr
+    }
+
+    fn aggr_expr(tp: ExprType, children: Vec<Expr>) -> Expr {
+        let mut expr = Expr::new();
+        expr.set_tp(tp);
+        expr.set_children(RepeatedField::from_vec(children));
+        expr
+    }
+
+    fn columns() -> Vec<ColumnInfo> {
+        vec![
+            new_col_info(1, types::LONG_LONG),
+            new_col_info(2, types::VARCHAR),
+            new_col_info(3, types::LONG_LONG),
+            new_col_info(4, types::DOUBLE),
+        ]
+    }
+
+    fn prepare_store(rows: usize) -> TestStore {
+        let raw_data: Vec<_> = (0..rows as i64)
+            .map(|i| {
+                let s = format!("name:{}", i % 7).into_bytes();
+                let v = if i % 5 == 0 {
+                    Datum::Null
+                } else {
+                    Datum::I64(i % 13)
+                };
+                vec![Datum::I64(i), Datum::Bytes(s), v, Datum::F64(i as f64 / 3.0)]
+            })
+            .collect();
+        TestStore::new(&gen_table_data(TABLE_ID, &columns(), &raw_data))
+    }
+
+    fn table_scan() -> TableScan {
+        let mut table_scan = TableScan::new();
+        table_scan.set_table_id(TABLE_ID);
+        table_scan.set_columns(RepeatedField::from_vec(columns()));
+        table_scan
+    }
+
+    fn selection() -> Selection {
+        let mut selection = Selection::new();
+        // col2 > 3 and col3 < 800.0
+        selection.mut_conditions().push(fn_expr(
+            ScalarFuncSig::GTInt,
+            vec![col_expr(2), i64_expr(3)],
+        ));
+        let mut real = Expr::new();
+        real.set_tp(ExprType::Float64);
+        real.mut_val().encode_f64(800.0).unwrap();
+        selection
+            .mut_conditions()
+            .push(fn_expr(ScalarFuncSig::LTReal, vec![col_expr(3), real]));
+        selection
+    }
+
+    fn topn(limit: u64) -> TopN {
+        let mut topn = TopN::new();
+        let mut item = ByItem::new();
+        item.set_expr(col_expr(2));
+        item.set_desc(true);
+        topn.mut_order_by().push(item);
+        let mut item = ByItem::new();
+        item.set_expr(col_expr(0));
+        topn.mut_order_by().push(item);
+        topn.set_limit(limit);
+        topn
+    }
+
+    fn limit(limit: u64) -> Limit {
+        let mut meta = Limit::new();
+        meta.set_limit(limit);
+        meta
+    }
+
+    fn aggregation() -> Aggregation {
+        let mut aggregation = Aggregation::new();
+        aggregation.mut_group_by().push(col_expr(1));
+        let aggr_funcs = vec![
+            aggr_expr(ExprType::Count, vec![col_expr(2)]),
+            aggr_expr(ExprType::Sum, vec![col_expr(2)]),
+            aggr_expr(ExprType::Avg, vec![col_expr(3)]),
+            aggr_expr(
+                ExprType::Max,
+                vec![fn_expr(ScalarFuncSig::PlusInt, vec![col_expr(2), col_expr(0)])],
+            ),
+        ];
+        aggregation.set_agg_func(RepeatedField::from_vec(aggr_funcs));
+        aggregation
+    }
+
+    fn collect_batches(exec: &mut BatchExecutor) -> (Vec<i64>, Vec<Vec<Datum>>) {
+        let (mut handles, mut rows) = (vec![], vec![]);
+        while let Some(batch) = exec.next_batch().unwrap() {
+            assert!(!batch.is_empty());
+            assert!(batch.len() <= BATCH_SIZE);
+            for i in 0..batch.len() {
+                rows.push(batch.row(i));
+            }
+            handles.extend(batch.handles);
+        }
+        (handles, rows)
+    }
+
+    #[test]
+    fn test_batch_executors() {
+        let ctx = Rc::new(EvalContext::default());
+        let cols = Rc::new(columns());
+        let offsets: Vec<_> = (0..cols.len()).collect();
+        let mut store = prepare_store(BATCH_SIZE * 3 + 10);
+        let (snapshot, start_ts) = store.get_snapshot();
+        let ranges = vec![get_range(TABLE_ID, i64::MIN, i64::MAX)];
+
+        for &(topn_limit, limit_limit) in &[(100, 30), (5000, 2100)] {
+            let mut statistics = Statistics::default();
+            let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+            let scan =
+                TableScanExecutor::new(&table_scan(), ranges.clone(), store, &mut statistics);
+            let selection =
+                SelectionExecutor::new(selection(), ctx.clone(), cols.clone(), box scan).unwrap();
+            let topn = TopNExecutor::new(topn(topn_limit), ctx.clone(), cols.clone(), box selection)
+                .unwrap();
+            let mut exec = LimitExecutor::new(limit(limit_limit), box topn);
+            let mut expect = vec![];
+            while let Some(row) = exec.next().unwrap() {
+                expect.push(row.handle);
+            }
+            assert!(!expect.is_empty());
+
+            let mut statistics = Statistics::default();
+            let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+            let scan = BatchTableScanExecutor::new(
+                &table_scan(),
+                ranges.clone(),
+                store,
+                &mut statistics,
+                ctx.clone(),
+                cols.clone(),
+                offsets.clone(),
+            );
+            let selection =
+                BatchSelectionExecutor::new(selection(), ctx.clone(), cols.clone(), box scan)
+                    .unwrap();
+            let topn =
+                BatchTopNExecutor::new(topn(topn_limit), ctx.clone(), cols.clone(), box selection)
+                    .unwrap();
+            let mut exec = BatchLimitExecutor::new(limit(limit_limit), box topn);
+            let (handles, rows) = collect_batches(&mut exec);
+            assert_eq!(handles, expect);
+            for (row, handle) in rows.iter().zip(handles) {
+                assert_eq!(row[0], Datum::I64(handle));
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch_aggregation() {
+        let ctx = Rc::new(EvalContext::default());
+        let cols = Rc::new(columns());
+        let offsets: Vec<_> = (0..cols.len()).collect();
+        let mut store = prepare_store(BATCH_SIZE * 2 + 10);
+        let (snapshot, start_ts) = store.get_snapshot();
+        let ranges = vec![get_range(TABLE_ID, i64::MIN, i64::MAX)];
+
+        let mut statistics = Statistics::default();
+        let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+        let scan = TableScanExecutor::new(&table_scan(), ranges.clone(), store, &mut statistics);
+        let mut exec = AggregationExecutor::new(
+            aggregation(),
+            ctx.clone(),
+            cols.clone(),
+            box scan,
+            SpillConfig::new(usize::MAX, PathBuf::new()),
+        ).unwrap();
+        let mut expect = vec![];
+        while let Some(row) = exec.next().unwrap() {
+            expect.push(row.data.value);
+        }
+        assert_eq!(expect.len(), 7);
+
+        let mut statistics = Statistics::default();
+        let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+        let scan = BatchTableScanExecutor::new(
+            &table_scan(),
+            ranges.clone(),
+            store,
+            &mut statistics,
+            ctx.clone(),
+            cols.clone(),
+            offsets.clone(),
+        );
+        let mut exec = BatchAggregationExecutor::new(
+            aggregation(),
+            ctx.clone(),
+            cols.clone(),
+            box scan,
+            SpillConfig::new(usize::MAX, PathBuf::new()),
+        ).unwrap();
+        let (_, mut rows) = collect_batches(&mut exec);
+        let encoded: Vec<_> = rows.iter()
+            .map(|row| datum::encode_value(row).unwrap())
+            .collect();
+        assert_eq!(encoded, expect);
+
+        // spill the groups of every batch, they are merged in the order of the group keys.
+        let mut statistics = Statistics::default();
+        let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+        let scan = BatchTableScanExecutor::new(
+            &table_scan(),
+            ranges,
+            store,
+            &mut statistics,
+            ctx.clone(),
+            cols.clone(),
+            offsets,
+        );
+        let spill_dir = TempDir::new("test-aggr-spill").unwrap();
+        let spill_cfg = SpillConfig::new(1, spill_dir.path().to_path_buf());
+        let mut exec =
+            BatchAggregationExecutor::new(aggregation(), ctx, cols, box scan, spill_cfg).unwrap();
+        let (_, mut spilled) = collect_batches(&mut exec);
+        // the float sums of avg are added in another order, so they are skipped.
+        for row in rows.iter_mut().chain(spilled.iter_mut()) {
+            row.remove(3);
+        }
+        let key = |row: &Vec<Datum>| datum::encode_value(&row[4..]).unwrap();
+        rows.sort_by_key(&key);
+        assert_eq!(spilled, rows);
+    }
+
+    #[test]
+    fn test_batch_table_scan_missing_column() {
+        let ctx = Rc::new(EvalContext::default());
+        let mut cols = columns();
+        let mut missing = new_col_info(5, types::LONG_LONG);
+        missing.set_default_val(datum::encode_value(&[Datum::I64(-1)]).unwrap());
+        cols.push(missing);
+        cols.push(new_col_info(6, types::VARCHAR));
+        let mut meta = table_scan();
+        meta.set_columns(RepeatedField::from_vec(cols.clone()));
+        let mut store = prepare_store(3);
+        let (snapshot, start_ts) = store.get_snapshot();
+        let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+        let mut statistics = Statistics::default();
+        let mut exec = BatchTableScanExecutor::new(
+            &meta,
+            vec![get_range(TABLE_ID, i64::MIN, i64::MAX)],
+            store,
+            &mut statistics,
+            ctx,
+            Rc::new(cols),
+            vec![0, 4, 5],
+        );
+        let batch = exec.next_batch().unwrap().unwrap();
+        assert_eq!(batch.handles, vec![0, 1, 2]);
+        assert_eq!(batch.columns[0], VectorValue::Int(vec![Some(0), Some(1), Some(2)]));
+        // Not decoded.
+        assert_eq!(batch.columns[1], VectorValue::Datum(vec![Datum::Null; 3]));
+        assert_eq!(batch.columns[4], VectorValue::Int(vec![Some(-1); 3]));
+        assert_eq!(batch.columns[5], VectorValue::Datum(vec![Datum::Null; 3]));
+        assert!(exec.next_batch().unwrap().is_none());
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::rc::Rc;
+
+use tipb::executor::Selection;
+use tipb::schema::ColumnInfo;
+
+use coprocessor::metrics::*;
+use coprocessor::select::xeval::EvalContext;
+use coprocessor::dag::expr::{Expression, VectorValue};
+use coprocessor::Result;
+
+use super::{Batch, BatchExecutor};
+use super::super::ExprColumnRefVisitor;
+
+pub struct BatchSelectionExecutor<'a> {
+    conditions: Vec<Expression>,
+    ctx: Rc<EvalContext>,
+    src: Box<BatchExecutor + 'a>,
+}
+
+impl<'a> BatchSelectionExecutor<'a> {
+    pub fn new(
+        mut meta: Selection,
+        ctx: Rc<EvalContext>,
+        columns_info: Rc<Vec<ColumnInfo>>,
+        src: Box<BatchExecutor + 'a>,
+    ) -> Result<BatchSelectionExecutor<'a>> {
+        let conditions = meta.take_conditions().into_vec();
+        let mut visitor = ExprColumnRefVisitor::new(columns_info.len());
+        visitor.batch_visit(&conditions)?;
+        COPR_EXECUTOR_COUNT.with_label_values(&["selection"]).inc();
+        Ok(BatchSelectionExecutor {
+            conditions: box_try!(Expression::batch_build(ctx.as_ref(), conditions)),
+            ctx: ctx,
+            src: src,
+        })
+    }
+
+    fn filter(&self, batch: &mut Batch) -> Result<()> {
+        // The rows filtered out by a condition are not evaluated by the following ones,
+        // the same as filtering row by row.
+        for filter in &self.conditions {
+            let keep: Vec<bool> = {
+                let val = box_try!(filter.eval_vector(&self.ctx, &batch.columns, batch.len()));
+                match *val {
+                    VectorValue::Int(ref v) => {
+                        v.iter().map(|v| v.map_or(false, |v| v != 0)).collect()
+                    }
+                    ref val => {
+                        let mut keep = Vec::with_capacity(val.len());
+                        for i in 0..val.len() {
+                            let b = box_try!(val.datum_at(i).into_bool(&self.ctx));
+                            keep.push(b.unwrap_or(false));
+                        }
+                        keep
+                    }
+                }
+            };
+            batch.retain(&keep);
+            if batch.is_empty() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> BatchExecutor for BatchSelectionExecutor<'a> {
+    fn next_batch(&mut self) -> Result<Option<Batch>> {
+        while let Some(mut batch) = self.src.next_batch()? {
+            self.filter(&mut batch)?;
+            if !batch.is_empty() {
+                return Ok(Some(batch));
+            }
+        }
+        Ok(None)
+    }
+}
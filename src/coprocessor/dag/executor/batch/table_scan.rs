@@ -0,0 +1,77 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::rc::Rc;
+
+use kvproto::coprocessor::KeyRange;
+use tipb::executor::TableScan;
+use tipb::schema::ColumnInfo;
+
+use storage::{SnapshotStore, Statistics};
+use coprocessor::select::xeval::EvalContext;
+use coprocessor::Result;
+
+use super::{Batch, BatchExecutor, BATCH_SIZE};
+use super::super::{inflate_with_col_for_dag, Executor, TableScanExecutor};
+
+pub struct BatchTableScanExecutor<'a> {
+    scan: TableScanExecutor<'a>,
+    ctx: Rc<EvalContext>,
+    cols: Rc<Vec<ColumnInfo>>,
+    // offset of the columns used by the DAG, the others are left as nulls.
+    cols_offset: Vec<usize>,
+}
+
+impl<'a> BatchTableScanExecutor<'a> {
+    pub fn new(
+        meta: &TableScan,
+        key_ranges: Vec<KeyRange>,
+        store: SnapshotStore<'a>,
+        statistics: &'a mut Statistics,
+        ctx: Rc<EvalContext>,
+        columns: Rc<Vec<ColumnInfo>>,
+        cols_offset: Vec<usize>,
+    ) -> BatchTableScanExecutor<'a> {
+        BatchTableScanExecutor {
+            scan: TableScanExecutor::new(meta, key_ranges, store, statistics),
+            ctx: ctx,
+            cols: columns,
+            cols_offset: cols_offset,
+        }
+    }
+}
+
+impl<'a> BatchExecutor for BatchTableScanExecutor<'a> {
+    fn next_batch(&mut self) -> Result<Option<Batch>> {
+        let mut handles = Vec::with_capacity(BATCH_SIZE);
+        let mut rows = Vec::with_capacity(BATCH_SIZE);
+        while rows.len() < BATCH_SIZE {
+            let row = match self.scan.next()? {
+                Some(row) => row,
+                None => break,
+            };
+            rows.push(inflate_with_col_for_dag(
+                &self.ctx,
+                &row.data,
+                self.cols.clone(),
+                &self.cols_offset,
+                row.handle,
+            )?);
+            handles.push(row.handle);
+        }
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Batch::from_rows(handles, rows, self.cols.len())))
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::rc::Rc;
+use std::vec::IntoIter;
+
+use tipb::executor::TopN;
+use tipb::expression::ByItem;
+use tipb::schema::ColumnInfo;
+
+use coprocessor::codec::datum::Datum;
+use coprocessor::dag::expr::Expression;
+use coprocessor::metrics::*;
+use coprocessor::select::topn_heap::{SortRow, TopNHeap};
+use coprocessor::select::xeval::EvalContext;
+use coprocessor::Result;
+
+use super::{Batch, BatchExecutor, BATCH_SIZE};
+use super::super::ExprColumnRefVisitor;
+
+pub struct BatchTopNExecutor<'a> {
+    order_by: Rc<Vec<ByItem>>,
+    order_by_exprs: Vec<Expression>,
+    cols_len: usize,
+    heap: Option<TopNHeap<Vec<Datum>>>,
+    iter: Option<IntoIter<SortRow<Vec<Datum>>>>,
+    ctx: Rc<EvalContext>,
+    src: Box<BatchExecutor + 'a>,
+}
+
+impl<'a> BatchTopNExecutor<'a> {
+    pub fn new(
+        mut meta: TopN,
+        ctx: Rc<EvalContext>,
+        columns_info: Rc<Vec<ColumnInfo>>,
+        src: Box<BatchExecutor + 'a>,
+    ) -> Result<BatchTopNExecutor<'a>> {
+        let mut order_by = meta.take_order_by().into_vec();
+        let mut visitor = ExprColumnRefVisitor::new(columns_info.len());
+        for by_item in &order_by {
+            visitor.visit(by_item.get_expr())?;
+        }
+        let order_by_exprs: Vec<Expression> = box_try!(
+            order_by
+                .iter_mut()
+                .map(|v| Expression::build(&ctx, v.take_expr()))
+                .collect()
+        );
+        COPR_EXECUTOR_COUNT.with_label_values(&["topn"]).inc();
+        Ok(BatchTopNExecutor {
+            order_by: Rc::new(order_by),
+            order_by_exprs: order_by_exprs,
+            cols_len: columns_info.len(),
+            heap: Some(TopNHeap::new(meta.get_limit() as usize)?),
+            iter: None,
+            ctx: ctx,
+            src: src,
+        })
+    }
+
+    fn fetch_all(&mut self) -> Result<()> {
+        let mut heap = self.heap.take().unwrap();
+        while let Some(batch) = self.src.next_batch()? {
+            let mut keys = Vec::with_capacity(self.order_by_exprs.len());
+            for expr in &self.order_by_exprs {
+                keys.push(box_try!(expr.eval_vector(&self.ctx, &batch.columns, batch.len())));
+            }
+            for (i, &handle) in batch.handles.iter().enumerate() {
+                let key = keys.iter().map(|k| k.datum_at(i)).collect();
+                heap.try_add_row(
+                    handle,
+                    batch.row(i),
+                    key,
+                    self.order_by.clone(),
+                    self.ctx.clone(),
+                )?;
+            }
+        }
+        self.iter = Some(heap.into_sorted_vec()?.into_iter());
+        Ok(())
+    }
+}
+
+impl<'a> BatchExecutor for BatchTopNExecutor<'a> {
+    fn next_batch(&mut self) -> Result<Option<Batch>> {
+        if self.iter.is_none() {
+            self.fetch_all()?;
+        }
+        let iter = self.iter.as_mut().unwrap();
+        let (mut handles, mut rows) = (vec![], vec![]);
+        for sort_row in iter.take(BATCH_SIZE) {
+            handles.push(sort_row.handle);
+            rows.push(sort_row.data);
+        }
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Batch::from_rows(handles, rows, self.cols_len)))
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tipb::executor::TableScan;
+
+use util::codec::number::NumberEncoder;
+use util::collections::HashSet;
+use storage::{Key, SnapshotStore, Statistics};
+use coprocessor::codec::table;
+use coprocessor::metrics::*;
+use coprocessor::Result;
+
+use super::{Executor, Row};
+
+/// Looks up the rows of the table by the handles of the rows from an index scan, the
+/// index and the rows are in the same region snapshot, which is checked before the DAG is
+/// executed.
+pub struct IndexLookupExecutor<'a> {
+    table_id: i64,
+    col_ids: HashSet<i64>,
+    store: SnapshotStore<'a>,
+    statistics: &'a mut Statistics,
+    src: Box<Executor + 'a>,
+}
+
+impl<'a> IndexLookupExecutor<'a> {
+    pub fn new(
+        meta: &TableScan,
+        store: SnapshotStore<'a>,
+        statistics: &'a mut Statistics,
+        src: Box<Executor + 'a>,
+    ) -> IndexLookupExecutor<'a> {
+        let col_ids = meta.get_columns()
+            .iter()
+            .filter(|c| !c.get_pk_handle())
+            .map(|c| c.get_column_id())
+            .collect();
+        COPR_EXECUTOR_COUNT
+            .with_label_values(&["index_lookup"])
+            .inc();
+        IndexLookupExecutor {
+            table_id: meta.get_table_id(),
+            col_ids: col_ids,
+            store: store,
+            statistics: statistics,
+            src: src,
+        }
+    }
+}
+
+impl<'a> Executor for IndexLookupExecutor<'a> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        let handle = match self.src.next()? {
+            Some(row) => row.handle,
+            None => return Ok(None),
+        };
+        let mut encoded_handle = Vec::with_capacity(8);
+        box_try!(encoded_handle.encode_i64(handle));
+        let key = table::encode_row_key(self.table_id, &encoded_handle);
+        let value = match self.store.get(&Key::from_raw(&key), self.statistics)? {
+            Some(value) => value,
+            None => {
+                return Err(box_err!(
+                    "row {} of table {} is not found by the index",
+                    handle,
+                    self.table_id
+                ))
+            }
+        };
+        let data = box_try!(table::cut_row(value, &self.col_ids));
+        Ok(Some(Row::new(handle, data)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::i64;
+
+    use kvproto::kvrpcpb::IsolationLevel;
+    use protobuf::RepeatedField;
+    use tipb::executor::IndexScan;
+
+    use super::*;
+    use super::super::index_scan::IndexScanExecutor;
+    use super::super::index_scan::test::{get_idx_range, prepare_index_data};
+    use super::super::scanner::test::{prepare_table_data, TestStore};
+
+    const TABLE_ID: i64 = 1;
+    const INDEX_ID: i64 = 1;
+    const KEY_NUMBER: usize = 10;
+
+    #[test]
+    fn test_index_lookup() {
+        let index_data = prepare_index_data(KEY_NUMBER, TABLE_ID, INDEX_ID);
+        // the last rows of the index are missing in the table.
+        let table_data = prepare_table_data(KEY_NUMBER - 2, TABLE_ID);
+        let mut kv_data = index_data.kv_data.clone();
+        kv_data.extend(table_data.kv_data.clone());
+        let mut test_store = TestStore::new(&kv_data);
+        let (snapshot, start_ts) = test_store.get_snapshot();
+
+        let mut index_scan = IndexScan::new();
+        index_scan.set_table_id(TABLE_ID);
+        index_scan.set_index_id(INDEX_ID);
+        index_scan.set_columns(RepeatedField::from_vec(index_data.get_index_cols()));
+        let mut table_scan = TableScan::new();
+        table_scan.set_table_id(TABLE_ID);
+        table_scan.set_columns(RepeatedField::from_vec(table_data.cols.clone()));
+
+        let ranges = vec![get_idx_range(TABLE_ID, INDEX_ID, i64::MIN, i64::MAX)];
+        let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+        let mut index_statistics = Statistics::default();
+        let scan = IndexScanExecutor::new(index_scan, ranges, store, &mut index_statistics);
+        let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+        let mut statistics = Statistics::default();
+        let mut lookup =
+            IndexLookupExecutor::new(&table_scan, store, &mut statistics, Box::new(scan));
+
+        for (handle, expect_row) in table_data.expect_rows.iter().enumerate() {
+            let row = lookup.next().unwrap().unwrap();
+            assert_eq!(row.handle, handle as i64);
+            assert_eq!(row.data.len(), table_data.cols.len());
+            for col in &table_data.cols {
+                let cid = col.get_column_id();
+                assert_eq!(row.data.get(cid).unwrap(), expect_row[&cid].as_slice());
+            }
+        }
+        assert!(lookup.next().is_err());
+    }
+}
@@ -142,7 +142,7 @@ impl<'a> Executor for IndexScanExecutor<'a> {
 }
 
 #[cfg(test)]
-mod test {
+pub mod test {
     use std::i64;
 
     use kvproto::kvrpcpb::IsolationLevel;
@@ -32,13 +32,24 @@ mod selection;
 mod topn;
 mod limit;
 mod aggregation;
+mod projection;
+mod index_lookup;
+mod stream_aggregation;
+mod spill;
+mod batch;
 
 pub use self::table_scan::TableScanExecutor;
 pub use self::index_scan::IndexScanExecutor;
 pub use self::selection::SelectionExecutor;
 pub use self::topn::TopNExecutor;
 pub use self::limit::LimitExecutor;
+pub use self::projection::ProjectionExecutor;
+pub use self::index_lookup::IndexLookupExecutor;
 pub use self::aggregation::AggregationExecutor;
+pub use self::stream_aggregation::StreamAggExecutor;
+pub use self::spill::SpillConfig;
+pub use self::batch::{Batch, BatchAggregationExecutor, BatchExecutor, BatchLimitExecutor,
+                      BatchSelectionExecutor, BatchTableScanExecutor, BatchTopNExecutor};
 
 pub struct ExprColumnRefVisitor {
     cols_offset: HashSet<usize>,
@@ -0,0 +1,167 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::rc::Rc;
+
+use tipb::executor::Projection;
+use tipb::schema::ColumnInfo;
+
+use coprocessor::codec::table::RowColsDict;
+use coprocessor::codec::datum::DatumEncoder;
+use coprocessor::metrics::*;
+use coprocessor::select::xeval::EvalContext;
+use coprocessor::dag::expr::Expression;
+use coprocessor::Result;
+
+use super::{inflate_with_col_for_dag, Executor, ExprColumnRefVisitor, Row};
+
+/// Evaluates the expressions on the rows, the value of an output row is the encoded
+/// results and its handle is kept.
+pub struct ProjectionExecutor<'a> {
+    exprs: Vec<Expression>,
+    cols: Rc<Vec<ColumnInfo>>,
+    related_cols_offset: Vec<usize>, // offset of related columns
+    ctx: Rc<EvalContext>,
+    src: Box<Executor + 'a>,
+}
+
+impl<'a> ProjectionExecutor<'a> {
+    pub fn new(
+        mut meta: Projection,
+        ctx: Rc<EvalContext>,
+        columns_info: Rc<Vec<ColumnInfo>>,
+        src: Box<Executor + 'a>,
+    ) -> Result<ProjectionExecutor<'a>> {
+        let exprs = meta.take_exprs().into_vec();
+        let mut visitor = ExprColumnRefVisitor::new(columns_info.len());
+        visitor.batch_visit(&exprs)?;
+        COPR_EXECUTOR_COUNT
+            .with_label_values(&["projection"])
+            .inc();
+        Ok(ProjectionExecutor {
+            exprs: box_try!(Expression::batch_build(ctx.as_ref(), exprs)),
+            cols: columns_info,
+            related_cols_offset: visitor.column_offsets(),
+            ctx: ctx,
+            src: src,
+        })
+    }
+}
+
+impl<'a> Executor for ProjectionExecutor<'a> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        let row = match self.src.next()? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let cols = inflate_with_col_for_dag(
+            &self.ctx,
+            &row.data,
+            self.cols.clone(),
+            &self.related_cols_offset,
+            row.handle,
+        )?;
+        let mut values = Vec::with_capacity(self.exprs.len());
+        for expr in &self.exprs {
+            values.push(box_try!(expr.eval(&self.ctx, &cols)));
+        }
+        let mut value = vec![];
+        box_try!(value.encode(&values, false));
+        Ok(Some(Row {
+            handle: row.handle,
+            data: RowColsDict::new(map![], value),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::i64;
+
+    use kvproto::kvrpcpb::IsolationLevel;
+    use protobuf::RepeatedField;
+    use tipb::executor::TableScan;
+    use tipb::expression::{Expr, ExprType, ScalarFuncSig};
+
+    use coprocessor::codec::mysql::types;
+    use coprocessor::codec::datum::{Datum, DatumDecoder};
+    use storage::{SnapshotStore, Statistics};
+    use util::codec::number::NumberEncoder;
+
+    use super::*;
+    use super::super::topn::test::gen_table_data;
+    use super::super::scanner::test::{get_range, new_col_info, TestStore};
+    use super::super::table_scan::TableScanExecutor;
+
+    fn col_expr(offset: i64) -> Expr {
+        let mut expr = Expr::new();
+        expr.set_tp(ExprType::ColumnRef);
+        expr.mut_val().encode_i64(offset).unwrap();
+        expr
+    }
+
+    #[test]
+    fn test_projection() {
+        let tid = 1;
+        let cis = vec![
+            new_col_info(1, types::LONG_LONG),
+            new_col_info(2, types::VARCHAR),
+            new_col_info(3, types::LONG_LONG),
+        ];
+        let raw_data = vec![
+            vec![Datum::I64(1), Datum::Bytes(b"a".to_vec()), Datum::I64(7)],
+            vec![Datum::I64(2), Datum::Bytes(b"b".to_vec()), Datum::Null],
+            vec![Datum::I64(3), Datum::Bytes(b"c".to_vec()), Datum::I64(-5)],
+        ];
+        let table_data = gen_table_data(tid, &cis, &raw_data);
+        let mut test_store = TestStore::new(&table_data);
+
+        let mut table_scan = TableScan::new();
+        table_scan.set_table_id(tid);
+        table_scan.set_columns(RepeatedField::from_vec(cis.clone()));
+        let key_ranges = vec![get_range(tid, i64::MIN, i64::MAX)];
+        let (snapshot, start_ts) = test_store.get_snapshot();
+        let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+        let mut statistics = Statistics::default();
+        let scan = TableScanExecutor::new(&table_scan, key_ranges, store, &mut statistics);
+
+        // col3 + col1, col2
+        let mut plus = Expr::new();
+        plus.set_tp(ExprType::ScalarFunc);
+        plus.set_sig(ScalarFuncSig::PlusInt);
+        plus.mut_children().push(col_expr(2));
+        plus.mut_children().push(col_expr(0));
+        let mut projection = Projection::new();
+        projection.mut_exprs().push(plus);
+        projection.mut_exprs().push(col_expr(1));
+        let mut exec = ProjectionExecutor::new(
+            projection,
+            Rc::new(EvalContext::default()),
+            Rc::new(cis),
+            Box::new(scan),
+        ).unwrap();
+
+        let expect = vec![
+            (1, Datum::I64(8), b"a"),
+            (2, Datum::Null, b"b"),
+            (3, Datum::I64(-2), b"c"),
+        ];
+        for (handle, sum, s) in expect {
+            let row = exec.next().unwrap().unwrap();
+            assert_eq!(row.handle, handle);
+            let values = row.data.value.as_slice().decode().unwrap();
+            assert_eq!(values, vec![sum, Datum::Bytes(s.to_vec())]);
+        }
+        assert!(exec.next().unwrap().is_none());
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use tempdir::TempDir;
+use tipb::expression::ExprType;
+
+use coprocessor::codec::datum::{self, Datum, DatumDecoder};
+use coprocessor::select::aggregate::{self, AggrFunc};
+use coprocessor::select::xeval::EvalContext;
+use coprocessor::Result;
+use util::codec::number::{NumberDecoder, NumberEncoder};
+
+/// The partial aggregates of a group.
+pub type Group = (Vec<u8>, Vec<Box<AggrFunc>>);
+
+/// `SpillConfig` limits the memory taken by the groups of a hash aggregation, the groups
+/// beyond `memory_quota` are spilled to the files under `dir`.
+#[derive(Debug, Clone)]
+pub struct SpillConfig {
+    pub memory_quota: usize,
+    pub dir: PathBuf,
+}
+
+impl SpillConfig {
+    pub fn new(memory_quota: usize, dir: PathBuf) -> SpillConfig {
+        SpillConfig {
+            memory_quota: memory_quota,
+            dir: dir,
+        }
+    }
+}
+
+/// `AggrSpiller` spills the partial aggregates of groups to temporary files when they
+/// take more memory than the quota. Every file is sorted by the group keys, so the
+/// files are merged by the keys afterwards.
+pub struct AggrSpiller {
+    // the directory to create the temporary directory in.
+    root: PathBuf,
+    dir: Option<TempDir>,
+    // the spilled files and the numbers of groups in them.
+    files: Vec<(File, usize)>,
+}
+
+impl AggrSpiller {
+    pub fn new(root: PathBuf) -> AggrSpiller {
+        AggrSpiller {
+            root: root,
+            dir: None,
+            files: vec![],
+        }
+    }
+
+    /// Takes the spilled files out, leaves the spiller empty.
+    pub fn take(&mut self) -> AggrSpiller {
+        AggrSpiller {
+            root: self.root.clone(),
+            dir: self.dir.take(),
+            files: mem::replace(&mut self.files, vec![]),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn spill(&mut self, mut groups: Vec<Group>) -> Result<()> {
+        if self.dir.is_none() {
+            // The root is only left empty in tests, the config always sets it.
+            let root = if self.root.as_os_str().is_empty() {
+                env::temp_dir()
+            } else {
+                self.root.clone()
+            };
+            self.dir = Some(box_try!(TempDir::new_in(root, "tikv-aggr-spill")));
+        }
+        let path = self.dir
+            .as_ref()
+            .unwrap()
+            .path()
+            .join(format!("{}", self.files.len()));
+        groups.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut writer = BufWriter::new(box_try!(File::create(&path)));
+        for &mut (ref key, ref mut aggrs) in &mut groups {
+            write_group(&mut writer, key, aggrs)?;
+        }
+        box_try!(writer.flush());
+        drop(writer);
+        self.files.push((box_try!(File::open(&path)), groups.len()));
+        Ok(())
+    }
+
+    /// Merges the spilled groups of the aggregate functions, which are given by their
+    /// types and whether they are distinct.
+    pub fn merge(self, ctx: Rc<EvalContext>, funcs: Vec<(ExprType, bool)>) -> Result<SpillMerger> {
+        let mut readers = Vec::with_capacity(self.files.len());
+        let mut heads = Vec::with_capacity(self.files.len());
+        for (file, count) in self.files {
+            let mut reader = SpillReader {
+                reader: BufReader::new(file),
+                remaining: count,
+            };
+            heads.push(reader.next()?);
+            readers.push(reader);
+        }
+        Ok(SpillMerger {
+            _dir: self.dir,
+            ctx: ctx,
+            funcs: funcs,
+            readers: readers,
+            heads: heads,
+        })
+    }
+}
+
+fn write_group<W: Write>(w: &mut W, key: &[u8], aggrs: &mut [Box<AggrFunc>]) -> Result<()> {
+    let mut record = Vec::with_capacity(aggrs.len() + 1);
+    record.push(Datum::Bytes(key.to_vec()));
+    for aggr in aggrs {
+        let mut partial = vec![];
+        aggr.partial(&mut partial)?;
+        record.push(Datum::Bytes(box_try!(datum::encode_value(&partial))));
+    }
+    let data = box_try!(datum::encode_value(&record));
+    box_try!(w.encode_u64(data.len() as u64));
+    box_try!(w.write_all(&data));
+    Ok(())
+}
+
+struct SpillReader {
+    reader: BufReader<File>,
+    remaining: usize,
+}
+
+impl SpillReader {
+    // Reads the key and the partial states of the next group.
+    fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<Vec<Datum>>)>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let len = box_try!(self.reader.decode_u64());
+        let mut data = vec![0; len as usize];
+        box_try!(self.reader.read_exact(&mut data));
+        let mut record = box_try!(data.as_slice().decode()).into_iter();
+        let key = match record.next() {
+            Some(Datum::Bytes(key)) => key,
+            d => return Err(box_err!("invalid spilled group key: {:?}", d)),
+        };
+        let mut partials = Vec::with_capacity(record.len());
+        for d in record {
+            let partial = match d {
+                Datum::Bytes(partial) => box_try!(partial.as_slice().decode()),
+                d => return Err(box_err!("invalid spilled partial state: {:?}", d)),
+            };
+            partials.push(partial);
+        }
+        Ok(Some((key, partials)))
+    }
+}
+
+/// Merges the spilled groups, the groups are returned in the order of their keys.
+pub struct SpillMerger {
+    // removes the files when dropped.
+    _dir: Option<TempDir>,
+    ctx: Rc<EvalContext>,
+    funcs: Vec<(ExprType, bool)>,
+    readers: Vec<SpillReader>,
+    // the next group of every file.
+    heads: Vec<Option<(Vec<u8>, Vec<Vec<Datum>>)>>,
+}
+
+impl SpillMerger {
+    pub fn next(&mut self) -> Result<Option<Group>> {
+        let key = match self.heads.iter().filter_map(|h| h.as_ref().map(|h| &h.0)).min() {
+            Some(key) => key.clone(),
+            None => return Ok(None),
+        };
+        let mut aggrs = Vec::with_capacity(self.funcs.len());
+        for &(tp, distinct) in &self.funcs {
+            aggrs.push(aggregate::build_aggr_func(tp, distinct)?);
+        }
+        // The files are merged in the order they are spilled, so functions like `First`
+        // see the partial states in the order of the rows.
+        for (head, reader) in self.heads.iter_mut().zip(&mut self.readers) {
+            if head.as_ref().map_or(true, |h| h.0 != key) {
+                continue;
+            }
+            let (_, partials) = head.take().unwrap();
+            for (aggr, partial) in aggrs.iter_mut().zip(partials) {
+                aggr.merge(&self.ctx, partial)?;
+            }
+            *head = reader.next()?;
+        }
+        Ok(Some((key, aggrs)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use coprocessor::codec::mysql::Decimal;
+    use super::*;
+
+    #[test]
+    fn test_spill_and_merge() {
+        let ctx = Rc::new(EvalContext::default());
+        let funcs = vec![
+            (ExprType::Count, false),
+            (ExprType::First, false),
+            (ExprType::Sum, false),
+            (ExprType::Avg, false),
+            (ExprType::Max, false),
+        ];
+        let new_group = |key: &[u8], vals: &[i64]| {
+            let mut aggrs = vec![];
+            for &(tp, distinct) in &funcs {
+                let mut aggr = aggregate::build_aggr_func(tp, distinct).unwrap();
+                for v in vals {
+                    aggr.update(&ctx, vec![Datum::I64(*v)]).unwrap();
+                }
+                aggrs.push(aggr);
+            }
+            (key.to_vec(), aggrs)
+        };
+
+        let root = TempDir::new("test-aggr-spill").unwrap();
+        let mut spiller = AggrSpiller::new(root.path().to_path_buf());
+        spiller
+            .spill(vec![new_group(b"b", &[3, 1]), new_group(b"a", &[2])])
+            .unwrap();
+        spiller.spill(vec![new_group(b"c", &[4])]).unwrap();
+        spiller
+            .spill(vec![new_group(b"b", &[5]), new_group(b"a", &[])])
+            .unwrap();
+        assert!(!spiller.is_empty());
+        // The files are spilled under the given directory.
+        assert_eq!(root.path().read_dir().unwrap().count(), 1);
+
+        let mut merger = spiller.merge(ctx.clone(), funcs.clone()).unwrap();
+        let expect = vec![
+            (b"a", vec![1, 2, 2, 1, 2, 2]),
+            (b"b", vec![3, 3, 9, 3, 9, 5]),
+            (b"c", vec![1, 4, 4, 1, 4, 4]),
+        ];
+        for (key, vals) in expect {
+            let (k, mut aggrs) = merger.next().unwrap().unwrap();
+            assert_eq!(k, key.to_vec());
+            let mut res = vec![];
+            for aggr in &mut aggrs {
+                aggr.calc(&mut res).unwrap();
+            }
+            let expect_res = vec![
+                Datum::U64(vals[0] as u64),
+                Datum::I64(vals[1]),
+                Datum::Dec(Decimal::from(vals[2])),
+                Datum::U64(vals[3] as u64),
+                Datum::Dec(Decimal::from(vals[4])),
+                Datum::I64(vals[5]),
+            ];
+            assert_eq!(res, expect_res);
+        }
+        assert!(merger.next().unwrap().is_none());
+        drop(merger);
+        assert_eq!(root.path().read_dir().unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::mem;
+use std::rc::Rc;
+
+use tipb::schema::ColumnInfo;
+use tipb::executor::Aggregation;
+
+use coprocessor::codec::table::RowColsDict;
+use coprocessor::codec::datum::{self, approximate_size, Datum, DatumEncoder};
+use coprocessor::select::aggregate::AggrFunc;
+use coprocessor::select::xeval::EvalContext;
+use coprocessor::dag::expr::Expression;
+use coprocessor::metrics::*;
+use coprocessor::Result;
+
+use super::{inflate_with_col_for_dag, Executor, ExprColumnRefVisitor, Row};
+use super::aggregation::AggrFuncExpr;
+
+/// Aggregates the rows that are ordered by the group by columns, e.g. the rows of an
+/// index scan whose leading columns are the group by columns. Only the current group
+/// is kept, the rows it outputs are the same as `AggregationExecutor`.
+pub struct StreamAggExecutor<'a> {
+    group_by: Vec<Expression>,
+    aggr_func: Vec<AggrFuncExpr>,
+    // the encoded group by values and the aggregate functions of the current group.
+    cur_group: Option<(Vec<u8>, Vec<Box<AggrFunc>>)>,
+    executed: bool,
+    ctx: Rc<EvalContext>,
+    cols: Rc<Vec<ColumnInfo>>,
+    related_cols_offset: Vec<usize>,
+    src: Box<Executor + 'a>,
+}
+
+impl<'a> StreamAggExecutor<'a> {
+    pub fn new(
+        mut meta: Aggregation,
+        ctx: Rc<EvalContext>,
+        columns: Rc<Vec<ColumnInfo>>,
+        src: Box<Executor + 'a>,
+    ) -> Result<StreamAggExecutor<'a>> {
+        let mut visitor = ExprColumnRefVisitor::new(columns.len());
+        let group_by = meta.take_group_by().into_vec();
+        visitor.batch_visit(&group_by)?;
+        let aggr_func = meta.take_agg_func().into_vec();
+        visitor.batch_visit(&aggr_func)?;
+        COPR_EXECUTOR_COUNT
+            .with_label_values(&["stream_aggregation"])
+            .inc();
+        Ok(StreamAggExecutor {
+            group_by: box_try!(Expression::batch_build(ctx.as_ref(), group_by)),
+            aggr_func: AggrFuncExpr::batch_build(ctx.as_ref(), aggr_func)?,
+            cur_group: None,
+            executed: false,
+            ctx: ctx,
+            cols: columns,
+            related_cols_offset: visitor.column_offsets(),
+            src: src,
+        })
+    }
+
+    fn get_group_key(&self, row: &[Datum]) -> Result<Vec<u8>> {
+        let mut vals = Vec::with_capacity(self.group_by.len());
+        for expr in &self.group_by {
+            vals.push(box_try!(expr.eval(&self.ctx, row)));
+        }
+        Ok(box_try!(datum::encode_value(&vals)))
+    }
+
+    fn build_row(&self, group_key: &[u8], mut aggrs: Vec<Box<AggrFunc>>) -> Result<Row> {
+        let mut aggr_cols = Vec::with_capacity(2 * aggrs.len());
+        for aggr in &mut aggrs {
+            aggr.calc(&mut aggr_cols)?;
+        }
+        let value_size = group_key.len() + approximate_size(&aggr_cols, false);
+        let mut value = Vec::with_capacity(value_size);
+        box_try!(value.encode(aggr_cols.as_slice(), false));
+        value.extend_from_slice(group_key);
+        Ok(Row {
+            handle: 0,
+            data: RowColsDict::new(map![], value),
+        })
+    }
+}
+
+impl<'a> Executor for StreamAggExecutor<'a> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        if self.executed {
+            return Ok(None);
+        }
+        while let Some(row) = self.src.next()? {
+            let cols = inflate_with_col_for_dag(
+                &self.ctx,
+                &row.data,
+                self.cols.clone(),
+                &self.related_cols_offset,
+                row.handle,
+            )?;
+            let group_key = self.get_group_key(&cols)?;
+            let same_group = match self.cur_group {
+                Some((ref key, _)) => *key == group_key,
+                None => false,
+            };
+            if same_group {
+                let aggrs = &mut self.cur_group.as_mut().unwrap().1;
+                for (expr, aggr) in self.aggr_func.iter().zip(aggrs) {
+                    aggr.update_with_expr(&self.ctx, expr, &cols)?;
+                }
+                continue;
+            }
+            let mut aggrs = Vec::with_capacity(self.aggr_func.len());
+            for expr in &self.aggr_func {
+                let mut aggr = expr.build_func()?;
+                aggr.update_with_expr(&self.ctx, expr, &cols)?;
+                aggrs.push(aggr);
+            }
+            let finished = mem::replace(&mut self.cur_group, Some((group_key, aggrs)));
+            if let Some((key, aggrs)) = finished {
+                return self.build_row(&key, aggrs).map(Some);
+            }
+        }
+        self.executed = true;
+        match self.cur_group.take() {
+            Some((key, aggrs)) => self.build_row(&key, aggrs).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::i64;
+
+    use kvproto::kvrpcpb::IsolationLevel;
+    use protobuf::RepeatedField;
+    use tipb::executor::TableScan;
+    use tipb::expression::{Expr, ExprType};
+
+    use coprocessor::codec::datum::DatumDecoder;
+    use coprocessor::codec::mysql::types;
+    use storage::{SnapshotStore, Statistics};
+    use util::codec::number::NumberEncoder;
+
+    use super::*;
+    use super::super::table_scan::TableScanExecutor;
+    use super::super::scanner::test::{get_range, new_col_info, TestStore};
+    use super::super::topn::test::gen_table_data;
+
+    fn build_col_ref(offset: i64) -> Expr {
+        let mut expr = Expr::new();
+        expr.set_tp(ExprType::ColumnRef);
+        expr.mut_val().encode_i64(offset).unwrap();
+        expr
+    }
+
+    #[test]
+    fn test_stream_aggregation() {
+        let tid = 1;
+        let cis = vec![
+            new_col_info(1, types::LONG_LONG),
+            new_col_info(2, types::VARCHAR),
+            new_col_info(3, types::LONG_LONG),
+        ];
+        // ordered by the second column.
+        let raw_data = vec![
+            (1, b"a", 1),
+            (2, b"a", 3),
+            (3, b"b", 2),
+            (4, b"c", 5),
+            (5, b"c", 4),
+            (6, b"c", 6),
+        ];
+        let raw_data: Vec<_> = raw_data
+            .into_iter()
+            .map(|(id, s, v)| vec![Datum::I64(id), Datum::Bytes(s.to_vec()), Datum::I64(v)])
+            .collect();
+        let table_data = gen_table_data(tid, &cis, &raw_data);
+        let mut test_store = TestStore::new(&table_data);
+        let mut table_scan = TableScan::new();
+        table_scan.set_table_id(tid);
+        table_scan.set_columns(RepeatedField::from_vec(cis.clone()));
+        let key_ranges = vec![get_range(tid, i64::MIN, i64::MAX)];
+        let (snapshot, start_ts) = test_store.get_snapshot();
+        let store = SnapshotStore::new(snapshot, start_ts, IsolationLevel::SI, true);
+        let mut statistics = Statistics::default();
+        let ts_ect = TableScanExecutor::new(&table_scan, key_ranges, store, &mut statistics);
+
+        let mut aggregation = Aggregation::default();
+        aggregation.mut_group_by().push(build_col_ref(1));
+        for tp in &[ExprType::Count, ExprType::Max] {
+            let mut expr = Expr::new();
+            expr.set_tp(*tp);
+            expr.mut_children().push(build_col_ref(2));
+            aggregation.mut_agg_func().push(expr);
+        }
+        let mut aggr_ect = StreamAggExecutor::new(
+            aggregation,
+            Rc::new(EvalContext::default()),
+            Rc::new(cis),
+            Box::new(ts_ect),
+        ).unwrap();
+
+        let expect = vec![(2, b"a", 3), (1, b"b", 2), (3, b"c", 6)];
+        for (cnt, key, max) in expect {
+            let row = aggr_ect.next().unwrap().unwrap();
+            let ds = row.data.value.as_slice().decode().unwrap();
+            assert_eq!(
+                ds,
+                vec![Datum::U64(cnt), Datum::I64(max), Datum::Bytes(key.to_vec())]
+            );
+        }
+        assert!(aggr_ect.next().unwrap().is_none());
+        assert!(aggr_ect.next().unwrap().is_none());
+    }
+}
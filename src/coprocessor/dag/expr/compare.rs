@@ -218,7 +218,7 @@ impl FnCall {
     }
 }
 
-fn do_compare<T, E, F>(e: E, op: CmpOp, get_order: F) -> Result<Option<i64>>
+pub fn do_compare<T, E, F>(e: E, op: CmpOp, get_order: F) -> Result<Option<i64>>
 where
     E: Fn(usize) -> Result<Option<T>>,
     F: Fn(T, T) -> Result<Ordering>,
@@ -250,7 +250,7 @@ where
 }
 
 #[inline]
-fn cmp_i64_with_unsigned_flag(
+pub fn cmp_i64_with_unsigned_flag(
     lhs: i64,
     lhs_unsigned: bool,
     rhs: i64,
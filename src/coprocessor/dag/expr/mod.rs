@@ -21,6 +21,7 @@ mod compare;
 mod arithmetic;
 mod math;
 mod json;
+mod vector;
 
 use std::{error, io, str};
 use std::borrow::Cow;
@@ -39,6 +40,7 @@ use util::codec::number::NumberDecoder;
 use util::codec::Error as CError;
 
 pub use coprocessor::select::xeval::EvalContext as StatementContext;
+pub use self::vector::VectorValue;
 
 quick_error! {
     #[derive(Debug)]
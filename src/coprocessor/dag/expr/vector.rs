@@ -0,0 +1,560 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+
+use tipb::expression::ScalarFuncSig;
+
+use coprocessor::codec::{datum, mysql, Datum};
+use coprocessor::codec::mysql::Decimal;
+use super::{Error, Expression, FnCall, Result, StatementContext};
+use super::compare::{self, CmpOp};
+
+/// The values of a column in a batch of rows.
+///
+/// The values are kept in a typed vector when all the non-null values are of the same type,
+/// otherwise they are kept as `Datum`s. The datum at any position is always the same as the
+/// one evaluated row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VectorValue {
+    Int(Vec<Option<i64>>),
+    Real(Vec<Option<f64>>),
+    Decimal(Vec<Option<Decimal>>),
+    Bytes(Vec<Option<Vec<u8>>>),
+    Datum(Vec<Datum>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Null,
+    Int,
+    Real,
+    Decimal,
+    Bytes,
+    Other,
+}
+
+fn kind_of(d: &Datum) -> Kind {
+    match *d {
+        Datum::Null => Kind::Null,
+        Datum::I64(_) => Kind::Int,
+        Datum::F64(_) => Kind::Real,
+        Datum::Dec(_) => Kind::Decimal,
+        Datum::Bytes(_) => Kind::Bytes,
+        _ => Kind::Other,
+    }
+}
+
+fn retain_by<T>(v: &mut Vec<T>, keep: &[bool]) {
+    let mut i = 0;
+    v.retain(|_| {
+        i += 1;
+        keep[i - 1]
+    });
+}
+
+impl VectorValue {
+    pub fn from_datums(datums: Vec<Datum>) -> VectorValue {
+        let mut kind = Kind::Null;
+        for d in &datums {
+            match (kind, kind_of(d)) {
+                (_, Kind::Null) => {}
+                (Kind::Null, k) => kind = k,
+                (k1, k2) if k1 == k2 => {}
+                _ => {
+                    kind = Kind::Other;
+                    break;
+                }
+            }
+        }
+        let iter = datums.into_iter();
+        match kind {
+            Kind::Int => VectorValue::Int(
+                iter.map(|d| match d {
+                    Datum::I64(i) => Some(i),
+                    _ => None,
+                }).collect(),
+            ),
+            Kind::Real => VectorValue::Real(
+                iter.map(|d| match d {
+                    Datum::F64(f) => Some(f),
+                    _ => None,
+                }).collect(),
+            ),
+            Kind::Decimal => VectorValue::Decimal(
+                iter.map(|d| match d {
+                    Datum::Dec(d) => Some(d),
+                    _ => None,
+                }).collect(),
+            ),
+            Kind::Bytes => VectorValue::Bytes(
+                iter.map(|d| match d {
+                    Datum::Bytes(b) => Some(b),
+                    _ => None,
+                }).collect(),
+            ),
+            Kind::Null | Kind::Other => VectorValue::Datum(iter.collect()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            VectorValue::Int(ref v) => v.len(),
+            VectorValue::Real(ref v) => v.len(),
+            VectorValue::Decimal(ref v) => v.len(),
+            VectorValue::Bytes(ref v) => v.len(),
+            VectorValue::Datum(ref v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn datum_at(&self, idx: usize) -> Datum {
+        match *self {
+            VectorValue::Int(ref v) => v[idx].map_or(Datum::Null, Datum::I64),
+            VectorValue::Real(ref v) => v[idx].map_or(Datum::Null, Datum::F64),
+            VectorValue::Decimal(ref v) => v[idx].clone().map_or(Datum::Null, Datum::Dec),
+            VectorValue::Bytes(ref v) => v[idx].clone().map_or(Datum::Null, Datum::Bytes),
+            VectorValue::Datum(ref v) => v[idx].clone(),
+        }
+    }
+
+    /// Keeps the values whose positions are marked in `keep`.
+    pub fn retain(&mut self, keep: &[bool]) {
+        match *self {
+            VectorValue::Int(ref mut v) => retain_by(v, keep),
+            VectorValue::Real(ref mut v) => retain_by(v, keep),
+            VectorValue::Decimal(ref mut v) => retain_by(v, keep),
+            VectorValue::Bytes(ref mut v) => retain_by(v, keep),
+            VectorValue::Datum(ref mut v) => retain_by(v, keep),
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        match *self {
+            VectorValue::Int(ref mut v) => v.truncate(len),
+            VectorValue::Real(ref mut v) => v.truncate(len),
+            VectorValue::Decimal(ref mut v) => v.truncate(len),
+            VectorValue::Bytes(ref mut v) => v.truncate(len),
+            VectorValue::Datum(ref mut v) => v.truncate(len),
+        }
+    }
+
+    /// Gets the values as integers, the same as `eval_int` on each of the datums.
+    pub fn as_ints(&self) -> Result<Cow<[Option<i64>]>> {
+        if let VectorValue::Int(ref v) = *self {
+            return Ok(Cow::Borrowed(v));
+        }
+        let ints = (0..self.len())
+            .map(|i| self.datum_at(i).as_int())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Cow::Owned(ints))
+    }
+
+    /// Gets the values as reals, the same as `eval_real` on each of the datums.
+    pub fn as_reals(&self) -> Result<Cow<[Option<f64>]>> {
+        if let VectorValue::Real(ref v) = *self {
+            return Ok(Cow::Borrowed(v));
+        }
+        let reals = (0..self.len())
+            .map(|i| self.datum_at(i).as_real())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Cow::Owned(reals))
+    }
+}
+
+impl Expression {
+    /// Evaluates the expression over `columns`, the columns of a batch of `rows` rows.
+    ///
+    /// The functions not supported by vectorized evaluation are evaluated row by row.
+    pub fn eval_vector<'a>(
+        &self,
+        ctx: &StatementContext,
+        columns: &'a [VectorValue],
+        rows: usize,
+    ) -> Result<Cow<'a, VectorValue>> {
+        match *self {
+            Expression::Constant(ref constant) => {
+                let datums = vec![constant.val.clone(); rows];
+                Ok(Cow::Owned(VectorValue::from_datums(datums)))
+            }
+            Expression::ColumnRef(ref column) => columns
+                .get(column.offset)
+                .map(Cow::Borrowed)
+                .ok_or_else(|| Error::ColumnOffset(column.offset)),
+            Expression::ScalarFn(ref f) => {
+                // Any error is reported by the row by row evaluation, as it may skip
+                // evaluating the arguments on some rows.
+                if let Some(Ok(v)) = f.eval_vector(ctx, columns, rows) {
+                    return Ok(Cow::Owned(v));
+                }
+                let mut datums = Vec::with_capacity(rows);
+                let mut row = Vec::with_capacity(columns.len());
+                for i in 0..rows {
+                    row.clear();
+                    row.extend(columns.iter().map(|c| c.datum_at(i)));
+                    datums.push(f.eval(ctx, &row)?);
+                }
+                Ok(Cow::Owned(VectorValue::from_datums(datums)))
+            }
+        }
+    }
+}
+
+impl FnCall {
+    /// Evaluates the function over whole columns, returns `None` if it's not supported.
+    fn eval_vector(
+        &self,
+        ctx: &StatementContext,
+        columns: &[VectorValue],
+        rows: usize,
+    ) -> Option<Result<VectorValue>> {
+        if mysql::has_unsigned_flag(self.tp.get_flag()) {
+            return None;
+        }
+        let res = match self.sig {
+            ScalarFuncSig::LTInt => self.compare_int_vector(ctx, columns, rows, CmpOp::LT),
+            ScalarFuncSig::LEInt => self.compare_int_vector(ctx, columns, rows, CmpOp::LE),
+            ScalarFuncSig::GTInt => self.compare_int_vector(ctx, columns, rows, CmpOp::GT),
+            ScalarFuncSig::GEInt => self.compare_int_vector(ctx, columns, rows, CmpOp::GE),
+            ScalarFuncSig::EQInt => self.compare_int_vector(ctx, columns, rows, CmpOp::EQ),
+            ScalarFuncSig::NEInt => self.compare_int_vector(ctx, columns, rows, CmpOp::NE),
+            ScalarFuncSig::NullEQInt => {
+                self.compare_int_vector(ctx, columns, rows, CmpOp::NullEQ)
+            }
+            ScalarFuncSig::LTReal => self.compare_real_vector(ctx, columns, rows, CmpOp::LT),
+            ScalarFuncSig::LEReal => self.compare_real_vector(ctx, columns, rows, CmpOp::LE),
+            ScalarFuncSig::GTReal => self.compare_real_vector(ctx, columns, rows, CmpOp::GT),
+            ScalarFuncSig::GEReal => self.compare_real_vector(ctx, columns, rows, CmpOp::GE),
+            ScalarFuncSig::EQReal => self.compare_real_vector(ctx, columns, rows, CmpOp::EQ),
+            ScalarFuncSig::NEReal => self.compare_real_vector(ctx, columns, rows, CmpOp::NE),
+            ScalarFuncSig::NullEQReal => {
+                self.compare_real_vector(ctx, columns, rows, CmpOp::NullEQ)
+            }
+            ScalarFuncSig::PlusInt => self.arith_int_vector(ctx, columns, rows, i64::checked_add),
+            ScalarFuncSig::MinusInt => self.arith_int_vector(ctx, columns, rows, i64::checked_sub),
+            ScalarFuncSig::MultiplyInt => {
+                self.arith_int_vector(ctx, columns, rows, i64::checked_mul)
+            }
+            ScalarFuncSig::PlusReal => self.arith_real_vector(ctx, columns, rows, |l, r| l + r),
+            ScalarFuncSig::MinusReal => self.arith_real_vector(ctx, columns, rows, |l, r| l - r),
+            ScalarFuncSig::MultiplyReal => {
+                self.arith_real_vector(ctx, columns, rows, |l, r| l * r)
+            }
+            ScalarFuncSig::LogicalAnd => self.logical_vector(ctx, columns, rows, |l, r| {
+                match (l, r) {
+                    (Some(0), _) | (_, Some(0)) => Some(0),
+                    (Some(_), Some(_)) => Some(1),
+                    _ => None,
+                }
+            }),
+            ScalarFuncSig::LogicalOr => self.logical_vector(ctx, columns, rows, |l, r| {
+                match (l, r) {
+                    (Some(l), _) if l != 0 => Some(1),
+                    (_, None) => None,
+                    (_, Some(r)) if r != 0 => Some(1),
+                    (l, _) => l,
+                }
+            }),
+            ScalarFuncSig::UnaryNot => self.children[0]
+                .eval_vector(ctx, columns, rows)
+                .and_then(|v| {
+                    let res = v.as_ints()?.iter().map(|v| v.map(|v| (v == 0) as i64)).collect();
+                    Ok(VectorValue::Int(res))
+                }),
+            ScalarFuncSig::IntIsNull => self.children[0]
+                .eval_vector(ctx, columns, rows)
+                .and_then(|v| {
+                    let res = v.as_ints()?.iter().map(|v| Some(v.is_none() as i64)).collect();
+                    Ok(VectorValue::Int(res))
+                }),
+            ScalarFuncSig::RealIsNull => self.children[0]
+                .eval_vector(ctx, columns, rows)
+                .and_then(|v| {
+                    let res = v.as_reals()?.iter().map(|v| Some(v.is_none() as i64)).collect();
+                    Ok(VectorValue::Int(res))
+                }),
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    fn compare_int_vector(
+        &self,
+        ctx: &StatementContext,
+        columns: &[VectorValue],
+        rows: usize,
+        op: CmpOp,
+    ) -> Result<VectorValue> {
+        let lhs_unsigned = mysql::has_unsigned_flag(self.children[0].get_tp().get_flag());
+        let rhs_unsigned = mysql::has_unsigned_flag(self.children[1].get_tp().get_flag());
+        let lhs = self.children[0].eval_vector(ctx, columns, rows)?;
+        let rhs = self.children[1].eval_vector(ctx, columns, rows)?;
+        let (lhs, rhs) = (lhs.as_ints()?, rhs.as_ints()?);
+        let res = lhs.iter()
+            .zip(rhs.iter())
+            .map(|(&l, &r)| {
+                let e = |i: usize| Ok(if i == 0 { l } else { r });
+                compare::do_compare(e, op, |l, r| {
+                    Ok(compare::cmp_i64_with_unsigned_flag(
+                        l,
+                        lhs_unsigned,
+                        r,
+                        rhs_unsigned,
+                    ))
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(VectorValue::Int(res))
+    }
+
+    fn compare_real_vector(
+        &self,
+        ctx: &StatementContext,
+        columns: &[VectorValue],
+        rows: usize,
+        op: CmpOp,
+    ) -> Result<VectorValue> {
+        let lhs = self.children[0].eval_vector(ctx, columns, rows)?;
+        let rhs = self.children[1].eval_vector(ctx, columns, rows)?;
+        let (lhs, rhs) = (lhs.as_reals()?, rhs.as_reals()?);
+        let res = lhs.iter()
+            .zip(rhs.iter())
+            .map(|(&l, &r)| {
+                let e = |i: usize| Ok(if i == 0 { l } else { r });
+                compare::do_compare(e, op, |l, r| datum::cmp_f64(l, r).map_err(Error::from))
+            })
+            .collect::<Result<_>>()?;
+        Ok(VectorValue::Int(res))
+    }
+
+    fn arith_int_vector<F>(
+        &self,
+        ctx: &StatementContext,
+        columns: &[VectorValue],
+        rows: usize,
+        f: F,
+    ) -> Result<VectorValue>
+    where
+        F: Fn(i64, i64) -> Option<i64>,
+    {
+        if self.children
+            .iter()
+            .any(|c| mysql::has_unsigned_flag(c.get_tp().get_flag()))
+        {
+            return Err(box_err!("unsigned arithmetic is not vectorized"));
+        }
+        let lhs = self.children[0].eval_vector(ctx, columns, rows)?;
+        let rhs = self.children[1].eval_vector(ctx, columns, rows)?;
+        let (lhs, rhs) = (lhs.as_ints()?, rhs.as_ints()?);
+        let res = lhs.iter()
+            .zip(rhs.iter())
+            .map(|(&l, &r)| match (l, r) {
+                (Some(l), Some(r)) => f(l, r).ok_or(Error::Overflow).map(Some),
+                _ => Ok(None),
+            })
+            .collect::<Result<_>>()?;
+        Ok(VectorValue::Int(res))
+    }
+
+    fn arith_real_vector<F>(
+        &self,
+        ctx: &StatementContext,
+        columns: &[VectorValue],
+        rows: usize,
+        f: F,
+    ) -> Result<VectorValue>
+    where
+        F: Fn(f64, f64) -> f64,
+    {
+        let lhs = self.children[0].eval_vector(ctx, columns, rows)?;
+        let rhs = self.children[1].eval_vector(ctx, columns, rows)?;
+        let (lhs, rhs) = (lhs.as_reals()?, rhs.as_reals()?);
+        let res = lhs.iter()
+            .zip(rhs.iter())
+            .map(|(&l, &r)| match (l, r) {
+                (Some(l), Some(r)) => {
+                    let res = f(l, r);
+                    if !res.is_finite() {
+                        return Err(Error::Overflow);
+                    }
+                    Ok(Some(res))
+                }
+                _ => Ok(None),
+            })
+            .collect::<Result<_>>()?;
+        Ok(VectorValue::Real(res))
+    }
+
+    fn logical_vector<F>(
+        &self,
+        ctx: &StatementContext,
+        columns: &[VectorValue],
+        rows: usize,
+        f: F,
+    ) -> Result<VectorValue>
+    where
+        F: Fn(Option<i64>, Option<i64>) -> Option<i64>,
+    {
+        let lhs = self.children[0].eval_vector(ctx, columns, rows)?;
+        let rhs = self.children[1].eval_vector(ctx, columns, rows)?;
+        let (lhs, rhs) = (lhs.as_ints()?, rhs.as_ints()?);
+        let res = lhs.iter().zip(rhs.iter()).map(|(&l, &r)| f(l, r)).collect();
+        Ok(VectorValue::Int(res))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{f64, i64};
+
+    use tipb::expression::{Expr, ScalarFuncSig};
+
+    use coprocessor::codec::Datum;
+    use coprocessor::codec::mysql::types;
+    use coprocessor::dag::expr::{Expression, StatementContext};
+    use coprocessor::dag::expr::test::fncall_expr;
+    use coprocessor::select::xeval::evaluator::test::{col_expr, datum_expr};
+    use super::*;
+
+    fn columns() -> Vec<VectorValue> {
+        vec![
+            VectorValue::from_datums(vec![
+                Datum::I64(1),
+                Datum::Null,
+                Datum::I64(-3),
+                Datum::I64(i64::MAX),
+            ]),
+            VectorValue::from_datums(vec![
+                Datum::F64(1.5),
+                Datum::F64(-2.0),
+                Datum::Null,
+                Datum::F64(f64::MAX),
+            ]),
+            VectorValue::from_datums(vec![
+                Datum::U64(1),
+                Datum::U64(u64::max_value()),
+                Datum::Null,
+                Datum::U64(0),
+            ]),
+            VectorValue::from_datums(vec![
+                Datum::Bytes(b"a".to_vec()),
+                Datum::Bytes(b"b".to_vec()),
+                Datum::Null,
+                Datum::Bytes(b"d".to_vec()),
+            ]),
+        ]
+    }
+
+    fn unsigned_col_expr(offset: i64) -> Expr {
+        let mut expr = col_expr(offset);
+        expr.mut_field_type().set_flag(types::UNSIGNED_FLAG as u32);
+        expr
+    }
+
+    #[test]
+    fn test_from_datums() {
+        let cases = vec![
+            (vec![Datum::I64(1), Datum::Null], VectorValue::Int(vec![Some(1), None])),
+            (vec![Datum::Null, Datum::F64(1.0)], VectorValue::Real(vec![None, Some(1.0)])),
+            (
+                vec![Datum::Bytes(b"a".to_vec())],
+                VectorValue::Bytes(vec![Some(b"a".to_vec())]),
+            ),
+            (vec![Datum::Null], VectorValue::Datum(vec![Datum::Null])),
+            (
+                vec![Datum::I64(1), Datum::U64(1)],
+                VectorValue::Datum(vec![Datum::I64(1), Datum::U64(1)]),
+            ),
+        ];
+        for (datums, expect) in cases {
+            let v = VectorValue::from_datums(datums.clone());
+            assert_eq!(v, expect);
+            for (i, d) in datums.into_iter().enumerate() {
+                assert_eq!(v.datum_at(i), d);
+            }
+        }
+
+        let mut v = VectorValue::from_datums(vec![Datum::I64(1), Datum::Null, Datum::I64(3)]);
+        v.retain(&[true, false, true]);
+        assert_eq!(v, VectorValue::Int(vec![Some(1), Some(3)]));
+        v.truncate(1);
+        assert_eq!(v, VectorValue::Int(vec![Some(1)]));
+    }
+
+    #[test]
+    fn test_eval_vector() {
+        let mut ctx = StatementContext::default();
+        ctx.ignore_truncate = true;
+        let columns = columns();
+        let rows = 4;
+        let cases = vec![
+            fncall_expr(ScalarFuncSig::GTInt, &[col_expr(0), datum_expr(Datum::I64(0))]),
+            fncall_expr(ScalarFuncSig::NullEQInt, &[col_expr(0), col_expr(0)]),
+            fncall_expr(ScalarFuncSig::LTInt, &[col_expr(0), unsigned_col_expr(2)]),
+            fncall_expr(ScalarFuncSig::LEReal, &[col_expr(1), datum_expr(Datum::F64(1.5))]),
+            fncall_expr(ScalarFuncSig::PlusInt, &[col_expr(0), datum_expr(Datum::I64(-1))]),
+            fncall_expr(ScalarFuncSig::MinusInt, &[col_expr(0), unsigned_col_expr(2)]),
+            fncall_expr(ScalarFuncSig::MinusReal, &[col_expr(1), col_expr(1)]),
+            fncall_expr(
+                ScalarFuncSig::LogicalAnd,
+                &[
+                    fncall_expr(ScalarFuncSig::IntIsNull, &[col_expr(0)]),
+                    fncall_expr(ScalarFuncSig::RealIsNull, &[col_expr(1)]),
+                ],
+            ),
+            fncall_expr(
+                ScalarFuncSig::LogicalOr,
+                &[
+                    fncall_expr(ScalarFuncSig::UnaryNot, &[col_expr(0)]),
+                    datum_expr(Datum::Null),
+                ],
+            ),
+            // Not vectorized.
+            fncall_expr(ScalarFuncSig::CastIntAsString, &[col_expr(0)]),
+            fncall_expr(ScalarFuncSig::EQString, &[col_expr(3), datum_expr(Datum::Null)]),
+        ];
+        for expr in cases {
+            let e = Expression::build(&ctx, expr).unwrap();
+            let vector = e.eval_vector(&ctx, &columns, rows);
+            let mut expect = vec![];
+            for i in 0..rows {
+                let row: Vec<_> = columns.iter().map(|c| c.datum_at(i)).collect();
+                expect.push(e.eval(&ctx, &row));
+            }
+            if expect.iter().any(|r| r.is_err()) {
+                assert!(vector.is_err(), "{:?}", e);
+                continue;
+            }
+            let vector = vector.unwrap();
+            assert_eq!(vector.len(), rows);
+            for (i, exp) in expect.into_iter().enumerate() {
+                assert_eq!(vector.datum_at(i), exp.unwrap(), "{:?} at {}", e, i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval_vector_overflow() {
+        let ctx = StatementContext::default();
+        let columns = columns();
+        let e = fncall_expr(ScalarFuncSig::PlusInt, &[col_expr(0), col_expr(0)]);
+        let e = Expression::build(&ctx, e).unwrap();
+        match e.eval_vector(&ctx, &columns, 4) {
+            Err(Error::Overflow) => {}
+            res => panic!("expect overflow, but got {:?}", res),
+        }
+    }
+}
@@ -16,12 +16,17 @@ use std::time::Duration;
 use std::rc::Rc;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::mem;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
+use futures::{AsyncSink, Future, Sink};
+use futures::executor::{self, Notify};
+use futures::sync::mpsc;
 use tipb::select::{self, Chunk, DAGRequest, SelectRequest};
 use tipb::analyze::{AnalyzeReq, AnalyzeType};
 use tipb::executor::ExecType;
 use tipb::schema::ColumnInfo;
-use protobuf::Message as PbMsg;
+use protobuf::{Message as PbMsg, RepeatedField};
 use kvproto::coprocessor::{KeyRange, Request, Response};
 use kvproto::errorpb::{self, ServerIsBusy};
 use kvproto::kvrpcpb::{CommandPri, IsolationLevel};
@@ -31,15 +36,19 @@ use util::worker::{BatchRunnable, FutureScheduler, Scheduler};
 use util::collections::HashMap;
 use util::threadpool::{Context, ContextFactory, ThreadPool, ThreadPoolBuilder};
 use server::{Config, OnResponse};
-use storage::{self, engine, Engine, FlowStatistics, Snapshot, Statistics, StatisticsSummary};
+use storage::{self, engine, ConcurrencyManager, Engine, FlowStatistics, Key, Snapshot, Statistics,
+              StatisticsSummary};
+use storage::txn::Error as TxnError;
 use storage::engine::Error as EngineError;
 use pd::PdTask;
+use raftstore::store::LoadStat;
 
 use super::codec::mysql;
 use super::codec::datum::Datum;
 use super::select::select::SelectContext;
 use super::select::xeval::EvalContext;
-use super::dag::DAGContext;
+use super::dag::{lookup_table_id, table_row_range, DAGContext};
+use super::dag::executor::SpillConfig;
 use super::statistics::analyze::AnalyzeContext;
 use super::metrics::*;
 use super::{Error, Result};
@@ -73,6 +82,9 @@ pub struct Host {
     low_priority_pool: ThreadPool<CopContext>,
     high_priority_pool: ThreadPool<CopContext>,
     max_running_task_count: usize,
+    stream_batch_row_limit: usize,
+    aggr_spill_cfg: SpillConfig,
+    cm: ConcurrencyManager,
 }
 
 pub type CopRequestStatistics = HashMap<u64, FlowStatistics>;
@@ -92,6 +104,7 @@ impl ContextFactory<CopContext> for CopContextFactory {
             select_stats: Default::default(),
             index_stats: Default::default(),
             request_stats: HashMap::default(),
+            read_load: HashMap::default(),
         }
     }
 }
@@ -100,6 +113,8 @@ struct CopContext {
     select_stats: StatisticsSummary,
     index_stats: StatisticsSummary,
     request_stats: CopRequestStatistics,
+    // The ranges read by the requests, sampled by region for load based split.
+    read_load: HashMap<u64, LoadStat>,
     sender: FutureScheduler<PdTask>,
 }
 
@@ -126,6 +141,15 @@ impl CopContext {
         flow_stats.add(&stats.write.flow_stats);
         flow_stats.add(&stats.data.flow_stats);
     }
+
+    fn add_read_load_by_region(&mut self, region_id: u64, start_keys: Vec<Vec<u8>>) {
+        let load_stat = self.read_load
+            .entry(region_id)
+            .or_insert_with(LoadStat::default);
+        for key in start_keys {
+            load_stat.record_range(&key);
+        }
+    }
 }
 
 impl Context for CopContext {
@@ -154,6 +178,14 @@ impl Context for CopContext {
                 error!("send coprocessor statistics: {:?}", e);
             };
         }
+        if !self.read_load.is_empty() {
+            let load_stats = mem::replace(&mut self.read_load, HashMap::default());
+            if let Err(e) = self.sender.schedule(PdTask::ReadLoad {
+                load_stats: load_stats,
+            }) {
+                error!("send coprocessor read load: {:?}", e);
+            };
+        }
 
     }
 }
@@ -164,6 +196,7 @@ impl Host {
         scheduler: Scheduler<Task>,
         cfg: &Config,
         r: FutureScheduler<PdTask>,
+        cm: ConcurrencyManager,
     ) -> Host {
         Host {
             engine: engine,
@@ -171,6 +204,12 @@ impl Host {
             reqs: HashMap::default(),
             last_req_id: 0,
             max_running_task_count: cfg.end_point_max_tasks,
+            stream_batch_row_limit: cfg.end_point_stream_batch_row_limit,
+            aggr_spill_cfg: SpillConfig::new(
+                cfg.end_point_aggr_memory_quota.0 as usize,
+                PathBuf::from(&cfg.end_point_aggr_spill_dir),
+            ),
+            cm: cm,
             pool: ThreadPoolBuilder::new(
                 thd_name!("endpoint-normal-pool"),
                 CopContextFactory { sender: r.clone() },
@@ -192,6 +231,31 @@ impl Host {
         }
     }
 
+    // Sends the response a streaming request yields on, returns the request if it goes on.
+    fn send_pending_response(&self, mut t: RequestTask) -> Option<RequestTask> {
+        let resp = match t.pending_resp.take() {
+            Some(resp) => resp,
+            None => return Some(t),
+        };
+        let notify = Arc::new(RequeueNotify::new(self.sched.clone()));
+        let res = match t.on_resp {
+            Responder::Stream(ref mut tx) => try_send_stream_response(tx, resp, &notify),
+            Responder::Unary(_) => unreachable!(),
+        };
+        match res {
+            Ok(None) => Some(t),
+            Ok(Some(resp)) => {
+                t.pending_resp = Some(resp);
+                notify.park(t);
+                None
+            }
+            Err(e) => {
+                debug!("failed to send the pending response: {:?}", e);
+                None
+            }
+        }
+    }
+
     fn running_task_count(&self) -> usize {
         self.pool.get_task_count() + self.low_priority_pool.get_task_count() +
             self.high_priority_pool.get_task_count()
@@ -220,7 +284,12 @@ impl Host {
             COPR_PENDING_REQS
                 .with_label_values(&[type_str, pri_str])
                 .add(1.0);
-            let end_point = TiDbEndPoint::new(snap.clone());
+            let end_point = TiDbEndPoint::new(
+                snap.clone(),
+                self.sched.clone(),
+                self.stream_batch_row_limit,
+                self.aggr_spill_cfg.clone(),
+            );
 
             let pool = match pri {
                 CommandPri::Low => &mut self.low_priority_pool,
@@ -229,9 +298,15 @@ impl Host {
             };
             pool.execute(move |ctx: &mut CopContext| {
                 let region_id = req.req.get_context().get_region_id();
+                let start_keys: Vec<_> = req.req
+                    .get_ranges()
+                    .iter()
+                    .map(|r| Key::from_raw(r.get_start()).encoded().to_owned())
+                    .collect();
                 let stats = end_point.handle_request(req);
                 ctx.add_statistics(type_str, &stats);
                 ctx.add_statistics_by_region(region_id, &stats);
+                ctx.add_read_load_by_region(region_id, start_keys);
                 COPR_PENDING_REQS
                     .with_label_values(&[type_str, pri_str])
                     .dec();
@@ -264,6 +339,7 @@ enum CopRequest {
     Analyze(AnalyzeReq),
 }
 
+#[derive(Clone)]
 pub struct ReqContext {
     // The deadline before which the task should be responded.
     pub deadline: Instant,
@@ -271,6 +347,7 @@ pub struct ReqContext {
     pub fill_cache: bool,
     // whether is a table scan request.
     pub table_scan: bool,
+    pub region_id: u64,
 }
 
 impl ReqContext {
@@ -292,19 +369,135 @@ impl ReqContext {
     }
 }
 
+/// A response of a streaming request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamResponse {
+    pub resp: Response,
+    /// The key of the last row in the response, a broken stream can be resumed by
+    /// requesting `resume_ranges` with it. It's `None` for the last response.
+    pub last_key: Option<Vec<u8>>,
+}
+
+/// The sender of a streaming request. It's bounded, so the rows are produced no faster
+/// than the responses are consumed.
+pub type StreamSender = mpsc::Sender<StreamResponse>;
+
+enum Responder {
+    Unary(OnResponse),
+    // It's `None` after the stream is closed.
+    Stream(Option<StreamSender>),
+}
+
+#[derive(Default)]
+struct RequeueState {
+    task: Option<RequestTask>,
+    notified: bool,
+}
+
+// Schedules a streaming request that yields on a full channel back to the endpoint once the
+// channel has room, so it doesn't block a thread of the pool while waiting.
+struct RequeueNotify {
+    sched: Mutex<Scheduler<Task>>,
+    state: Mutex<RequeueState>,
+}
+
+impl RequeueNotify {
+    fn new(sched: Scheduler<Task>) -> RequeueNotify {
+        RequeueNotify {
+            sched: Mutex::new(sched),
+            state: Mutex::new(RequeueState::default()),
+        }
+    }
+
+    // Holds the request until the channel has room, it may have room already.
+    fn park(&self, t: RequestTask) {
+        let t = {
+            let mut state = self.state.lock().unwrap();
+            if !state.notified {
+                state.task = Some(t);
+                return;
+            }
+            t
+        };
+        self.requeue(t);
+    }
+
+    fn requeue(&self, t: RequestTask) {
+        let sched = self.sched.lock().unwrap();
+        if let Err(e) = sched.schedule(Task::Request(t)) {
+            // The stream is closed when the request is dropped.
+            debug!("failed to requeue {}", e.0);
+        }
+    }
+}
+
+impl Notify for RequeueNotify {
+    fn notify(&self, _: usize) {
+        let t = {
+            let mut state = self.state.lock().unwrap();
+            state.notified = true;
+            state.task.take()
+        };
+        if let Some(t) = t {
+            self.requeue(t);
+        }
+    }
+}
+
+// Sends the response if the channel has room, or gives it back and `notify` is notified once
+// the channel has room or the receiver is dropped. The stream is closed if the receiver is
+// dropped.
+fn try_send_stream_response(
+    tx: &mut Option<StreamSender>,
+    resp: StreamResponse,
+    notify: &Arc<RequeueNotify>,
+) -> Result<Option<StreamResponse>> {
+    let sender = match tx.take() {
+        Some(sender) => sender,
+        None => return Err(box_err!("stream is closed")),
+    };
+    let mut sender = executor::spawn(sender);
+    let res = sender.start_send_notify(resp, notify, 0);
+    match res {
+        Ok(AsyncSink::Ready) => {
+            *tx = Some(sender.into_inner());
+            Ok(None)
+        }
+        Ok(AsyncSink::NotReady(resp)) => {
+            *tx = Some(sender.into_inner());
+            Ok(Some(resp))
+        }
+        Err(_) => Err(box_err!("stream is closed")),
+    }
+}
+
 pub struct RequestTask {
     req: Request,
     start_ts: Option<u64>,
     wait_time: Option<f64>,
     timer: Instant,
     statistics: Statistics,
-    on_resp: OnResponse,
+    on_resp: Responder,
+    // The streamed response the request yields on, it's sent before the request goes on.
+    pending_resp: Option<StreamResponse>,
     cop_req: Option<Result<CopRequest>>,
     ctx: ReqContext,
 }
 
 impl RequestTask {
     pub fn new(req: Request, on_resp: OnResponse) -> RequestTask {
+        RequestTask::with_responder(req, Responder::Unary(on_resp))
+    }
+
+    /// Creates a streaming request, the rows of DAG requests that can be resumed by key are
+    /// sent in responses of at most `end-point-stream-batch-row-limit` rows. Other requests
+    /// are responded in one response. The whole stream is bounded by the deadline of the
+    /// request.
+    pub fn new_stream(req: Request, tx: StreamSender) -> RequestTask {
+        RequestTask::with_responder(req, Responder::Stream(Some(tx)))
+    }
+
+    fn with_responder(req: Request, on_resp: Responder) -> RequestTask {
         let timer = Instant::now_coarse();
         let deadline = timer + Duration::from_secs(REQUEST_MAX_HANDLE_SECS);
         let mut start_ts = None;
@@ -357,6 +550,7 @@ impl RequestTask {
             isolation_level: req.get_context().get_isolation_level(),
             fill_cache: !req.get_context().get_not_fill_cache(),
             table_scan: table_scan,
+            region_id: req.get_context().get_region_id(),
         };
         RequestTask {
             req: req,
@@ -365,6 +559,7 @@ impl RequestTask {
             timer: timer,
             statistics: Default::default(),
             on_resp: on_resp,
+            pending_resp: None,
             cop_req: Some(cop_req),
             ctx: req_ctx,
         }
@@ -375,6 +570,42 @@ impl RequestTask {
         self.ctx.check_if_outdated()
     }
 
+    /// Pushes the max ts with the start ts of the request, and checks whether the keys it
+    /// reads have keys of running async-commit or 1PC prewrites the request may miss.
+    fn check_memory_locks(&self, cm: &ConcurrencyManager) -> Result<()> {
+        let start_ts = match self.start_ts {
+            Some(ts) => ts,
+            None => return Ok(()),
+        };
+        if self.ctx.isolation_level == IsolationLevel::RC {
+            cm.update_max_ts(start_ts);
+            return Ok(());
+        }
+        let mut ranges: Vec<_> = self.req
+            .get_ranges()
+            .iter()
+            .map(|r| (r.get_start().to_vec(), r.get_end().to_vec()))
+            .collect();
+        // The rows looked up by the handles of an index are read besides the ranges.
+        if let Some(Ok(CopRequest::DAG(ref dag))) = self.cop_req {
+            if let Some(table_id) = lookup_table_id(dag) {
+                ranges.push(table_row_range(table_id));
+            }
+        }
+        for (start, end) in ranges {
+            let start_key = Key::from_raw(&start);
+            let end_key = if end.is_empty() {
+                vec![]
+            } else {
+                Key::from_raw(&end).encoded().to_owned()
+            };
+            if let Err(e) = cm.read_bounded_range_check(start_key.encoded(), &end_key, start_ts) {
+                return Err(Error::from(TxnError::from(e)));
+            }
+        }
+        Ok(())
+    }
+
     fn stop_record_waiting(&mut self) {
         if self.wait_time.is_some() {
             return;
@@ -448,10 +679,18 @@ impl BatchRunnable<Task> for Host {
         for task in tasks.drain(..) {
             match task {
                 Task::Request(req) => {
+                    let req = match self.send_pending_response(req) {
+                        Some(req) => req,
+                        None => continue,
+                    };
                     if let Err(e) = req.check_outdated() {
                         on_error(e, req);
                         continue;
                     }
+                    if let Err(e) = req.check_memory_locks(&self.cm) {
+                        on_error(e, req);
+                        continue;
+                    }
                     let key = {
                         let ctx = req.req.get_context();
                         (
@@ -607,17 +846,44 @@ fn notify_batch_failed<E: Into<Error> + Debug>(e: E, reqs: Vec<RequestTask>) {
 
 fn respond(resp: Response, mut t: RequestTask) -> Statistics {
     t.stop_record_handling();
-    (t.on_resp)(resp);
+    match t.on_resp {
+        Responder::Unary(on_resp) => on_resp(resp),
+        Responder::Stream(Some(tx)) => {
+            let resp = StreamResponse {
+                resp: resp,
+                last_key: None,
+            };
+            // A new sender always has room for a response, so it doesn't wait.
+            if let Err(e) = tx.clone().send(resp).wait() {
+                debug!("failed to send the last response: {:?}", e);
+            }
+        }
+        Responder::Stream(None) => {}
+    }
     t.statistics
 }
 
 pub struct TiDbEndPoint {
     snap: Box<Snapshot>,
+    // Streaming requests are scheduled back by it after they yield.
+    sched: Scheduler<Task>,
+    stream_batch_row_limit: usize,
+    aggr_spill_cfg: SpillConfig,
 }
 
 impl TiDbEndPoint {
-    pub fn new(snap: Box<Snapshot>) -> TiDbEndPoint {
-        TiDbEndPoint { snap: snap }
+    pub fn new(
+        snap: Box<Snapshot>,
+        sched: Scheduler<Task>,
+        stream_batch_row_limit: usize,
+        aggr_spill_cfg: SpillConfig,
+    ) -> TiDbEndPoint {
+        TiDbEndPoint {
+            snap: snap,
+            sched: sched,
+            stream_batch_row_limit: stream_batch_row_limit,
+            aggr_spill_cfg: aggr_spill_cfg,
+        }
     }
 }
 
@@ -629,7 +895,12 @@ impl TiDbEndPoint {
         }
         let resp = match t.cop_req.take().unwrap() {
             Ok(CopRequest::Select(sel)) => self.handle_select(sel, &mut t),
-            Ok(CopRequest::DAG(dag)) => self.handle_dag(dag, &mut t),
+            Ok(CopRequest::DAG(dag)) => {
+                if let Responder::Stream(_) = t.on_resp {
+                    return self.handle_streaming_dag(dag, t);
+                }
+                self.handle_dag(dag, &mut t)
+            }
             Ok(CopRequest::Analyze(analyze)) => self.handle_analyze(analyze, &mut t),
             Err(err) => Err(err),
         };
@@ -651,10 +922,81 @@ impl TiDbEndPoint {
             dag.get_time_zone_offset(),
             dag.get_flags()
         )));
-        let ctx = DAGContext::new(dag, ranges, self.snap.as_ref(), eval_ctx.clone(), &t.ctx);
+        let ctx = DAGContext::new(
+            dag,
+            ranges,
+            self.snap.as_ref(),
+            eval_ctx.clone(),
+            &t.ctx,
+            self.aggr_spill_cfg.clone(),
+        );
         ctx.handle_request(&mut t.statistics)
     }
 
+    // The request yields when the channel is full, and it's scheduled back to go on after
+    // the last streamed row once the channel has room.
+    fn handle_streaming_dag(&self, dag: DAGRequest, mut t: RequestTask) -> Statistics {
+        // Only the rows of a table scan are streamed.
+        let desc = dag.get_executors()
+            .first()
+            .map_or(false, |exec| exec.get_tbl_scan().get_desc());
+        let notify = Arc::new(RequeueNotify::new(self.sched.clone()));
+        let res = {
+            let ranges = t.req.get_ranges().to_vec();
+            let eval_ctx = match EvalContext::new(dag.get_time_zone_offset(), dag.get_flags()) {
+                Ok(ctx) => Rc::new(ctx),
+                Err(e) => return on_error(box_err!(e), t),
+            };
+            let ctx = DAGContext::new(
+                dag.clone(),
+                ranges,
+                self.snap.as_ref(),
+                eval_ctx,
+                &t.ctx,
+                self.aggr_spill_cfg.clone(),
+            );
+            let tx = match t.on_resp {
+                Responder::Stream(ref mut tx) => tx,
+                Responder::Unary(_) => unreachable!(),
+            };
+            let pending_resp = &mut t.pending_resp;
+            let mut on_resp = |resp: Response, last_key: Vec<u8>| -> Result<bool> {
+                let resp = StreamResponse {
+                    resp: resp,
+                    last_key: Some(last_key),
+                };
+                match try_send_stream_response(tx, resp, &notify)? {
+                    None => Ok(true),
+                    Some(resp) => {
+                        *pending_resp = Some(resp);
+                        Ok(false)
+                    }
+                }
+            };
+            ctx.handle_streaming_request(
+                &mut t.statistics,
+                self.stream_batch_row_limit,
+                &mut on_resp,
+            )
+        };
+        match res {
+            Ok(Some(resp)) => respond(resp, t),
+            Ok(None) => {
+                let ranges = {
+                    let last_key = t.pending_resp.as_ref().unwrap().last_key.as_ref().unwrap();
+                    resume_ranges(t.req.get_ranges(), last_key, desc)
+                };
+                t.req.set_ranges(RepeatedField::from_vec(ranges));
+                t.cop_req = Some(Ok(CopRequest::DAG(dag)));
+                // The statistics are collected when the request goes on again.
+                let stats = mem::replace(&mut t.statistics, Statistics::default());
+                notify.park(t);
+                stats
+            }
+            Err(e) => on_error(e, t),
+        }
+    }
+
     pub fn handle_analyze(&self, analyze: AnalyzeReq, t: &mut RequestTask) -> Result<Response> {
         let ranges = t.req.get_ranges().to_vec();
         let ctx = AnalyzeContext::new(
@@ -698,6 +1040,33 @@ pub fn prefix_next(key: &[u8]) -> Vec<u8> {
     }
 }
 
+/// Returns the parts of `ranges` after the row key `last_key`, or before it if the scan is
+/// in descending order.
+pub fn resume_ranges(ranges: &[KeyRange], last_key: &[u8], desc: bool) -> Vec<KeyRange> {
+    let next_key = prefix_next(last_key);
+    let mut res = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let mut range = range.clone();
+        if desc {
+            if range.get_start() >= last_key {
+                continue;
+            }
+            if range.get_end() > last_key {
+                range.set_end(last_key.to_vec());
+            }
+        } else {
+            if range.get_end() <= next_key.as_slice() {
+                continue;
+            }
+            if range.get_start() < next_key.as_slice() {
+                range.set_start(next_key.clone());
+            }
+        }
+        res.push(range);
+    }
+    res
+}
+
 /// `is_point` checks if the key range represents a point.
 pub fn is_point(range: &KeyRange) -> bool {
     range.get_end() == &*prefix_next(range.get_start())
@@ -748,9 +1117,13 @@ mod tests {
     use std::thread;
     use std::time::Duration;
 
+    use futures::Stream;
     use kvproto::coprocessor::Request;
+    use tipb::executor::Executor;
+
+    use coprocessor::codec::table;
 
-    use util::worker::{FutureWorker, Worker};
+    use util::worker::{dummy_scheduler, FutureWorker, Worker};
     use util::time::Instant;
 
     #[test]
@@ -760,12 +1133,104 @@ mod tests {
             isolation_level: IsolationLevel::RC,
             fill_cache: true,
             table_scan: true,
+            region_id: 1,
         };
         assert_eq!(ctx.get_scan_tag(), STR_REQ_TYPE_SELECT);
         ctx.table_scan = false;
         assert_eq!(ctx.get_scan_tag(), STR_REQ_TYPE_INDEX);
     }
 
+    #[test]
+    fn test_try_send_stream_response() {
+        let resp = || StreamResponse {
+            resp: Response::new(),
+            last_key: None,
+        };
+        let notify = Arc::new(RequeueNotify::new(dummy_scheduler()));
+        let (tx, rx) = ::futures::sync::mpsc::channel(0);
+        let mut tx = Some(tx);
+        assert!(try_send_stream_response(&mut tx, resp(), &notify).unwrap().is_none());
+        // The channel is full, the response is given back.
+        let pending = try_send_stream_response(&mut tx, resp(), &notify).unwrap();
+        assert!(pending.is_some());
+        assert!(!notify.state.lock().unwrap().notified);
+
+        // The channel has room after a response is received.
+        let mut rx = rx.wait();
+        rx.next().unwrap().unwrap();
+        assert!(notify.state.lock().unwrap().notified);
+        let pending = try_send_stream_response(&mut tx, pending.unwrap(), &notify).unwrap();
+        assert!(pending.is_none());
+
+        // The stream is closed after the receiver is dropped.
+        drop(rx);
+        assert!(try_send_stream_response(&mut tx, resp(), &notify).is_err());
+        assert!(tx.is_none());
+    }
+
+    #[test]
+    fn test_check_memory_locks() {
+        let table_id = 1;
+        let new_req = |lookup: bool| {
+            let mut dag = DAGRequest::new();
+            dag.set_start_ts(10);
+            let mut exec = Executor::new();
+            exec.set_tp(ExecType::TypeIndexScan);
+            exec.mut_idx_scan().set_table_id(table_id);
+            dag.mut_executors().push(exec);
+            if lookup {
+                let mut exec = Executor::new();
+                exec.set_tp(ExecType::TypeTableScan);
+                exec.mut_tbl_scan().set_table_id(table_id);
+                dag.mut_executors().push(exec);
+            }
+            let mut range = KeyRange::new();
+            range.set_start(table::encode_index_seek_key(table_id, 1, &[]));
+            range.set_end(table::encode_index_seek_key(table_id, 2, &[]));
+            let mut req = Request::new();
+            req.set_tp(REQ_TYPE_DAG);
+            req.set_data(dag.write_to_bytes().unwrap());
+            req.mut_ranges().push(range);
+            RequestTask::new(req, box |_| {})
+        };
+
+        let cm = ConcurrencyManager::new();
+        let row_key = Key::from_raw(&table::encode_row_key(table_id, &[0; 8]));
+        let keys = vec![row_key.encoded().to_owned()];
+        cm.lock_keys(1, &keys, row_key.encoded(), 5, 0, 100).unwrap();
+        assert!(new_req(false).check_memory_locks(&cm).is_ok());
+        // The rows looked up by the index are checked too.
+        match new_req(true).check_memory_locks(&cm) {
+            Err(Error::Locked(_)) => {}
+            res => panic!("expect locked, but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_resume_ranges() {
+        let new_range = |start: &[u8], end: &[u8]| {
+            let mut range = KeyRange::new();
+            range.set_start(start.to_vec());
+            range.set_end(end.to_vec());
+            range
+        };
+        let ranges = vec![new_range(b"a", b"c"), new_range(b"d", b"f")];
+
+        let resumed = resume_ranges(&ranges, b"a", false);
+        assert_eq!(resumed, vec![new_range(b"b", b"c"), new_range(b"d", b"f")]);
+        let resumed = resume_ranges(&ranges, b"d", false);
+        assert_eq!(resumed, vec![new_range(b"e", b"f")]);
+        let resumed = resume_ranges(&ranges, b"e", false);
+        assert!(resumed.is_empty());
+
+        let resumed = resume_ranges(&ranges, b"e", true);
+        assert_eq!(resumed, vec![new_range(b"a", b"c"), new_range(b"d", b"e")]);
+        let resumed = resume_ranges(&ranges, b"b", true);
+        assert_eq!(resumed, vec![new_range(b"a", b"b")]);
+        let resumed = resume_ranges(&ranges, b"a", true);
+        assert!(resumed.is_empty());
+    }
+
     #[test]
     fn test_req_outdated() {
         let mut worker = Worker::new("test-endpoint");
@@ -773,7 +1238,13 @@ mod tests {
         let mut cfg = Config::default();
         cfg.end_point_concurrency = 1;
         let pd_worker = FutureWorker::new("test-pd-worker");
-        let end_point = Host::new(engine, worker.scheduler(), &cfg, pd_worker.scheduler());
+        let end_point = Host::new(
+            engine,
+            worker.scheduler(),
+            &cfg,
+            pd_worker.scheduler(),
+            ConcurrencyManager::new(),
+        );
         worker.start_batch(end_point, 30).unwrap();
         let (tx, rx) = mpsc::channel();
         let mut task = RequestTask::new(Request::new(), box move |msg| { tx.send(msg).unwrap(); });
@@ -791,7 +1262,13 @@ mod tests {
         let mut cfg = Config::default();
         cfg.end_point_concurrency = 1;
         let pd_worker = FutureWorker::new("test-pd-worker");
-        let mut end_point = Host::new(engine, worker.scheduler(), &cfg, pd_worker.scheduler());
+        let mut end_point = Host::new(
+            engine,
+            worker.scheduler(),
+            &cfg,
+            pd_worker.scheduler(),
+            ConcurrencyManager::new(),
+        );
         end_point.max_running_task_count = 3;
         worker.start_batch(end_point, 30).unwrap();
         let (tx, rx) = mpsc::channel();
@@ -85,6 +85,6 @@ impl From<txn::Error> for Error {
     }
 }
 
-pub use self::endpoint::{CopRequestStatistics, CopSender, Host as EndPointHost, RequestTask,
-                         Task as EndPointTask, REQ_TYPE_DAG, REQ_TYPE_INDEX, REQ_TYPE_SELECT,
-                         SINGLE_GROUP};
+pub use self::endpoint::{resume_ranges, CopRequestStatistics, CopSender, Host as EndPointHost,
+                         RequestTask, StreamResponse, StreamSender, Task as EndPointTask,
+                         REQ_TYPE_DAG, REQ_TYPE_INDEX, REQ_TYPE_SELECT, SINGLE_GROUP};
@@ -11,15 +11,34 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::{mem, u64};
 use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use protobuf::{self, Message};
+use tipb::analyze;
 use tipb::expression::ExprType;
 
-use coprocessor::codec::Datum;
+use coprocessor::codec::convert;
+use coprocessor::codec::datum::{self, Datum, DatumDecoder};
+use coprocessor::statistics::fmsketch::FMSketch;
 use coprocessor::Result;
 
 use super::xeval::{evaluator, EvalContext};
 
-pub fn build_aggr_func(tp: ExprType) -> Result<Box<AggrFunc>> {
+// The same as the max size of the sketches built by analyze.
+const APPROX_COUNT_DISTINCT_SKETCH_SIZE: usize = 10000;
+
+/// Builds the aggregate function of `tp`, a distinct function only aggregates the
+/// distinct arguments.
+pub fn build_aggr_func(tp: ExprType, distinct: bool) -> Result<Box<AggrFunc>> {
+    if distinct {
+        return match tp {
+            ExprType::Count => Ok(box Distinct::new(None)),
+            ExprType::Sum => Ok(box Distinct::new(Some(1))),
+            et => Err(box_err!("unsupport distinct AggrExprType: {:?}", et)),
+        };
+    }
     match tp {
         ExprType::Count => Ok(box Count { c: 0 }),
         ExprType::First => Ok(box First { e: None }),
@@ -30,6 +49,28 @@ pub fn build_aggr_func(tp: ExprType) -> Result<Box<AggrFunc>> {
         }),
         ExprType::Max => Ok(box Extremum::new(Ordering::Less)),
         ExprType::Min => Ok(box Extremum::new(Ordering::Greater)),
+        ExprType::GroupConcat => Ok(box GroupConcat {
+            res: None,
+            sep: None,
+            max_len: u64::MAX,
+        }),
+        ExprType::Agg_BitAnd => Ok(box BitOp::new(u64::MAX, |a, b| a & b)),
+        ExprType::Agg_BitOr => Ok(box BitOp::new(0, |a, b| a | b)),
+        ExprType::Agg_BitXor => Ok(box BitOp::new(0, |a, b| a ^ b)),
+        ExprType::VarPop |
+        ExprType::VarSamp |
+        ExprType::Variance |
+        ExprType::Std |
+        ExprType::Stddev |
+        ExprType::StddevPop |
+        ExprType::StddevSamp => Ok(box Variance {
+            count: 0,
+            sum: 0f64,
+            m2: 0f64,
+        }),
+        ExprType::ApproxCountDistinct => Ok(box ApproxCountDistinct {
+            sketch: FMSketch::new(APPROX_COUNT_DISTINCT_SKETCH_SIZE),
+        }),
         et => Err(box_err!("unsupport AggrExprType: {:?}", et)),
     }
 }
@@ -40,6 +81,38 @@ pub trait AggrFunc {
     fn update(&mut self, ctx: &EvalContext, args: Vec<Datum>) -> Result<()>;
     /// `calc` calculates the aggregated result and push it to collector.
     fn calc(&mut self, collector: &mut Vec<Datum>) -> Result<()>;
+    /// `partial` pushes the partial aggregate state to collector, it can be merged
+    /// into another context of the same function by `merge`. The encoding of the state
+    /// is documented on every function, it's only kept in the spilled files, which are
+    /// removed after the request, so it can be changed freely.
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()>;
+    /// `merge` merges a partial aggregate state pushed by `partial`.
+    fn merge(&mut self, ctx: &EvalContext, partial: Vec<Datum>) -> Result<()>;
+    /// `mem_size` returns the approximate memory taken by the aggregate context, it
+    /// should be cheap as it's checked after every update.
+    fn mem_size(&self) -> usize {
+        mem::size_of_val(self)
+    }
+}
+
+// The approximate memory taken by the datum out of the context.
+fn datum_heap_size(d: &Option<Datum>) -> usize {
+    match *d {
+        Some(Datum::Bytes(ref bs)) => bs.capacity(),
+        Some(Datum::Json(ref j)) => j.binary_len(),
+        _ => 0,
+    }
+}
+
+fn check_partial_len(partial: &[Datum], len: usize) -> Result<()> {
+    if partial.len() != len {
+        return Err(box_err!(
+            "partial state should have {} values, but got {}",
+            len,
+            partial.len()
+        ));
+    }
+    Ok(())
 }
 
 struct Count {
@@ -61,6 +134,19 @@ impl AggrFunc for Count {
         collector.push(Datum::U64(self.c));
         Ok(())
     }
+
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        self.calc(collector)
+    }
+
+    fn merge(&mut self, _: &EvalContext, partial: Vec<Datum>) -> Result<()> {
+        check_partial_len(&partial, 1)?;
+        match partial[0] {
+            Datum::U64(c) => self.c += c,
+            ref d => return Err(box_err!("invalid partial count: {:?}", d)),
+        }
+        Ok(())
+    }
 }
 
 struct First {
@@ -86,6 +172,25 @@ impl AggrFunc for First {
         collector.push(self.e.take().unwrap_or(Datum::Null));
         Ok(())
     }
+
+    // The first row may be null, so whether it's seen is pushed before it.
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        collector.push(Datum::from(self.e.is_some()));
+        self.calc(collector)
+    }
+
+    fn merge(&mut self, _: &EvalContext, mut partial: Vec<Datum>) -> Result<()> {
+        check_partial_len(&partial, 2)?;
+        let e = partial.pop();
+        if self.e.is_none() && partial[0] == Datum::I64(1) {
+            self.e = e;
+        }
+        Ok(())
+    }
+
+    fn mem_size(&self) -> usize {
+        mem::size_of_val(self) + datum_heap_size(&self.e)
+    }
 }
 
 struct Sum {
@@ -132,6 +237,19 @@ impl AggrFunc for Sum {
         collector.push(Datum::Dec(d));
         Ok(())
     }
+
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        collector.push(self.res.take().unwrap_or(Datum::Null));
+        Ok(())
+    }
+
+    fn merge(&mut self, ctx: &EvalContext, partial: Vec<Datum>) -> Result<()> {
+        self.update(ctx, partial)
+    }
+
+    fn mem_size(&self) -> usize {
+        mem::size_of_val(self) + datum_heap_size(&self.res)
+    }
 }
 
 struct Avg {
@@ -151,6 +269,25 @@ impl AggrFunc for Avg {
         collector.push(Datum::U64(self.cnt));
         self.sum.calc(collector)
     }
+
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        collector.push(Datum::U64(self.cnt));
+        self.sum.partial(collector)
+    }
+
+    fn merge(&mut self, ctx: &EvalContext, mut partial: Vec<Datum>) -> Result<()> {
+        check_partial_len(&partial, 2)?;
+        let sum = partial.pop().unwrap();
+        match partial[0] {
+            Datum::U64(cnt) => self.cnt += cnt,
+            ref d => return Err(box_err!("invalid partial count: {:?}", d)),
+        }
+        self.sum.merge(ctx, vec![sum])
+    }
+
+    fn mem_size(&self) -> usize {
+        mem::size_of_val(self) + datum_heap_size(&self.sum.res)
+    }
 }
 
 struct Extremum {
@@ -191,4 +328,541 @@ impl AggrFunc for Extremum {
         collector.push(self.datum.take().unwrap_or(Datum::Null));
         Ok(())
     }
+
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        self.calc(collector)
+    }
+
+    fn merge(&mut self, ctx: &EvalContext, partial: Vec<Datum>) -> Result<()> {
+        self.update(ctx, partial)
+    }
+
+    fn mem_size(&self) -> usize {
+        mem::size_of_val(self) + datum_heap_size(&self.datum)
+    }
+}
+
+/// `GroupConcat` concatenates the arguments of the rows, the last two arguments are the
+/// separator and `group_concat_max_len`, the result is truncated to the max length in
+/// bytes. The rows with null arguments are skipped.
+///
+/// The values are concatenated in the order of the rows, the request can't carry the
+/// `ORDER BY` of `GROUP_CONCAT`, so such a function shouldn't be pushed down.
+///
+/// The partial state is the concatenated values, the separator and the max length,
+/// they are all null if there is no value.
+struct GroupConcat {
+    res: Option<Vec<u8>>,
+    sep: Option<Vec<u8>>,
+    max_len: u64,
+}
+
+impl GroupConcat {
+    fn append(&mut self, value: Vec<u8>, sep: Vec<u8>, max_len: u64) {
+        self.max_len = max_len;
+        if self.res.is_none() {
+            self.res = Some(value);
+            self.sep = Some(sep);
+        } else {
+            let res = self.res.as_mut().unwrap();
+            if res.len() as u64 >= max_len {
+                return;
+            }
+            res.extend_from_slice(&sep);
+            res.extend_from_slice(&value);
+        }
+        let res = self.res.as_mut().unwrap();
+        if res.len() as u64 > max_len {
+            res.truncate(max_len as usize);
+        }
+    }
+}
+
+impl AggrFunc for GroupConcat {
+    fn update(&mut self, _: &EvalContext, mut args: Vec<Datum>) -> Result<()> {
+        if args.len() < 3 {
+            return Err(box_err!(
+                "group_concat needs at least one column, the separator and the max length, \
+                 but got {}",
+                args.len()
+            ));
+        }
+        let max_len = match args.pop().unwrap() {
+            Datum::I64(l) if l > 0 => l as u64,
+            Datum::U64(l) if l > 0 => l,
+            d => return Err(box_err!("invalid group_concat max length: {:?}", d)),
+        };
+        let sep = box_try!(args.pop().unwrap().into_string()).into_bytes();
+        if args.iter().any(|arg| *arg == Datum::Null) {
+            return Ok(());
+        }
+        let mut value = vec![];
+        for arg in args {
+            value.extend_from_slice(box_try!(arg.into_string()).as_bytes());
+        }
+        self.append(value, sep, max_len);
+        Ok(())
+    }
+
+    fn calc(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        collector.push(self.res.take().map_or(Datum::Null, Datum::Bytes));
+        Ok(())
+    }
+
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        let max_len = if self.res.is_some() {
+            Datum::U64(self.max_len)
+        } else {
+            Datum::Null
+        };
+        self.calc(collector)?;
+        collector.push(self.sep.take().map_or(Datum::Null, Datum::Bytes));
+        collector.push(max_len);
+        Ok(())
+    }
+
+    fn merge(&mut self, _: &EvalContext, partial: Vec<Datum>) -> Result<()> {
+        check_partial_len(&partial, 3)?;
+        let mut partial = partial.into_iter();
+        let (value, sep) = (partial.next().unwrap(), partial.next().unwrap());
+        match (value, sep, partial.next().unwrap()) {
+            (Datum::Bytes(value), Datum::Bytes(sep), Datum::U64(max_len)) => {
+                self.append(value, sep, max_len)
+            }
+            (Datum::Null, _, _) => {}
+            (value, sep, max_len) => {
+                return Err(box_err!(
+                    "invalid partial group_concat: {:?}, {:?}, {:?}",
+                    value,
+                    sep,
+                    max_len
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn mem_size(&self) -> usize {
+        let res = self.res.as_ref().map_or(0, |res| res.capacity());
+        let sep = self.sep.as_ref().map_or(0, |sep| sep.capacity());
+        mem::size_of_val(self) + res + sep
+    }
+}
+
+/// `BitOp` folds the arguments by a bitwise operation, the result is `init` if all the
+/// arguments are null.
+struct BitOp {
+    res: u64,
+    op: fn(u64, u64) -> u64,
+}
+
+impl BitOp {
+    fn new(init: u64, op: fn(u64, u64) -> u64) -> BitOp {
+        BitOp { res: init, op: op }
+    }
+}
+
+// Converts the datum to an unsigned integer as MySQL does for bit operations.
+fn datum_as_u64(ctx: &EvalContext, d: Datum) -> Result<u64> {
+    let i = match d {
+        Datum::U64(u) => return Ok(u),
+        Datum::I64(i) => i,
+        Datum::Bytes(ref bs) => box_try!(convert::bytes_to_int(ctx, bs)),
+        d => box_try!(d.into_f64(ctx)).round() as i64,
+    };
+    Ok(i as u64)
+}
+
+impl AggrFunc for BitOp {
+    fn update(&mut self, ctx: &EvalContext, mut args: Vec<Datum>) -> Result<()> {
+        if args.len() != 1 {
+            return Err(box_err!(
+                "bit operations only support one column, but got {}",
+                args.len()
+            ));
+        }
+        let arg = args.pop().unwrap();
+        if arg == Datum::Null {
+            return Ok(());
+        }
+        self.res = (self.op)(self.res, datum_as_u64(ctx, arg)?);
+        Ok(())
+    }
+
+    fn calc(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        collector.push(Datum::U64(self.res));
+        Ok(())
+    }
+
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        self.calc(collector)
+    }
+
+    fn merge(&mut self, _: &EvalContext, partial: Vec<Datum>) -> Result<()> {
+        check_partial_len(&partial, 1)?;
+        match partial[0] {
+            Datum::U64(u) => self.res = (self.op)(self.res, u),
+            ref d => return Err(box_err!("invalid partial bit operation: {:?}", d)),
+        }
+        Ok(())
+    }
+}
+
+/// `Variance` collects the count, the sum and the sum of squared differences from the
+/// mean of the arguments, all the variance and standard deviation functions are
+/// calculated from them, and so are they merged.
+///
+/// The result is the count, the sum and the sum of squared differences, TiDB calculates
+/// the final value from them like the average from the count and the sum of `Avg`. So is
+/// the partial state.
+struct Variance {
+    count: u64,
+    sum: f64,
+    m2: f64,
+}
+
+impl Variance {
+    fn merge_state(&mut self, count: u64, sum: f64, m2: f64) {
+        if count == 0 {
+            return;
+        }
+        if self.count > 0 {
+            let (n1, n2) = (self.count as f64, count as f64);
+            let d = n1 * sum - n2 * self.sum;
+            self.m2 += d * d / (n1 * n2 * (n1 + n2));
+        }
+        self.count += count;
+        self.sum += sum;
+        self.m2 += m2;
+    }
+}
+
+impl AggrFunc for Variance {
+    fn update(&mut self, ctx: &EvalContext, mut args: Vec<Datum>) -> Result<()> {
+        if args.len() != 1 {
+            return Err(box_err!(
+                "variance only support one column, but got {}",
+                args.len()
+            ));
+        }
+        let arg = args.pop().unwrap();
+        if arg == Datum::Null {
+            return Ok(());
+        }
+        let v = box_try!(arg.into_f64(ctx));
+        self.merge_state(1, v, 0f64);
+        Ok(())
+    }
+
+    fn calc(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        collector.push(Datum::U64(self.count));
+        collector.push(Datum::F64(self.sum));
+        collector.push(Datum::F64(self.m2));
+        Ok(())
+    }
+
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        self.calc(collector)
+    }
+
+    fn merge(&mut self, _: &EvalContext, partial: Vec<Datum>) -> Result<()> {
+        check_partial_len(&partial, 3)?;
+        match (&partial[0], &partial[1], &partial[2]) {
+            (&Datum::U64(count), &Datum::F64(sum), &Datum::F64(m2)) => {
+                self.merge_state(count, sum, m2)
+            }
+            _ => return Err(box_err!("invalid partial variance: {:?}", partial)),
+        }
+        Ok(())
+    }
+}
+
+/// `ApproxCountDistinct` estimates the number of the distinct arguments by a
+/// `FMSketch`, the result is the sketch encoded as a `tipb::analyze::FMSketch` message
+/// so it can be merged with others, so is the partial state.
+struct ApproxCountDistinct {
+    sketch: FMSketch,
+}
+
+impl AggrFunc for ApproxCountDistinct {
+    fn update(&mut self, _: &EvalContext, args: Vec<Datum>) -> Result<()> {
+        if args.iter().any(|arg| *arg == Datum::Null) {
+            return Ok(());
+        }
+        let bytes = box_try!(datum::encode_value(&args));
+        self.sketch.insert(&bytes);
+        Ok(())
+    }
+
+    fn calc(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        let sketch = FMSketch::new(APPROX_COUNT_DISTINCT_SKETCH_SIZE);
+        let sketch = mem::replace(&mut self.sketch, sketch);
+        let data = box_try!(sketch.into_proto().write_to_bytes());
+        collector.push(Datum::Bytes(data));
+        Ok(())
+    }
+
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        self.calc(collector)
+    }
+
+    fn merge(&mut self, _: &EvalContext, partial: Vec<Datum>) -> Result<()> {
+        check_partial_len(&partial, 1)?;
+        let sketch: analyze::FMSketch = match partial[0] {
+            Datum::Bytes(ref data) => box_try!(protobuf::parse_from_bytes(data)),
+            ref d => return Err(box_err!("invalid partial sketch: {:?}", d)),
+        };
+        self.sketch.merge(&sketch);
+        Ok(())
+    }
+
+    fn mem_size(&self) -> usize {
+        self.sketch.mem_size()
+    }
+}
+
+/// `Distinct` collects the distinct arguments of `COUNT(DISTINCT)` and `SUM(DISTINCT)`,
+/// the result is a list of the encoded arguments, which are unioned with the others
+/// before counting or summing them up.
+///
+/// The result and the partial state are the same: the bytes datums of the arguments
+/// encoded by `datum::encode_value`, in ascending order, then encoded as a list by
+/// `datum::encode_value` too.
+struct Distinct {
+    values: BTreeSet<Vec<u8>>,
+    // the bytes taken by the values.
+    values_size: usize,
+    // the number of arguments it supports.
+    args_len: Option<usize>,
+}
+
+impl Distinct {
+    fn new(args_len: Option<usize>) -> Distinct {
+        Distinct {
+            values: BTreeSet::new(),
+            values_size: 0,
+            args_len: args_len,
+        }
+    }
+
+    fn insert(&mut self, value: Vec<u8>) {
+        let size = mem::size_of::<Vec<u8>>() + value.capacity();
+        if self.values.insert(value) {
+            self.values_size += size;
+        }
+    }
+}
+
+impl AggrFunc for Distinct {
+    fn update(&mut self, _: &EvalContext, args: Vec<Datum>) -> Result<()> {
+        if let Some(len) = self.args_len {
+            if args.len() != len {
+                return Err(box_err!(
+                    "distinct only support {} columns, but got {}",
+                    len,
+                    args.len()
+                ));
+            }
+        }
+        if args.iter().any(|arg| *arg == Datum::Null) {
+            return Ok(());
+        }
+        let value = box_try!(datum::encode_value(&args));
+        self.insert(value);
+        Ok(())
+    }
+
+    fn calc(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        self.values_size = 0;
+        let values: Vec<_> = mem::replace(&mut self.values, BTreeSet::new())
+            .into_iter()
+            .map(Datum::Bytes)
+            .collect();
+        collector.push(Datum::Bytes(box_try!(datum::encode_value(&values))));
+        Ok(())
+    }
+
+    fn partial(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        self.calc(collector)
+    }
+
+    fn merge(&mut self, _: &EvalContext, partial: Vec<Datum>) -> Result<()> {
+        check_partial_len(&partial, 1)?;
+        let values = match partial[0] {
+            Datum::Bytes(ref data) => box_try!(data.as_slice().decode()),
+            ref d => return Err(box_err!("invalid partial distinct values: {:?}", d)),
+        };
+        for value in values {
+            match value {
+                Datum::Bytes(value) => self.insert(value),
+                d => return Err(box_err!("invalid partial distinct value: {:?}", d)),
+            }
+        }
+        Ok(())
+    }
+
+    fn mem_size(&self) -> usize {
+        mem::size_of_val(self) + self.values_size
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn aggregate(tp: ExprType, distinct: bool, rows: &[Vec<Datum>]) -> Box<AggrFunc> {
+        let ctx = EvalContext::default();
+        let mut aggr = build_aggr_func(tp, distinct).unwrap();
+        for row in rows {
+            aggr.update(&ctx, row.clone()).unwrap();
+        }
+        aggr
+    }
+
+    // Returns the results of aggregating all the rows, and of merging the partial states
+    // of the two halves of the rows.
+    fn calc_and_merge(tp: ExprType, distinct: bool, rows: &[Vec<Datum>]) -> Vec<Vec<Datum>> {
+        let ctx = EvalContext::default();
+        let mut res = vec![];
+        aggregate(tp, distinct, rows).calc(&mut res).unwrap();
+
+        let mut merged = build_aggr_func(tp, distinct).unwrap();
+        let (left, right) = rows.split_at(rows.len() / 2);
+        for half in &[left, right] {
+            let mut partial = vec![];
+            aggregate(tp, distinct, half)
+                .partial(&mut partial)
+                .unwrap();
+            merged.merge(&ctx, partial).unwrap();
+        }
+        let mut merged_res = vec![];
+        merged.calc(&mut merged_res).unwrap();
+        vec![res, merged_res]
+    }
+
+    #[test]
+    fn test_group_concat() {
+        let sep = || Datum::Bytes(b", ".to_vec());
+        let rows = |max_len| {
+            vec![
+                vec![Datum::Bytes(b"a".to_vec()), Datum::I64(1), sep(), Datum::U64(max_len)],
+                vec![Datum::Null, Datum::I64(2), sep(), Datum::U64(max_len)],
+                vec![Datum::Bytes(b"b".to_vec()), Datum::I64(3), sep(), Datum::U64(max_len)],
+                vec![Datum::Bytes(b"c".to_vec()), Datum::F64(4.5), sep(), Datum::U64(max_len)],
+            ]
+        };
+        for res in calc_and_merge(ExprType::GroupConcat, false, &rows(1024)) {
+            assert_eq!(res, vec![Datum::Bytes(b"a1, b3, c4.5".to_vec())]);
+        }
+        for res in calc_and_merge(ExprType::GroupConcat, false, &rows(1024)[1..2]) {
+            assert_eq!(res, vec![Datum::Null]);
+        }
+        // the result is truncated to the max length.
+        for res in calc_and_merge(ExprType::GroupConcat, false, &rows(4)) {
+            assert_eq!(res, vec![Datum::Bytes(b"a1, ".to_vec())]);
+        }
+        let ctx = EvalContext::default();
+        let mut aggr = build_aggr_func(ExprType::GroupConcat, false).unwrap();
+        assert!(aggr.update(&ctx, vec![Datum::I64(1), sep()]).is_err());
+        let args = vec![Datum::I64(1), sep(), Datum::I64(0)];
+        assert!(aggr.update(&ctx, args).is_err());
+    }
+
+    #[test]
+    fn test_bit_op() {
+        let rows = vec![
+            vec![Datum::I64(7)],
+            vec![Datum::Null],
+            vec![Datum::U64(6)],
+            vec![Datum::Bytes(b"14".to_vec())],
+            vec![Datum::F64(3.2)],
+        ];
+        let cases = vec![
+            (ExprType::Agg_BitAnd, 2),
+            (ExprType::Agg_BitOr, 15),
+            (ExprType::Agg_BitXor, 12),
+        ];
+        for (tp, expect) in cases {
+            for res in calc_and_merge(tp, false, &rows) {
+                assert_eq!(res, vec![Datum::U64(expect)]);
+            }
+        }
+        for res in calc_and_merge(ExprType::Agg_BitAnd, false, &rows[1..2]) {
+            assert_eq!(res, vec![Datum::U64(u64::MAX)]);
+        }
+    }
+
+    #[test]
+    fn test_variance() {
+        let rows = vec![
+            vec![Datum::I64(1)],
+            vec![Datum::F64(2.0)],
+            vec![Datum::Null],
+            vec![Datum::U64(3)],
+            vec![Datum::Bytes(b"4".to_vec())],
+        ];
+        // the mean is 2.5 and the sum of squared differences is 5.
+        let tps = vec![
+            ExprType::VarPop,
+            ExprType::VarSamp,
+            ExprType::Variance,
+            ExprType::Std,
+            ExprType::Stddev,
+            ExprType::StddevPop,
+            ExprType::StddevSamp,
+        ];
+        for tp in tps {
+            for res in calc_and_merge(tp, false, &rows) {
+                assert_eq!(res, vec![Datum::U64(4), Datum::F64(10.0), Datum::F64(5.0)]);
+            }
+            let single = vec![Datum::U64(1), Datum::F64(1.0), Datum::F64(0.0)];
+            assert_eq!(calc_and_merge(tp, false, &rows[..1])[0], single);
+            let empty = vec![Datum::U64(0), Datum::F64(0.0), Datum::F64(0.0)];
+            assert_eq!(calc_and_merge(tp, false, &rows[2..3])[0], empty);
+
+            let mut partial = vec![];
+            aggregate(tp, false, &rows).partial(&mut partial).unwrap();
+            assert_eq!(partial, vec![Datum::U64(4), Datum::F64(10.0), Datum::F64(5.0)]);
+        }
+    }
+
+    #[test]
+    fn test_distinct() {
+        let rows = vec![
+            vec![Datum::I64(2)],
+            vec![Datum::I64(1)],
+            vec![Datum::Null],
+            vec![Datum::I64(2)],
+            vec![Datum::I64(1)],
+        ];
+        let values: Vec<_> = vec![1, 2]
+            .into_iter()
+            .map(|i| Datum::Bytes(datum::encode_value(&[Datum::I64(i)]).unwrap()))
+            .collect();
+        let expect = vec![Datum::Bytes(datum::encode_value(&values).unwrap())];
+        for tp in &[ExprType::Count, ExprType::Sum] {
+            for res in calc_and_merge(*tp, true, &rows) {
+                assert_eq!(res, expect);
+            }
+        }
+        assert!(build_aggr_func(ExprType::Max, true).is_err());
+
+        // the size of the values is only counted once.
+        let size = aggregate(ExprType::Count, true, &rows[..2]).mem_size();
+        assert_eq!(aggregate(ExprType::Count, true, &rows).mem_size(), size);
+        assert!(size > aggregate(ExprType::Count, true, &[]).mem_size());
+    }
+
+    #[test]
+    fn test_approx_count_distinct() {
+        let rows: Vec<_> = (0..1000).map(|i| vec![Datum::I64(i % 100)]).collect();
+        for res in calc_and_merge(ExprType::ApproxCountDistinct, false, &rows) {
+            let sketch: analyze::FMSketch = match res[0] {
+                Datum::Bytes(ref data) => protobuf::parse_from_bytes(data).unwrap(),
+                ref d => panic!("unexpected result {:?}", d),
+            };
+            assert_eq!(sketch.get_mask(), 0);
+            assert_eq!(sketch.get_hashset().len(), 100);
+        }
+    }
 }
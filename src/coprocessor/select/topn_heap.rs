@@ -26,24 +26,25 @@ use super::xeval::EvalContext;
 
 const HEAP_MAX_CAPACITY: usize = 1024;
 
-pub struct SortRow {
+/// A row to sort, `data` is the payload of the row.
+pub struct SortRow<T = RowColsDict> {
     pub handle: i64,
-    pub data: RowColsDict,
+    pub data: T,
     pub key: Vec<Datum>,
     order_cols: Rc<Vec<ByItem>>,
     ctx: Rc<EvalContext>,
     err: Rc<RefCell<Option<String>>>,
 }
 
-impl SortRow {
+impl<T> SortRow<T> {
     fn new(
         handle: i64,
-        data: RowColsDict,
+        data: T,
         key: Vec<Datum>,
         order_cols: Rc<Vec<ByItem>>,
         ctx: Rc<EvalContext>,
         err: Rc<RefCell<Option<String>>>,
-    ) -> SortRow {
+    ) -> SortRow<T> {
         SortRow {
             handle: handle,
             data: data,
@@ -54,7 +55,7 @@ impl SortRow {
         }
     }
 
-    fn cmp_and_check(&self, right: &SortRow) -> Result<Ordering> {
+    fn cmp_and_check(&self, right: &SortRow<T>) -> Result<Ordering> {
         // check err
         self.check_err()?;
         let values = self.key.iter().zip(right.key.iter());
@@ -91,14 +92,14 @@ impl SortRow {
     }
 }
 
-pub struct TopNHeap {
-    pub rows: BinaryHeap<SortRow>,
+pub struct TopNHeap<T = RowColsDict> {
+    pub rows: BinaryHeap<SortRow<T>>,
     limit: usize,
     err: Rc<RefCell<Option<String>>>,
 }
 
-impl TopNHeap {
-    pub fn new(limit: usize) -> Result<TopNHeap> {
+impl<T> TopNHeap<T> {
+    pub fn new(limit: usize) -> Result<TopNHeap<T>> {
         if limit == usize::MAX {
             return Err(box_err!("invalid limit"));
         }
@@ -121,7 +122,7 @@ impl TopNHeap {
     pub fn try_add_row(
         &mut self,
         handle: i64,
-        data: RowColsDict,
+        data: T,
         values: Vec<Datum>,
         order_cols: Rc<Vec<ByItem>>,
         ctx: Rc<EvalContext>,
@@ -141,7 +142,7 @@ impl TopNHeap {
         self.check_err()
     }
 
-    pub fn into_sorted_vec(self) -> Result<Vec<SortRow>> {
+    pub fn into_sorted_vec(self) -> Result<Vec<SortRow<T>>> {
         let sorted_data = self.rows.into_sorted_vec();
         // check is needed here since err may caused by any call of cmp
         if let Some(ref err_msg) = *self.err.as_ref().borrow() {
@@ -151,8 +152,8 @@ impl TopNHeap {
     }
 }
 
-impl Ord for SortRow {
-    fn cmp(&self, right: &SortRow) -> Ordering {
+impl<T> Ord for SortRow<T> {
+    fn cmp(&self, right: &SortRow<T>) -> Ordering {
         if let Ok(order) = self.cmp_and_check(right) {
             return order;
         }
@@ -160,16 +161,16 @@ impl Ord for SortRow {
     }
 }
 
-impl PartialEq for SortRow {
-    fn eq(&self, right: &SortRow) -> bool {
+impl<T> PartialEq for SortRow<T> {
+    fn eq(&self, right: &SortRow<T>) -> bool {
         self.cmp(right) == Ordering::Equal
     }
 }
 
-impl Eq for SortRow {}
+impl<T> Eq for SortRow<T> {}
 
-impl PartialOrd for SortRow {
-    fn partial_cmp(&self, rhs: &SortRow) -> Option<Ordering> {
+impl<T> PartialOrd for SortRow<T> {
+    fn partial_cmp(&self, rhs: &SortRow<T>) -> Option<Ordering> {
         Some(self.cmp(rhs))
     }
 }
@@ -458,9 +459,9 @@ mod tests {
 
     #[test]
     fn test_topn_limit_oom() {
-        let topn_heap = TopNHeap::new(usize::MAX - 1);
+        let topn_heap: Result<TopNHeap> = TopNHeap::new(usize::MAX - 1);
         assert!(topn_heap.is_ok());
-        let topn_heap = TopNHeap::new(usize::MAX);
+        let topn_heap: Result<TopNHeap> = TopNHeap::new(usize::MAX);
         assert!(topn_heap.is_err());
     }
 }
@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use std::collections::HashSet;
+use std::mem;
 use byteorder::{ByteOrder, LittleEndian};
 use murmur3::murmur3_x64_128;
 use tipb::analyze;
@@ -44,6 +45,12 @@ impl FMSketch {
         self.insert_hash_value(hash);
     }
 
+    /// Returns the approximate memory taken by the sketch.
+    pub fn mem_size(&self) -> usize {
+        // Every slot of the set holds a hash value and its hash.
+        mem::size_of::<FMSketch>() + self.hash_set.capacity() * 2 * mem::size_of::<u64>()
+    }
+
     pub fn into_proto(self) -> analyze::FMSketch {
         let mut proto = analyze::FMSketch::new();
         proto.set_mask(self.mask);
@@ -52,6 +59,18 @@ impl FMSketch {
         proto
     }
 
+    /// Merges another sketch, the result is the same as the sketch of all the elements.
+    pub fn merge(&mut self, other: &analyze::FMSketch) {
+        if other.get_mask() > self.mask {
+            let mask = other.get_mask();
+            self.hash_set.retain(|&x| x & mask == 0);
+            self.mask = mask;
+        }
+        for hash_val in other.get_hashset() {
+            self.insert_hash_value(*hash_val);
+        }
+    }
+
     fn insert_hash_value(&mut self, hash_val: u64) {
         if (hash_val & self.mask) != 0 {
             return;
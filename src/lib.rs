@@ -22,6 +22,7 @@
 #![cfg_attr(not(feature = "dev"), allow(unknown_lints))]
 #![recursion_limit = "100"]
 #![feature(ascii_ctype)]
+#![feature(integer_atomics)]
 #![allow(module_inception)]
 #![allow(should_implement_trait)]
 #![allow(large_enum_variant)]
@@ -86,5 +87,6 @@ pub mod raftstore;
 pub mod pd;
 pub mod server;
 pub mod coprocessor;
+pub mod cdc;
 
 pub use storage::Storage;
@@ -368,4 +368,49 @@ impl PdClient for RpcClient {
             .request(req, executor, LEADER_CHANGE_RETRY)
             .execute()
     }
+
+    fn get_tso(&self) -> PdFuture<u64> {
+        let timer = Instant::now();
+
+        let mut req = pdpb::TsoRequest::new();
+        req.set_header(self.header());
+        req.set_count(1);
+
+        let executor = move |client: &RwLock<Inner>, req: pdpb::TsoRequest| {
+            let (tx, rx) = client.rl().client.tso();
+            Box::new(
+                tx.sink_map_err(Error::Grpc)
+                    .send((req, WriteFlags::default()))
+                    .and_then(move |tx| {
+                        rx.map_err(Error::Grpc)
+                            .into_future()
+                            .map_err(|(e, _)| e)
+                            .and_then(move |(resp, _)| {
+                                // Keeps the stream open until the response is received.
+                                drop(tx);
+                                PD_REQUEST_HISTOGRAM_VEC
+                                    .with_label_values(&["get_tso"])
+                                    .observe(duration_to_sec(timer.elapsed()));
+                                let resp = match resp {
+                                    Some(resp) => resp,
+                                    None => return Err(box_err!("tso stream is closed")),
+                                };
+                                check_resp_header(resp.get_header())?;
+                                let ts = resp.get_timestamp();
+                                Ok(compose_ts(ts.get_physical(), ts.get_logical()))
+                            })
+                    }),
+            ) as PdFuture<_>
+        };
+
+        self.leader_client
+            .request(req, executor, LEADER_CHANGE_RETRY)
+            .execute()
+    }
+}
+
+const PHYSICAL_SHIFT_BITS: u64 = 18;
+
+fn compose_ts(physical: i64, logical: i64) -> u64 {
+    ((physical as u64) << PHYSICAL_SHIFT_BITS) + logical as u64
 }
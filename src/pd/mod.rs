@@ -144,6 +144,9 @@ pub trait PdClient: Send + Sync {
 
     // Report pd the split region.
     fn report_split(&self, left: metapb::Region, right: metapb::Region) -> PdFuture<()>;
+
+    // Get a timestamp from the tso of pd.
+    fn get_tso(&self) -> PdFuture<u64>;
 }
 
 const REQUEST_TIMEOUT: u64 = 2; // 2s
@@ -33,7 +33,7 @@ use pd::{PdClient, RegionStat};
 use raftstore::store::Msg;
 use raftstore::store::util::{get_region_approximate_size, is_epoch_stale};
 use raftstore::store::store::StoreInfo;
-use raftstore::store::Callback;
+use raftstore::store::{Callback, LoadStat};
 use storage::FlowStatistics;
 use util::collections::HashMap;
 use prometheus::local::LocalHistogram;
@@ -71,7 +71,9 @@ pub enum Task {
         peer: metapb::Peer,
     },
     ReadStats { read_stats: HashMap<u64, FlowStatistics>, },
+    ReadLoad { load_stats: HashMap<u64, LoadStat>, },
     DestroyPeer { region_id: u64 },
+    AdvanceResolvedTs,
 }
 
 pub struct StoreStat {
@@ -149,7 +151,11 @@ impl Display for Task {
             Task::ReadStats { ref read_stats } => {
                 write!(f, "get the read statistics {:?}", read_stats)
             }
+            Task::ReadLoad { ref load_stats } => {
+                write!(f, "get the read load of {} regions", load_stats.len())
+            }
             Task::DestroyPeer { ref region_id } => write!(f, "destroy peer {}", region_id),
+            Task::AdvanceResolvedTs => write!(f, "advance resolved ts"),
         }
     }
 }
@@ -452,6 +458,31 @@ impl<T: PdClient> Runner<T> {
         }
     }
 
+    // The load is forwarded to the raftstore, which splits the hot regions.
+    fn handle_read_load(&mut self, load_stats: HashMap<u64, LoadStat>) {
+        if let Err(e) = self.ch.try_send(Msg::RegionsReadLoad {
+            load_stats: load_stats,
+        }) {
+            error!("[store {}] send read load failed {:?}", self.store_id, e);
+        }
+    }
+
+    // The ts is got from PD, so all the transactions prewritten later commit after it.
+    fn handle_advance_resolved_ts(&self, handle: &Handle) {
+        let ch = self.ch.clone();
+        let store_id = self.store_id;
+        let f = self.pd_client.get_tso().then(move |resp| {
+            match resp {
+                Ok(tso) => if let Err(e) = ch.try_send(Msg::AdvanceResolvedTs { tso: tso }) {
+                    error!("[store {}] send tso {} failed {:?}", store_id, tso, e);
+                },
+                Err(e) => error!("[store {}] get tso failed {:?}", store_id, e),
+            }
+            Ok(())
+        });
+        handle.spawn(f);
+    }
+
     fn handle_destory_peer(&mut self, region_id: u64) {
         match self.region_peers.remove(&region_id) {
             None => return,
@@ -529,7 +560,9 @@ impl<T: PdClient> Runnable<Task> for Runner<T> {
             Task::ReportSplit { left, right } => self.handle_report_split(handle, left, right),
             Task::ValidatePeer { region, peer } => self.handle_validate_peer(handle, region, peer),
             Task::ReadStats { read_stats } => self.handle_read_stats(read_stats),
+            Task::ReadLoad { load_stats } => self.handle_read_load(load_stats),
             Task::DestroyPeer { region_id } => self.handle_destory_peer(region_id),
+            Task::AdvanceResolvedTs => self.handle_advance_resolved_ts(handle),
         };
     }
 }
@@ -37,8 +37,8 @@ mod read_only;
 
 pub use self::storage::{RaftState, Storage};
 pub use self::errors::{Error, Result, StorageError};
-pub use self::raft::{quorum, vote_resp_msg_type, Config, Raft, SoftState, StateRole, INVALID_ID,
-                     INVALID_INDEX};
+pub use self::raft::{quorum, vote_resp_msg_type, Config, JointConfig, Raft, SoftState, StateRole,
+                     INVALID_ID, INVALID_INDEX};
 pub use self::raft_log::{RaftLog, NO_LIMIT};
 pub use self::raw_node::{is_empty_snap, Peer, RawNode, Ready, SnapshotStatus};
 pub use self::status::Status;
@@ -71,6 +71,10 @@ pub struct Progress {
     // RecentActive can be reset to false after an election timeout.
     pub recent_active: bool,
 
+    // is_learner is true if the peer is a learner. A learner receives entries and snapshots
+    // from the leader like a follower, but it doesn't vote or count toward the quorum.
+    pub is_learner: bool,
+
     // Inflights is a sliding window for the inflight messages.
     // When inflights is full, no more message should be sent.
     // When a leader sends out a message, the index of the last
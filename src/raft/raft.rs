@@ -29,7 +29,8 @@
 use std::cmp;
 
 use rand::{self, Rng};
-use kvproto::eraftpb::{Entry, EntryType, HardState, Message, MessageType, Snapshot};
+use kvproto::eraftpb::{ConfChange, ConfChangeType, Entry, EntryType, HardState, Message,
+                       MessageType, Snapshot};
 use protobuf::repeated::RepeatedField;
 
 use super::storage::Storage;
@@ -37,7 +38,7 @@ use super::progress::{Inflights, Progress, ProgressState};
 use super::errors::{Error, Result, StorageError};
 use super::raft_log::{self, RaftLog};
 use super::read_only::{ReadOnly, ReadOnlyOption, ReadState};
-use super::FlatMap;
+use super::{FlatMap, HashSet};
 
 // CAMPAIGN_PRE_ELECTION represents the first phase of a normal election when
 // Config.pre_vote is true.
@@ -81,6 +82,14 @@ pub struct Config {
     /// peer is private and only used for testing right now.
     pub peers: Vec<u64>,
 
+    /// learners contains the IDs of all learner nodes (including self if the
+    /// local node is a learner) in the raft cluster. Learners only receive
+    /// entries from the leader node. They don't vote or promote themselves.
+    /// ConfState doesn't record learners, so unlike peers, they should be
+    /// set every time raft starts unless the storage returns them in
+    /// RaftState.learners.
+    pub learners: Vec<u64>,
+
     /// ElectionTick is the number of node.tick invocations that must pass between
     /// elections. That is, if a follower does not receive any message from the
     /// leader of current term before ElectionTick has elapsed, it will become
@@ -159,6 +168,28 @@ impl Config {
     }
 }
 
+/// JointConfig is the configuration C_old,new of joint consensus. While raft is in it,
+/// the voters in progress are the union of both voter sets, and commits, elections and
+/// read index need a quorum of each set.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct JointConfig {
+    pub incoming: HashSet<u64>,
+    pub outgoing: HashSet<u64>,
+}
+
+impl JointConfig {
+    // has_quorum returns true if the ids accepted by `f` contain a quorum of both voter sets.
+    fn has_quorum<F: Fn(u64) -> bool>(&self, f: F) -> bool {
+        for voters in &[&self.incoming, &self.outgoing] {
+            let n = voters.iter().filter(|id| f(**id)).count();
+            if n < quorum(voters.len()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
 // SoftState provides state that is useful for logging and debugging.
 // The state is volatile and does not need to be persisted to the WAL.
 #[derive(Default, PartialEq, Debug)]
@@ -183,6 +214,9 @@ pub struct Raft<T: Storage> {
     pub max_msg_size: u64,
     pub prs: FlatMap<u64, Progress>,
 
+    /// The joint configuration when raft is in joint consensus.
+    pub joint: Option<JointConfig>,
+
     pub state: StateRole,
 
     pub votes: FlatMap<u64, bool>,
@@ -288,6 +322,7 @@ impl<T: Storage> Raft<T> {
             max_inflight: c.max_inflight_msgs,
             max_msg_size: c.max_size_per_msg,
             prs: FlatMap::with_capacity(peers.len()),
+            joint: None,
             state: StateRole::Follower,
             check_quorum: c.check_quorum,
             pre_vote: c.pre_vote,
@@ -311,6 +346,37 @@ impl<T: Storage> Raft<T> {
         for p in peers {
             r.prs.insert(*p, new_progress(1, r.max_inflight));
         }
+        let mut learners: &[u64] = &c.learners;
+        if !rs.learners.is_empty() {
+            if !learners.is_empty() {
+                panic!(
+                    "{} cannot specify both new(learners) and RaftState.learners",
+                    c.tag
+                )
+            }
+            learners = &rs.learners;
+        }
+        for l in learners {
+            if r.prs.contains_key(l) {
+                panic!("{} node {} is in both learners and peers", c.tag, l);
+            }
+            let mut pr = new_progress(1, r.max_inflight);
+            pr.is_learner = true;
+            r.prs.insert(*l, pr);
+        }
+        if let Some(joint) = rs.joint {
+            for id in joint.incoming.union(&joint.outgoing) {
+                if r.prs.get(id).map_or(true, |pr| pr.is_learner) {
+                    panic!(
+                        "{} joint voter {} is not in ConfState.Nodes {:?}",
+                        c.tag,
+                        id,
+                        peers
+                    );
+                }
+            }
+            r.joint = Some(joint);
+        }
         if rs.hard_state != HardState::new() {
             r.load_state(rs.hard_state);
         }
@@ -320,10 +386,11 @@ impl<T: Storage> Raft<T> {
         let term = r.term;
         r.become_follower(term, INVALID_ID);
         info!(
-            "{} newRaft [peers: {:?}, term: {:?}, commit: {}, applied: {}, last_index: {}, \
-             last_term: {}]",
+            "{} newRaft [peers: {:?}, learners: {:?}, term: {:?}, commit: {}, applied: {}, \
+             last_index: {}, last_term: {}]",
             r.tag,
             r.nodes(),
+            r.learner_nodes(),
             r.term,
             r.raft_log.committed,
             r.raft_log.get_applied(),
@@ -378,7 +445,7 @@ impl<T: Storage> Raft<T> {
     }
 
     fn quorum(&self) -> usize {
-        quorum(self.prs.len())
+        quorum(self.prs.values().filter(|pr| !pr.is_learner).count())
     }
 
     // for testing leader lease
@@ -398,13 +465,29 @@ impl<T: Storage> Raft<T> {
         self.randomized_election_timeout
     }
 
+    // nodes returns the voters, learners are not included.
     pub fn nodes(&self) -> Vec<u64> {
         let mut nodes = Vec::with_capacity(self.prs.len());
-        nodes.extend(self.prs.keys());
+        nodes.extend(
+            self.prs
+                .iter()
+                .filter(|&(_, pr)| !pr.is_learner)
+                .map(|(id, _)| id),
+        );
         nodes.sort();
         nodes
     }
 
+    pub fn learner_nodes(&self) -> Vec<u64> {
+        let mut learners: Vec<_> = self.prs
+            .iter()
+            .filter(|&(_, pr)| pr.is_learner)
+            .map(|(id, _)| *id)
+            .collect();
+        learners.sort();
+        learners
+    }
+
     // send persists state to stable storage and then sends to its mailbox.
     fn send(&mut self, mut m: Message) {
         m.set_from(self.id);
@@ -599,15 +682,28 @@ impl<T: Storage> Raft<T> {
     // the commit index changed (in which case the caller should call
     // r.bcast_append).
     pub fn maybe_commit(&mut self) -> bool {
+        // An index is committed in the joint configuration when it's committed by both
+        // voter sets.
+        let joint_mci = self.joint.as_ref().map(|joint| {
+            cmp::min(
+                self.quorum_matched(&joint.incoming),
+                self.quorum_matched(&joint.outgoing),
+            )
+        });
+        if let Some(mci) = joint_mci {
+            return self.raft_log.maybe_commit(mci, self.term);
+        }
+        // Learners don't count toward the quorum.
+        let voters = self.prs.values().filter(|pr| !pr.is_learner).count();
         let mut mis_arr = [0; 5];
         let mut mis_vec;
-        let mis = if self.prs.len() <= 5 {
-            &mut mis_arr[..self.prs.len()]
+        let mis = if voters <= 5 {
+            &mut mis_arr[..voters]
         } else {
-            mis_vec = vec![0; self.prs.len()];
+            mis_vec = vec![0; voters];
             mis_vec.as_mut_slice()
         };
-        for (i, pr) in self.prs.values().enumerate() {
+        for (i, pr) in self.prs.values().filter(|pr| !pr.is_learner).enumerate() {
             mis[i] = pr.matched;
         }
         // reverse sort
@@ -616,6 +712,20 @@ impl<T: Storage> Raft<T> {
         self.raft_log.maybe_commit(mci, self.term)
     }
 
+    // quorum_matched returns the largest index matched by a quorum of `voters`.
+    fn quorum_matched(&self, voters: &HashSet<u64>) -> u64 {
+        let mut mis: Vec<_> = voters
+            .iter()
+            .map(|id| self.prs.get(id).map_or(0, |pr| pr.matched))
+            .collect();
+        if mis.is_empty() {
+            return 0;
+        }
+        // reverse sort
+        mis.sort_by(|a, b| b.cmp(a));
+        mis[quorum(mis.len()) - 1]
+    }
+
     pub fn reset(&mut self, term: u64) {
         if self.term != term {
             self.term = term;
@@ -632,7 +742,9 @@ impl<T: Storage> Raft<T> {
         let (last_index, max_inflight) = (self.raft_log.last_index(), self.max_inflight);
         let self_id = self.id;
         for (id, p) in &mut self.prs {
+            let is_learner = p.is_learner;
             *p = new_progress(last_index + 1, max_inflight);
+            p.is_learner = is_learner;
             if id == &self_id {
                 p.matched = last_index;
             }
@@ -791,7 +903,8 @@ impl<T: Storage> Raft<T> {
             (MessageType::MsgRequestVote, self.term)
         };
         let id = self.id;
-        if self.quorum() == self.poll(id, vote_resp_msg_type(vote_msg), true) {
+        let gr = self.poll(id, vote_resp_msg_type(vote_msg), true);
+        if self.vote_result(gr) == Some(true) {
             // We won the election after voting for ourselves (which must mean that
             // this is a single-node cluster). Advance to the next state.
             if campaign_type == CAMPAIGN_PRE_ELECTION {
@@ -801,7 +914,12 @@ impl<T: Storage> Raft<T> {
             }
             return;
         }
-        let ids: Vec<_> = self.prs.keys().cloned().collect();
+        // Learners don't vote.
+        let ids: Vec<_> = self.prs
+            .iter()
+            .filter(|&(_, pr)| !pr.is_learner)
+            .map(|(id, _)| *id)
+            .collect();
         for id in ids {
             if id == self.id {
                 continue;
@@ -848,6 +966,31 @@ impl<T: Storage> Raft<T> {
         self.votes.values().filter(|x| **x).count()
     }
 
+    // vote_result returns Some(true) if the election is won, Some(false) if it's lost,
+    // and None if it's still pending. `granted` is the number of granted votes.
+    fn vote_result(&self, granted: usize) -> Option<bool> {
+        let joint = match self.joint {
+            Some(ref joint) => joint,
+            None => {
+                if self.quorum() == granted {
+                    return Some(true);
+                } else if self.quorum() == self.votes.len() - granted {
+                    return Some(false);
+                }
+                return None;
+            }
+        };
+        let votes = &self.votes;
+        if joint.has_quorum(|id| votes.get(&id) == Some(&true)) {
+            Some(true)
+        } else if !joint.has_quorum(|id| votes.get(&id) != Some(&false)) {
+            // Even if all the pending voters grant, the election can't be won.
+            Some(false)
+        } else {
+            None
+        }
+    }
+
     pub fn step(&mut self, m: Message) -> Result<()> {
         // Handle the message term, which may result in our stepping down to a follower.
 
@@ -957,6 +1100,14 @@ impl<T: Storage> Raft<T> {
 
         match m.get_msg_type() {
             MessageType::MsgHup => if self.state != StateRole::Leader {
+                if !self.promotable() {
+                    warn!(
+                        "{} is not promotable and can not campaign at term {}",
+                        self.tag,
+                        self.term
+                    );
+                    return Ok(());
+                }
                 let ents = self.raft_log
                     .slice(
                         self.raft_log.applied + 1,
@@ -1160,6 +1311,14 @@ impl<T: Storage> Raft<T> {
             );
             return;
         }
+        if self.prs[&lead_transferee].is_learner {
+            debug!(
+                "{} ignored transferring leadership to learner {}",
+                self.tag,
+                lead_transferee
+            );
+            return;
+        }
         // Transfer leadership to third party.
         info!(
             "{} [term {}] starts to transfer leadership to {}",
@@ -1246,8 +1405,24 @@ impl<T: Storage> Raft<T> {
                     return;
                 }
 
+                // Only the acks of voters make a quorum.
+                if self.prs[&m.get_from()].is_learner {
+                    return;
+                }
                 let ack_count = self.read_only.recv_ack(m);
-                if ack_count < self.quorum() {
+                let has_quorum = match self.joint {
+                    None => ack_count >= self.quorum(),
+                    Some(ref joint) => {
+                        let self_id = self.id;
+                        self.read_only
+                            .pending_read_index
+                            .get(m.get_context())
+                            .map_or(false, |rs| {
+                                joint.has_quorum(|id| id == self_id || rs.acks.contains(&id))
+                            })
+                    }
+                };
+                if !has_quorum {
                     return;
                 }
 
@@ -1362,7 +1537,7 @@ impl<T: Storage> Raft<T> {
                     return;
                 }
 
-                if self.quorum() > 1 {
+                if self.joint.is_some() || self.quorum() > 1 {
                     // thinking: use an interally defined context instead of the user given context.
                     // We can express this in terms of the term and index instead of
                     // a user-supplied value.
@@ -1480,15 +1655,15 @@ impl<T: Storage> Raft<T> {
                     m.get_msg_type(),
                     self.votes.len() - gr
                 );
-                if self.quorum() == gr {
-                    if self.state == StateRole::PreCandidate {
+                match self.vote_result(gr) {
+                    Some(true) => if self.state == StateRole::PreCandidate {
                         self.campaign(CAMPAIGN_ELECTION);
                     } else {
                         self.become_leader();
                         self.bcast_append();
-                    }
-                } else if self.quorum() == self.votes.len() - gr {
-                    self.become_follower(term, INVALID_ID);
+                    },
+                    Some(false) => self.become_follower(term, INVALID_ID),
+                    None => {}
                 }
             }
             MessageType::MsgTimeoutNow => debug!(
@@ -1709,6 +1884,24 @@ impl<T: Storage> Raft<T> {
             meta.get_index(),
             meta.get_term()
         );
+        // ConfState doesn't record learners, so the known learners are kept.
+        let learners: Vec<_> = self.prs
+            .iter()
+            .filter(|&(id, pr)| pr.is_learner && !meta.get_conf_state().get_nodes().contains(id))
+            .map(|(id, _)| *id)
+            .collect();
+        // ConfState doesn't record the joint configuration either, so it's kept only if the
+        // snapshot has the same voters.
+        let keep_joint = self.joint.as_ref().map_or(false, |joint| {
+            let nodes = meta.get_conf_state().get_nodes();
+            nodes.len() == joint.incoming.union(&joint.outgoing).count() &&
+                nodes
+                    .iter()
+                    .all(|id| joint.incoming.contains(id) || joint.outgoing.contains(id))
+        });
+        if !keep_joint {
+            self.joint = None;
+        }
         self.prs = FlatMap::with_capacity(meta.get_conf_state().get_nodes().len());
         for &n in meta.get_conf_state().get_nodes() {
             let next_idx = self.raft_log.last_index() + 1;
@@ -1721,6 +1914,12 @@ impl<T: Storage> Raft<T> {
                 self.prs[&n]
             );
         }
+        for id in learners {
+            let next_idx = self.raft_log.last_index() + 1;
+            let matched = if id == self.id { next_idx - 1 } else { 0 };
+            self.set_progress(id, matched, next_idx);
+            self.prs.get_mut(&id).unwrap().is_learner = true;
+        }
         None
     }
 
@@ -1742,14 +1941,23 @@ impl<T: Storage> Raft<T> {
     }
 
     // promotable indicates whether state machine can be promoted to leader,
-    // which is true when its own id is in progress list.
+    // which is true when its own id is in progress list and it's not a learner.
     pub fn promotable(&self) -> bool {
-        self.prs.contains_key(&self.id)
+        self.prs.get(&self.id).map_or(false, |pr| !pr.is_learner)
+    }
+
+    pub fn is_learner(&self) -> bool {
+        self.prs.get(&self.id).map_or(false, |pr| pr.is_learner)
     }
 
+    // add_node adds a voter, or promotes the node to a voter if it's a learner.
     pub fn add_node(&mut self, id: u64) {
         self.pending_conf = false;
-        if self.prs.contains_key(&id) {
+        if let Some(pr) = self.prs.get_mut(&id) {
+            if pr.is_learner {
+                info!("{} promotes learner {} to voter", self.tag, id);
+                pr.is_learner = false;
+            }
             // Ignore any redundant addNode calls (which can happen because the
             // initial bootstrapping entries are applied twice).
             return;
@@ -1758,6 +1966,21 @@ impl<T: Storage> Raft<T> {
         self.set_progress(id, 0, last_index + 1);
     }
 
+    // add_learner adds a learner, which is ignored if the node is already a voter, as a voter
+    // can't be demoted.
+    pub fn add_learner(&mut self, id: u64) {
+        self.pending_conf = false;
+        if let Some(pr) = self.prs.get(&id) {
+            if !pr.is_learner {
+                warn!("{} ignores adding voter {} as a learner", self.tag, id);
+            }
+            return;
+        }
+        let last_index = self.raft_log.last_index();
+        self.set_progress(id, 0, last_index + 1);
+        self.prs.get_mut(&id).unwrap().is_learner = true;
+    }
+
     pub fn remove_node(&mut self, id: u64) {
         self.del_progress(id);
         self.pending_conf = false;
@@ -1778,6 +2001,103 @@ impl<T: Storage> Raft<T> {
         }
     }
 
+    // enter_joint applies the voter changes in `ccs` atomically by entering the joint
+    // configuration of the current voters and the voters after the changes. AddNode
+    // promotes a learner, and RemoveNode on a learner removes it at once. The joint
+    // configuration should be left by leave_joint after it's committed, until then the
+    // application should persist it so it's restored by RaftState.joint on restart.
+    pub fn enter_joint(&mut self, ccs: &[ConfChange]) {
+        self.pending_conf = false;
+        if self.joint.is_some() {
+            warn!("{} ignores entering joint configuration again", self.tag);
+            return;
+        }
+        let outgoing: HashSet<u64> = self.prs
+            .iter()
+            .filter(|&(_, pr)| !pr.is_learner)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut incoming = outgoing.clone();
+        for cc in ccs {
+            let id = cc.get_node_id();
+            match cc.get_change_type() {
+                ConfChangeType::AddNode => {
+                    incoming.insert(id);
+                }
+                ConfChangeType::RemoveNode => {
+                    incoming.remove(&id);
+                    if self.prs.get(&id).map_or(false, |pr| pr.is_learner) {
+                        self.del_progress(id);
+                    }
+                }
+            }
+        }
+        let last_index = self.raft_log.last_index();
+        for id in &incoming {
+            if let Some(pr) = self.prs.get_mut(id) {
+                pr.is_learner = false;
+                continue;
+            }
+            self.set_progress(*id, 0, last_index + 1);
+        }
+        info!(
+            "{} enters joint configuration [incoming: {:?}, outgoing: {:?}]",
+            self.tag,
+            incoming,
+            outgoing
+        );
+        self.joint = Some(JointConfig {
+            incoming: incoming,
+            outgoing: outgoing,
+        });
+    }
+
+    // leave_joint leaves the joint configuration, the voters that are not in the incoming
+    // voter set are removed.
+    pub fn leave_joint(&mut self) {
+        self.pending_conf = false;
+        let joint = match self.joint.take() {
+            Some(joint) => joint,
+            None => {
+                warn!("{} ignores leaving joint configuration", self.tag);
+                return;
+            }
+        };
+        let removed: Vec<_> = self.prs
+            .iter()
+            .filter(|&(id, pr)| !pr.is_learner && !joint.incoming.contains(id))
+            .map(|(id, _)| *id)
+            .collect();
+        info!(
+            "{} leaves joint configuration, removes {:?}",
+            self.tag,
+            removed
+        );
+        for id in &removed {
+            self.del_progress(*id);
+        }
+
+        // The leader steps down if it's removed, so a new leader can be elected by the
+        // remaining voters.
+        if self.state == StateRole::Leader && removed.contains(&self.id) {
+            let term = self.term;
+            self.become_follower(term, INVALID_ID);
+            return;
+        }
+        if self.prs.is_empty() {
+            return;
+        }
+        if self.maybe_commit() {
+            self.bcast_append();
+        }
+        if self.state == StateRole::Leader &&
+            self.lead_transferee
+                .map_or(false, |id| removed.contains(&id))
+        {
+            self.abort_leader_transfer()
+        }
+    }
+
     pub fn reset_pending_conf(&mut self) {
         self.pending_conf = false;
     }
@@ -1837,21 +2157,32 @@ impl<T: Storage> Raft<T> {
     // check_quorum_active also resets all recent_active to false.
     fn check_quorum_active(&mut self) -> bool {
         let mut act = 0;
+        let mut active = HashSet::default();
         let self_id = self.id;
         for (id, p) in &mut self.prs {
             if id == &self_id {
                 // self is always active
                 act += 1;
+                active.insert(*id);
+                continue;
+            }
+
+            if p.is_learner {
+                p.recent_active = false;
                 continue;
             }
 
             if p.recent_active {
                 act += 1;
+                active.insert(*id);
             }
 
             p.recent_active = false;
         }
-        act >= self.quorum()
+        match self.joint {
+            Some(ref joint) => joint.has_quorum(|id| active.contains(&id)),
+            None => act >= self.quorum(),
+        }
     }
 
     pub fn send_timeout_now(&mut self, to: u64) {
@@ -300,6 +300,45 @@ impl<T: Storage> RawNode<T> {
         cs
     }
 
+    // apply_learner_conf_change applies an AddNode conf change that adds a learner.
+    // ConfChangeType has no type for learners, so the application tells which conf
+    // changes add learners. A learner is promoted by an AddNode conf change applied by
+    // apply_conf_change.
+    pub fn apply_learner_conf_change(&mut self, cc: &ConfChange) -> ConfState {
+        if cc.get_node_id() != INVALID_ID {
+            assert_eq!(
+                cc.get_change_type(),
+                ConfChangeType::AddNode,
+                "unexpected learner conf type"
+            );
+            self.raft.add_learner(cc.get_node_id());
+        } else {
+            self.raft.reset_pending_conf();
+        }
+        let mut cs = ConfState::new();
+        cs.set_nodes(self.raft.nodes());
+        cs
+    }
+
+    // apply_joint_conf_change applies a set of AddNode and RemoveNode conf changes
+    // atomically by entering the joint configuration. Like learner conf changes, the
+    // application tells which conf change entries carry joint conf changes. It should
+    // be followed by leave_joint once the entry is committed.
+    pub fn apply_joint_conf_change(&mut self, ccs: &[ConfChange]) -> ConfState {
+        self.raft.enter_joint(ccs);
+        let mut cs = ConfState::new();
+        cs.set_nodes(self.raft.nodes());
+        cs
+    }
+
+    // leave_joint applies the conf change that leaves the joint configuration.
+    pub fn leave_joint(&mut self) -> ConfState {
+        self.raft.leave_joint();
+        let mut cs = ConfState::new();
+        cs.set_nodes(self.raft.nodes());
+        cs
+    }
+
     // Step advances the state machine using the given message.
     pub fn step(&mut self, m: Message) -> Result<()> {
         // ignore unexpected local messages receiving over network
@@ -30,12 +30,19 @@ use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use kvproto::eraftpb::{ConfState, Entry, HardState, Snapshot};
 use raft::errors::{Error, Result, StorageError};
+use raft::JointConfig;
 use util::{self, HandyRwLock};
 
 #[derive(Debug, Clone)]
 pub struct RaftState {
     pub hard_state: HardState,
     pub conf_state: ConfState,
+    /// ConfState doesn't record learners, so the storage that persists them returns
+    /// them here. They should not be in `conf_state.nodes`.
+    pub learners: Vec<u64>,
+    /// The joint configuration raft was in, the voters of both sets should be in
+    /// `conf_state.nodes`.
+    pub joint: Option<JointConfig>,
 }
 
 /// Storage is an trait that may be implemented by the application
@@ -72,6 +79,8 @@ pub trait Storage {
 
 pub struct MemStorageCore {
     hard_state: HardState,
+    learners: Vec<u64>,
+    joint: Option<JointConfig>,
     snapshot: Snapshot,
     // TODO: maybe vec_deque
     // entries[i] has raft log position i+snapshot.get_metadata().get_index()
@@ -84,6 +93,8 @@ impl Default for MemStorageCore {
             // When starting from scratch populate the list with a dummy entry at term zero.
             entries: vec![Entry::new()],
             hard_state: HardState::new(),
+            learners: vec![],
+            joint: None,
             snapshot: Snapshot::new(),
         }
     }
@@ -95,6 +106,16 @@ impl MemStorageCore {
         self.hard_state = hs;
     }
 
+    /// set_learners saves the current learners.
+    pub fn set_learners(&mut self, learners: Vec<u64>) {
+        self.learners = learners;
+    }
+
+    /// set_joint saves the current joint configuration.
+    pub fn set_joint(&mut self, joint: Option<JointConfig>) {
+        self.joint = joint;
+    }
+
     fn inner_last_index(&self) -> u64 {
         self.entries[0].get_index() + self.entries.len() as u64 - 1
     }
@@ -244,6 +265,8 @@ impl Storage for MemStorage {
         Ok(RaftState {
             hard_state: core.hard_state.clone(),
             conf_state: core.snapshot.get_metadata().get_conf_state().clone(),
+            learners: core.learners.clone(),
+            joint: core.joint.clone(),
         })
     }
 
@@ -18,22 +18,35 @@ use super::Result;
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
+    /// When it is true, a region containing keys of more than one table
+    /// will be split at the table prefix, so a region never holds two tables.
+    pub split_region_on_table: bool,
     /// When region [a, b) size meets region_max_size, it will be split
     /// into two region into [a, c), [c, b). And the size of [a, c) will
     /// be region_split_size (or a little bit smaller).
     pub region_max_size: ReadableSize,
     pub region_split_size: ReadableSize,
+    /// When the number of keys in region [a, b) meets region_max_keys,
+    /// it will be split into [a, c), [c, b) in the same way, and [a, c)
+    /// will hold region_split_keys keys (or a little bit fewer).
+    pub region_max_keys: u64,
+    pub region_split_keys: u64,
 }
 
 /// Default region split size.
 pub const SPLIT_SIZE_MB: u64 = 96;
+/// Default region split keys.
+pub const SPLIT_KEYS: u64 = 960000;
 
 impl Default for Config {
     fn default() -> Config {
         let split_size = ReadableSize::mb(SPLIT_SIZE_MB);
         Config {
+            split_region_on_table: true,
             region_split_size: split_size,
             region_max_size: split_size / 2 * 3,
+            region_split_keys: SPLIT_KEYS,
+            region_max_keys: SPLIT_KEYS / 2 * 3,
         }
     }
 }
@@ -47,6 +60,13 @@ impl Config {
                 self.region_split_size.0
             ));
         }
+        if self.region_max_keys < self.region_split_keys {
+            return Err(box_err!(
+                "region max keys {} must >= split keys {}",
+                self.region_max_keys,
+                self.region_split_keys
+            ));
+        }
 
         Ok(())
     }
@@ -65,5 +85,10 @@ mod tests {
         cfg.region_max_size = ReadableSize(10);
         cfg.region_split_size = ReadableSize(20);
         assert!(cfg.validate().is_err());
+
+        cfg = Config::default();
+        cfg.region_max_keys = 10;
+        cfg.region_split_keys = 20;
+        assert!(cfg.validate().is_err());
     }
 }
@@ -13,8 +13,9 @@
 
 use rocksdb::DB;
 
-use kvproto::raft_cmdpb::RaftCmdRequest;
+use kvproto::raft_cmdpb::{AdminRequest, AdminResponse, RaftCmdRequest};
 use kvproto::metapb::Region;
+use raft::StateRole;
 
 use util::transport::{RetryableSendCh, Sender};
 use raftstore::store::msg::Msg;
@@ -30,12 +31,15 @@ struct ObserverEntry {
 #[derive(Default)]
 pub struct Registry {
     observers: Vec<ObserverEntry>, // TODO: add endpoint
+    // Whether any observer needs the applied commands.
+    observes_applied_cmds: bool,
 }
 
 impl Registry {
     /// register an Observer to dispatcher.
     pub fn register_observer(&mut self, priority: u32, ro: Box<RegionObserver + Send + Sync>) {
         ro.start();
+        self.observes_applied_cmds |= ro.observes_applied_cmds();
         let r = ObserverEntry {
             priority: priority,
             observer: ro,
@@ -63,6 +67,15 @@ impl CoprocessorHost {
             SIZE_CHECK_OBSERVER_PRIORITY,
             Box::new(split_size_check_observer),
         );
+        let split_keys_check_observer =
+            KeysCheckObserver::new(cfg.region_max_keys, cfg.region_split_keys);
+        registry.register_observer(
+            KEYS_CHECK_OBSERVER_PRIORITY,
+            Box::new(split_keys_check_observer),
+        );
+        if cfg.split_region_on_table {
+            registry.register_observer(TABLE_CHECK_OBSERVER_PRIORITY, Box::new(TableCheckObserver));
+        }
         CoprocessorHost { registry: registry }
     }
 
@@ -111,6 +124,66 @@ impl CoprocessorHost {
         }
     }
 
+    /// Call the hook on all the observers in priority order, `bypass` is ignored.
+    fn notify<H>(&self, region: &Region, mut hook: H)
+    where
+        H: FnMut(&RegionObserver, &mut ObserverContext),
+    {
+        let mut ctx = ObserverContext::new(region);
+        for entry in &self.registry.observers {
+            hook(entry.observer.as_ref(), &mut ctx);
+        }
+    }
+
+    /// Returns whether any observer needs the applied commands, `post_apply` and
+    /// `post_apply_admin` don't need to be called otherwise.
+    pub fn observes_applied_cmds(&self) -> bool {
+        self.registry.observes_applied_cmds
+    }
+
+    /// Notifies all the observers that the read/write request at log `index` is applied
+    /// successfully.
+    pub fn post_apply(&self, region: &Region, index: u64, req: &RaftCmdRequest) {
+        if req.has_admin_request() {
+            return;
+        }
+        self.notify(region, |o, ctx| {
+            o.post_apply_query(ctx, index, req.get_requests())
+        });
+    }
+
+    /// Notifies all the observers that the admin request at log `index` is applied
+    /// successfully.
+    pub fn post_apply_admin(
+        &self,
+        region: &Region,
+        index: u64,
+        req: &AdminRequest,
+        resp: &AdminResponse,
+    ) {
+        self.notify(region, |o, ctx| o.post_apply_admin(ctx, index, req, resp));
+    }
+
+    /// Notifies all the observers that the role of the local peer has changed.
+    pub fn on_role_change(&self, region: &Region, role: StateRole) {
+        self.notify(region, |o, ctx| o.on_role_change(ctx, role));
+    }
+
+    /// Notifies all the observers that the region has changed.
+    pub fn on_region_changed(&self, region: &Region, event: RegionChangeEvent) {
+        self.notify(region, |o, ctx| o.on_region_changed(ctx, event));
+    }
+
+    /// Notifies all the observers that the snapshot at log `index` is applied.
+    pub fn on_apply_snapshot(&self, region: &Region, index: u64) {
+        self.notify(region, |o, ctx| o.on_apply_snapshot(ctx, index));
+    }
+
+    /// Notifies all the observers that the leader advances the resolved ts of the region.
+    pub fn on_advance_resolved_ts(&self, region: &Region, ts: u64) {
+        self.notify(region, |o, ctx| o.on_advance_resolved_ts(ctx, ts));
+    }
+
     pub fn new_split_check_status(&self, region: &Region, engine: &DB) -> SplitCheckStatus {
         let mut ob_ctx = ObserverContext::new(region);
         let mut split_status = SplitCheckStatus::default();
@@ -157,7 +230,8 @@ mod test {
     use protobuf::RepeatedField;
 
     use kvproto::metapb::Region;
-    use kvproto::raft_cmdpb::{AdminRequest, RaftCmdRequest, Request};
+    use kvproto::raft_cmdpb::{AdminRequest, AdminResponse, RaftCmdRequest, Request};
+    use raft::StateRole;
 
     struct TestCoprocessor {
         bypass: Arc<AtomicBool>,
@@ -209,6 +283,40 @@ mod test {
             self.called.fetch_add(3, Ordering::SeqCst);
             ctx.bypass = self.bypass.load(Ordering::SeqCst);
         }
+
+        fn on_role_change(&self, _: &mut ObserverContext, _: StateRole) {
+            self.called.fetch_add(4, Ordering::SeqCst);
+        }
+
+        fn observes_applied_cmds(&self) -> bool {
+            true
+        }
+
+        fn post_apply_query(&self, _: &mut ObserverContext, _: u64, _: &[Request]) {
+            self.called.fetch_add(5, Ordering::SeqCst);
+        }
+
+        fn post_apply_admin(
+            &self,
+            _: &mut ObserverContext,
+            _: u64,
+            _: &AdminRequest,
+            _: &AdminResponse,
+        ) {
+            self.called.fetch_add(6, Ordering::SeqCst);
+        }
+
+        fn on_region_changed(&self, _: &mut ObserverContext, _: RegionChangeEvent) {
+            self.called.fetch_add(7, Ordering::SeqCst);
+        }
+
+        fn on_apply_snapshot(&self, _: &mut ObserverContext, _: u64) {
+            self.called.fetch_add(8, Ordering::SeqCst);
+        }
+
+        fn on_advance_resolved_ts(&self, _: &mut ObserverContext, _: u64) {
+            self.called.fetch_add(9, Ordering::SeqCst);
+        }
     }
 
     fn share_bool() -> Arc<AtomicBool> {
@@ -240,7 +348,9 @@ mod test {
         let (bypass1, called1, r1) = (share_bool(), share_usize(), share_bool());
         let observer1 = TestCoprocessor::new(bypass1.clone(), called1.clone(), r1.clone());
         let mut host = CoprocessorHost::default();
+        assert!(!host.observes_applied_cmds());
         host.registry.register_observer(3, Box::new(observer1));
+        assert!(host.observes_applied_cmds());
         let region = Region::new();
         let mut admin_req = RaftCmdRequest::new();
         admin_req.set_admin_request(AdminRequest::new());
@@ -290,5 +400,34 @@ mod test {
         set_all!(&[&r2], true);
         assert!(host.pre_propose(&region, &mut admin_req).is_err());
         assert_all!(&[&called1, &called2], &[0, 1]);
+
+        // role change is delivered to all the observers.
+        set_all!(&[&called1, &called2], 0);
+        set_all!(&[&bypass2], true);
+        host.on_role_change(&region, StateRole::Leader);
+        assert_all!(&[&called1, &called2], &[4, 4]);
+
+        // applied requests are delivered to all the observers, except admin requests.
+        set_all!(&[&called1, &called2], 0);
+        host.post_apply(&region, 1, &admin_req);
+        assert_all!(&[&called1, &called2], &[0, 0]);
+        host.post_apply(&region, 2, &query_req);
+        assert_all!(&[&called1, &called2], &[5, 5]);
+
+        set_all!(&[&called1, &called2], 0);
+        host.post_apply_admin(&region, 3, &AdminRequest::new(), &AdminResponse::new());
+        assert_all!(&[&called1, &called2], &[6, 6]);
+
+        set_all!(&[&called1, &called2], 0);
+        host.on_region_changed(&region, RegionChangeEvent::Split);
+        assert_all!(&[&called1, &called2], &[7, 7]);
+
+        set_all!(&[&called1, &called2], 0);
+        host.on_apply_snapshot(&region, 4);
+        assert_all!(&[&called1, &called2], &[8, 8]);
+
+        set_all!(&[&called1, &called2], 0);
+        host.on_advance_resolved_ts(&region, 5);
+        assert_all!(&[&called1, &called2], &[9, 9]);
     }
 }
@@ -12,9 +12,10 @@
 // limitations under the License.
 
 use rocksdb::DB;
-use kvproto::raft_cmdpb::{AdminRequest, Request};
+use kvproto::raft_cmdpb::{AdminRequest, AdminResponse, Request};
 use kvproto::metapb::Region;
 use protobuf::RepeatedField;
+use raft::StateRole;
 
 pub mod dispatcher;
 pub mod split_observer;
@@ -28,8 +29,9 @@ pub use self::config::Config;
 pub use self::region_snapshot::{RegionIterator, RegionSnapshot};
 pub use self::dispatcher::{CoprocessorHost, Registry};
 pub use self::error::{Error, Result};
-pub use self::split_check::{SizeCheckObserver, Status as SplitCheckStatus,
-                            SIZE_CHECK_OBSERVER_PRIORITY};
+pub use self::split_check::{KeysCheckObserver, SizeCheckObserver, Status as SplitCheckStatus,
+                            TableCheckObserver, KEYS_CHECK_OBSERVER_PRIORITY,
+                            SIZE_CHECK_OBSERVER_PRIORITY, TABLE_CHECK_OBSERVER_PRIORITY};
 
 /// Coprocessor is used to provide a convient way to inject code to
 /// KV processing.
@@ -58,7 +60,22 @@ impl<'a> ObserverContext<'a> {
     }
 }
 
+/// The kind of change that happened to a region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionChangeEvent {
+    /// The region is split, it's notified for all the regions after split.
+    Split,
+    /// The peers of the region are changed.
+    ChangePeer,
+    /// The local peer of the region is destroyed.
+    Destroy,
+}
+
 /// Observer hook of region level.
+///
+/// The `post_*` and `on_*` hooks are notifications, they are delivered to all the
+/// observers in priority order regardless of `bypass`. They are called on the apply
+/// path, so they should hand over the work to other threads instead of blocking.
 pub trait RegionObserver: Coprocessor {
     /// Hook to call before execute admin request.
     fn pre_admin(&self, _: &mut ObserverContext, _: &mut AdminRequest) -> Result<()> {
@@ -75,6 +92,44 @@ pub trait RegionObserver: Coprocessor {
     /// Please note that improper implementation can lead to data inconsistency.
     fn pre_apply_query(&self, _: &mut ObserverContext, _: &mut RepeatedField<Request>) {}
 
+    /// Whether the observer needs the applied commands. The apply worker only keeps the
+    /// commands for `post_apply_query` and `post_apply_admin` if an observer needs them.
+    fn observes_applied_cmds(&self) -> bool {
+        false
+    }
+
+    /// Hook to call after read/write request at log `index` is applied successfully.
+    ///
+    /// It's called after the written data is flushed to the engine.
+    fn post_apply_query(&self, _: &mut ObserverContext, _: u64, _: &[Request]) {}
+
+    /// Hook to call after admin request at log `index` is applied successfully.
+    ///
+    /// It's called after the written data is flushed to the engine, the region in
+    /// context is the one after the request is applied.
+    fn post_apply_admin(
+        &self,
+        _: &mut ObserverContext,
+        _: u64,
+        _: &AdminRequest,
+        _: &AdminResponse,
+    ) {
+    }
+
+    /// Hook to call when the raft role of the local peer changes.
+    fn on_role_change(&self, _: &mut ObserverContext, _: StateRole) {}
+
+    /// Hook to call after the region is changed, the region in context is the new one.
+    fn on_region_changed(&self, _: &mut ObserverContext, _: RegionChangeEvent) {}
+
+    /// Hook to call after the data of a snapshot at log `index` is applied to the engine.
+    fn on_apply_snapshot(&self, _: &mut ObserverContext, _: u64) {}
+
+    /// Hook to call when the leader advances the resolved ts of the region with `ts`, see
+    /// `ConcurrencyManager::resolve_ts`. No transaction commits at or before `ts` except the
+    /// ones holding locks in the region, it's ordered with the applied commands.
+    fn on_advance_resolved_ts(&self, _: &mut ObserverContext, _: u64) {}
+
     /// Hook to call before handle split region task. If it returns a None,
     /// then `on_split_check` can be skippped.
     //
@@ -0,0 +1,221 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rocksdb::DB;
+use raftstore::store::util;
+use storage::types::split_encoded_key_on_ts;
+
+use super::super::{Coprocessor, ObserverContext, RegionObserver};
+use super::super::metrics::*;
+use super::Status;
+
+#[derive(Default)]
+pub struct KeysStatus {
+    current_count: u64,
+    // The last counted key without ts, versions of a key are counted once.
+    last_key: Vec<u8>,
+    split_key: Option<Vec<u8>>,
+}
+
+pub struct KeysCheckObserver {
+    region_max_keys: u64,
+    split_keys: u64,
+}
+
+impl KeysCheckObserver {
+    pub fn new(region_max_keys: u64, split_keys: u64) -> KeysCheckObserver {
+        KeysCheckObserver {
+            region_max_keys,
+            split_keys,
+        }
+    }
+}
+
+impl Coprocessor for KeysCheckObserver {}
+
+impl RegionObserver for KeysCheckObserver {
+    fn new_split_check_status(&self, ctx: &mut ObserverContext, status: &mut Status, engine: &DB) {
+        let keys_status = KeysStatus::default();
+        let region = ctx.region();
+        let region_id = region.get_id();
+        let region_keys = match util::get_region_approximate_keys(engine, region) {
+            Ok(keys) => keys,
+            Err(e) => {
+                error!(
+                    "[region {}] failed to get approximate keys: {}",
+                    region_id,
+                    e
+                );
+                // Need to check keys.
+                status.keys = Some(keys_status);
+                return;
+            }
+        };
+
+        REGION_KEYS_HISTOGRAM.observe(region_keys as f64);
+        if region_keys >= self.region_max_keys {
+            info!(
+                "[region {}] approximate keys {} >= {}, need to do split check",
+                region_id,
+                region_keys,
+                self.region_max_keys
+            );
+            // Need to check keys.
+            status.keys = Some(keys_status);
+        } // else { Does not need to check keys. }
+    }
+
+    fn on_split_check(
+        &self,
+        _: &mut ObserverContext,
+        status: &mut Status,
+        key: &[u8],
+        _: u64,
+    ) -> Option<Vec<u8>> {
+        if let Some(keys_status) = status.keys.as_mut() {
+            let user_key = match split_encoded_key_on_ts(key) {
+                Ok((user_key, _)) => user_key,
+                Err(_) => key,
+            };
+            if user_key == keys_status.last_key.as_slice() {
+                return None;
+            }
+            keys_status.last_key = user_key.to_vec();
+            keys_status.current_count += 1;
+            if keys_status.current_count > self.split_keys && keys_status.split_key.is_none() {
+                keys_status.split_key = Some(key.to_vec());
+            }
+            if keys_status.current_count >= self.region_max_keys {
+                keys_status.split_key.take()
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::mpsc;
+
+    use tempdir::TempDir;
+    use kvproto::metapb::{Peer, Region};
+    use rocksdb::{ColumnFamilyOptions, DBOptions, Writable, DB};
+
+    use storage::{make_key, ALL_CFS, CF_WRITE};
+    use storage::mvcc::{Write, WriteType};
+    use raftstore::store::{keys, Msg, SplitCheckRunner, SplitCheckTask};
+    use util::rocksdb::{new_engine_opt, CFOptions};
+    use util::worker::Runnable;
+    use util::transport::RetryableSendCh;
+    use util::properties::{MvccPropertiesCollectorFactory, SizePropertiesCollectorFactory};
+
+    use raftstore::coprocessor::{Config, CoprocessorHost};
+
+    fn put_write(engine: &DB, key: &[u8], ts: u64) {
+        let handle = engine.cf_handle(CF_WRITE).unwrap();
+        let k = keys::data_key(make_key(key).append_ts(ts).encoded());
+        let v = Write::new(WriteType::Put, ts, None).to_bytes();
+        engine.put_cf(handle, &k, &v).unwrap();
+    }
+
+    #[test]
+    fn test_split_check() {
+        let path = TempDir::new("test-raftstore").unwrap();
+        let path_str = path.path().to_str().unwrap();
+        let db_opts = DBOptions::new();
+        let mut cf_opts = ColumnFamilyOptions::new();
+        let f = Box::new(SizePropertiesCollectorFactory::default());
+        cf_opts.add_table_properties_collector_factory("tikv.size-collector", f);
+        let f = Box::new(MvccPropertiesCollectorFactory::default());
+        cf_opts.add_table_properties_collector_factory("tikv.mvcc-properties-collector", f);
+        let cfs_opts = ALL_CFS
+            .iter()
+            .map(|cf| CFOptions::new(cf, cf_opts.clone()))
+            .collect();
+        let engine = Arc::new(new_engine_opt(path_str, db_opts, cfs_opts).unwrap());
+
+        let mut region = Region::new();
+        region.set_id(1);
+        region.set_start_key(vec![]);
+        region.set_end_key(vec![]);
+        region.mut_peers().push(Peer::new());
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(5);
+
+        let (tx, rx) = mpsc::sync_channel(100);
+        let ch = RetryableSendCh::new(tx, "test-split");
+        let mut cfg = Config::default();
+        cfg.split_region_on_table = false;
+        cfg.region_max_keys = 100;
+        cfg.region_split_keys = 80;
+
+        let mut runnable = SplitCheckRunner::new(
+            engine.clone(),
+            ch.clone(),
+            Arc::new(CoprocessorHost::new(cfg, ch.clone())),
+        );
+
+        // Every key has two versions, which are counted once.
+        for i in 0..90 {
+            let key = format!("{:04}", i);
+            put_write(&engine, key.as_bytes(), 1);
+            put_write(&engine, key.as_bytes(), 2);
+        }
+        engine.flush_cf(engine.cf_handle(CF_WRITE).unwrap(), true).unwrap();
+
+        runnable.run(SplitCheckTask::new(&region));
+        // keys has not reached the max_keys 100 yet.
+        match rx.try_recv() {
+            Ok(Msg::ApproximateRegionSize { region_id, .. }) => {
+                assert_eq!(region_id, region.get_id());
+            }
+            others => panic!("expect approximate region size, but got {:?}", others),
+        }
+        assert!(rx.try_recv().is_err());
+
+        for i in 90..110 {
+            put_write(&engine, format!("{:04}", i).as_bytes(), 1);
+        }
+        engine.flush_cf(engine.cf_handle(CF_WRITE).unwrap(), true).unwrap();
+
+        runnable.run(SplitCheckTask::new(&region));
+        match rx.try_recv() {
+            Ok(Msg::ApproximateRegionSize { region_id, .. }) => {
+                assert_eq!(region_id, region.get_id());
+            }
+            others => panic!("expect approximate region size, but got {:?}", others),
+        }
+        match rx.try_recv() {
+            Ok(Msg::SplitRegion {
+                region_id,
+                region_epoch,
+                split_key,
+                ..
+            }) => {
+                assert_eq!(region_id, region.get_id());
+                assert_eq!(&region_epoch, region.get_region_epoch());
+                // The 81st key, with the latest version.
+                assert_eq!(split_key, make_key(b"0080").append_ts(2).encoded().as_slice());
+            }
+            others => panic!("expect split check result, but got {:?}", others),
+        }
+
+        drop(rx);
+        // It should be safe even the result can't be sent back.
+        runnable.run(SplitCheckTask::new(&region));
+    }
+}
@@ -12,20 +12,32 @@
 // limitations under the License.
 
 mod size;
+mod keys;
+mod table;
 
 use self::size::SizeStatus;
+use self::keys::KeysStatus;
+use self::table::TableStatus;
 
 pub use self::size::SizeCheckObserver;
+pub use self::keys::KeysCheckObserver;
+pub use self::table::TableCheckObserver;
 pub const SIZE_CHECK_OBSERVER_PRIORITY: u32 = 200;
+pub const KEYS_CHECK_OBSERVER_PRIORITY: u32 = 300;
+pub const TABLE_CHECK_OBSERVER_PRIORITY: u32 = 400;
 
 #[derive(Default)]
 pub struct Status {
     // For SizeCheckObserver
     size: Option<SizeStatus>,
+    // For KeysCheckObserver
+    keys: Option<KeysStatus>,
+    // For TableCheckObserver
+    table: Option<TableStatus>,
 }
 
 impl Status {
     pub fn skip(&self) -> bool {
-        self.size.is_none()
+        self.size.is_none() && self.keys.is_none() && self.table.is_none()
     }
 }
@@ -0,0 +1,241 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rocksdb::DB;
+use coprocessor::codec::table;
+use raftstore::store::keys;
+use util::codec::bytes::{encode_bytes, BytesDecoder};
+
+use super::super::{Coprocessor, ObserverContext, RegionObserver};
+use super::Status;
+
+#[derive(Default)]
+pub struct TableStatus {
+    // Whether the first key of the region is checked.
+    first_key_checked: bool,
+    // The table prefix of the first key, None if it's not a table key.
+    first_table_prefix: Option<Vec<u8>>,
+}
+
+/// `TableCheckObserver` splits a region at the table prefix `t{table_id}` when it
+/// contains keys of more than one table.
+pub struct TableCheckObserver;
+
+impl Coprocessor for TableCheckObserver {}
+
+impl RegionObserver for TableCheckObserver {
+    fn new_split_check_status(&self, ctx: &mut ObserverContext, status: &mut Status, _: &DB) {
+        let region = ctx.region();
+        if is_same_table(region.get_start_key(), region.get_end_key()) {
+            // Does not need to check table.
+            return;
+        }
+        status.table = Some(TableStatus::default());
+    }
+
+    fn on_split_check(
+        &self,
+        _: &mut ObserverContext,
+        status: &mut Status,
+        key: &[u8],
+        _: u64,
+    ) -> Option<Vec<u8>> {
+        let table_status = match status.table.as_mut() {
+            Some(table_status) => table_status,
+            None => return None,
+        };
+        let prefix = table_prefix(keys::origin_key(key));
+        if !table_status.first_key_checked {
+            table_status.first_key_checked = true;
+            table_status.first_table_prefix = prefix;
+            return None;
+        }
+        match prefix {
+            Some(prefix) => if table_status.first_table_prefix.as_ref() != Some(&prefix) {
+                // The first key of another table, split at its table prefix.
+                Some(keys::data_key(&encode_bytes(&prefix)))
+            } else {
+                None
+            },
+            None => None,
+        }
+    }
+}
+
+/// Gets the table prefix of an encoded key, None if it's not a table key.
+fn table_prefix(encoded_key: &[u8]) -> Option<Vec<u8>> {
+    let mut key = match encoded_key.decode_bytes(false) {
+        Ok(key) => key,
+        Err(_) => return None,
+    };
+    if table::decode_table_id(&key).is_err() {
+        return None;
+    }
+    key.truncate(table::TABLE_PREFIX_KEY_LEN);
+    Some(key)
+}
+
+/// Checks whether the encoded region range [`start_key`, `end_key`) can hold the keys
+/// of only one table.
+fn is_same_table(start_key: &[u8], end_key: &[u8]) -> bool {
+    let start_id = match table_prefix(start_key) {
+        Some(prefix) => table::decode_table_id(&prefix).unwrap(),
+        None => return false,
+    };
+    let end = match end_key.decode_bytes(false) {
+        Ok(end) => end,
+        Err(_) => return false,
+    };
+    match table::decode_table_id(&end) {
+        Ok(end_id) if end_id == start_id => true,
+        // The region ends at the prefix of the next table.
+        Ok(end_id) => {
+            end.len() == table::TABLE_PREFIX_KEY_LEN && Some(end_id) == start_id.checked_add(1)
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::mpsc;
+
+    use tempdir::TempDir;
+    use kvproto::metapb::{Peer, Region};
+    use rocksdb::{ColumnFamilyOptions, DBOptions, Writable};
+
+    use storage::{make_key, ALL_CFS};
+    use raftstore::store::{Msg, SplitCheckRunner, SplitCheckTask};
+    use util::rocksdb::{new_engine_opt, CFOptions};
+    use util::worker::Runnable;
+    use util::transport::RetryableSendCh;
+    use util::properties::SizePropertiesCollectorFactory;
+    use util::codec::number::NumberEncoder;
+
+    use raftstore::coprocessor::{Config, CoprocessorHost};
+    use super::*;
+
+    fn row_key(table_id: i64, handle: i64) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(table::ID_LEN);
+        buf.encode_i64(handle).unwrap();
+        table::encode_row_key(table_id, &buf)
+    }
+
+    fn new_row_key(table_id: i64, handle: i64) -> Vec<u8> {
+        make_key(&row_key(table_id, handle)).encoded().to_owned()
+    }
+
+    #[test]
+    fn test_is_same_table() {
+        let prefix = |id| encode_bytes(&table::encode_table_prefix(id));
+        let cases = vec![
+            (vec![], vec![], false),
+            (new_row_key(1, 1), vec![], false),
+            (vec![], new_row_key(1, 1), false),
+            (encode_bytes(b"m"), prefix(1), false),
+            (new_row_key(1, 1), new_row_key(1, 10), true),
+            (prefix(1), new_row_key(1, 10), true),
+            (new_row_key(1, 1), prefix(2), true),
+            (prefix(1), prefix(2), true),
+            (new_row_key(1, 1), new_row_key(2, 1), false),
+            (new_row_key(1, 1), prefix(3), false),
+        ];
+        for (start_key, end_key, same) in cases {
+            assert_eq!(
+                is_same_table(&start_key, &end_key),
+                same,
+                "{:?} {:?}",
+                start_key,
+                end_key
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_check() {
+        let path = TempDir::new("test-raftstore").unwrap();
+        let path_str = path.path().to_str().unwrap();
+        let db_opts = DBOptions::new();
+        let mut cf_opts = ColumnFamilyOptions::new();
+        let f = Box::new(SizePropertiesCollectorFactory::default());
+        cf_opts.add_table_properties_collector_factory("tikv.size-collector", f);
+        let cfs_opts = ALL_CFS
+            .iter()
+            .map(|cf| CFOptions::new(cf, cf_opts.clone()))
+            .collect();
+        let engine = Arc::new(new_engine_opt(path_str, db_opts, cfs_opts).unwrap());
+
+        let mut region = Region::new();
+        region.set_id(1);
+        region.set_start_key(vec![]);
+        region.set_end_key(vec![]);
+        region.mut_peers().push(Peer::new());
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(5);
+
+        let (tx, rx) = mpsc::sync_channel(100);
+        let ch = RetryableSendCh::new(tx, "test-split");
+        let cfg = Config::default();
+        let mut runnable = SplitCheckRunner::new(
+            engine.clone(),
+            ch.clone(),
+            Arc::new(CoprocessorHost::new(cfg, ch.clone())),
+        );
+
+        let put = |key: &[u8]| {
+            let key = make_key(key).append_ts(1);
+            let key = keys::data_key(key.encoded());
+            engine.put(&key, b"v").unwrap();
+        };
+        let mut check = |region: &Region, expect: Option<Vec<u8>>| {
+            runnable.run(SplitCheckTask::new(region));
+            match rx.try_recv() {
+                Ok(Msg::ApproximateRegionSize { region_id, .. }) => {
+                    assert_eq!(region_id, region.get_id());
+                }
+                others => panic!("expect approximate region size, but got {:?}", others),
+            }
+            match (rx.try_recv(), expect) {
+                (Ok(Msg::SplitRegion { split_key, .. }), Some(expect)) => {
+                    assert_eq!(split_key, expect);
+                }
+                (Err(_), None) => {}
+                (others, expect) => panic!("expect {:?}, but got {:?}", expect, others),
+            }
+        };
+
+        // Only non-table keys.
+        put(b"m1");
+        put(b"m2");
+        check(&region, None);
+
+        // Split at the prefix of table 1.
+        for i in 0..3 {
+            put(&row_key(1, i));
+        }
+        check(&region, Some(encode_bytes(&table::encode_table_prefix(1))));
+
+        // A region holds only table 1 doesn't need to split.
+        region.set_start_key(encode_bytes(&table::encode_table_prefix(1)));
+        check(&region, None);
+
+        // Split at the prefix of table 3.
+        put(&row_key(3, 1));
+        check(&region, Some(encode_bytes(&table::encode_table_prefix(3))));
+
+        // The region ends at table 2.
+        region.set_end_key(encode_bytes(&table::encode_table_prefix(2)));
+        check(&region, None);
+    }
+}
@@ -25,6 +25,8 @@ pub struct Config {
     // true for high reliability, prevent data loss when power failure.
     pub sync_log: bool,
     pub raftdb_path: String,
+    // the directory holding the sst files built when applying imported data.
+    pub import_dir: String,
 
     // store capacity. 0 means no limit.
     pub capacity: ReadableSize,
@@ -37,6 +39,10 @@ pub struct Config {
     pub raft_max_inflight_msgs: usize,
     // When the entry exceed the max size, reject to propose it.
     pub raft_entry_max_size: ReadableSize,
+    /// Whether followers serve the linearizable reads sent with `read_quorum`. It's off by
+    /// default, as kvproto has no dedicated flag for follower reads yet and clients may set
+    /// `read_quorum` on the reads they expect the leader to serve.
+    pub allow_follower_read: bool,
 
     // Interval to gc unnecessary raft log (ms).
     pub raft_log_gc_tick_interval: ReadableDuration,
@@ -53,6 +59,11 @@ pub struct Config {
     /// When size change of region exceed the diff since last check, it
     /// will be checked again whether it should be split.
     pub region_split_check_diff: ReadableSize,
+    /// When the qps of a region stays above region_split_qps_threshold for
+    /// region_split_load_duration, it will be split to balance the load.
+    /// 0 means never split a region by load, which is the default.
+    pub region_split_qps_threshold: u64,
+    pub region_split_load_duration: ReadableDuration,
     /// Interval (ms) to check whether start compaction for a region.
     pub region_compact_check_interval: ReadableDuration,
     /// When delete keys of a region exceeds the size, a compaction will
@@ -82,6 +93,9 @@ pub struct Config {
     // Interval (ms) to check region whether the data is consistent.
     pub consistency_check_interval: ReadableDuration,
 
+    // Interval to advance the resolved ts of the leaders for the stale reads, 0 disables it.
+    pub advance_resolved_ts_interval: ReadableDuration,
+
     pub report_region_flow_interval: ReadableDuration,
 
     // The lease provided by a successfully proposed and applied entry.
@@ -108,6 +122,7 @@ impl Default for Config {
         Config {
             sync_log: true,
             raftdb_path: String::new(),
+            import_dir: String::new(),
             capacity: ReadableSize(0),
             raft_base_tick_interval: ReadableDuration::secs(1),
             raft_heartbeat_ticks: 2,
@@ -115,6 +130,7 @@ impl Default for Config {
             raft_max_size_per_msg: ReadableSize::mb(1),
             raft_max_inflight_msgs: 256,
             raft_entry_max_size: ReadableSize::mb(8),
+            allow_follower_read: false,
             raft_log_gc_tick_interval: ReadableDuration::secs(10),
             raft_log_gc_threshold: 50,
             // Assume the average size of entries is 1k.
@@ -122,6 +138,8 @@ impl Default for Config {
             raft_log_gc_size_limit: split_size * 3 / 4,
             split_region_check_tick_interval: ReadableDuration::secs(10),
             region_split_check_diff: split_size / 16,
+            region_split_qps_threshold: 0,
+            region_split_load_duration: ReadableDuration::secs(30),
             // Disable manual compaction by default.
             region_compact_check_interval: ReadableDuration::secs(0),
             region_compact_delete_keys_count: 1_000_000,
@@ -139,6 +157,7 @@ impl Default for Config {
             // Disable consistency check by default as it will hurt performance.
             // We should turn on this only in our tests.
             consistency_check_interval: ReadableDuration::secs(0),
+            advance_resolved_ts_interval: ReadableDuration::secs(1),
             report_region_flow_interval: ReadableDuration::minutes(1),
             raft_store_max_leader_lease: ReadableDuration::secs(9),
             right_derive_when_split: true,
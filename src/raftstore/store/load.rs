@@ -0,0 +1,296 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{cmp, mem};
+
+use rand::{self, Rng};
+
+use kvproto::raft_cmdpb::{CmdType, RaftCmdRequest};
+use storage::CF_LOCK;
+use storage::types;
+use util::collections::HashSet;
+
+// The max number of keys sampled between two checks.
+const SAMPLE_NUM: usize = 128;
+
+/// `LoadStat` samples the keys accessed in a region, so that a hot region can be
+/// split at a key which balances the load between the two halves.
+///
+/// Keys are sampled by reservoir sampling, every accessed key has the same chance
+/// to be sampled.
+#[derive(Default, Debug)]
+pub struct LoadStat {
+    // The number of keys accessed since last check.
+    accessed: u64,
+    samples: Vec<Vec<u8>>,
+    // The number of consecutive checks the qps is above the threshold.
+    hot_checks: u64,
+}
+
+impl LoadStat {
+    /// Records the keys accessed by the write and get requests. The ts of the keys is
+    /// stripped and every user key is recorded once per request, so a prewrite that
+    /// puts both the lock and the value of a key counts as one access.
+    ///
+    /// Snapshot requests don't carry keys, the ranges read on them are recorded by
+    /// `record_range` instead.
+    pub fn record_request(&mut self, req: &RaftCmdRequest) {
+        let mut keys = HashSet::default();
+        for r in req.get_requests() {
+            let (cf, key) = match r.get_cmd_type() {
+                CmdType::Get => (r.get_get().get_cf(), r.get_get().get_key()),
+                CmdType::Put => (r.get_put().get_cf(), r.get_put().get_key()),
+                CmdType::Delete => (r.get_delete().get_cf(), r.get_delete().get_key()),
+                CmdType::DeleteRange => {
+                    keys.insert(r.get_delete_range().get_start_key().to_vec());
+                    continue;
+                }
+                CmdType::Snap | CmdType::Prewrite | CmdType::Invalid => continue,
+            };
+            keys.insert(user_key(cf, key));
+        }
+        for key in keys {
+            self.record(&key);
+        }
+    }
+
+    /// Records a range read on a snapshot of the region by its start key.
+    pub fn record_range(&mut self, start_key: &[u8]) {
+        self.record(start_key);
+    }
+
+    pub fn record(&mut self, key: &[u8]) {
+        self.record_n(key, 1);
+    }
+
+    // record_n records a key which stands for `n` accessed keys, a key stands for more
+    // than one key when it's a sample merged from another `LoadStat`.
+    fn record_n(&mut self, key: &[u8], n: u64) {
+        self.accessed += n;
+        if self.samples.len() < SAMPLE_NUM {
+            self.samples.push(key.to_vec());
+            return;
+        }
+        let i = rand::thread_rng().gen_range(0, self.accessed);
+        if i < SAMPLE_NUM as u64 * n {
+            self.samples[(i % SAMPLE_NUM as u64) as usize] = key.to_vec();
+        }
+    }
+
+    /// Merges the load recorded outside the raftstore, like the ranges read by
+    /// coprocessor requests.
+    pub fn merge(&mut self, other: LoadStat) {
+        if other.samples.is_empty() {
+            self.accessed += other.accessed;
+            return;
+        }
+        let n = other.accessed / other.samples.len() as u64;
+        let mut rest = other.accessed % other.samples.len() as u64;
+        for key in other.samples {
+            // Spread the remainder, so the accessed count is kept exactly.
+            let extra = if rest > 0 {
+                rest -= 1;
+                1
+            } else {
+                0
+            };
+            self.record_n(&key, n + extra);
+        }
+    }
+
+    /// Checks the load since last check, which is `millis` milliseconds ago. Returns the
+    /// key to split the region at if the qps has been above `qps_threshold` for `hot_checks`
+    /// consecutive checks.
+    pub fn check(&mut self, millis: u64, qps_threshold: u64, hot_checks: u64) -> Option<Vec<u8>> {
+        let qps = self.accessed * 1000 / cmp::max(millis, 1);
+        let samples = mem::replace(&mut self.samples, vec![]);
+        self.accessed = 0;
+        if qps < qps_threshold {
+            self.hot_checks = 0;
+            return None;
+        }
+        self.hot_checks += 1;
+        if self.hot_checks < hot_checks {
+            return None;
+        }
+        let split_key = balanced_split_key(samples);
+        if split_key.is_some() {
+            self.hot_checks = 0;
+        }
+        split_key
+    }
+}
+
+// user_key strips the ts of the MVCC keys in the write and default CFs. Other keys, like the
+// raw keys, are kept as is.
+fn user_key(cf: &str, key: &[u8]) -> Vec<u8> {
+    if cf == CF_LOCK || !types::is_mvcc_key(key) {
+        return key.to_vec();
+    }
+    types::truncate_ts(key).to_vec()
+}
+
+/// Picks the sampled key closest to the median which leaves samples on both sides,
+/// returns None if all the samples are the same key.
+fn balanced_split_key(mut samples: Vec<Vec<u8>>) -> Option<Vec<u8>> {
+    if samples.is_empty() {
+        return None;
+    }
+    samples.sort();
+    let mid = samples.len() / 2;
+    // The split key is the first key of the right half, so the candidates are
+    // the first key equal to the median and the first key after it.
+    let mut first = mid;
+    while first > 0 && samples[first - 1] == samples[mid] {
+        first -= 1;
+    }
+    let mut next = mid;
+    while next < samples.len() && samples[next] == samples[mid] {
+        next += 1;
+    }
+    let dist = |i: usize| (2 * i as i64 - samples.len() as i64).abs();
+    let i = match (first > 0, next < samples.len()) {
+        (true, true) => if dist(first) <= dist(next) {
+            first
+        } else {
+            next
+        },
+        (true, false) => first,
+        (false, true) => next,
+        (false, false) => return None,
+    };
+    Some(samples.swap_remove(i))
+}
+
+#[cfg(test)]
+mod tests {
+    use kvproto::raft_cmdpb::Request;
+    use storage::{Key, CF_DEFAULT, CF_WRITE};
+
+    use super::*;
+
+    fn new_put(cf: &str, key: &[u8]) -> Request {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Put);
+        req.mut_put().set_cf(cf.to_owned());
+        req.mut_put().set_key(key.to_vec());
+        req
+    }
+
+    #[test]
+    fn test_record_request() {
+        let key = Key::from_raw(b"key");
+        let mut req = RaftCmdRequest::new();
+        // A prewrite puts the lock and the value of the key.
+        req.mut_requests().push(new_put(CF_LOCK, key.encoded()));
+        req.mut_requests()
+            .push(new_put(CF_DEFAULT, key.append_ts(10).encoded()));
+        let mut stat = LoadStat::default();
+        stat.record_request(&req);
+        assert_eq!(stat.accessed, 1);
+        assert_eq!(stat.samples, vec![key.encoded().to_owned()]);
+
+        // A commit puts the write of the key.
+        let mut req = RaftCmdRequest::new();
+        req.mut_requests()
+            .push(new_put(CF_WRITE, key.append_ts(11).encoded()));
+        stat.record_request(&req);
+        assert_eq!(stat.accessed, 2);
+        assert_eq!(stat.samples[1], key.encoded().to_owned());
+
+        // Raw keys are kept as is, however long they are.
+        for (i, key) in vec![&b"k"[..], b"raw_key_of_16bye"].into_iter().enumerate() {
+            let mut req = RaftCmdRequest::new();
+            req.mut_requests().push(new_put(CF_DEFAULT, key));
+            stat.record_request(&req);
+            assert_eq!(stat.samples[2 + i], key.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_merge_load_stat() {
+        let mut other = LoadStat::default();
+        for i in 0..1000 {
+            other.record_range(format!("k{:04}", i).as_bytes());
+        }
+        let mut stat = LoadStat::default();
+        stat.record(b"k");
+        stat.merge(other);
+        assert_eq!(stat.accessed, 1001);
+        assert_eq!(stat.samples.len(), SAMPLE_NUM);
+        // The merged load is hot enough to split.
+        assert!(stat.check(1_000, 1000, 1).is_some());
+
+        // The accessed count of a load without samples is still merged.
+        stat.merge(LoadStat {
+            accessed: 10,
+            ..Default::default()
+        });
+        assert_eq!(stat.accessed, 10);
+    }
+
+    #[test]
+    fn test_balanced_split_key() {
+        let cases = vec![
+            (vec![], None),
+            (vec!["a"], None),
+            (vec!["a", "a", "a"], None),
+            (vec!["b", "a"], Some("b")),
+            (vec!["d", "c", "b", "a"], Some("c")),
+            (vec!["a", "b", "b", "b", "c"], Some("b")),
+            (vec!["a", "a", "a", "a", "b"], Some("b")),
+            (vec!["a", "b", "b", "b", "b"], Some("b")),
+            (vec!["a", "b", "b", "b", "c", "c"], Some("c")),
+        ];
+        for (samples, expect) in cases {
+            let samples: Vec<_> = samples.iter().map(|k| k.as_bytes().to_vec()).collect();
+            let expect = expect.map(|k| k.as_bytes().to_vec());
+            assert_eq!(balanced_split_key(samples.clone()), expect, "{:?}", samples);
+        }
+    }
+
+    #[test]
+    fn test_load_stat() {
+        let mut stat = LoadStat::default();
+        let record = |stat: &mut LoadStat| {
+            for i in 0..1000 {
+                stat.record(format!("k{:04}", i).as_bytes());
+            }
+        };
+
+        record(&mut stat);
+        assert_eq!(stat.samples.len(), SAMPLE_NUM);
+        // qps 100 is below the threshold.
+        assert_eq!(stat.check(10_000, 200, 2), None);
+        assert_eq!(stat.hot_checks, 0);
+        assert!(stat.samples.is_empty());
+
+        // Split after 2 hot checks.
+        record(&mut stat);
+        assert_eq!(stat.check(1_000, 200, 2), None);
+        assert_eq!(stat.hot_checks, 1);
+        record(&mut stat);
+        let split_key = stat.check(1_000, 200, 2).unwrap();
+        assert!(split_key.as_slice() > &b"k0000"[..]);
+        assert_eq!(stat.hot_checks, 0);
+
+        // A single hot key can't be split.
+        for _ in 0..3 {
+            for _ in 0..1000 {
+                stat.record(b"k");
+            }
+            assert_eq!(stat.check(1_000, 200, 1), None);
+        }
+        assert_eq!(stat.hot_checks, 3);
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::result;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use time::Timespec;
+use protobuf::RepeatedField;
+use kvproto::metapb;
+use kvproto::raft_cmdpb::{CmdType, RaftCmdRequest, RaftCmdResponse};
+
+use util::HandyRwLock;
+use util::collections::HashMap;
+use util::time::monotonic_raw_now;
+use super::worker::apply;
+use super::peer::check_epoch;
+use super::cmd_resp;
+use super::metrics::*;
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+fn timespec_to_ns(ts: Timespec) -> u64 {
+    ts.sec as u64 * NANOSECONDS_PER_SECOND + ts.nsec as u64
+}
+
+/// The states of a leader peer needed to serve reads out of the raftstore thread.
+///
+/// A delegate is created when the peer becomes leader or its region changes, so the region
+/// and the term never change in it. The applied index term and the lease states are renewed
+/// in place by the raftstore thread.
+pub struct ReadDelegate {
+    tag: String,
+    region: metapb::Region,
+    peer_id: u64,
+    term: u64,
+    applied_index_term: AtomicU64,
+    // The safe leader lease expired time in nanoseconds of the monotonic raw clock,
+    // 0 means the leader holds no safe lease.
+    lease_expired_ns: AtomicU64,
+    // Whether raft allows lease reads, see `Raft::in_lease`.
+    in_lease: AtomicBool,
+}
+
+impl ReadDelegate {
+    pub fn new(tag: String, region: metapb::Region, peer_id: u64, term: u64) -> ReadDelegate {
+        ReadDelegate {
+            tag: tag,
+            region: region,
+            peer_id: peer_id,
+            term: term,
+            applied_index_term: AtomicU64::new(0),
+            lease_expired_ns: AtomicU64::new(0),
+            in_lease: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    pub fn region(&self) -> &metapb::Region {
+        &self.region
+    }
+
+    #[inline]
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    pub fn update(
+        &self,
+        applied_index_term: u64,
+        lease_expired_time: Option<Timespec>,
+        in_lease: bool,
+    ) {
+        self.applied_index_term
+            .store(applied_index_term, Ordering::Release);
+        let lease_expired_ns = lease_expired_time.map_or(0, timespec_to_ns);
+        self.lease_expired_ns
+            .store(lease_expired_ns, Ordering::Release);
+        self.in_lease.store(in_lease, Ordering::Release);
+    }
+
+    /// Checks whether the request can be served with the states published by the raftstore,
+    /// the same checks are done by the raftstore before it reads locally.
+    fn can_read(&self, req: &RaftCmdRequest) -> bool {
+        let header = req.get_header();
+        if header.get_read_quorum() || header.get_peer().get_id() != self.peer_id {
+            return false;
+        }
+        if header.get_term() != 0 && header.get_term() != self.term {
+            return false;
+        }
+        if check_epoch(&self.region, req).is_err() {
+            return false;
+        }
+        if self.applied_index_term.load(Ordering::Acquire) != self.term {
+            // Leader transfer must have happened, the applied data may be stale.
+            return false;
+        }
+        if !self.in_lease.load(Ordering::Acquire) {
+            return false;
+        }
+        let lease_expired_ns = self.lease_expired_ns.load(Ordering::Acquire);
+        lease_expired_ns != 0 && timespec_to_ns(monotonic_raw_now()) <= lease_expired_ns
+    }
+}
+
+/// `LocalReader` serves snapshot requests on the calling thread when the leader lease
+/// is valid, so that lease reads don't queue behind the raft ready handling.
+///
+/// The raftstore publishes a `ReadDelegate` for every leader peer. The delegates are
+/// replaced only when the leaders or the regions change, so readers seldom wait for the
+/// lock, and lease renewals never block them.
+#[derive(Clone)]
+pub struct LocalReader {
+    delegates: Arc<RwLock<HashMap<u64, Arc<ReadDelegate>>>>,
+}
+
+impl LocalReader {
+    pub fn new() -> LocalReader {
+        LocalReader {
+            delegates: Arc::new(RwLock::new(HashMap::default())),
+        }
+    }
+
+    pub fn insert(&self, region_id: u64, delegate: Arc<ReadDelegate>) {
+        self.delegates.wl().insert(region_id, delegate);
+    }
+
+    pub fn remove(&self, region_id: u64) {
+        self.delegates.wl().remove(&region_id);
+    }
+
+    /// Tries to serve the read request locally, the request is given back if it has to
+    /// be served by the raftstore.
+    ///
+    /// Only snapshot requests are served, the snapshot should be taken on the kv engine
+    /// right after the response is returned.
+    pub fn read(&self, req: RaftCmdRequest) -> result::Result<RaftCmdResponse, RaftCmdRequest> {
+        if req.has_admin_request() || req.get_requests().is_empty() ||
+            req.get_requests()
+                .iter()
+                .any(|r| r.get_cmd_type() != CmdType::Snap)
+        {
+            return Err(req);
+        }
+        let region_id = req.get_header().get_region_id();
+        let delegate = match self.delegates.rl().get(&region_id) {
+            Some(delegate) => delegate.clone(),
+            None => return Err(req),
+        };
+        if !delegate.can_read(&req) {
+            return Err(req);
+        }
+
+        let mut responses = Vec::with_capacity(req.get_requests().len());
+        for _ in req.get_requests() {
+            let mut resp = apply::do_snap(delegate.region.clone()).unwrap();
+            resp.set_cmd_type(CmdType::Snap);
+            responses.push(resp);
+        }
+        let mut resp = RaftCmdResponse::new();
+        resp.set_responses(RepeatedField::from_vec(responses));
+        cmd_resp::bind_term(&mut resp, delegate.term);
+        debug!("{} serve snapshot request locally", delegate.tag);
+        PEER_PROPOSAL_COUNTER_VEC
+            .with_label_values(&["local_reader"])
+            .inc();
+        Ok(resp)
+    }
+}
+
+impl Default for LocalReader {
+    fn default() -> LocalReader {
+        LocalReader::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+    use kvproto::metapb::Peer;
+    use kvproto::raft_cmdpb::Request;
+
+    use super::*;
+
+    fn new_snap_request(region: &metapb::Region, peer_id: u64) -> RaftCmdRequest {
+        let mut req = RaftCmdRequest::new();
+        req.mut_header().set_region_id(region.get_id());
+        req.mut_header()
+            .set_region_epoch(region.get_region_epoch().clone());
+        let mut peer = Peer::new();
+        peer.set_id(peer_id);
+        req.mut_header().set_peer(peer);
+        let mut snap = Request::new();
+        snap.set_cmd_type(CmdType::Snap);
+        req.mut_requests().push(snap);
+        req
+    }
+
+    #[test]
+    fn test_local_reader() {
+        let mut region = metapb::Region::new();
+        region.set_id(1);
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(3);
+        let reader = LocalReader::new();
+
+        // No leader on this store.
+        let req = new_snap_request(&region, 10);
+        let req = reader.read(req).unwrap_err();
+
+        let delegate = Arc::new(ReadDelegate::new("test".to_owned(), region.clone(), 10, 5));
+        reader.insert(1, delegate.clone());
+        // The leader holds no lease.
+        let req = reader.read(req).unwrap_err();
+        // The leader hasn't applied any entry in its term.
+        let lease = monotonic_raw_now() + Duration::seconds(10);
+        delegate.update(4, Some(lease), true);
+        let req = reader.read(req).unwrap_err();
+
+        // Raft doesn't allow lease reads.
+        delegate.update(5, Some(lease), false);
+        let req = reader.read(req).unwrap_err();
+
+        delegate.update(5, Some(lease), true);
+        let resp = reader.read(req.clone()).unwrap();
+        assert_eq!(resp.get_header().get_current_term(), 5);
+        assert_eq!(resp.get_responses().len(), 1);
+        assert_eq!(resp.get_responses()[0].get_snap().get_region(), &region);
+
+        // The requests the raftstore should handle.
+        let mut stale_term = req.clone();
+        stale_term.mut_header().set_term(4);
+        assert!(reader.read(stale_term).is_err());
+        let mut stale_epoch = req.clone();
+        stale_epoch
+            .mut_header()
+            .mut_region_epoch()
+            .set_version(1);
+        assert!(reader.read(stale_epoch).is_err());
+        let mut other_peer = req.clone();
+        other_peer.mut_header().mut_peer().set_id(11);
+        assert!(reader.read(other_peer).is_err());
+        let mut read_quorum = req.clone();
+        read_quorum.mut_header().set_read_quorum(true);
+        assert!(reader.read(read_quorum).is_err());
+        let mut get = req.clone();
+        get.mut_requests()[0].set_cmd_type(CmdType::Get);
+        assert!(reader.read(get).is_err());
+
+        // The lease expires.
+        delegate.update(5, Some(monotonic_raw_now() - Duration::seconds(1)), true);
+        let req = reader.read(req).unwrap_err();
+        delegate.update(5, None, true);
+        let req = reader.read(req).unwrap_err();
+
+        delegate.update(5, Some(lease), true);
+        reader.remove(1);
+        assert!(reader.read(req).is_err());
+    }
+}
@@ -20,10 +20,14 @@ pub mod bootstrap;
 pub mod cmd_resp;
 pub mod util;
 pub mod debug;
+pub mod unsafe_recovery;
 pub mod store;
 
 mod peer;
 mod peer_storage;
+mod load;
+mod local_reader;
+mod resolved_ts;
 mod snap;
 mod worker;
 mod metrics;
@@ -34,6 +38,9 @@ pub use self::store::{create_event_loop, Engines, Store, StoreChannel, StoreStat
 pub use self::config::Config;
 pub use self::transport::Transport;
 pub use self::peer::{Peer, PeerStat};
+pub use self::load::LoadStat;
+pub use self::local_reader::LocalReader;
+pub use self::resolved_ts::Resolver;
 pub use self::bootstrap::{bootstrap_store, clear_prepare_bootstrap, clear_prepare_bootstrap_state,
                           prepare_bootstrap, write_prepare_bootstrap};
 pub use self::engine::{Iterable, Mutable, Peekable};
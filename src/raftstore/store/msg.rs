@@ -16,10 +16,14 @@ use std::boxed::FnBox;
 use std::fmt;
 
 use kvproto::raft_serverpb::RaftMessage;
-use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse};
+use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse, Request};
 use kvproto::metapb::RegionEpoch;
 use raft::SnapshotStatus;
 use util::escape;
+use util::collections::{HashMap, HashSet};
+
+use super::load::LoadStat;
+use super::unsafe_recovery::{ForceRemoveCallback, ReportCallback};
 
 pub type Callback = Box<FnBox(RaftCmdResponse) + Send>;
 pub type BatchCallback = Box<FnBox(Vec<Option<RaftCmdResponse>>) + Send>;
@@ -35,6 +39,7 @@ pub enum Tick {
     SnapGc,
     CompactLockCf,
     ConsistencyCheck,
+    AdvanceResolvedTs,
 }
 
 #[derive(Debug, PartialEq)]
@@ -86,6 +91,36 @@ pub enum Msg {
 
     // For region size
     ApproximateRegionSize { region_id: u64, region_size: u64 },
+
+    // For load based split, the load of the reads served outside the raftstore.
+    RegionsReadLoad { load_stats: HashMap<u64, LoadStat> },
+
+    // Advances the resolved ts of the leaders with the tso got from PD.
+    AdvanceResolvedTs { tso: u64 },
+
+    // For bulk import, all the put requests must belong to one region.
+    Import {
+        requests: Vec<Request>,
+        callback: Callback,
+    },
+
+    // Read the applied data at `read_ts` on any peer of the region, it fails if the
+    // resolved ts of the region is less than `read_ts`.
+    StaleRead {
+        read_ts: u64,
+        request: RaftCmdRequest,
+        callback: Callback,
+    },
+
+    // For unsafe recovery, reports the states of the peers on the store.
+    UnsafeRecoveryReport { callback: ReportCallback },
+
+    // For unsafe recovery, removes the peers on the failed stores from the regions that
+    // lost the quorum without going through raft.
+    UnsafeRecoveryForceRemove {
+        failed_stores: HashSet<u64>,
+        callback: ForceRemoveCallback,
+    },
 }
 
 impl fmt::Debug for Msg {
@@ -121,6 +156,23 @@ impl fmt::Debug for Msg {
                 region_id,
                 region_size
             ),
+            Msg::RegionsReadLoad { ref load_stats } => {
+                write!(fmt, "Read load of {} regions", load_stats.len())
+            }
+            Msg::AdvanceResolvedTs { tso } => write!(fmt, "Advance resolved ts to {}", tso),
+            Msg::Import { ref requests, .. } => {
+                write!(fmt, "Import {} requests", requests.len())
+            }
+            Msg::StaleRead { read_ts, .. } => write!(fmt, "Stale read at {}", read_ts),
+            Msg::UnsafeRecoveryReport { .. } => write!(fmt, "Unsafe recovery report"),
+            Msg::UnsafeRecoveryForceRemove {
+                ref failed_stores,
+                ..
+            } => write!(
+                fmt,
+                "Unsafe recovery force remove peers on {:?}",
+                failed_stores
+            ),
         }
     }
 }
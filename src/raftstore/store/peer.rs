@@ -18,7 +18,7 @@ use std::collections::VecDeque;
 use std::{cmp, mem, slice};
 use std::time::{Duration, Instant};
 
-use time::Timespec;
+use time::{Duration as TimeDuration, Timespec};
 use rocksdb::{WriteBatch, DB};
 use rocksdb::rocksdb_options::WriteOptions;
 use protobuf::{self, Message, MessageStatic};
@@ -51,16 +51,23 @@ use super::msg::Callback;
 use super::cmd_resp;
 use super::transport::Transport;
 use super::engine::Snapshot;
+use super::load::LoadStat;
+use super::local_reader::{LocalReader, ReadDelegate};
+use super::resolved_ts;
 use super::metrics::*;
 use super::local_metrics::{RaftMessageMetrics, RaftMetrics, RaftProposeMetrics, RaftReadyMetrics};
 
 const TRANSFER_LEADER_ALLOW_LOG_LAG: u64 = 10;
 const DEFAULT_APPEND_WB_SIZE: usize = 4 * 1024;
+// The max number of safe ts a follower keeps before it applies to their indexes.
+const MAX_PENDING_SAFE_TS: usize = 64;
 
 struct ReadIndexRequest {
     id: u64,
     cmds: Vec<(RaftCmdRequest, Callback)>,
     renew_lease_time: Timespec,
+    // The commit index returned by raft, the commands can be served after it's applied.
+    read_index: Option<u64>,
 }
 
 impl ReadIndexRequest {
@@ -94,12 +101,50 @@ impl ReadIndexQueue {
     }
 
     fn clear_uncommitted(&mut self, term: u64) {
-        for mut read in self.reads.drain(self.ready_cnt..) {
+        let end = self.reads.len();
+        self.clear_reads(end, term);
+    }
+
+    // clear_reads drops the reads that are not ready before `end`.
+    fn clear_reads(&mut self, end: usize, term: u64) {
+        for mut read in self.reads.drain(self.ready_cnt..end) {
             for (_, cb) in read.cmds.drain(..) {
                 apply::notify_stale_req(term, cb);
             }
         }
     }
+
+    // advance marks the read of `ctx` ready at `index`, and returns its renew lease time.
+    // The reads before it that are not ready are dropped, as their read index responses
+    // have been lost, for example when the leader dropped the requests forwarded by a
+    // follower. It returns None if the read has been dropped already.
+    fn advance(&mut self, ctx: &[u8], index: u64, term: u64) -> Option<Timespec> {
+        let pos = match self.reads
+            .iter()
+            .skip(self.ready_cnt)
+            .position(|read| read.binary_id() == ctx)
+        {
+            Some(pos) => self.ready_cnt + pos,
+            None => return None,
+        };
+        self.clear_reads(pos, term);
+        let i = self.ready_cnt;
+        self.ready_cnt += 1;
+        let read = &mut self.reads[i];
+        read.read_index = Some(index);
+        Some(read.renew_lease_time)
+    }
+
+    // clear_expired drops the reads that are not ready and are proposed before `deadline`.
+    fn clear_expired(&mut self, deadline: Timespec, term: u64) {
+        let expired = self.reads
+            .iter()
+            .skip(self.ready_cnt)
+            .take_while(|read| read.renew_lease_time < deadline)
+            .count();
+        let end = self.ready_cnt + expired;
+        self.clear_reads(end, term);
+    }
 }
 
 /// The returned states of the peer after checking whether it is stale
@@ -255,6 +300,17 @@ pub struct Peer {
     leader_lease_expired_time: Option<Either<Timespec, Timespec>>,
 
     pub peer_stat: PeerStat,
+    /// The keys accessed recently, to split the region when it's hot.
+    pub load_stat: LoadStat,
+
+    // The states published to serve lease reads out of the raftstore thread,
+    // `read_delegate` is Some only when the peer is leader.
+    local_reader: LocalReader,
+    read_delegate: Option<Arc<ReadDelegate>>,
+
+    // (applied index, safe ts) sent by the leader, a follower advances its resolved ts to
+    // the safe ts after it has applied to the index.
+    pending_safe_ts: VecDeque<(u64, u64)>,
 }
 
 impl Peer {
@@ -365,6 +421,10 @@ impl Peer {
             cfg: cfg,
             leader_lease_expired_time: None,
             peer_stat: PeerStat::default(),
+            load_stat: LoadStat::default(),
+            local_reader: store.local_reader.clone(),
+            read_delegate: None,
+            pending_safe_ts: VecDeque::new(),
         };
 
         // If this region has only one peer and I am the one, campaign directly.
@@ -405,6 +465,7 @@ impl Peer {
             initialized
         };
         self.pending_remove = true;
+        self.update_read_delegate();
         Some(DestroyPeerJob {
             async_remove: async_remove,
             initialized: initialized,
@@ -418,6 +479,9 @@ impl Peer {
 
         let region = self.get_store().get_region().clone();
         info!("{} begin to destroy", self.tag);
+        if self.read_delegate.take().is_some() {
+            self.local_reader.remove(self.region_id);
+        }
 
         // Set Tombstone state explicitly
         let kv_wb = WriteBatch::new();
@@ -548,6 +612,7 @@ impl Peer {
                     self.leader_lease_expired_time = Some(Either::Right(
                         self.next_lease_expired_time(monotonic_raw_now()),
                     ));
+                    self.update_read_delegate();
 
                     metrics.timeout_now += 1;
                 }
@@ -659,6 +724,8 @@ impl Peer {
     fn on_role_changed(&mut self, ready: &Ready, worker: &FutureWorker<PdTask>) {
         // Update leader lease when the Raft state changes.
         if let Some(ref ss) = ready.ss {
+            self.coprocessor_host
+                .on_role_change(self.region(), ss.raft_state);
             match ss.raft_state {
                 StateRole::Leader => {
                     // The local read can only be performed after a new leader has applied
@@ -854,30 +921,72 @@ impl Peer {
             // line won't be called twice for the same snapshot.
             self.raft_group.advance_apply(self.last_applying_idx);
         }
+        self.update_read_delegate();
+    }
+
+    /// Publishes the leader states to the `LocalReader`, it should be called whenever the
+    /// role, the region, the applied index term or the lease of the peer changes.
+    pub fn update_read_delegate(&mut self) {
+        if !self.is_leader() || self.pending_remove {
+            if self.read_delegate.take().is_some() {
+                self.local_reader.remove(self.region_id);
+            }
+            return;
+        }
+
+        let stale = match self.read_delegate {
+            Some(ref delegate) => {
+                delegate.term() != self.term() ||
+                    delegate.region().get_region_epoch() != self.region().get_region_epoch()
+            }
+            None => true,
+        };
+        if stale {
+            let delegate = Arc::new(ReadDelegate::new(
+                self.tag.clone(),
+                self.region().clone(),
+                self.peer_id(),
+                self.term(),
+            ));
+            self.local_reader.insert(self.region_id, delegate.clone());
+            self.read_delegate = Some(delegate);
+        }
+
+        let lease_expired_time = match self.leader_lease_expired_time {
+            Some(Either::Left(safe_expired_time)) => Some(safe_expired_time),
+            _ => None,
+        };
+        self.read_delegate
+            .as_ref()
+            .unwrap()
+            .update(
+                self.get_store().applied_index_term,
+                lease_expired_time,
+                self.raft_group.raft.in_lease(),
+            );
     }
 
     fn apply_reads(&mut self, ready: &Ready) {
         let mut propose_time = None;
-        if self.ready_to_handle_read() {
-            for state in &ready.read_states {
-                let mut read = self.pending_reads.reads.pop_front().unwrap();
-                assert_eq!(state.request_ctx.as_slice(), read.binary_id());
-                for (req, cb) in read.cmds.drain(..) {
-                    // TODO: we should add test case that a split happens before pending
-                    // read-index is handled. To do this we need to control async-apply
-                    // procedure precisely.
-                    cb(self.handle_read(req));
-                }
-                propose_time = Some(read.renew_lease_time);
-            }
-        } else {
-            for state in &ready.read_states {
-                let read = &self.pending_reads.reads[self.pending_reads.ready_cnt];
-                assert_eq!(state.request_ctx.as_slice(), read.binary_id());
-                self.pending_reads.ready_cnt += 1;
-                propose_time = Some(read.renew_lease_time);
+        let term = self.term();
+        for state in &ready.read_states {
+            match self.pending_reads
+                .advance(&state.request_ctx, state.index, term)
+            {
+                Some(t) => propose_time = Some(t),
+                None => warn!(
+                    "{} ignores the read index of dropped read {:?}",
+                    self.tag,
+                    state.request_ctx
+                ),
             }
         }
+        if self.pending_reads.ready_cnt > 0 && self.ready_to_handle_read() {
+            // TODO: we should add test case that a split happens before pending
+            // read-index is handled. To do this we need to control async-apply
+            // procedure precisely.
+            self.handle_ready_reads();
+        }
 
         // Note that only after handle read_states can we identify what requests are
         // actually stale.
@@ -887,6 +996,10 @@ impl Peer {
             self.pending_reads.clear_uncommitted(term);
         }
 
+        // A follower has no lease to renew.
+        if !self.is_leader() {
+            return;
+        }
         if let Some(Either::Right(_)) = self.leader_lease_expired_time {
             return;
         }
@@ -915,6 +1028,11 @@ impl Peer {
             .advance_apply(res.apply_state.get_applied_index());
         self.mut_store().apply_state = res.apply_state.clone();
         self.mut_store().applied_index_term = res.applied_index_term;
+        if self.is_leader() {
+            self.mut_store().resolved_ts = res.resolved_ts;
+        } else {
+            self.advance_safe_ts();
+        }
         self.peer_stat.written_keys += res.metrics.written_keys;
         self.peer_stat.written_bytes += res.metrics.written_bytes;
         store_stat.engine_total_bytes_written += res.metrics.written_bytes;
@@ -934,13 +1052,22 @@ impl Peer {
         }
 
         if self.pending_reads.ready_cnt > 0 && self.ready_to_handle_read() {
-            for _ in 0..self.pending_reads.ready_cnt {
-                let mut read = self.pending_reads.reads.pop_front().unwrap();
-                for (req, cb) in read.cmds.drain(..) {
-                    cb(self.handle_read(req));
-                }
+            self.handle_ready_reads();
+        }
+    }
+
+    // Serves the reads whose read index has been applied, in order.
+    fn handle_ready_reads(&mut self) {
+        let applied_index = self.get_store().applied_index();
+        while self.pending_reads.ready_cnt > 0 {
+            if self.pending_reads.reads[0].read_index.unwrap() > applied_index {
+                break;
+            }
+            let mut read = self.pending_reads.reads.pop_front().unwrap();
+            self.pending_reads.ready_cnt -= 1;
+            for (req, cb) in read.cmds.drain(..) {
+                cb(self.handle_read(req));
             }
-            self.pending_reads.ready_cnt = 0;
         }
     }
 
@@ -1041,6 +1168,9 @@ impl Peer {
         }
 
         metrics.all += 1;
+        if self.is_leader() {
+            self.load_stat.record_request(&req);
+        }
 
         let mut is_conf_change = false;
 
@@ -1156,6 +1286,11 @@ impl Peer {
             return Ok(RequestPolicy::ProposeNormal);
         }
 
+        // A follower can only serve linearizable reads, see `is_follower_read`.
+        if !self.is_leader() {
+            return Ok(RequestPolicy::ReadIndex);
+        }
+
         if (req.has_header() && req.get_header().get_read_quorum()) ||
             !self.raft_group.raft.in_lease()
         {
@@ -1321,6 +1456,9 @@ impl Peer {
         metrics.read_index += 1;
 
         let renew_lease_time = monotonic_raw_now();
+        if !self.is_leader() {
+            return self.follower_read_index(req, cb, renew_lease_time);
+        }
         if let Some(read) = self.pending_reads.reads.back_mut() {
             if read.renew_lease_time + self.cfg.raft_store_max_leader_lease() > renew_lease_time {
                 read.cmds.push((req, cb));
@@ -1351,6 +1489,7 @@ impl Peer {
             id: id,
             cmds: vec![(req, cb)],
             renew_lease_time: renew_lease_time,
+            read_index: None,
         });
 
         match self.leader_lease_expired_time {
@@ -1374,6 +1513,47 @@ impl Peer {
         true
     }
 
+    /// Fails the follower reads that have waited for the read index longer than an election
+    /// timeout with a retriable error, the leader may have dropped them silently. It's
+    /// checked on every raft base tick.
+    pub fn check_expired_follower_reads(&mut self) {
+        if self.is_leader() {
+            return;
+        }
+        let timeout =
+            self.cfg.raft_base_tick_interval.0 * self.cfg.raft_election_timeout_ticks as u32;
+        let deadline = monotonic_raw_now() - TimeDuration::from_std(timeout).unwrap();
+        let term = self.term();
+        self.pending_reads.clear_expired(deadline, term);
+    }
+
+    /// Asks the leader for the read index, the read is served locally once the follower
+    /// has applied to it. Reads are never batched on followers, as a follower doesn't
+    /// know whether the index it has got is still up to date.
+    fn follower_read_index(
+        &mut self,
+        req: RaftCmdRequest,
+        cb: Callback,
+        renew_lease_time: Timespec,
+    ) -> bool {
+        if self.leader_id() == raft::INVALID_ID {
+            // The message is dropped by raft, as there is no leader to forward it to.
+            apply::notify_stale_req(self.term(), cb);
+            return false;
+        }
+
+        let id = self.pending_reads.next_id();
+        let ctx: [u8; 8] = unsafe { mem::transmute(id) };
+        self.raft_group.read_index(ctx.to_vec());
+        self.pending_reads.reads.push_back(ReadIndexRequest {
+            id: id,
+            cmds: vec![(req, cb)],
+            renew_lease_time: renew_lease_time,
+            read_index: None,
+        });
+        true
+    }
+
     fn propose_normal(
         &mut self,
         mut req: RaftCmdRequest,
@@ -1496,6 +1676,156 @@ impl Peer {
         resp
     }
 
+    /// Sends the resolved ts of the leader at `applied_index` to the followers as their
+    /// safe ts.
+    pub fn send_safe_ts<T: Transport>(&mut self, trans: &T, applied_index: u64, safe_ts: u64) {
+        if !self.is_leader() {
+            return;
+        }
+        let peer_ids: Vec<_> = self.region()
+            .get_peers()
+            .iter()
+            .map(|p| p.get_id())
+            .filter(|&id| id != self.peer_id())
+            .collect();
+        let term = self.term();
+        for id in peer_ids {
+            let mut msg = resolved_ts::new_safe_ts_message(term, applied_index, safe_ts);
+            msg.set_from(self.peer_id());
+            msg.set_to(id);
+            if let Err(e) = self.send_raft_message(msg, trans) {
+                warn!("{} failed to send safe ts to {}: {:?}", self.tag, id, e);
+            }
+        }
+    }
+
+    /// Handles the safe ts sent by the leader `from` in `term`, which takes effect after the
+    /// peer has applied to `applied_index`. The safe ts from a stale leader is ignored.
+    pub fn on_safe_ts(&mut self, from: u64, term: u64, applied_index: u64, safe_ts: u64) {
+        if self.is_leader() || from != self.leader_id() || term != self.term() {
+            return;
+        }
+        if self.pending_safe_ts.len() >= MAX_PENDING_SAFE_TS {
+            // The peer lags behind, the earlier safe ts take effect first.
+            return;
+        }
+        self.pending_safe_ts.push_back((applied_index, safe_ts));
+        self.advance_safe_ts();
+    }
+
+    fn advance_safe_ts(&mut self) {
+        let applied_index = self.get_store().applied_index();
+        loop {
+            let (index, safe_ts) = match self.pending_safe_ts.front() {
+                Some(&(index, safe_ts)) if index <= applied_index => (index, safe_ts),
+                _ => return,
+            };
+            debug!("{} advance safe ts to {} at index {}", self.tag, safe_ts, index);
+            self.pending_safe_ts.pop_front();
+            let store = self.mut_store();
+            store.resolved_ts = cmp::max(store.resolved_ts, safe_ts);
+        }
+    }
+
+    /// Reads the applied data for a read at `read_ts`, which can be served once the resolved
+    /// ts of the region reaches `read_ts`. The leader advances the resolved ts by itself, and
+    /// a follower by the safe ts sent by the leader.
+    pub fn stale_read(&mut self, req: RaftCmdRequest, read_ts: u64) -> RaftCmdResponse {
+        let resolved_ts = self.get_store().resolved_ts;
+        if read_ts > resolved_ts {
+            let e = box_err!(
+                "{} resolved ts {} is less than read ts {}",
+                self.tag,
+                resolved_ts,
+                read_ts
+            );
+            return cmd_resp::new_error(e);
+        }
+        PEER_PROPOSAL_COUNTER_VEC
+            .with_label_values(&["stale_read"])
+            .inc();
+        self.handle_read(req)
+    }
+
+    /// Removes the peers on `failed_stores` from the region without going through raft, then
+    /// campaigns to elect a new leader among the surviving peers. It's only used by unsafe
+    /// recovery, and does nothing if the surviving peers still make a quorum.
+    pub fn force_remove_peers(
+        &mut self,
+        failed_stores: &HashSet<u64>,
+    ) -> Result<Option<metapb::Region>> {
+        let failed: Vec<_> = self.region()
+            .get_peers()
+            .iter()
+            .filter(|p| failed_stores.contains(&p.get_store_id()))
+            .cloned()
+            .collect();
+        if failed.is_empty() {
+            return Ok(None);
+        }
+        if failed_stores.contains(&self.peer.get_store_id()) {
+            return Err(box_err!("{} the local store is marked as failed", self.tag));
+        }
+        if self.raft_group.raft.joint.is_some() {
+            return Err(box_err!("{} is in joint consensus", self.tag));
+        }
+        let voters: Vec<u64> = self.raft_group
+            .raft
+            .prs
+            .iter()
+            .filter(|&(_, pr)| !pr.is_learner)
+            .map(|(&id, _)| id)
+            .collect();
+        let alive = voters
+            .iter()
+            .filter(|&&id| failed.iter().all(|p| p.get_id() != id))
+            .count();
+        if alive >= raft::quorum(voters.len()) {
+            return Ok(None);
+        }
+        // The apply delegate is registered again with the new region, it must not have
+        // entries being applied.
+        if self.is_applying_snapshot() || self.has_pending_snapshot() ||
+            self.last_applying_idx != self.get_store().applied_index()
+        {
+            return Err(box_err!("{} is applying, retry later", self.tag));
+        }
+
+        // Every removed peer bumps the conf version like a conf change does, so the
+        // surviving peers end up with the same epoch.
+        let mut region = self.region().clone();
+        let peers = region
+            .take_peers()
+            .into_iter()
+            .filter(|p| !failed_stores.contains(&p.get_store_id()))
+            .collect();
+        region.set_peers(protobuf::RepeatedField::from_vec(peers));
+        let conf_ver = region.get_region_epoch().get_conf_ver() + failed.len() as u64;
+        region.mut_region_epoch().set_conf_ver(conf_ver);
+        let kv_wb = WriteBatch::new();
+        write_peer_state(&self.kv_engine, &kv_wb, &region, PeerState::Normal)?;
+        let mut write_opts = WriteOptions::new();
+        write_opts.set_sync(true);
+        self.kv_engine.write_opt(kv_wb, &write_opts)?;
+
+        warn!(
+            "{} force remove peers {:?}, new region {:?}",
+            self.tag,
+            failed,
+            region
+        );
+        for p in &failed {
+            self.raft_group.raft.remove_node(p.get_id());
+            self.peer_heartbeats.remove(&p.get_id());
+            self.remove_peer_from_cache(p.get_id());
+        }
+        self.mut_store().region = region.clone();
+        let reg = ApplyTask::register(self);
+        self.apply_scheduler.schedule(reg).unwrap();
+        self.raft_group.campaign()?;
+        Ok(Some(region))
+    }
+
     pub fn term(&self) -> u64 {
         self.raft_group.raft.term
     }
@@ -1508,6 +1838,18 @@ impl Peer {
     }
 }
 
+/// Checks whether the request asks a follower to serve a linearizable read, which is
+/// opted in by `read_quorum` in the header when `allow_follower_read` is on. The follower
+/// gets the read index from the leader, and serves the read after it has applied to the
+/// index.
+pub fn is_follower_read(req: &RaftCmdRequest) -> bool {
+    req.get_header().get_read_quorum() && !req.has_admin_request() &&
+        !req.get_requests().is_empty() &&
+        req.get_requests().iter().all(|r| {
+            r.get_cmd_type() == CmdType::Get || r.get_cmd_type() == CmdType::Snap
+        })
+}
+
 pub fn check_epoch(region: &metapb::Region, req: &RaftCmdRequest) -> Result<()> {
     let (mut check_ver, mut check_conf_ver) = (false, false);
     if req.has_admin_request() {
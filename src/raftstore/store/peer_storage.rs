@@ -264,6 +264,8 @@ pub struct PeerStorage {
     pub raft_state: RaftLocalState,
     pub apply_state: RaftApplyState,
     pub applied_index_term: u64,
+    // All the transactions committed at or before the ts have been applied, see `Resolver`.
+    pub resolved_ts: u64,
     pub last_term: u64,
 
     snap_state: RefCell<SnapState>,
@@ -501,6 +503,7 @@ impl PeerStorage {
             snap_tried_cnt: RefCell::new(0),
             tag: tag,
             applied_index_term: RAFT_INIT_LOG_TERM,
+            resolved_ts: 0,
             last_term: last_term,
             cache: EntryCache::default(),
             stats: stats,
@@ -526,6 +529,8 @@ impl PeerStorage {
             return Ok(RaftState {
                 hard_state: hard_state,
                 conf_state: conf_state,
+                learners: vec![],
+                joint: None,
             });
         }
 
@@ -533,9 +538,13 @@ impl PeerStorage {
             conf_state.mut_nodes().push(p.get_id());
         }
 
+        // Raftstore only adds voters by single peer changes, so a region never has
+        // learners or a joint configuration to restore.
         Ok(RaftState {
             hard_state: hard_state,
             conf_state: conf_state,
+            learners: vec![],
+            joint: None,
         })
     }
 
@@ -1174,6 +1183,9 @@ impl PeerStorage {
         }
 
         self.schedule_applying_snapshot();
+        // The resolved ts is reported again after the entries following the snapshot
+        // are applied.
+        self.resolved_ts = 0;
         let prev_region = self.region.clone();
         self.region = snap_region;
 
@@ -1396,6 +1408,7 @@ mod test {
     use raft::{Error as RaftError, StorageError};
     use tempdir::*;
     use protobuf;
+    use raftstore::coprocessor::CoprocessorHost;
     use raftstore::store::{bootstrap, Engines};
     use raftstore::store::worker::RegionRunner;
     use raftstore::store::worker::RegionTask;
@@ -1682,7 +1695,13 @@ mod test {
         let mut worker = Worker::new("snap_manager");
         let sched = worker.scheduler();
         let mut s = new_storage_from_ents(sched, &td, &ents);
-        let runner = RegionRunner::new(s.kv_engine.clone(), s.raft_engine.clone(), mgr, 0);
+        let runner = RegionRunner::new(
+            s.kv_engine.clone(),
+            s.raft_engine.clone(),
+            mgr,
+            0,
+            Arc::new(CoprocessorHost::default()),
+        );
         worker.start(runner).unwrap();
         let snap = s.snapshot();
         let unavailable = RaftError::Store(StorageError::SnapshotTemporarilyUnavailable);
@@ -1965,8 +1984,13 @@ mod test {
         let mut worker = Worker::new("snap_manager");
         let sched = worker.scheduler();
         let s1 = new_storage_from_ents(sched.clone(), &td1, &ents);
-        let runner =
-            RegionRunner::new(s1.kv_engine.clone(), s1.raft_engine.clone(), mgr.clone(), 0);
+        let runner = RegionRunner::new(
+            s1.kv_engine.clone(),
+            s1.raft_engine.clone(),
+            mgr.clone(),
+            0,
+            Arc::new(CoprocessorHost::default()),
+        );
         worker.start(runner).unwrap();
         assert!(s1.snapshot().is_err());
         let snap1 = match *s1.snap_state.borrow() {
@@ -0,0 +1,293 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::collections::BTreeMap;
+
+use rocksdb::DB;
+use kvproto::metapb::Region;
+use kvproto::eraftpb::{Message, MessageType};
+
+use storage::CF_LOCK;
+use storage::mvcc::{Lock, LockType};
+use util::codec::number::{NumberDecoder, NumberEncoder};
+use util::collections::HashMap;
+use raftstore::Result;
+use super::engine::Iterable;
+use super::keys;
+
+/// `Resolver` tracks the outstanding locks of a region with the applied writes, and
+/// computes the resolved ts of the region: all the transactions committed at or before
+/// the resolved ts have been applied, so a read at the resolved ts sees a stable result.
+///
+/// The resolved ts is advanced by `advance_ts` with the ts got from PD on the leader,
+/// see `ConcurrencyManager::resolve_ts`, and the leader sends it to the followers as the
+/// safe ts, see `new_safe_ts_message`. The ts observed from the applied writes can't be
+/// used, as the commit ts of an async-commit or 1PC transaction may be less than the commit
+/// ts of a transaction applied before it.
+///
+/// A lock blocks the resolved ts at its start ts, or at its min commit ts if it's an
+/// async-commit lock, as the transaction can't be committed before it.
+#[derive(Default)]
+pub struct Resolver {
+    // encoded key -> the ts the lock blocks the resolved ts at.
+    locks: HashMap<Vec<u8>, u64>,
+    // ts -> the number of locks with the ts.
+    lock_ts: BTreeMap<u64, usize>,
+    max_ts: u64,
+}
+
+impl Resolver {
+    /// Builds the resolver from the locks of the region in the engine.
+    pub fn from_region(engine: &DB, region: &Region) -> Result<Resolver> {
+        let mut resolver = Resolver::default();
+        let start_key = keys::enc_start_key(region);
+        let end_key = keys::enc_end_key(region);
+        engine.scan_cf(CF_LOCK, &start_key, &end_key, false, &mut |key, value| {
+            resolver.on_lock_put(keys::origin_key(key), value);
+            Ok(true)
+        })?;
+        Ok(resolver)
+    }
+
+    pub fn resolved_ts(&self) -> u64 {
+        match self.lock_ts.keys().next() {
+            Some(&min_ts) => cmp::min(self.max_ts, min_ts.saturating_sub(1)),
+            None => self.max_ts,
+        }
+    }
+
+    /// Handles a lock put, `key` is the encoded key without the data prefix.
+    pub fn on_lock_put(&mut self, key: &[u8], value: &[u8]) {
+        let lock = match Lock::parse(value) {
+            Ok(lock) => lock,
+            Err(e) => {
+                error!("failed to parse lock of key {:?}: {:?}", key, e);
+                return;
+            }
+        };
+        // A pessimistic lock doesn't block reads, it becomes a normal lock by prewrite.
+        if lock.lock_type == LockType::Pessimistic {
+            self.untrack_lock(key);
+        } else if lock.use_async_commit {
+            self.track_lock(key.to_vec(), cmp::max(lock.min_commit_ts, lock.ts + 1));
+        } else {
+            self.track_lock(key.to_vec(), lock.ts);
+        }
+    }
+
+    /// Advances the resolved ts to `ts` if no lock blocks it, `ts` must be got by
+    /// `ConcurrencyManager::resolve_ts` on the leader.
+    pub fn advance_ts(&mut self, ts: u64) {
+        self.max_ts = cmp::max(self.max_ts, ts);
+    }
+
+    pub fn track_lock(&mut self, key: Vec<u8>, ts: u64) {
+        if let Some(old_ts) = self.locks.insert(key, ts) {
+            self.remove_lock_ts(old_ts);
+        }
+        *self.lock_ts.entry(ts).or_insert(0) += 1;
+    }
+
+    pub fn untrack_lock(&mut self, key: &[u8]) {
+        if let Some(ts) = self.locks.remove(key) {
+            self.remove_lock_ts(ts);
+        }
+    }
+
+    /// Untracks the locks in the encoded range [`start_key`, `end_key`), an empty
+    /// `end_key` means no upper bound.
+    pub fn untrack_range(&mut self, start_key: &[u8], end_key: &[u8]) {
+        self.retain(|key| key < start_key || (!end_key.is_empty() && key >= end_key));
+    }
+
+    /// Untracks the locks out of the region, used after the region is split.
+    pub fn retain_region(&mut self, region: &Region) {
+        let (start_key, end_key) = (region.get_start_key(), region.get_end_key());
+        self.retain(|key| key >= start_key && (end_key.is_empty() || key < end_key));
+    }
+
+    fn retain<F: Fn(&[u8]) -> bool>(&mut self, f: F) {
+        let removed: Vec<_> = self.locks
+            .keys()
+            .filter(|k| !f(k))
+            .cloned()
+            .collect();
+        for key in removed {
+            self.untrack_lock(&key);
+        }
+    }
+
+    fn remove_lock_ts(&mut self, ts: u64) {
+        let empty = {
+            let count = self.lock_ts.get_mut(&ts).unwrap();
+            *count -= 1;
+            *count == 0
+        };
+        if empty {
+            self.lock_ts.remove(&ts);
+        }
+    }
+}
+
+/// Creates the message the leader sends the resolved ts to a follower with. A follower can
+/// serve stale reads at `safe_ts` once it has applied to `applied_index`, the applied index
+/// of the leader when the resolved ts was computed.
+///
+/// kvproto has no message for the safe ts, so it's sent as a `MsgCheckQuorum`, which raft
+/// never sends to other peers, with the applied index in `index` and the safe ts in
+/// `context`. The raftstore handles it before stepping raft, see `parse_safe_ts_message`.
+pub fn new_safe_ts_message(term: u64, applied_index: u64, safe_ts: u64) -> Message {
+    let mut context = Vec::with_capacity(8);
+    context.encode_u64(safe_ts).unwrap();
+    let mut msg = Message::new();
+    msg.set_msg_type(MessageType::MsgCheckQuorum);
+    msg.set_term(term);
+    msg.set_index(applied_index);
+    msg.set_context(context);
+    msg
+}
+
+/// Returns the applied index and the safe ts if `msg` is sent by `new_safe_ts_message`.
+pub fn parse_safe_ts_message(msg: &Message) -> Option<(u64, u64)> {
+    if msg.get_msg_type() != MessageType::MsgCheckQuorum {
+        return None;
+    }
+    let mut context = msg.get_context();
+    match context.decode_u64() {
+        Ok(safe_ts) if context.is_empty() => Some((msg.get_index(), safe_ts)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempdir::TempDir;
+    use rocksdb::Writable;
+
+    use storage::{make_key, ALL_CFS};
+    use util::rocksdb::{self, new_engine};
+    use super::*;
+
+    fn lock_value(lock_type: LockType, ts: u64) -> Vec<u8> {
+        Lock::new(lock_type, b"pk".to_vec(), ts, 0, None).to_bytes()
+    }
+
+    #[test]
+    fn test_resolver() {
+        let mut resolver = Resolver::default();
+        assert_eq!(resolver.resolved_ts(), 0);
+        resolver.advance_ts(12);
+        assert_eq!(resolver.resolved_ts(), 12);
+
+        let k1 = make_key(b"k1").encoded().to_owned();
+        let k2 = make_key(b"k2").encoded().to_owned();
+        resolver.on_lock_put(&k1, &lock_value(LockType::Put, 10));
+        assert_eq!(resolver.resolved_ts(), 9);
+        resolver.on_lock_put(&k2, &lock_value(LockType::Delete, 5));
+        assert_eq!(resolver.resolved_ts(), 4);
+
+        // The commit ts of the writes is not observed.
+        resolver.untrack_lock(&k2);
+        assert_eq!(resolver.resolved_ts(), 9);
+        resolver.untrack_lock(&k1);
+        assert_eq!(resolver.resolved_ts(), 12);
+        resolver.advance_ts(15);
+        assert_eq!(resolver.resolved_ts(), 15);
+
+        // Pessimistic locks don't block reads.
+        resolver.on_lock_put(&k1, &lock_value(LockType::Pessimistic, 15));
+        assert_eq!(resolver.resolved_ts(), 15);
+        resolver.on_lock_put(&k1, &lock_value(LockType::Put, 15));
+        assert_eq!(resolver.resolved_ts(), 14);
+        // The lock is overwritten.
+        resolver.track_lock(k1.clone(), 16);
+        assert_eq!(resolver.resolved_ts(), 15);
+
+        // Bad locks are ignored.
+        resolver.on_lock_put(&k2, b"bad");
+        assert_eq!(resolver.resolved_ts(), 15);
+
+        resolver.track_lock(k2.clone(), 13);
+        let mut region = Region::new();
+        region.set_end_key(k2.clone());
+        resolver.retain_region(&region);
+        assert_eq!(resolver.resolved_ts(), 15);
+        resolver.advance_ts(20);
+        assert_eq!(resolver.resolved_ts(), 15);
+        resolver.untrack_range(&[], &k2);
+        assert_eq!(resolver.resolved_ts(), 20);
+
+        // A lock at ts 0 doesn't underflow.
+        resolver.track_lock(k1.clone(), 0);
+        assert_eq!(resolver.resolved_ts(), 0);
+    }
+
+    #[test]
+    fn test_safe_ts_message() {
+        let msg = new_safe_ts_message(5, 10, 20);
+        assert_eq!(msg.get_term(), 5);
+        assert_eq!(parse_safe_ts_message(&msg), Some((10, 20)));
+
+        let mut msg = Message::new();
+        msg.set_msg_type(MessageType::MsgHeartbeat);
+        assert_eq!(parse_safe_ts_message(&msg), None);
+        msg.set_msg_type(MessageType::MsgCheckQuorum);
+        assert_eq!(parse_safe_ts_message(&msg), None);
+    }
+
+    #[test]
+    fn test_resolver_async_commit() {
+        let mut resolver = Resolver::default();
+        resolver.advance_ts(30);
+        let k1 = make_key(b"k1").encoded().to_owned();
+        let k2 = make_key(b"k2").encoded().to_owned();
+        // An async-commit lock blocks the resolved ts at its min commit ts.
+        let lock = Lock::new(LockType::Put, b"k1".to_vec(), 10, 0, None)
+            .with_min_commit_ts(20)
+            .with_async_commit(vec![k2.clone()]);
+        resolver.on_lock_put(&k1, &lock.to_bytes());
+        assert_eq!(resolver.resolved_ts(), 19);
+        let lock = Lock::new(LockType::Put, b"k1".to_vec(), 10, 0, None)
+            .with_async_commit(vec![]);
+        resolver.on_lock_put(&k2, &lock.to_bytes());
+        assert_eq!(resolver.resolved_ts(), 10);
+        resolver.untrack_lock(&k2);
+        assert_eq!(resolver.resolved_ts(), 19);
+        resolver.untrack_lock(&k1);
+        assert_eq!(resolver.resolved_ts(), 30);
+    }
+
+    #[test]
+    fn test_resolver_from_region() {
+        let path = TempDir::new("test-resolved-ts").unwrap();
+        let engine = Arc::new(new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
+        let handle = rocksdb::get_cf_handle(&engine, CF_LOCK).unwrap();
+        for (k, ts) in vec![(b"k1", 10), (b"k3", 5), (b"k5", 3)] {
+            let key = keys::data_key(make_key(k).encoded());
+            engine
+                .put_cf(handle, &key, &lock_value(LockType::Put, ts))
+                .unwrap();
+        }
+
+        let mut region = Region::new();
+        region.set_start_key(make_key(b"k1").encoded().to_owned());
+        region.set_end_key(make_key(b"k4").encoded().to_owned());
+        let mut resolver = Resolver::from_region(&engine, &region).unwrap();
+        assert_eq!(resolver.locks.len(), 2);
+        resolver.advance_ts(20);
+        assert_eq!(resolver.resolved_ts(), 4);
+    }
+}
@@ -221,11 +221,10 @@ use std::time::Instant;
 use crc::crc32::{self, Digest, Hasher32};
 use protobuf::RepeatedField;
 use kvproto::raft_serverpb::{SnapshotCFFile, SnapshotMeta};
-use rocksdb::{DBCompressionType, EnvOptions, IngestExternalFileOptions, SstFileWriter};
+use rocksdb::{IngestExternalFileOptions, SstFileWriter};
 use util::rocksdb;
 use util::time::duration_to_sec;
 use util::file::{delete_file_if_exist, file_exists, get_file_size};
-use util::rocksdb::get_fastest_supported_compression_type;
 
 pub const SNAPSHOT_VERSION: u64 = 2;
 const META_FILE_SUFFIX: &'static str = ".meta";
@@ -503,16 +502,11 @@ impl Snap {
                     .open(&cf_file.tmp_path)?;
                 cf_file.file = Some(f);
             } else {
-                let handle = snap.cf_handle(cf_file.cf)?;
-                let mut io_options = snap.get_db().get_options_cf(handle).clone();
-                io_options.compression(get_fastest_supported_compression_type());
-                // in rocksdb 5.5.1, SstFileWriter will try to use bottommost_compression and
-                // compression_per_level first, so to make sure our specified compression type
-                // being used, we must set them empty or disabled.
-                io_options.compression_per_level(&[]);
-                io_options.bottommost_compression(DBCompressionType::Disable);
-                let mut writer = SstFileWriter::new(EnvOptions::new(), io_options);
-                box_try!(writer.open(cf_file.tmp_path.as_path().to_str().unwrap()));
+                let writer = box_try!(rocksdb::new_sst_writer(
+                    &snap.get_db(),
+                    cf_file.cf,
+                    cf_file.tmp_path.as_path().to_str().unwrap()
+                ));
                 cf_file.sst_writer = Some(writer);
             }
         }
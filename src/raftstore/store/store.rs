@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::boxed::FnBox;
 use std::sync::Arc;
 use std::sync::mpsc::{self, Receiver as StdReceiver, TryRecvError};
 use std::rc::Rc;
@@ -18,13 +19,13 @@ use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::collections::Bound::{Excluded, Included, Unbounded};
 use std::time::{Duration, Instant};
-use std::thread;
+use std::{cmp, thread};
 use std::u64;
 
 use rocksdb::{WriteBatch, DB};
 use rocksdb::rocksdb_options::WriteOptions;
 use mio::{self, EventLoop, EventLoopConfig, Sender};
-use protobuf;
+use protobuf::{self, RepeatedField};
 use time::{self, Timespec};
 
 use kvproto::raft_serverpb::{PeerState, RaftMessage, RaftSnapshotData, RaftTruncatedState,
@@ -34,8 +35,8 @@ use kvproto::pdpb::StoreStats;
 use util::{escape, rocksdb};
 use util::time::{duration_to_sec, SlowTimer};
 use pd::{PdClient, PdRunner, PdTask};
-use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, RaftCmdRequest, RaftCmdResponse,
-                          StatusCmdType, StatusResponse};
+use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, CmdType, RaftCmdRequest,
+                          RaftCmdResponse, Request, StatusCmdType, StatusResponse};
 use protobuf::Message;
 use raft::{self, SnapshotStatus, INVALID_INDEX};
 use raftstore::{Error, Result};
@@ -44,13 +45,13 @@ use util::worker::{FutureWorker, Scheduler, Stopped, Worker};
 use util::transport::SendCh;
 use util::RingQueue;
 use util::collections::{HashMap, HashSet};
-use storage::{CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+use storage::{ConcurrencyManager, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
 use raftstore::coprocessor::CoprocessorHost;
 use raftstore::coprocessor::split_observer::SplitObserver;
 use super::worker::{ApplyRunner, ApplyTask, ApplyTaskRes, CompactRunner, CompactTask,
                     ConsistencyCheckRunner, ConsistencyCheckTask, RaftlogGcRunner, RaftlogGcTask,
                     RegionRunner, RegionTask, SplitCheckRunner, SplitCheckTask};
-use super::worker::apply::{ChangePeer, ExecResult};
+use super::worker::apply::{ChangePeer, ExecResult, IMPORT_CMD_UUID};
 use super::{util, Msg, SignificantMsg, SnapManager, SnapshotDeleter, Tick};
 use super::keys::{self, data_end_key, data_key, enc_end_key, enc_start_key};
 use super::engine::{Iterable, Peekable, Snapshot as EngineSnapshot};
@@ -62,6 +63,10 @@ use super::cmd_resp::{bind_term, new_error};
 use super::transport::Transport;
 use super::metrics::*;
 use super::local_metrics::RaftMetrics;
+use super::load::LoadStat;
+use super::local_reader::LocalReader;
+use super::resolved_ts;
+use super::unsafe_recovery::{ForceRemoveCallback, PeerReport, ReportCallback};
 
 type Key = Vec<u8>;
 
@@ -153,6 +158,9 @@ pub struct Store<T, C: 'static> {
     pd_client: Arc<C>,
 
     pub coprocessor_host: Arc<CoprocessorHost>,
+    pub local_reader: LocalReader,
+    // Pushes the max ts for the stale reads and bounds the resolved ts of the leaders.
+    cm: ConcurrencyManager,
 
     snap_mgr: SnapManager,
 
@@ -195,6 +203,8 @@ impl<T, C> Store<T, C> {
         mgr: SnapManager,
         pd_worker: FutureWorker<PdTask>,
         mut coprocessor_host: CoprocessorHost,
+        local_reader: LocalReader,
+        cm: ConcurrencyManager,
     ) -> Result<Store<T, C>> {
         // TODO: we can get cluster meta regularly too later.
         cfg.validate()?;
@@ -229,6 +239,8 @@ impl<T, C> Store<T, C> {
             trans: trans,
             pd_client: pd_client,
             coprocessor_host: Arc::new(coprocessor_host),
+            local_reader: local_reader,
+            cm: cm,
             snap_mgr: mgr,
             raft_metrics: RaftMetrics::default(),
             entry_cache_metries: Rc::new(RefCell::new(CacheQueryStats::default())),
@@ -500,6 +512,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         self.register_snap_mgr_gc_tick(event_loop);
         self.register_compact_lock_cf_tick(event_loop);
         self.register_consistency_check_tick(event_loop);
+        self.register_advance_resolved_ts_tick(event_loop);
 
         let split_check_runner = SplitCheckRunner::new(
             self.kv_engine.clone(),
@@ -514,6 +527,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             self.raft_engine.clone(),
             self.snap_mgr.clone(),
             self.cfg.snap_apply_batch_size.0 as usize,
+            self.coprocessor_host.clone(),
         );
         box_try!(self.region_worker.start(runner));
 
@@ -605,6 +619,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             if peer.raft_group.tick() {
                 peer.mark_to_be_checked(&mut self.pending_raft_groups);
             }
+            peer.check_expired_follower_reads();
 
             // If this peer detects the leader is missing for a long long time,
             // it should consider itself as a stale peer which is removed from
@@ -659,11 +674,21 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                     }
                     self.store_stat.lock_cf_bytes_written += res.metrics.lock_cf_written_bytes;
                     self.on_ready_result(res.region_id, res.exec_res);
+                    // The applied index term and the region may change.
+                    if let Some(p) = self.region_peers.get_mut(&res.region_id) {
+                        p.update_read_delegate();
+                    }
                 },
                 Ok(ApplyTaskRes::Destroy(p)) => {
                     let store_id = self.store_id();
                     self.destroy_peer(p.region_id(), util::new_peer(store_id, p.id()));
                 }
+                Ok(ApplyTaskRes::ResolvedTs(res)) => for (region_id, index, ts) in res {
+                    if let Some(p) = self.region_peers.get_mut(&region_id) {
+                        p.mut_store().resolved_ts = ts;
+                        p.send_safe_ts(&self.trans, index, ts);
+                    }
+                },
                 Err(TryRecvError::Empty) => break,
                 Err(e) => panic!("unexpected error {:?}", e),
             }
@@ -766,6 +791,14 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             return Ok(());
         }
 
+        if let Some((index, safe_ts)) = resolved_ts::parse_safe_ts_message(msg.get_message()) {
+            if let Some(peer) = self.region_peers.get_mut(&region_id) {
+                let term = msg.get_message().get_term();
+                peer.on_safe_ts(msg.get_from_peer().get_id(), term, index, safe_ts);
+            }
+            return Ok(());
+        }
+
         if self.check_msg(&msg)? {
             return Ok(());
         }
@@ -1615,7 +1648,10 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             Some(peer) => peer,
             None => return Err(Error::RegionNotFound(region_id)),
         };
-        if !peer.is_leader() {
+        // A follower which knows the leader can serve linearizable reads if it's allowed.
+        let follower_read = self.cfg.allow_follower_read && peer::is_follower_read(msg) &&
+            peer.leader_id() != raft::INVALID_ID;
+        if !peer.is_leader() && !follower_read {
             return Err(Error::NotLeader(
                 region_id,
                 peer.get_peer_from_cache(peer.leader_id()),
@@ -1771,6 +1807,8 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     fn on_split_region_check_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        self.on_load_split_check();
+
         // To avoid frequent scan, we only add new scan tasks if all previous tasks
         // have finished.
         // TODO: check whether a gc progress has been started.
@@ -1801,6 +1839,43 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         self.register_split_region_check_tick(event_loop);
     }
 
+    // Splits the regions whose load stays high, it's checked on every split region
+    // check tick.
+    fn on_load_split_check(&mut self) {
+        if self.cfg.region_split_qps_threshold == 0 {
+            return;
+        }
+        let interval = cmp::max(self.cfg.split_region_check_tick_interval.as_millis(), 1);
+        let hot_checks = cmp::max(self.cfg.region_split_load_duration.as_millis() / interval, 1);
+        let mut hot_regions = vec![];
+        for peer in self.region_peers.values_mut() {
+            // The load is checked even if it's not leader now, so that the stale
+            // samples are cleaned.
+            let threshold = self.cfg.region_split_qps_threshold;
+            let split_key = match peer.load_stat.check(interval, threshold, hot_checks) {
+                Some(split_key) => split_key,
+                None => continue,
+            };
+            if !peer.is_leader() {
+                continue;
+            }
+            info!(
+                "{} region is hot, try to split at {}",
+                peer.tag,
+                escape(&split_key)
+            );
+            let region = peer.region();
+            hot_regions.push((
+                region.get_id(),
+                region.get_region_epoch().clone(),
+                split_key,
+            ));
+        }
+        for (region_id, region_epoch, split_key) in hot_regions {
+            self.on_prepare_split_region(region_id, region_epoch, split_key, None);
+        }
+    }
+
     fn register_compact_check_tick(&self, event_loop: &mut EventLoop<Self>) {
         if let Err(e) = register_timer(
             event_loop,
@@ -1934,6 +2009,192 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         peer.approximate_size = Some(region_size);
     }
 
+    fn on_regions_read_load(&mut self, load_stats: HashMap<u64, LoadStat>) {
+        for (region_id, load_stat) in load_stats {
+            if let Some(peer) = self.region_peers.get_mut(&region_id) {
+                if peer.is_leader() {
+                    peer.load_stat.merge(load_stat);
+                }
+            }
+        }
+    }
+
+    fn on_import(&mut self, requests: Vec<Request>, cb: Callback) {
+        let (region_id, peer, epoch) = match self.find_import_region(&requests) {
+            Ok(res) => res,
+            Err(e) => {
+                cb.call_box((new_error(e),));
+                return;
+            }
+        };
+        let mut request = new_admin_request(region_id, peer);
+        request.mut_header().set_region_epoch(epoch);
+        request.mut_header().set_uuid(IMPORT_CMD_UUID.to_vec());
+        request.set_requests(RepeatedField::from_vec(requests));
+        self.propose_raft_command(request, cb);
+    }
+
+    fn on_unsafe_recovery_report(&mut self, cb: ReportCallback) {
+        let reports = self.region_peers
+            .values()
+            .filter(|p| p.is_initialized())
+            .map(|p| {
+                let store = p.get_store();
+                PeerReport {
+                    region: p.region().clone(),
+                    peer_id: p.peer_id(),
+                    term: p.term(),
+                    last_index: store.last_index(),
+                    commit_index: store.committed_index(),
+                    applied_index: store.applied_index(),
+                    is_leader: p.is_leader(),
+                }
+            })
+            .collect();
+        cb.call_box((reports,));
+    }
+
+    fn on_unsafe_recovery_force_remove(
+        &mut self,
+        failed_stores: HashSet<u64>,
+        cb: ForceRemoveCallback,
+    ) {
+        warn!(
+            "{} unsafe recovery, removing peers on stores {:?}",
+            self.tag,
+            failed_stores
+        );
+        let mut results = vec![];
+        for (&region_id, peer) in &mut self.region_peers {
+            match peer.force_remove_peers(&failed_stores) {
+                Ok(None) => continue,
+                Ok(Some(region)) => {
+                    self.pending_raft_groups.insert(region_id);
+                    results.push((region_id, Ok(region)));
+                }
+                Err(e) => {
+                    error!(
+                        "{} failed to force remove peers: {:?}",
+                        peer.tag,
+                        e
+                    );
+                    results.push((region_id, Err(e)));
+                }
+            }
+        }
+        cb.call_box((results,));
+    }
+
+    fn register_advance_resolved_ts_tick(&self, event_loop: &mut EventLoop<Self>) {
+        if let Err(e) = register_timer(
+            event_loop,
+            Tick::AdvanceResolvedTs,
+            self.cfg.advance_resolved_ts_interval.as_millis(),
+        ) {
+            error!("{} register advance resolved ts tick err: {:?}", self.tag, e);
+        };
+    }
+
+    fn on_advance_resolved_ts_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        if let Err(e) = self.pd_worker.schedule(PdTask::AdvanceResolvedTs) {
+            error!("{} failed to get tso: {:?}", self.tag, e);
+        }
+        self.register_advance_resolved_ts_tick(event_loop);
+    }
+
+    // The resolved ts is advanced with the ts got from PD, so an idle region doesn't stall.
+    // Only the leaders advance it, as the async-commit and 1PC prewrites are bounded by the
+    // max ts of the leader, and then send it to the followers as the safe ts.
+    fn on_advance_resolved_ts(&mut self, tso: u64) {
+        let ts = self.cm.resolve_ts(tso);
+        let regions: Vec<_> = self.region_peers
+            .iter()
+            .filter(|&(_, p)| p.is_leader() && !p.is_applying_snapshot())
+            .map(|(&region_id, _)| region_id)
+            .collect();
+        if regions.is_empty() {
+            return;
+        }
+        let task = ApplyTask::advance_resolved_ts(regions, ts);
+        if let Err(e) = self.apply_worker.schedule(task) {
+            error!("{} failed to advance resolved ts: {:?}", self.tag, e);
+        }
+    }
+
+    fn on_stale_read(&mut self, read_ts: u64, request: RaftCmdRequest, cb: Callback) {
+        if let Err(e) = self.validate_stale_read(&request) {
+            cb.call_box((new_error(e),));
+            return;
+        }
+        // An async-commit or 1PC transaction prewritten later must commit after the read.
+        self.cm.update_max_ts(read_ts);
+        let region_id = request.get_header().get_region_id();
+        let peer = self.region_peers.get_mut(&region_id).unwrap();
+        cb.call_box((peer.stale_read(request, read_ts),));
+    }
+
+    fn validate_stale_read(&self, msg: &RaftCmdRequest) -> Result<()> {
+        self.validate_store_id(msg)?;
+        if msg.has_admin_request() || msg.get_requests().is_empty() ||
+            msg.get_requests().iter().any(|r| {
+                r.get_cmd_type() != CmdType::Get && r.get_cmd_type() != CmdType::Snap
+            }) {
+            return Err(box_err!("only get and snap can be stale read"));
+        }
+        let region_id = msg.get_header().get_region_id();
+        // Any peer can serve stale reads, a follower with the safe ts sent by the leader.
+        let peer = match self.region_peers.get(&region_id) {
+            Some(peer) => peer,
+            None => return Err(Error::RegionNotFound(region_id)),
+        };
+        let peer_id = msg.get_header().get_peer().get_id();
+        if peer.peer_id() != peer_id {
+            return Err(box_err!(
+                "mismatch peer id {} != {}",
+                peer.peer_id(),
+                peer_id
+            ));
+        }
+        Ok(())
+    }
+
+    fn find_import_region(
+        &self,
+        requests: &[Request],
+    ) -> Result<(u64, metapb::Peer, metapb::RegionEpoch)> {
+        let mut keys = Vec::with_capacity(requests.len());
+        for req in requests {
+            if req.get_cmd_type() != CmdType::Put {
+                return Err(box_err!(
+                    "only put can be imported, but got {:?}",
+                    req.get_cmd_type()
+                ));
+            }
+            keys.push(req.get_put().get_key());
+        }
+        let start_key = match keys.iter().min() {
+            Some(key) => *key,
+            None => return Err(box_err!("nothing to import")),
+        };
+        let region_id = match self.region_ranges
+            .range((Excluded(data_key(start_key)), Unbounded::<Key>))
+            .next()
+        {
+            Some((_, &region_id)) => region_id,
+            None => return Err(box_err!("no region contains key {}", escape(start_key))),
+        };
+        let peer = &self.region_peers[&region_id];
+        let region = peer.region();
+        for key in keys {
+            util::check_key_in_region(key, region)?;
+        }
+        Ok((
+            region_id,
+            peer.peer.clone(),
+            region.get_region_epoch().clone(),
+        ))
+    }
+
     fn on_pd_heartbeat_tick(&mut self, event_loop: &mut EventLoop<Self>) {
         for peer in self.region_peers.values_mut() {
             peer.check_peers();
@@ -2473,6 +2734,19 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
                 region_id,
                 region_size,
             } => self.on_approximate_region_size(region_id, region_size),
+            Msg::RegionsReadLoad { load_stats } => self.on_regions_read_load(load_stats),
+            Msg::AdvanceResolvedTs { tso } => self.on_advance_resolved_ts(tso),
+            Msg::Import { requests, callback } => self.on_import(requests, callback),
+            Msg::StaleRead {
+                read_ts,
+                request,
+                callback,
+            } => self.on_stale_read(read_ts, request, callback),
+            Msg::UnsafeRecoveryReport { callback } => self.on_unsafe_recovery_report(callback),
+            Msg::UnsafeRecoveryForceRemove {
+                failed_stores,
+                callback,
+            } => self.on_unsafe_recovery_force_remove(failed_stores, callback),
         }
     }
 
@@ -2488,6 +2762,7 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             Tick::SnapGc => self.on_snap_mgr_gc(event_loop),
             Tick::CompactLockCf => self.on_compact_lock_cf(event_loop),
             Tick::ConsistencyCheck => self.on_consistency_check_tick(event_loop),
+            Tick::AdvanceResolvedTs => self.on_advance_resolved_ts_tick(event_loop),
         }
         slow_log!(t, "{} handle timeout {:?}", self.tag, timeout);
     }
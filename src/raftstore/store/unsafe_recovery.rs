@@ -0,0 +1,148 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unsafe recovery brings back the regions that lost the quorum after a majority of their
+//! stores failed for good.
+//!
+//! The flow is driven by an administrator who restarts every surviving store with
+//! `--unsafe-recover-failed-stores`, and runs on the store once it is online:
+//! 1. Every surviving store reports the states of its peers by `report`.
+//! 2. Every surviving store removes the peers on the failed stores by `force_remove_peers`.
+//!    The regions that lost the quorum drop those peers from the conf state without going
+//!    through raft, then campaign so the surviving peers elect a new leader. The removal is
+//!    deterministic, so the surviving peers of a region end up with the same epoch.
+//! 3. The ranges that no surviving peer covers are found by `find_lost_ranges` over the
+//!    regions in PD that have a surviving peer, they need to be recreated as empty regions.
+//!
+//! The writes committed only on the failed stores are lost, hence "unsafe".
+
+use std::boxed::FnBox;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use kvproto::metapb::Region;
+
+use raftstore::{Error, Result};
+use util::collections::HashSet;
+use util::transport::SendCh;
+use super::Msg;
+
+/// The state of a peer reported for unsafe recovery.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerReport {
+    pub region: Region,
+    pub peer_id: u64,
+    pub term: u64,
+    pub last_index: u64,
+    pub commit_index: u64,
+    pub applied_index: u64,
+    pub is_leader: bool,
+}
+
+pub type ReportCallback = Box<FnBox(Vec<PeerReport>) + Send>;
+/// Called with the regions the peers are removed from, and the new regions or errors.
+pub type ForceRemoveCallback = Box<FnBox(Vec<(u64, Result<Region>)>) + Send>;
+
+/// Collects the states of all the initialized peers on the store.
+pub fn report(ch: &SendCh<Msg>, timeout: Duration) -> Result<Vec<PeerReport>> {
+    let (tx, rx) = mpsc::channel();
+    ch.send(Msg::UnsafeRecoveryReport {
+        callback: box move |reports| { tx.send(reports).unwrap(); },
+    })?;
+    rx.recv_timeout(timeout)
+        .map_err(|_| Error::Timeout(format!("report timeout for {:?}", timeout)))
+}
+
+/// Removes the peers on `failed_stores` from the regions on the store that lost the quorum.
+pub fn force_remove_peers(
+    ch: &SendCh<Msg>,
+    failed_stores: HashSet<u64>,
+    timeout: Duration,
+) -> Result<Vec<(u64, Result<Region>)>> {
+    let (tx, rx) = mpsc::channel();
+    ch.send(Msg::UnsafeRecoveryForceRemove {
+        failed_stores: failed_stores,
+        callback: box move |res| { tx.send(res).unwrap(); },
+    })?;
+    rx.recv_timeout(timeout)
+        .map_err(|_| Error::Timeout(format!("force remove timeout for {:?}", timeout)))
+}
+
+/// Finds the key ranges not covered by any of the regions. An empty end key means no upper
+/// bound.
+pub fn find_lost_ranges(regions: &[Region]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut ranges: Vec<_> = regions
+        .iter()
+        .map(|r| (r.get_start_key(), r.get_end_key()))
+        .collect();
+    ranges.sort();
+
+    let mut lost = vec![];
+    // All the keys before `covered` are covered.
+    let mut covered: &[u8] = b"";
+    for (start_key, end_key) in ranges {
+        if start_key > covered {
+            lost.push((covered.to_vec(), start_key.to_vec()));
+        }
+        if end_key.is_empty() {
+            return lost;
+        }
+        if end_key > covered {
+            covered = end_key;
+        }
+    }
+    lost.push((covered.to_vec(), vec![]));
+    lost
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_region(start_key: &[u8], end_key: &[u8]) -> Region {
+        let mut region = Region::new();
+        region.set_start_key(start_key.to_vec());
+        region.set_end_key(end_key.to_vec());
+        region
+    }
+
+    #[test]
+    fn test_find_lost_ranges() {
+        let cases = vec![
+            (vec![], vec![("", "")]),
+            (vec![("", "")], vec![]),
+            (vec![("", "k1"), ("k1", "")], vec![]),
+            (vec![("k1", "k3")], vec![("", "k1"), ("k3", "")]),
+            (
+                vec![("k5", ""), ("", "k1"), ("k2", "k3")],
+                vec![("k1", "k2"), ("k3", "k5")],
+            ),
+            // Stale regions may overlap with the others.
+            (
+                vec![("", "k3"), ("k1", "k2"), ("k2", "k4"), ("k5", "k6")],
+                vec![("k4", "k5"), ("k6", "")],
+            ),
+        ];
+        for (regions, expect) in cases {
+            let regions: Vec<_> = regions
+                .into_iter()
+                .map(|(s, e)| new_region(s.as_bytes(), e.as_bytes()))
+                .collect();
+            let expect: Vec<_> = expect
+                .into_iter()
+                .map(|(s, e)| (s.as_bytes().to_vec(), e.as_bytes().to_vec()))
+                .collect();
+            assert_eq!(find_lost_ranges(&regions), expect, "{:?}", regions);
+        }
+    }
+}
@@ -19,8 +19,8 @@ use kvproto::raft_serverpb::RaftMessage;
 use raftstore::{Error, Result};
 use raftstore::store::keys;
 use rocksdb::{Range, TablePropertiesCollection, Writable, WriteBatch, DB};
-use storage::LARGE_CFS;
-use util::properties::SizeProperties;
+use storage::{CF_WRITE, LARGE_CFS};
+use util::properties::{RowsProperties, SizeProperties};
 use util::rocksdb as rocksdb_util;
 use super::engine::{IterOption, Iterable};
 
@@ -176,6 +176,22 @@ pub fn get_region_approximate_size(db: &DB, region: &metapb::Region) -> Result<u
     Ok(size)
 }
 
+/// Gets the approximate number of keys in the region, which is counted by the
+/// rows properties of the write CF, so every version of a key is counted once.
+pub fn get_region_approximate_keys(db: &DB, region: &metapb::Region) -> Result<u64> {
+    let cf = rocksdb_util::get_cf_handle(db, CF_WRITE)?;
+    let start = keys::enc_start_key(region);
+    let end = keys::enc_end_key(region);
+    let range = Range::new(&start, &end);
+    let (mut keys, _) = db.get_approximate_memtable_stats_cf(cf, &range);
+    let collection = db.get_properties_of_tables_in_range(cf, &[range])?;
+    for (_, v) in &*collection {
+        let props = RowsProperties::decode(v.user_collected_properties())?;
+        keys += props.get_approximate_rows_in_range(&start, &end);
+    }
+    Ok(keys)
+}
+
 #[cfg(test)]
 mod tests {
     use std::process;
@@ -186,11 +202,12 @@ mod tests {
 
     use super::*;
     use raftstore::store::peer_storage;
-    use util::properties::SizePropertiesCollectorFactory;
+    use util::properties::{MvccPropertiesCollectorFactory, SizePropertiesCollectorFactory};
 
     use rocksdb::{ColumnFamilyOptions, DBOptions, SeekKey, Writable, WriteBatch, DB};
     use util::rocksdb::{get_cf_handle, new_engine_opt, CFOptions};
-    use storage::ALL_CFS;
+    use storage::{make_key, ALL_CFS};
+    use storage::mvcc::{Write, WriteType};
     use tempdir::TempDir;
 
     // Tests the util function `check_key_in_region`.
@@ -349,6 +366,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_region_approximate_keys() {
+        let path = TempDir::new("_test_raftstore_region_approximate_keys").expect("");
+        let path_str = path.path().to_str().unwrap();
+        let db_opts = DBOptions::new();
+        let mut cf_opts = ColumnFamilyOptions::new();
+        cf_opts.set_level_zero_file_num_compaction_trigger(10);
+        let f = Box::new(MvccPropertiesCollectorFactory::default());
+        cf_opts.add_table_properties_collector_factory("tikv.mvcc-properties-collector", f);
+        let cfs_opts = LARGE_CFS
+            .iter()
+            .map(|cf| CFOptions::new(cf, cf_opts.clone()))
+            .collect();
+        let db = rocksdb_util::new_engine_opt(path_str, db_opts, cfs_opts).unwrap();
+
+        let cf = db.cf_handle(CF_WRITE).unwrap();
+        let cases = [("a", 1), ("b", 2), ("c", 3)];
+        for &(key, versions) in &cases {
+            for &(k, versions) in &[(" ", 1), (key, versions)] {
+                for ts in 0..versions {
+                    let k = keys::data_key(make_key(k.as_bytes()).append_ts(ts).encoded());
+                    let v = Write::new(WriteType::Put, ts, None).to_bytes();
+                    db.put_cf(cf, &k, &v).unwrap();
+                }
+            }
+            db.flush_cf(cf, true).unwrap();
+        }
+
+        // Versions of a key are counted once.
+        let region = make_region(1, vec![], vec![]);
+        assert_eq!(get_region_approximate_keys(&db, &region).unwrap(), 3);
+    }
+
     fn check_data(db: &DB, cfs: &[&str], expected: &[(&[u8], &[u8])]) {
         for cf in cfs {
             let handle = get_cf_handle(db, cf).unwrap();
@@ -16,8 +16,10 @@ use std::sync::Arc;
 use std::sync::mpsc::Sender;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
 
-use rocksdb::{Writable, WriteBatch, DB};
+use rocksdb::{IngestExternalFileOptions, Writable, WriteBatch, DB};
 use rocksdb::rocksdb_options::WriteOptions;
 use protobuf::RepeatedField;
 
@@ -31,21 +33,28 @@ use util::worker::Runnable;
 use util::{escape, rocksdb};
 use util::time::{duration_to_sec, SlowTimer};
 use util::collections::{HashMap, HashMapEntry as MapEntry};
-use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT};
+use util::file::delete_file_if_exist;
+use storage::{CfName, ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
 use raftstore::{Error, Result};
-use raftstore::coprocessor::CoprocessorHost;
+use raftstore::coprocessor::{CoprocessorHost, RegionChangeEvent};
 use raftstore::store::{cmd_resp, keys, util, Store};
 use raftstore::store::msg::Callback;
 use raftstore::store::engine::{Mutable, Peekable, Snapshot};
 use raftstore::store::peer_storage::{self, compact_raft_log, write_initial_apply_state,
                                      write_peer_state};
 use raftstore::store::peer::{check_epoch, parse_data_at, Peer};
+use raftstore::store::resolved_ts::Resolver;
 use raftstore::store::metrics::*;
 
 use super::metrics::*;
 
 const WRITE_BATCH_MAX_KEYS: usize = 128;
 const DEFAULT_APPLY_WB_SIZE: usize = 4 * 1024;
+// Import commands that only put at least so many keys into default and write cfs
+// are ingested as sst files.
+const INGEST_MIN_KEYS: usize = 1024;
+// Commands proposed by bulk import are marked with this uuid in the request header.
+pub const IMPORT_CMD_UUID: &'static [u8] = b"import";
 
 pub struct PendingCmd {
     pub index: u64,
@@ -165,21 +174,34 @@ pub enum ExecResult {
     DeleteRange { ranges: Vec<Range> },
 }
 
+/// The events observers are notified of after the write batch is written to engine.
+enum ObserveEvent {
+    /// A read/write command applied successfully, (region, index, command).
+    Query(Region, u64, RaftCmdRequest),
+    /// An admin command applied successfully, (region, index, request, response).
+    Admin(Region, u64, AdminRequest, AdminResponse),
+    RegionChanged(Region, RegionChangeEvent),
+}
+
 struct ApplyContext<'a> {
     pub host: &'a CoprocessorHost,
+    pub import_dir: &'a Path,
     pub wb: Option<WriteBatch>,
     pub cbs: Vec<(Callback, RaftCmdResponse)>,
+    pub observe_events: Vec<ObserveEvent>,
     pub wb_last_bytes: u64,
     pub wb_last_keys: u64,
     pub sync_log: bool,
 }
 
 impl<'a> ApplyContext<'a> {
-    fn new(host: &CoprocessorHost) -> ApplyContext {
+    fn new(host: &'a CoprocessorHost, import_dir: &'a Path) -> ApplyContext<'a> {
         ApplyContext {
             host: host,
+            import_dir: import_dir,
             wb: Some(WriteBatch::with_capacity(DEFAULT_APPLY_WB_SIZE)),
             cbs: vec![],
+            observe_events: vec![],
             wb_last_bytes: 0,
             wb_last_keys: 0,
             sync_log: false,
@@ -263,6 +285,12 @@ fn should_flush_to_engine(cmd: &RaftCmdRequest, wb_keys: usize) -> bool {
         return true;
     }
 
+    // Ingested files bypass the write batch, so keys in the write batch must be flushed
+    // first, otherwise they may overwrite the newer ingested data later.
+    if is_ingest_cmd(cmd) {
+        return true;
+    }
+
     // When encounter DeleteRange command, we must flush current write batch to engine first,
     // because current write batch may contains keys are covered by DeleteRange.
     for req in cmd.get_requests() {
@@ -274,6 +302,21 @@ fn should_flush_to_engine(cmd: &RaftCmdRequest, wb_keys: usize) -> bool {
     false
 }
 
+fn is_ingest_cmd(cmd: &RaftCmdRequest) -> bool {
+    let requests = cmd.get_requests();
+    if cmd.get_header().get_uuid() != IMPORT_CMD_UUID || cmd.has_admin_request() ||
+        requests.len() < INGEST_MIN_KEYS
+    {
+        return false;
+    }
+    requests.iter().all(|req| {
+        req.get_cmd_type() == CmdType::Put && {
+            let cf = req.get_put().get_cf();
+            cf.is_empty() || cf == CF_DEFAULT || cf == CF_WRITE
+        }
+    })
+}
+
 #[derive(Debug)]
 pub struct ApplyDelegate {
     // peer_id
@@ -294,6 +337,8 @@ pub struct ApplyDelegate {
     term: u64,
     pending_cmds: PendingCmdQueue,
     metrics: ApplyMetrics,
+    // Tracks the locks of the region, None before any entry is applied.
+    resolver: Option<Resolver>,
 }
 
 impl ApplyDelegate {
@@ -322,9 +367,31 @@ impl ApplyDelegate {
             term: reg.term,
             pending_cmds: Default::default(),
             metrics: Default::default(),
+            resolver: None,
+        }
+    }
+
+    pub fn resolved_ts(&self) -> u64 {
+        self.resolver.as_ref().map_or(0, |r| r.resolved_ts())
+    }
+
+    fn load_resolver(&mut self) {
+        if self.resolver.is_none() {
+            // The data of a snapshot is applied after the delegate is registered,
+            // so the locks are loaded lazily.
+            let resolver = Resolver::from_region(&self.engine, &self.region)
+                .unwrap_or_else(|e| panic!("{} failed to load locks: {:?}", self.tag, e));
+            self.resolver = Some(resolver);
         }
     }
 
+    /// Advances the resolved ts with `ts` got by `ConcurrencyManager::resolve_ts`.
+    fn advance_resolved_ts(&mut self, ts: u64) -> u64 {
+        self.load_resolver();
+        self.resolver.as_mut().unwrap().advance_ts(ts);
+        self.resolved_ts()
+    }
+
     fn handle_raft_committed_entries(
         &mut self,
         apply_ctx: &mut ApplyContext,
@@ -333,6 +400,7 @@ impl ApplyDelegate {
         if committed_entries.is_empty() {
             return vec![];
         }
+        self.load_resolver();
         // If we send multiple ConfChange commands, only first one will be proposed correctly,
         // others will be saved as a normal entry with no data, so we must re-propose these
         // commands again.
@@ -524,16 +592,47 @@ impl ApplyDelegate {
 
         let cmd_cb = self.find_cb(index, term, &cmd);
         apply_ctx.host.pre_apply(&self.region, &mut cmd);
-        let (mut resp, exec_result) = self.apply_raft_cmd(apply_ctx.wb_mut(), index, term, &cmd);
+        let import_dir = apply_ctx.import_dir;
+        let (mut resp, exec_result) =
+            self.apply_raft_cmd(apply_ctx.wb_mut(), import_dir, index, term, &cmd);
 
         debug!("{} applied command at log index {}", self.tag, index);
 
+        if !resp.get_header().has_error() && apply_ctx.host.observes_applied_cmds() {
+            let event = if cmd.has_admin_request() {
+                ObserveEvent::Admin(
+                    self.region.clone(),
+                    index,
+                    cmd.take_admin_request(),
+                    resp.get_admin_response().clone(),
+                )
+            } else {
+                ObserveEvent::Query(self.region.clone(), index, cmd)
+            };
+            apply_ctx.observe_events.push(event);
+        }
+        match exec_result {
+            Some(ExecResult::ChangePeer(ref cp)) => apply_ctx.observe_events.push(
+                ObserveEvent::RegionChanged(cp.region.clone(), RegionChangeEvent::ChangePeer),
+            ),
+            Some(ExecResult::SplitRegion {
+                ref left,
+                ref right,
+                ..
+            }) => for region in &[left, right] {
+                apply_ctx.observe_events.push(ObserveEvent::RegionChanged(
+                    (*region).clone(),
+                    RegionChangeEvent::Split,
+                ));
+            },
+            _ => {}
+        }
+
         let cb = match cmd_cb {
             None => return exec_result,
             Some(cb) => cb,
         };
 
-        // TODO: Involve post apply hook.
         // TODO: if we have exec_result, maybe we should return this callback too. Outer
         // store will call it after handing exec result.
         cmd_resp::bind_term(&mut resp, self.term);
@@ -551,6 +650,7 @@ impl ApplyDelegate {
     fn apply_raft_cmd(
         &mut self,
         wb: &mut WriteBatch,
+        import_dir: &Path,
         index: u64,
         term: u64,
         req: &RaftCmdRequest,
@@ -558,7 +658,7 @@ impl ApplyDelegate {
         // if pending remove, apply should be aborted already.
         assert!(!self.pending_remove);
 
-        let mut ctx = self.new_ctx(wb, index, term, req);
+        let mut ctx = self.new_ctx(wb, import_dir, index, term, req);
         ctx.wb.set_save_point();
         let (resp, exec_result) = self.exec_raft_cmd(&mut ctx).unwrap_or_else(|e| {
             // clear dirty values.
@@ -594,6 +694,9 @@ impl ApplyDelegate {
                     } else {
                         self.region = left.clone();
                     }
+                    if let Some(ref mut resolver) = self.resolver {
+                        resolver.retain_region(&self.region);
+                    }
                     self.metrics.size_diff_hint = 0;
                     self.metrics.delete_keys_hint = 0;
                 }
@@ -645,6 +748,7 @@ impl ApplyDelegate {
     fn new_ctx<'a>(
         &self,
         wb: &'a mut WriteBatch,
+        import_dir: &'a Path,
         index: u64,
         term: u64,
         req: &'a RaftCmdRequest,
@@ -652,6 +756,7 @@ impl ApplyDelegate {
         ExecContext {
             apply_state: self.apply_state.clone(),
             wb: wb,
+            import_dir: import_dir,
             req: req,
             index: index,
             term: term,
@@ -662,6 +767,7 @@ impl ApplyDelegate {
 struct ExecContext<'a> {
     apply_state: RaftApplyState,
     wb: &'a mut WriteBatch,
+    import_dir: &'a Path,
     req: &'a RaftCmdRequest,
     index: u64,
     term: u64,
@@ -677,6 +783,8 @@ impl ApplyDelegate {
         check_epoch(&self.region, ctx.req)?;
         if ctx.req.has_admin_request() {
             self.exec_admin_cmd(ctx)
+        } else if is_ingest_cmd(ctx.req) {
+            self.exec_ingest_cmd(ctx)
         } else {
             self.exec_write_cmd(ctx)
         }
@@ -1030,6 +1138,7 @@ impl ApplyDelegate {
 
             responses.push(resp);
         }
+        self.track_write_cmd(requests);
 
         let mut resp = RaftCmdResponse::new();
         resp.set_responses(RepeatedField::from_vec(responses));
@@ -1043,6 +1152,119 @@ impl ApplyDelegate {
         Ok((resp, exec_res))
     }
 
+    fn exec_ingest_cmd(
+        &mut self,
+        ctx: &ExecContext,
+    ) -> Result<(RaftCmdResponse, Option<ExecResult>)> {
+        let requests = ctx.req.get_requests();
+        let mut default_kvs = Vec::with_capacity(requests.len());
+        let mut write_kvs = Vec::with_capacity(requests.len());
+        for req in requests {
+            let put = req.get_put();
+            check_data_key(put.get_key(), &self.region)?;
+            let kv = (keys::data_key(put.get_key()), put.get_value());
+            if put.get_cf() == CF_WRITE {
+                write_kvs.push(kv);
+            } else {
+                default_kvs.push(kv);
+            }
+        }
+        for kvs in &mut [&mut default_kvs, &mut write_kvs] {
+            kvs.sort_by(|a, b| a.0.cmp(&b.0));
+            if kvs.windows(2).any(|w| w[0].0 == w[1].0) {
+                // Sst file can't contain duplicated keys, apply it as a normal write command.
+                return self.exec_write_cmd(ctx);
+            }
+        }
+
+        for &(cf, ref kvs) in &[(CF_DEFAULT, default_kvs), (CF_WRITE, write_kvs)] {
+            if !kvs.is_empty() {
+                self.ingest_kvs(ctx.import_dir, ctx.index, cf, kvs)?;
+            }
+        }
+        self.track_write_cmd(requests);
+
+        let mut responses = Vec::with_capacity(requests.len());
+        for req in requests {
+            self.metrics.size_diff_hint += req.get_put().get_key().len() as i64;
+            self.metrics.size_diff_hint += req.get_put().get_value().len() as i64;
+            let mut resp = Response::new();
+            resp.set_cmd_type(CmdType::Put);
+            responses.push(resp);
+        }
+        let mut resp = RaftCmdResponse::new();
+        resp.set_responses(RepeatedField::from_vec(responses));
+        Ok((resp, None))
+    }
+
+    // Tracks the locks written by the command for the resolved ts,
+    // it's called after all the requests succeed as a failed command is rolled back.
+    fn track_write_cmd(&mut self, requests: &[Request]) {
+        let resolver = match self.resolver {
+            Some(ref mut resolver) => resolver,
+            None => return,
+        };
+        for req in requests {
+            match req.get_cmd_type() {
+                CmdType::Put if req.get_put().get_cf() == CF_LOCK => {
+                    let put = req.get_put();
+                    resolver.on_lock_put(put.get_key(), put.get_value());
+                }
+                CmdType::Delete if req.get_delete().get_cf() == CF_LOCK => {
+                    resolver.untrack_lock(req.get_delete().get_key());
+                }
+                CmdType::DeleteRange if req.get_delete_range().get_cf() == CF_LOCK => {
+                    let delete_range = req.get_delete_range();
+                    resolver.untrack_range(
+                        delete_range.get_start_key(),
+                        delete_range.get_end_key(),
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Ingests the sorted kvs into the cf. If the store crashes before the apply state is
+    // persisted, the entry will be applied again, which is fine because ingesting the
+    // same kvs twice leaves the same data.
+    fn ingest_kvs(
+        &self,
+        import_dir: &Path,
+        index: u64,
+        cf: CfName,
+        kvs: &[(Vec<u8>, &[u8])],
+    ) -> Result<()> {
+        box_try!(fs::create_dir_all(import_dir));
+        let file = import_dir.join(format!("{}_{}_{}.sst", self.region.get_id(), index, cf));
+        delete_file_if_exist(&file);
+        let res = self.ingest_file(&file, cf, kvs);
+        // Ingesting with move_files may leave the file in place when it falls back to copy.
+        delete_file_if_exist(&file);
+        res?;
+        APPLY_INGEST_KEYS_COUNTER.inc_by(kvs.len() as f64).unwrap();
+        Ok(())
+    }
+
+    fn ingest_file(&self, file: &Path, cf: CfName, kvs: &[(Vec<u8>, &[u8])]) -> Result<()> {
+        let path = file.to_str().unwrap();
+        let mut writer = box_try!(rocksdb::new_sst_writer(&self.engine, cf, path));
+        for &(ref key, value) in kvs {
+            box_try!(writer.put(key, value));
+        }
+        box_try!(writer.finish());
+
+        let handle = box_try!(rocksdb::get_cf_handle(&self.engine, cf));
+        let mut ingest_opt = IngestExternalFileOptions::new();
+        // The file is only used by this ingestion, no need to copy it.
+        ingest_opt.move_files(true);
+        box_try!(
+            self.engine
+                .ingest_external_file_cf(handle, &ingest_opt, &[path])
+        );
+        Ok(())
+    }
+
     fn handle_put(&mut self, ctx: &ExecContext, req: &Request) -> Result<Response> {
         let (key, value) = (req.get_put().get_key(), req.get_put().get_value());
         check_data_key(key, &self.region)?;
@@ -1350,12 +1572,18 @@ pub struct Destroy {
     region_id: u64,
 }
 
+pub struct AdvanceResolvedTs {
+    regions: Vec<u64>,
+    ts: u64,
+}
+
 /// region related task.
 pub enum Task {
     Applies(Vec<Apply>),
     Registration(Registration),
     Proposals(Vec<RegionProposal>),
     Destroy(Destroy),
+    AdvanceResolvedTs(AdvanceResolvedTs),
 }
 
 impl Task {
@@ -1372,6 +1600,13 @@ impl Task {
             region_id: region_id,
         })
     }
+
+    pub fn advance_resolved_ts(regions: Vec<u64>, ts: u64) -> Task {
+        Task::AdvanceResolvedTs(AdvanceResolvedTs {
+            regions: regions,
+            ts: ts,
+        })
+    }
 }
 
 impl Display for Task {
@@ -1383,6 +1618,12 @@ impl Display for Task {
                 write!(f, "[region {}] Reg {:?}", r.region.get_id(), r.apply_state)
             }
             Task::Destroy(ref d) => write!(f, "[region {}] destroy", d.region_id),
+            Task::AdvanceResolvedTs(ref a) => write!(
+                f,
+                "advance resolved ts to {} for {} regions",
+                a.ts,
+                a.regions.len()
+            ),
         }
     }
 }
@@ -1404,6 +1645,7 @@ pub struct ApplyRes {
     pub region_id: u64,
     pub apply_state: RaftApplyState,
     pub applied_index_term: u64,
+    pub resolved_ts: u64,
     pub exec_res: Vec<ExecResult>,
     pub metrics: ApplyMetrics,
 }
@@ -1412,6 +1654,8 @@ pub struct ApplyRes {
 pub enum TaskRes {
     Applys(Vec<ApplyRes>),
     Destroy(ApplyDelegate),
+    // (region id, applied index, resolved ts)
+    ResolvedTs(Vec<(u64, u64, u64)>),
 }
 
 // TODO: use threadpool to do task concurrently
@@ -1421,6 +1665,7 @@ pub struct Runner {
     delegates: HashMap<u64, ApplyDelegate>,
     notifier: Sender<TaskRes>,
     sync_log: bool,
+    import_dir: PathBuf,
     tag: String,
 }
 
@@ -1431,12 +1676,18 @@ impl Runner {
         for (&region_id, p) in store.get_peers() {
             delegates.insert(region_id, ApplyDelegate::from_peer(p));
         }
+        let import_dir = if store.config().import_dir.is_empty() {
+            env::temp_dir().join(format!("tikv-import-{}", store.store_id()))
+        } else {
+            PathBuf::from(&store.config().import_dir)
+        };
         Runner {
             db: store.kv_engine(),
             host: store.coprocessor_host.clone(),
             delegates: delegates,
             notifier: notifier,
             sync_log: sync_log,
+            import_dir: import_dir,
             tag: format!("[store {}]", store.store_id()),
         }
     }
@@ -1445,7 +1696,7 @@ impl Runner {
         let t = SlowTimer::new();
 
         let mut applys_res = Vec::with_capacity(applys.len());
-        let mut apply_ctx = ApplyContext::new(self.host.as_ref());
+        let mut apply_ctx = ApplyContext::new(self.host.as_ref(), &self.import_dir);
         let mut committed_count = 0;
         for apply in applys {
             if apply.entries.is_empty() {
@@ -1467,6 +1718,10 @@ impl Runner {
 
                 if delegate.pending_remove {
                     delegate.destroy();
+                    apply_ctx.observe_events.push(ObserveEvent::RegionChanged(
+                        delegate.region.clone(),
+                        RegionChangeEvent::Destroy,
+                    ));
                 }
 
                 applys_res.push(ApplyRes {
@@ -1475,6 +1730,7 @@ impl Runner {
                     exec_res: results,
                     metrics: delegate.metrics.clone(),
                     applied_index_term: delegate.applied_index_term,
+                    resolved_ts: delegate.resolved_ts(),
                 });
             }
             if e.get().pending_remove {
@@ -1498,6 +1754,22 @@ impl Runner {
             cb(resp);
         }
 
+        // Observers are notified after the data is written, so the engine is consistent
+        // with the commands they see.
+        for event in apply_ctx.observe_events.drain(..) {
+            match event {
+                ObserveEvent::Query(region, index, cmd) => {
+                    apply_ctx.host.post_apply(&region, index, &cmd)
+                }
+                ObserveEvent::Admin(region, index, req, resp) => {
+                    apply_ctx.host.post_apply_admin(&region, index, &req, &resp)
+                }
+                ObserveEvent::RegionChanged(region, event) => {
+                    apply_ctx.host.on_region_changed(&region, event)
+                }
+            }
+        }
+
         if !applys_res.is_empty() {
             self.notifier.send(TaskRes::Applys(applys_res)).unwrap();
         }
@@ -1569,10 +1841,26 @@ impl Runner {
         if let Some(mut meta) = self.delegates.remove(&d.region_id) {
             info!("{} remove from apply delegates", meta.tag);
             meta.destroy();
+            self.host
+                .on_region_changed(&meta.region, RegionChangeEvent::Destroy);
             self.notifier.send(TaskRes::Destroy(meta)).unwrap();
         }
     }
 
+    fn handle_advance_resolved_ts(&mut self, a: AdvanceResolvedTs) {
+        let mut resolved_ts = Vec::with_capacity(a.regions.len());
+        for region_id in a.regions {
+            if let Some(delegate) = self.delegates.get_mut(&region_id) {
+                self.host.on_advance_resolved_ts(&delegate.region, a.ts);
+                let ts = delegate.advance_resolved_ts(a.ts);
+                resolved_ts.push((region_id, delegate.apply_state.get_applied_index(), ts));
+            }
+        }
+        if !resolved_ts.is_empty() {
+            self.notifier.send(TaskRes::ResolvedTs(resolved_ts)).unwrap();
+        }
+    }
+
     fn handle_shutdown(&mut self) {
         for p in self.delegates.values_mut() {
             p.clear_pending_commands();
@@ -1587,6 +1875,7 @@ impl Runnable<Task> for Runner {
             Task::Proposals(props) => self.handle_proposals(props),
             Task::Registration(s) => self.handle_registration(s),
             Task::Destroy(d) => self.handle_destroy(d),
+            Task::AdvanceResolvedTs(a) => self.handle_advance_resolved_ts(a),
         }
     }
 
@@ -1606,8 +1895,10 @@ mod tests {
     use kvproto::raft_cmdpb::CmdType;
 
     use super::*;
-    use storage::{ALL_CFS, CF_WRITE};
+    use storage::{make_key, ALL_CFS, CF_WRITE};
+    use storage::mvcc::{Lock, LockType};
     use util::collections::HashMap;
+    use raftstore::coprocessor::{Coprocessor, ObserverContext, RegionObserver};
 
     pub fn create_tmp_engine(path: &str) -> (TempDir, Arc<DB>) {
         let path = TempDir::new(path).unwrap();
@@ -1624,6 +1915,7 @@ mod tests {
             delegates: HashMap::default(),
             notifier: tx,
             sync_log: false,
+            import_dir: PathBuf::new(),
             tag: "".to_owned(),
         }
     }
@@ -1662,6 +1954,25 @@ mod tests {
             wb.put(key.as_bytes(), b"value").unwrap();
         }
         assert_eq!(should_flush_to_engine(&req, wb.count()), false);
+
+        // Ingest command
+        let mut req = RaftCmdRequest::new();
+        for i in 0..INGEST_MIN_KEYS {
+            let mut put = Request::new();
+            put.set_cmd_type(CmdType::Put);
+            put.mut_put().set_cf(CF_WRITE.to_owned());
+            put.mut_put().set_key(format!("key_{}", i).into_bytes());
+            req.mut_requests().push(put);
+        }
+        let wb = WriteBatch::new();
+        // Only commands marked as imports are ingested.
+        assert_eq!(should_flush_to_engine(&req, wb.count()), false);
+        req.mut_header().set_uuid(IMPORT_CMD_UUID.to_vec());
+        assert_eq!(should_flush_to_engine(&req, wb.count()), true);
+
+        // Puts into lock cf can't be ingested.
+        req.mut_requests()[0].mut_put().set_cf(CF_LOCK.to_owned());
+        assert_eq!(should_flush_to_engine(&req, wb.count()), false);
     }
 
     #[test]
@@ -1822,6 +2133,11 @@ mod tests {
             self
         }
 
+        fn import(mut self) -> EntryBuilder {
+            self.req.mut_header().set_uuid(IMPORT_CMD_UUID.to_vec());
+            self
+        }
+
         fn put(self, key: &[u8], value: &[u8]) -> EntryBuilder {
             self.add_put_req(None, key, value)
         }
@@ -1886,6 +2202,13 @@ mod tests {
             self
         }
 
+        fn compute_hash(mut self) -> EntryBuilder {
+            self.req
+                .mut_admin_request()
+                .set_cmd_type(AdminCmdType::ComputeHash);
+            self
+        }
+
         fn build(mut self) -> Entry {
             self.entry.set_data(self.req.write_to_bytes().unwrap());
             self.entry
@@ -1894,7 +2217,7 @@ mod tests {
 
     #[test]
     fn test_handle_raft_committed_entries() {
-        let (_path, db) = create_tmp_engine("test-delegate");
+        let (path, db) = create_tmp_engine("test-delegate");
         let mut reg = Registration::default();
         reg.region.set_end_key(b"k5".to_vec());
         reg.region.mut_region_epoch().set_version(3);
@@ -1909,7 +2232,7 @@ mod tests {
             .capture_resp(&mut delegate, tx.clone())
             .build();
         let host = CoprocessorHost::default();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, path.path());
         let res = delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -1936,7 +2259,7 @@ mod tests {
             .put_cf(CF_LOCK, b"k1", b"v1")
             .epoch(1, 3)
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, path.path());
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -1959,7 +2282,7 @@ mod tests {
             .epoch(1, 1)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, path.path());
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -1976,7 +2299,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, path.path());
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2002,7 +2325,7 @@ mod tests {
         let lock_written_bytes = delegate.metrics.lock_cf_written_bytes;
         let delete_keys_hint = delegate.metrics.delete_keys_hint;
         let size_diff_hint = delegate.metrics.size_diff_hint;
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, path.path());
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2026,7 +2349,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, path.path());
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![delete_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2040,7 +2363,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, path.path());
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![delete_range_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2057,7 +2380,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, path.path());
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![delete_range_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2078,7 +2401,7 @@ mod tests {
                 .build();
             entries.push(put_entry);
         }
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, path.path());
         delegate.handle_raft_committed_entries(&mut apply_ctx, entries);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2092,4 +2415,187 @@ mod tests {
             WRITE_BATCH_MAX_KEYS as u64 + 8
         );
     }
+
+    #[test]
+    fn test_resolved_ts() {
+        let (path, db) = create_tmp_engine("test-resolved-ts");
+        let lock_key = |k: &[u8]| make_key(k).encoded().to_owned();
+        let write_key = |k: &[u8], ts| make_key(k).append_ts(ts).encoded().to_owned();
+        let lock = |ts| Lock::new(LockType::Put, b"k1".to_vec(), ts, 0, None).to_bytes();
+        // A lock left by the data before the delegate is registered.
+        let lock_handle = db.cf_handle(CF_LOCK).unwrap();
+        db.put_cf(lock_handle, &keys::data_key(&lock_key(b"k0")), &lock(5))
+            .unwrap();
+        let mut delegate = ApplyDelegate::from_registration(db.clone(), Registration::default());
+        assert_eq!(delegate.resolved_ts(), 0);
+        assert_eq!(delegate.advance_resolved_ts(20), 4);
+
+        let host = CoprocessorHost::default();
+        let mut apply = |delegate: &mut ApplyDelegate, entry| {
+            let mut apply_ctx = ApplyContext::new(&host, path.path());
+            delegate.handle_raft_committed_entries(&mut apply_ctx, vec![entry]);
+            db.write(apply_ctx.wb.take().unwrap()).unwrap();
+        };
+
+        let entry = EntryBuilder::new(1, 1)
+            .put_cf(CF_LOCK, &lock_key(b"k1"), &lock(10))
+            .put_cf(CF_WRITE, &write_key(b"k2", 25), b"v")
+            .epoch(0, 0)
+            .build();
+        apply(&mut delegate, entry);
+        assert_eq!(delegate.resolved_ts(), 4);
+        let entry = EntryBuilder::new(2, 1)
+            .delete_cf(CF_LOCK, &lock_key(b"k0"))
+            .epoch(0, 0)
+            .build();
+        apply(&mut delegate, entry);
+        assert_eq!(delegate.resolved_ts(), 9);
+
+        // The locks of a failed command are not tracked.
+        let mut region = Region::new();
+        region.set_end_key(b"k5".to_vec());
+        delegate.region = region;
+        let entry = EntryBuilder::new(3, 1)
+            .put_cf(CF_LOCK, &lock_key(b"k3"), &lock(6))
+            .put(b"k6", b"v")
+            .epoch(0, 0)
+            .build();
+        apply(&mut delegate, entry);
+        assert_eq!(delegate.resolved_ts(), 9);
+
+        let entry = EntryBuilder::new(4, 1)
+            .delete_cf(CF_LOCK, &lock_key(b"k1"))
+            .put_cf(CF_WRITE, &write_key(b"k1", 12), b"v")
+            .epoch(0, 0)
+            .build();
+        apply(&mut delegate, entry);
+        // The commit ts of the writes doesn't advance the resolved ts.
+        assert_eq!(delegate.resolved_ts(), 20);
+        assert_eq!(delegate.advance_resolved_ts(30), 30);
+    }
+
+    #[test]
+    fn test_ingest_cmd() {
+        let (path, db) = create_tmp_engine("test-ingest");
+        let mut delegate = ApplyDelegate::from_registration(db.clone(), Registration::default());
+        let (tx, rx) = mpsc::channel();
+
+        let put_entry = EntryBuilder::new(1, 1)
+            .put_cf(CF_WRITE, b"k0", b"v0")
+            .epoch(0, 0)
+            .capture_resp(&mut delegate, tx.clone())
+            .build();
+        let mut builder = EntryBuilder::new(2, 1);
+        for i in 0..INGEST_MIN_KEYS {
+            let key = format!("k{}", i);
+            builder = builder
+                .put_cf(CF_WRITE, key.as_bytes(), b"v1")
+                .put(key.as_bytes(), b"v1");
+        }
+        let ingest_entry = builder
+            .import()
+            .epoch(0, 0)
+            .capture_resp(&mut delegate, tx.clone())
+            .build();
+        let host = CoprocessorHost::default();
+        let import_dir = path.path().join("import");
+        let mut apply_ctx = ApplyContext::new(&host, &import_dir);
+        delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry, ingest_entry]);
+        db.write(apply_ctx.wb.take().unwrap()).unwrap();
+        for (cb, resp) in apply_ctx.cbs.drain(..) {
+            cb(resp);
+        }
+        for _ in 0..2 {
+            let resp = rx.try_recv().unwrap();
+            assert!(!resp.get_header().has_error(), "{:?}", resp);
+        }
+        assert_eq!(delegate.apply_state.get_applied_index(), 2);
+
+        // The value written before ingestion must be overwritten.
+        let write_handle = db.cf_handle(CF_WRITE).unwrap();
+        for i in 0..INGEST_MIN_KEYS {
+            let key = keys::data_key(format!("k{}", i).as_bytes());
+            assert_eq!(db.get_cf(write_handle, &key).unwrap().unwrap(), b"v1");
+            assert_eq!(db.get(&key).unwrap().unwrap(), b"v1");
+        }
+        // The sst files are removed after ingestion.
+        assert_eq!(fs::read_dir(&import_dir).unwrap().count(), 0);
+    }
+
+    #[derive(Clone, Default)]
+    struct ApplyObserver {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Coprocessor for ApplyObserver {}
+
+    impl RegionObserver for ApplyObserver {
+        fn observes_applied_cmds(&self) -> bool {
+            true
+        }
+
+        fn post_apply_query(&self, ctx: &mut ObserverContext, index: u64, _: &[Request]) {
+            let event = format!("query {} at {}", ctx.region().get_id(), index);
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn post_apply_admin(
+            &self,
+            ctx: &mut ObserverContext,
+            index: u64,
+            req: &AdminRequest,
+            _: &AdminResponse,
+        ) {
+            let event = format!(
+                "{:?} {} at {}",
+                req.get_cmd_type(),
+                ctx.region().get_id(),
+                index
+            );
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_region_changed(&self, ctx: &mut ObserverContext, event: RegionChangeEvent) {
+            let event = format!("{:?} {}", event, ctx.region().get_id());
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn test_observer_events() {
+        let (tx, rx) = mpsc::channel();
+        let (_tmp, db) = create_tmp_engine("apply-observer");
+        let observer = ApplyObserver::default();
+        let mut host = CoprocessorHost::default();
+        host.registry
+            .register_observer(1, Box::new(observer.clone()));
+        let mut runner = new_runner(db.clone(), Arc::new(host), tx);
+
+        let mut reg = Registration::default();
+        reg.id = 1;
+        reg.region.set_id(2);
+        reg.region.mut_region_epoch().set_version(1);
+        reg.apply_state.set_applied_index(3);
+        runner.run(Task::Registration(reg));
+
+        // Failed commands are not observed.
+        let entries = vec![
+            EntryBuilder::new(4, 1).put(b"k1", b"v1").epoch(0, 1).build(),
+            EntryBuilder::new(5, 1).put(b"k2", b"v2").epoch(0, 0).build(),
+            EntryBuilder::new(6, 1).compute_hash().build(),
+        ];
+        runner.run(Task::applies(vec![Apply::new(2, 1, entries)]));
+        match rx.try_recv() {
+            Ok(TaskRes::Applys(_)) => {}
+            e => panic!("unexpected apply result: {:?}", e),
+        }
+        assert_eq!(
+            *observer.events.lock().unwrap(),
+            vec!["query 2 at 4", "ComputeHash 2 at 6"]
+        );
+
+        observer.events.lock().unwrap().clear();
+        runner.run(Task::destroy(2));
+        assert_eq!(*observer.events.lock().unwrap(), vec!["Destroy 2"]);
+    }
 }
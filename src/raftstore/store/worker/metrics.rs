@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use prometheus::{exponential_buckets, CounterVec, Histogram, HistogramVec};
+use prometheus::{exponential_buckets, Counter, CounterVec, Histogram, HistogramVec};
 
 lazy_static! {
     pub static ref SNAP_COUNTER_VEC: CounterVec =
@@ -62,4 +62,10 @@ lazy_static! {
             "Proposal count of all regions in a mio tick",
             exponential_buckets(1.0, 2.0, 20).unwrap()
         ).unwrap();
+
+    pub static ref APPLY_INGEST_KEYS_COUNTER: Counter =
+        register_counter!(
+            "tikv_raftstore_apply_ingest_keys_total",
+            "Total number of keys ingested by apply"
+        ).unwrap();
 }
@@ -25,6 +25,7 @@ use kvproto::eraftpb::Snapshot as RaftSnapshot;
 use util::threadpool::{DefaultContext, ThreadPool, ThreadPoolBuilder};
 use util::worker::Runnable;
 use util::{escape, rocksdb};
+use raftstore::coprocessor::CoprocessorHost;
 use raftstore::store::engine::{Mutable, Snapshot};
 use raftstore::store::peer_storage::{JOB_STATUS_CANCELLED, JOB_STATUS_CANCELLING,
                                      JOB_STATUS_FAILED, JOB_STATUS_FINISHED, JOB_STATUS_PENDING,
@@ -95,6 +96,7 @@ struct SnapContext {
     raft_db: Arc<DB>,
     batch_size: usize,
     mgr: SnapManager,
+    host: Arc<CoprocessorHost>,
 }
 
 impl SnapContext {
@@ -209,6 +211,7 @@ impl SnapContext {
             region_id,
             timer.elapsed()
         );
+        self.host.on_apply_snapshot(&region, idx);
         Ok(())
     }
 
@@ -267,7 +270,13 @@ pub struct Runner {
 }
 
 impl Runner {
-    pub fn new(kv_db: Arc<DB>, raft_db: Arc<DB>, mgr: SnapManager, batch_size: usize) -> Runner {
+    pub fn new(
+        kv_db: Arc<DB>,
+        raft_db: Arc<DB>,
+        mgr: SnapManager,
+        batch_size: usize,
+        host: Arc<CoprocessorHost>,
+    ) -> Runner {
         Runner {
             pool: ThreadPoolBuilder::with_default_factory(thd_name!("snap generator"))
                 .thread_count(GENERATE_POOL_SIZE)
@@ -277,6 +286,7 @@ impl Runner {
                 raft_db: raft_db,
                 mgr: mgr,
                 batch_size: batch_size,
+                host: host,
             },
         }
     }
@@ -35,6 +35,8 @@ const DEFAULT_MESSAGES_PER_TICK: usize = 4096;
 // Enpoints may occur very deep recursion,
 // so enlarge their stack size to 10 MB.
 const DEFAULT_ENDPOINT_STACK_SIZE_MB: u64 = 10;
+const DEFAULT_ENDPOINT_STREAM_BATCH_ROW_LIMIT: usize = 128;
+const DEFAULT_ENDPOINT_AGGR_MEMORY_QUOTA_MB: u64 = 128;
 
 // Assume a request can be finished in 1ms, a request at position x will wait about
 // 0.001 * x secs to be actual started. A server-is-busy error will trigger 2 seconds
@@ -64,6 +66,11 @@ pub struct Config {
     pub end_point_concurrency: usize,
     pub end_point_max_tasks: usize,
     pub end_point_stack_size: ReadableSize,
+    pub end_point_stream_batch_row_limit: usize,
+    pub end_point_aggr_memory_quota: ReadableSize,
+    // The directory to spill the aggregates beyond the quota, `<data-dir>/aggr-spill` is
+    // used if it's empty.
+    pub end_point_aggr_spill_dir: String,
     // Server labels to specify some attributes about this server.
     #[serde(with = "config::order_map_serde")]
     pub labels: HashMap<String, String>,
@@ -91,6 +98,9 @@ impl Default for Config {
             end_point_concurrency: concurrency,
             end_point_max_tasks: DEFAULT_MAX_RUNNING_TASK_COUNT,
             end_point_stack_size: ReadableSize::mb(DEFAULT_ENDPOINT_STACK_SIZE_MB),
+            end_point_stream_batch_row_limit: DEFAULT_ENDPOINT_STREAM_BATCH_ROW_LIMIT,
+            end_point_aggr_memory_quota: ReadableSize::mb(DEFAULT_ENDPOINT_AGGR_MEMORY_QUOTA_MB),
+            end_point_aggr_spill_dir: String::new(),
         }
     }
 }
@@ -127,6 +137,14 @@ impl Config {
             return Err(box_err!("server.end-point-stack-size is too small."));
         }
 
+        if self.end_point_stream_batch_row_limit == 0 {
+            return Err(box_err!("server.end-point-stream-batch-row-limit should not be 0."));
+        }
+
+        if self.end_point_aggr_memory_quota.0 == 0 {
+            return Err(box_err!("server.end-point-aggr-memory-quota should not be 0."));
+        }
+
         for (k, v) in &self.labels {
             validate_label(k, "key")?;
             validate_label(v, "value")?;
@@ -190,6 +208,14 @@ mod tests {
         invalid_cfg.end_point_max_tasks = 0;
         assert!(invalid_cfg.validate().is_err());
 
+        let mut invalid_cfg = cfg.clone();
+        invalid_cfg.end_point_stream_batch_row_limit = 0;
+        assert!(invalid_cfg.validate().is_err());
+
+        let mut invalid_cfg = cfg.clone();
+        invalid_cfg.end_point_aggr_memory_quota = ReadableSize(0);
+        assert!(invalid_cfg.validate().is_err());
+
         invalid_cfg = Config::default();
         invalid_cfg.addr = "0.0.0.0:1000".to_owned();
         assert!(invalid_cfg.validate().is_err());
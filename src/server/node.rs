@@ -27,11 +27,11 @@ use protobuf::RepeatedField;
 use util::transport::SendCh;
 use util::worker::FutureWorker;
 use raftstore::coprocessor::dispatcher::CoprocessorHost;
-use raftstore::store::{self, keys, Config as StoreConfig, Engines, Msg, Peekable, SignificantMsg,
-                       SnapManager, Store, StoreChannel, Transport};
+use raftstore::store::{self, keys, Config as StoreConfig, Engines, LocalReader, Msg, Peekable,
+                       SignificantMsg, SnapManager, Store, StoreChannel, Transport};
 use super::Result;
 use server::Config as ServerConfig;
-use storage::{Config as StorageConfig, RaftKv, Storage};
+use storage::{Config as StorageConfig, ConcurrencyManager, RaftKv, Storage};
 use super::transport::RaftStoreRouter;
 
 const MAX_CHECK_CLUSTER_BOOTSTRAPPED_RETRY_COUNT: u64 = 60;
@@ -129,6 +129,8 @@ where
         significant_msg_receiver: Receiver<SignificantMsg>,
         pd_worker: FutureWorker<PdTask>,
         coprocessor_host: CoprocessorHost,
+        local_reader: LocalReader,
+        cm: ConcurrencyManager,
     ) -> Result<()>
     where
         T: Transport + 'static,
@@ -168,6 +170,8 @@ where
             significant_msg_receiver,
             pd_worker,
             coprocessor_host,
+            local_reader,
+            cm,
         )?;
         Ok(())
     }
@@ -322,6 +326,8 @@ where
         significant_msg_receiver: Receiver<SignificantMsg>,
         pd_worker: FutureWorker<PdTask>,
         coprocessor_host: CoprocessorHost,
+        local_reader: LocalReader,
+        cm: ConcurrencyManager,
     ) -> Result<()>
     where
         T: Transport + 'static,
@@ -354,6 +360,8 @@ where
                 snap_mgr,
                 pd_worker,
                 coprocessor_host,
+                local_reader,
+                cm,
             ) {
                 Err(e) => panic!("construct store {} err {:?}", store_id, e),
                 Ok(s) => s,
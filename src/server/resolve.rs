@@ -229,6 +229,9 @@ mod tests {
         fn report_split(&self, _: metapb::Region, _: metapb::Region) -> PdFuture<()> {
             unimplemented!();
         }
+        fn get_tso(&self) -> PdFuture<u64> {
+            unimplemented!();
+        }
     }
 
     fn new_store(addr: &str, state: metapb::StoreState) -> metapb::Store {
@@ -94,7 +94,8 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
             let mut sb = ServerBuilder::new(env.clone())
                 .bind(ip, addr.port())
                 .channel_args(channel_args)
-                .register_service(create_tikv(kv_service));
+                .register_service(create_tikv(kv_service.clone()))
+                .register_service(create_coprocessor_stream(kv_service));
             if let Some(engines) = debug_engines {
                 sb = sb.register_service(create_debug(DebugService::new(engines)));
             }
@@ -139,6 +140,7 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
             self.end_point_worker.scheduler(),
             cfg,
             self.pd_scheduler.clone(),
+            self.storage.get_concurrency_manager(),
         );
         box_try!(
             self.end_point_worker
@@ -17,9 +17,12 @@ use std::io::Write;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use mio::Token;
-use grpc::{ClientStreamingSink, RequestStream, RpcContext, RpcStatus, RpcStatusCode, UnarySink};
+use grpc::{ClientStreamingSink, Marshaller, Method, MethodType, RequestStream, RpcContext,
+           RpcStatus, RpcStatusCode, ServerStreamingSink, Service as GrpcService, ServiceBuilder,
+           UnarySink, WriteFlags};
+use grpc::{pb_de, pb_ser};
 use futures::{future, Future, Stream};
-use futures::sync::oneshot;
+use futures::sync::{mpsc, oneshot};
 use protobuf::RepeatedField;
 use kvproto::tikvpb_grpc;
 use kvproto::raft_serverpb::*;
@@ -27,12 +30,14 @@ use kvproto::kvrpcpb::*;
 use kvproto::coprocessor::*;
 use kvproto::errorpb::{Error as RegionError, ServerIsBusy};
 
+use util::escape;
 use util::worker::Scheduler;
 use util::buf::PipeBuffer;
 use storage::{self, Key, Mutation, Options, Storage, Value};
-use storage::txn::Error as TxnError;
-use storage::mvcc::{Error as MvccError, Write as MvccWrite, WriteType};
+use storage::txn::{Error as TxnError, WaitForEntry};
+use storage::mvcc::{import_modifies, Error as MvccError, Write as MvccWrite, WriteType};
 use storage::engine::Error as EngineError;
+use storage::engine::raftkv::modifies_to_requests;
 use server::transport::RaftStoreRouter;
 use server::snap::Task as SnapTask;
 use server::metrics::*;
@@ -41,6 +46,37 @@ use raftstore::store::Msg as StoreMessage;
 use coprocessor::{EndPointTask, RequestTask};
 
 const SCHEDULER_IS_BUSY: &'static str = "scheduler is busy";
+const MAX_TS_NOT_SYNCED: &'static str = "max ts not synced";
+// There is no deadlock error in `KeyError` yet, so a deadlock is reported as an abort starting
+// with this prefix, see `deadlock_error_msg` for the format.
+const DEADLOCK_ERROR_PREFIX: &'static str = "Deadlock";
+// The responses of a coprocessor stream buffered before the endpoint yields.
+const COPROCESSOR_STREAM_BUFFER_SIZE: usize = 8;
+
+// `CoprocessorStream` of `tikvpb.Tikv` isn't defined by the kvproto in use yet, so the method
+// is declared here as the generated ones.
+const METHOD_TIKV_COPROCESSOR_STREAM: Method<Request, Response> = Method {
+    ty: MethodType::ServerStreaming,
+    name: "/tikvpb.Tikv/CoprocessorStream",
+    req_mar: Marshaller {
+        ser: pb_ser,
+        de: pb_de,
+    },
+    resp_mar: Marshaller {
+        ser: pb_ser,
+        de: pb_de,
+    },
+};
+
+/// Creates the service of the `CoprocessorStream` RPC, it's registered along with the one
+/// created by `create_tikv`.
+pub fn create_coprocessor_stream<T: RaftStoreRouter + 'static>(s: Service<T>) -> GrpcService {
+    ServiceBuilder::new()
+        .add_server_streaming_handler(&METHOD_TIKV_COPROCESSOR_STREAM, move |ctx, req, sink| {
+            s.coprocessor_stream(ctx, req, sink)
+        })
+        .build()
+}
 
 #[derive(Clone)]
 pub struct Service<T: RaftStoreRouter + 'static> {
@@ -81,6 +117,44 @@ impl<T: RaftStoreRouter + 'static> Service<T> {
         let status = RpcStatus::new(code, Some(format!("{}", err)));
         ctx.spawn(sink.fail(status).map_err(|_| ()));
     }
+
+    // The rows of a DAG request are streamed, the key of the last row in a response is
+    // dropped as `Response` has no field for it yet.
+    fn coprocessor_stream(
+        &self,
+        ctx: RpcContext,
+        req: Request,
+        sink: ServerStreamingSink<Response>,
+    ) {
+        let label = "coprocessor_stream";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let (tx, rx) = mpsc::channel(COPROCESSOR_STREAM_BUFFER_SIZE);
+        let res = self.end_point_scheduler
+            .schedule(EndPointTask::Request(RequestTask::new_stream(req, tx)));
+        if let Err(e) = res {
+            let status = RpcStatus::new(
+                RpcStatusCode::ResourceExhausted,
+                Some(format!("{}", Error::from(e))),
+            );
+            ctx.spawn(sink.fail(status).map_err(|_| ()));
+            return;
+        }
+
+        let future = rx.map(|resp| (resp.resp, WriteFlags::default()))
+            // The receiver never fails.
+            .map_err(|_| Error::Other(box_err!("failed to receive coprocessor responses")))
+            .forward(sink)
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
 }
 
 fn make_callback<T: Debug + Send + 'static>() -> (Box<FnBox(T) + Send>, oneshot::Receiver<T>) {
@@ -89,6 +163,18 @@ fn make_callback<T: Debug + Send + 'static>() -> (Box<FnBox(T) + Send>, oneshot:
     (box callback, rx)
 }
 
+// Raw requests fail before being scheduled on invalid arguments, such as an unknown CF, and on
+// engine errors, which are reported in the response like the errors after being scheduled.
+fn raw_future<T: Send + 'static>(
+    res: storage::Result<()>,
+    future: oneshot::Receiver<storage::Result<T>>,
+) -> Box<Future<Item = storage::Result<T>, Error = Error> + Send> {
+    match res {
+        Ok(()) => box future.map_err(Error::from),
+        Err(e) => box future::ok(Err(e)),
+    }
+}
+
 impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
     fn kv_get(&self, ctx: RpcContext, mut req: GetRequest, sink: UnarySink<GetResponse>) {
         let label = "kv_get";
@@ -279,8 +365,68 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_import(&self, _: RpcContext, _: ImportRequest, _: UnarySink<ImportResponse>) {
-        unimplemented!();
+    fn kv_import(
+        &self,
+        ctx: RpcContext,
+        mut req: ImportRequest,
+        sink: UnarySink<ImportResponse>,
+    ) {
+        let label = "kv_import";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let mut mutations = Vec::with_capacity(req.get_mutations().len());
+        for mut x in req.take_mutations().into_vec() {
+            let mutation = match x.get_op() {
+                Op::Put => Mutation::Put((Key::from_raw(x.get_key()), x.take_value())),
+                Op::Del => Mutation::Delete(Key::from_raw(x.get_key())),
+                Op::Lock => Mutation::Lock(Key::from_raw(x.get_key())),
+                op => {
+                    let e: Error = box_err!("invalid op {:?} in import mutations", op);
+                    self.send_fail_status(ctx, sink, e, RpcStatusCode::InvalidArgument);
+                    return;
+                }
+            };
+            mutations.push(mutation);
+        }
+        let modifies = match import_modifies(mutations, req.get_commit_version()) {
+            Ok(modifies) => modifies,
+            Err(e) => {
+                let e = Error::from(storage::Error::from(e));
+                self.send_fail_status(ctx, sink, e, RpcStatusCode::InvalidArgument);
+                return;
+            }
+        };
+
+        let (cb, future) = make_callback();
+        let req = StoreMessage::Import {
+            requests: modifies_to_requests(modifies),
+            callback: cb,
+        };
+
+        if let Err(e) = self.ch.try_send(req) {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|mut v| {
+                let mut resp = ImportResponse::new();
+                if v.get_header().has_error() {
+                    resp.set_region_error(v.mut_header().take_error());
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
     }
 
     fn kv_cleanup(
@@ -596,14 +742,8 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let (cb, future) = make_callback();
         let res = self.storage
-            .async_raw_get(req.take_context(), req.take_key(), cb);
-        if let Err(e) = res {
-            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
-            return;
-        }
-
-        let future = future
-            .map_err(Error::from)
+            .async_raw_get(req.take_context(), String::new(), req.take_key(), cb);
+        let future = raw_future(res, future)
             .map(|v| {
                 let mut resp = RawGetResponse::new();
                 if let Some(err) = extract_region_error(&v) {
@@ -636,17 +776,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let (cb, future) = make_callback();
         let res = self.storage.async_raw_scan(
             req.take_context(),
+            String::new(),
             req.take_start_key(),
             req.get_limit() as usize,
+            false,
             cb,
         );
-        if let Err(e) = res {
-            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
-            return;
-        }
-
-        let future = future
-            .map_err(Error::from)
+        let future = raw_future(res, future)
             .map(|v| {
                 let mut resp = RawScanResponse::new();
                 if let Some(err) = extract_region_error(&v) {
@@ -673,15 +809,15 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
             .start_coarse_timer();
 
         let (cb, future) = make_callback();
-        let res = self.storage
-            .async_raw_put(req.take_context(), req.take_key(), req.take_value(), cb);
-        if let Err(e) = res {
-            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
-            return;
-        }
-
-        let future = future
-            .map_err(Error::from)
+        let res = self.storage.async_raw_put(
+            req.take_context(),
+            String::new(),
+            req.take_key(),
+            req.take_value(),
+            0,
+            cb,
+        );
+        let future = raw_future(res, future)
             .map(|v| {
                 let mut resp = RawPutResponse::new();
                 if let Some(err) = extract_region_error(&v) {
@@ -714,14 +850,8 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let (cb, future) = make_callback();
         let res = self.storage
-            .async_raw_delete(req.take_context(), req.take_key(), cb);
-        if let Err(e) = res {
-            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
-            return;
-        }
-
-        let future = future
-            .map_err(Error::from)
+            .async_raw_delete(req.take_context(), String::new(), req.take_key(), cb);
+        let future = raw_future(res, future)
             .map(|v| {
                 let mut resp = RawDeleteResponse::new();
                 if let Some(err) = extract_region_error(&v) {
@@ -992,6 +1122,13 @@ fn extract_region_error<T>(res: &storage::Result<T>) -> Option<RegionError> {
             err.set_server_is_busy(server_is_busy_err);
             Some(err)
         }
+        Err(Error::Txn(TxnError::MaxTsNotSynced { .. })) => {
+            let mut err = RegionError::new();
+            let mut server_is_busy_err = ServerIsBusy::new();
+            server_is_busy_err.set_reason(MAX_TS_NOT_SYNCED.to_owned());
+            err.set_server_is_busy(server_is_busy_err);
+            Some(err)
+        }
         _ => None,
     }
 }
@@ -1021,8 +1158,18 @@ fn extract_key_error(err: &storage::Error) -> KeyError {
             lock_info.set_lock_ttl(ttl);
             key_error.set_locked(lock_info);
         }
+        storage::Error::Txn(TxnError::Deadlock {
+            start_ts,
+            lock_ts,
+            ref lock_key,
+            ref wait_chain,
+        }) => {
+            warn!("txn deadlocks: {:?}", err);
+            key_error.set_abort(deadlock_error_msg(start_ts, lock_ts, lock_key, wait_chain));
+        }
         storage::Error::Txn(TxnError::Mvcc(MvccError::WriteConflict { .. })) |
-        storage::Error::Txn(TxnError::Mvcc(MvccError::TxnLockNotFound { .. })) => {
+        storage::Error::Txn(TxnError::Mvcc(MvccError::TxnLockNotFound { .. })) |
+        storage::Error::Txn(TxnError::Mvcc(MvccError::AsyncCommitUndetermined { .. })) => {
             warn!("txn conflicts: {:?}", err);
             key_error.set_retryable(format!("{:?}", err));
         }
@@ -1034,6 +1181,28 @@ fn extract_key_error(err: &storage::Error) -> KeyError {
     key_error
 }
 
+/// Formats a deadlock as `Deadlock: <start_ts> waits for <lock_ts> on <key>, wait chain:
+/// [<txn> -> <wait_for_txn> on <key>, ...]`, keys are escaped.
+fn deadlock_error_msg(
+    start_ts: u64,
+    lock_ts: u64,
+    lock_key: &[u8],
+    wait_chain: &[WaitForEntry],
+) -> String {
+    let chain: Vec<_> = wait_chain
+        .iter()
+        .map(|e| format!("{} -> {} on {}", e.txn, e.wait_for_txn, escape(&e.key)))
+        .collect();
+    format!(
+        "{}: {} waits for {} on {}, wait chain: [{}]",
+        DEADLOCK_ERROR_PREFIX,
+        start_ts,
+        lock_ts,
+        escape(lock_key),
+        chain.join(", ")
+    )
+}
+
 fn extract_kv_pairs(res: storage::Result<Vec<storage::Result<storage::KvPair>>>) -> Vec<KvPair> {
     match res {
         Ok(res) => res.into_iter()
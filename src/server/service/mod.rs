@@ -14,5 +14,5 @@
 mod kv;
 mod debug;
 
-pub use self::kv::Service as KvService;
+pub use self::kv::{create_coprocessor_stream, Service as KvService};
 pub use self::debug::Service as DebugService;
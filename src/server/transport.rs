@@ -11,18 +11,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::result;
 use std::sync::{Arc, RwLock};
 use std::sync::mpsc::Sender;
 use std::net::SocketAddr;
 use kvproto::raft_serverpb::RaftMessage;
-use kvproto::raft_cmdpb::RaftCmdRequest;
+use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse};
 
 use util::transport::SendCh;
 use util::HandyRwLock;
 use util::worker::{Scheduler, Stopped};
 use util::collections::HashSet;
 use raft::SnapshotStatus;
-use raftstore::store::{BatchCallback, Callback, Msg as StoreMsg, SignificantMsg, Transport};
+use raftstore::store::{BatchCallback, Callback, LocalReader, Msg as StoreMsg, SignificantMsg,
+                       Transport};
 use raftstore::Result as RaftStoreResult;
 use server::raft_client::RaftClient;
 use server::Result;
@@ -56,6 +58,26 @@ pub trait RaftStoreRouter: Send + Clone {
         self.try_send(StoreMsg::new_batch_raft_snapshot_cmd(batch, on_finished))
     }
 
+    // Send a read request at `read_ts` to local store, which can be served by any peer.
+    fn send_stale_read(
+        &self,
+        read_ts: u64,
+        request: RaftCmdRequest,
+        callback: Callback,
+    ) -> RaftStoreResult<()> {
+        self.try_send(StoreMsg::StaleRead {
+            read_ts,
+            request,
+            callback,
+        })
+    }
+
+    // Serve the read request on the calling thread, the request is given back if it
+    // has to be sent to local store.
+    fn local_read(&self, req: RaftCmdRequest) -> result::Result<RaftCmdResponse, RaftCmdRequest> {
+        Err(req)
+    }
+
     // Send significant message. We should guarantee that the message can't be dropped.
     fn significant_send(&self, msg: SignificantMsg) -> RaftStoreResult<()>;
 
@@ -86,16 +108,19 @@ pub trait RaftStoreRouter: Send + Clone {
 pub struct ServerRaftStoreRouter {
     pub ch: SendCh<StoreMsg>,
     pub significant_msg_sender: Sender<SignificantMsg>,
+    pub local_reader: LocalReader,
 }
 
 impl ServerRaftStoreRouter {
     pub fn new(
         ch: SendCh<StoreMsg>,
         significant_msg_sender: Sender<SignificantMsg>,
+        local_reader: LocalReader,
     ) -> ServerRaftStoreRouter {
         ServerRaftStoreRouter {
             ch: ch,
             significant_msg_sender: significant_msg_sender,
+            local_reader: local_reader,
         }
     }
 }
@@ -127,6 +152,10 @@ impl RaftStoreRouter for ServerRaftStoreRouter {
         self.try_send(StoreMsg::new_batch_raft_snapshot_cmd(batch, on_finished))
     }
 
+    fn local_read(&self, req: RaftCmdRequest) -> result::Result<RaftCmdResponse, RaftCmdRequest> {
+        self.local_reader.read(req)
+    }
+
     fn significant_send(&self, msg: SignificantMsg) -> RaftStoreResult<()> {
         if let Err(e) = self.significant_msg_sender.send(msg) {
             return Err(box_err!("failed to sendsignificant msg {:?}", e));
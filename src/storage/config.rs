@@ -43,6 +43,11 @@ pub struct Config {
     pub scheduler_concurrency: usize,
     pub scheduler_worker_pool_size: usize,
     pub scheduler_pending_write_threshold: ReadableSize,
+    // Raw values are stored with their expire time, see `storage::ttl`.
+    pub enable_ttl: bool,
+    // Obsolete MVCC versions are dropped by the compaction filter of the write CF instead of
+    // being deleted by GC commands.
+    pub enable_compaction_filter_gc: bool,
 }
 
 impl Default for Config {
@@ -57,6 +62,8 @@ impl Default for Config {
             scheduler_concurrency: DEFAULT_SCHED_CONCURRENCY,
             scheduler_worker_pool_size: if total_cpu >= 16 { 8 } else { 4 },
             scheduler_pending_write_threshold: ReadableSize::mb(DEFAULT_SCHED_PENDING_WRITE_MB),
+            enable_ttl: false,
+            enable_compaction_filter_gc: false,
         }
     }
 }
@@ -81,6 +81,18 @@ pub trait Engine: Send + Debug {
         on_finished: BatchCallback<Box<Snapshot>>,
     ) -> Result<()>;
 
+    /// Takes a snapshot to read at `read_ts`, which may be served by any replica once all
+    /// the transactions committed at or before `read_ts` have been applied on it. The data
+    /// newer than `read_ts` may be missed, so the snapshot must only be read at `read_ts`.
+    fn async_stale_snapshot(
+        &self,
+        ctx: &Context,
+        _read_ts: u64,
+        callback: Callback<Box<Snapshot>>,
+    ) -> Result<()> {
+        self.async_snapshot(ctx, callback)
+    }
+
     fn write(&self, ctx: &Context, batch: Vec<Modify>) -> Result<()> {
         let timeout = Duration::from_secs(DEFAULT_TIMEOUT_SECS);
         match wait_op!(|cb| self.async_write(ctx, batch, cb).unwrap(), timeout) {
@@ -135,6 +147,15 @@ pub trait Snapshot: Send {
     fn get_properties_cf(&self, _: CfName) -> Result<TablePropertiesCollection> {
         Err(Error::RocksDb("no user properties".to_owned()))
     }
+    /// The encoded keys of the snapshot are not less than `lower_bound`, it's `None` if
+    /// the snapshot is not bounded, e.g. the snapshot of the whole engine.
+    fn lower_bound(&self) -> Option<&[u8]> {
+        None
+    }
+    /// The encoded keys of the snapshot are less than `upper_bound`.
+    fn upper_bound(&self) -> Option<&[u8]> {
+        None
+    }
     fn clone(&self) -> Box<Snapshot>;
 }
 
@@ -250,6 +271,15 @@ impl Statistics {
         self.lock.processed + self.write.processed + self.data.processed
     }
 
+    pub fn mut_cf_statistics(&mut self, cf: &str) -> &mut CFStatistics {
+        match cf {
+            CF_DEFAULT => &mut self.data,
+            CF_LOCK => &mut self.lock,
+            CF_WRITE => &mut self.write,
+            _ => unreachable!(),
+        }
+    }
+
     pub fn details(&self) -> Vec<(&str, Vec<(&str, usize)>)> {
         vec![
             (CF_DEFAULT, self.data.details()),
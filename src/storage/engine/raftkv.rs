@@ -247,6 +247,41 @@ impl<S: RaftStoreRouter> RaftKv<S> {
         self.call_command(cmd, cb)
     }
 
+    fn exec_snap_request(&self, ctx: &Context, req: Request, cb: Callback<CmdRes>) -> Result<()> {
+        let header = self.new_request_header(ctx);
+        let mut cmd = RaftCmdRequest::new();
+        cmd.set_header(header);
+        cmd.set_requests(RepeatedField::from_vec(vec![req]));
+        // The snapshot is taken on the calling thread if the leader lease is valid.
+        match self.router.local_read(cmd) {
+            Ok(resp) => {
+                let (cb_ctx, res) = on_result(resp, 1, self.db.clone());
+                cb((cb_ctx, res.map_err(Error::into)));
+                Ok(())
+            }
+            Err(cmd) => self.call_command(cmd, cb),
+        }
+    }
+
+    fn exec_stale_snap_request(
+        &self,
+        ctx: &Context,
+        read_ts: u64,
+        req: Request,
+        cb: Callback<CmdRes>,
+    ) -> Result<()> {
+        let header = self.new_request_header(ctx);
+        let mut cmd = RaftCmdRequest::new();
+        cmd.set_header(header);
+        cmd.set_requests(RepeatedField::from_vec(vec![req]));
+        let db = self.db.clone();
+        self.router.send_stale_read(read_ts, cmd, box move |resp| {
+            let (cb_ctx, res) = on_result(resp, 1, db);
+            cb((cb_ctx, res.map_err(Error::into)));
+        })?;
+        Ok(())
+    }
+
     fn batch_exec_snap_requests(
         &self,
         batch: Vec<(Context, Vec<Request>)>,
@@ -273,6 +308,46 @@ fn invalid_resp_type(exp: CmdType, act: CmdType) -> Error {
     ))
 }
 
+/// Converts the modifies to the raft write requests which are proposed as a whole.
+pub fn modifies_to_requests(mut modifies: Vec<Modify>) -> Vec<Request> {
+    let mut reqs = Vec::with_capacity(modifies.len());
+    while !modifies.is_empty() {
+        let m = modifies.pop().unwrap();
+        let mut req = Request::new();
+        match m {
+            Modify::Delete(cf, k) => {
+                let mut delete = DeleteRequest::new();
+                delete.set_key(k.encoded().to_owned());
+                if cf != CF_DEFAULT {
+                    delete.set_cf(cf.to_string());
+                }
+                req.set_cmd_type(CmdType::Delete);
+                req.set_delete(delete);
+            }
+            Modify::Put(cf, k, v) => {
+                let mut put = PutRequest::new();
+                put.set_key(k.encoded().to_owned());
+                put.set_value(v);
+                if cf != CF_DEFAULT {
+                    put.set_cf(cf.to_string());
+                }
+                req.set_cmd_type(CmdType::Put);
+                req.set_put(put);
+            }
+            Modify::DeleteRange(cf, start_key, end_key) => {
+                let mut delete_range = DeleteRangeRequest::new();
+                delete_range.set_cf(cf.to_string());
+                delete_range.set_start_key(start_key.encoded().to_owned());
+                delete_range.set_end_key(end_key.encoded().to_owned());
+                req.set_cmd_type(CmdType::DeleteRange);
+                req.set_delete_range(delete_range);
+            }
+        }
+        reqs.push(req);
+    }
+    reqs
+}
+
 impl<S: RaftStoreRouter> Debug for RaftKv<S> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "RaftKv")
@@ -283,44 +358,10 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
     fn async_write(
         &self,
         ctx: &Context,
-        mut modifies: Vec<Modify>,
+        modifies: Vec<Modify>,
         cb: Callback<()>,
     ) -> engine::Result<()> {
-        let mut reqs = Vec::with_capacity(modifies.len());
-        while !modifies.is_empty() {
-            let m = modifies.pop().unwrap();
-            let mut req = Request::new();
-            match m {
-                Modify::Delete(cf, k) => {
-                    let mut delete = DeleteRequest::new();
-                    delete.set_key(k.encoded().to_owned());
-                    if cf != CF_DEFAULT {
-                        delete.set_cf(cf.to_string());
-                    }
-                    req.set_cmd_type(CmdType::Delete);
-                    req.set_delete(delete);
-                }
-                Modify::Put(cf, k, v) => {
-                    let mut put = PutRequest::new();
-                    put.set_key(k.encoded().to_owned());
-                    put.set_value(v);
-                    if cf != CF_DEFAULT {
-                        put.set_cf(cf.to_string());
-                    }
-                    req.set_cmd_type(CmdType::Put);
-                    req.set_put(put);
-                }
-                Modify::DeleteRange(cf, start_key, end_key) => {
-                    let mut delete_range = DeleteRangeRequest::new();
-                    delete_range.set_cf(cf.to_string());
-                    delete_range.set_start_key(start_key.encoded().to_owned());
-                    delete_range.set_end_key(end_key.encoded().to_owned());
-                    req.set_cmd_type(CmdType::DeleteRange);
-                    req.set_delete_range(delete_range);
-                }
-            }
-            reqs.push(req);
-        }
+        let reqs = modifies_to_requests(modifies);
 
         ASYNC_REQUESTS_COUNTER_VEC
             .with_label_values(&["write", "all"])
@@ -369,7 +410,7 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
             .with_label_values(&["snapshot"])
             .start_coarse_timer();
 
-        self.exec_requests(ctx, vec![req], box move |(cb_ctx, res)| match res {
+        self.exec_snap_request(ctx, req, box move |(cb_ctx, res)| match res {
             Ok(CmdRes::Resp(r)) => cb((
                 cb_ctx,
                 Err(invalid_resp_type(CmdType::Snap, r[0].get_cmd_type()).into()),
@@ -397,6 +438,45 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
             })
     }
 
+    fn async_stale_snapshot(
+        &self,
+        ctx: &Context,
+        read_ts: u64,
+        cb: Callback<Box<Snapshot>>,
+    ) -> engine::Result<()> {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Snap);
+
+        ASYNC_REQUESTS_COUNTER_VEC
+            .with_label_values(&["stale_snapshot", "all"])
+            .inc();
+        self.exec_stale_snap_request(ctx, read_ts, req, box move |(cb_ctx, res)| match res {
+            Ok(CmdRes::Resp(r)) => cb((
+                cb_ctx,
+                Err(invalid_resp_type(CmdType::Snap, r[0].get_cmd_type()).into()),
+            )),
+            Ok(CmdRes::Snap(s)) => {
+                ASYNC_REQUESTS_COUNTER_VEC
+                    .with_label_values(&["stale_snapshot", "success"])
+                    .inc();
+                cb((cb_ctx, Ok(box s)))
+            }
+            Err(e) => {
+                let tag = get_tag_from_engine_error(&e);
+                ASYNC_REQUESTS_COUNTER_VEC
+                    .with_label_values(&["stale_snapshot", tag])
+                    .inc();
+                cb((cb_ctx, Err(e)))
+            }
+        }).map_err(|e| {
+                let tag = get_tag_from_error(&e);
+                ASYNC_REQUESTS_COUNTER_VEC
+                    .with_label_values(&["stale_snapshot", tag])
+                    .inc();
+                e.into()
+            })
+    }
+
     fn async_batch_snapshot(
         &self,
         batch: Vec<Context>,
@@ -498,6 +578,19 @@ impl Snapshot for RegionSnapshot {
         RegionSnapshot::get_properties_cf(self, cf).map_err(|e| e.into())
     }
 
+    fn lower_bound(&self) -> Option<&[u8]> {
+        Some(self.get_start_key())
+    }
+
+    fn upper_bound(&self) -> Option<&[u8]> {
+        let end_key = self.get_end_key();
+        if end_key.is_empty() {
+            None
+        } else {
+            Some(end_key)
+        }
+    }
+
     fn clone(&self) -> Box<Snapshot> {
         Box::new(RegionSnapshot::clone(self))
     }
@@ -40,6 +40,19 @@ lazy_static! {
             "Total number of pending commands."
         ).unwrap();
 
+    pub static ref SCHED_LOCK_WAIT_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_scheduler_lock_wait_total",
+            "Total number of commands waiting for transaction locks."
+        ).unwrap();
+
+    pub static ref DETECTOR_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_scheduler_deadlock_detect_total",
+            "Total number of deadlock detections.",
+            &["type"]
+        ).unwrap();
+
     pub static ref SCHED_WORKER_COUNTER_VEC: CounterVec =
         register_counter_vec!(
             "tikv_scheduler_worker_command_total",
@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use std::thread;
+use std::cmp;
 use std::boxed::FnBox;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::sync::mpsc::{self, Receiver};
@@ -22,12 +23,16 @@ use std::u64;
 use kvproto::kvrpcpb::{CommandPri, LockInfo};
 use kvproto::errorpb;
 use self::metrics::*;
+use self::mvcc::compaction_filter::GcContext;
+use pd::PdTask;
+use util::worker::FutureScheduler;
 
 pub mod engine;
 pub mod mvcc;
 pub mod txn;
 pub mod config;
 pub mod types;
+pub mod ttl;
 mod metrics;
 
 pub use self::config::{Config, DEFAULT_DATA_DIR, DEFAULT_ROCKSDB_SUB_DIR};
@@ -35,8 +40,10 @@ pub use self::engine::{new_local_engine, CFStatistics, Cursor, Engine, Error as
                        FlowStatistics, Modify, ScanMode, Snapshot, Statistics, StatisticsSummary,
                        TEMP_DIR};
 pub use self::engine::raftkv::RaftKv;
-pub use self::txn::{Msg, Scheduler, SnapshotStore, StoreScanner};
-pub use self::types::{make_key, Key, KvPair, MvccInfo, Value};
+pub use self::txn::{ConcurrencyManager, MaxTsObserver, MaxTsSyncRunner, MaxTsSyncTask, Msg,
+                    Scheduler, SnapshotStore, StoreScanner, MAX_TS_OBSERVER_PRIORITY};
+pub use self::types::{make_key, Key, KvPair, MvccInfo, PrewriteResult, SecondaryLocksStatus,
+                      Value};
 pub type Callback<T> = Box<FnBox(Result<T>) + Send>;
 
 pub type CfName = &'static str;
@@ -85,6 +92,9 @@ pub enum StorageCb {
     MvccInfoByKey(Callback<MvccInfo>),
     MvccInfoByStartTs(Callback<Option<(Key, MvccInfo)>>),
     Locks(Callback<Vec<LockInfo>>),
+    PrewriteResult(Callback<PrewriteResult>),
+    SecondaryLocksStatus(Callback<SecondaryLocksStatus>),
+    TTL(Callback<Option<u64>>),
 }
 
 pub enum Command {
@@ -112,12 +122,27 @@ pub enum Command {
         start_ts: u64,
         options: Options,
     },
+    AcquirePessimisticLock {
+        ctx: Context,
+        keys: Vec<Key>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        for_update_ts: u64,
+        // How long to wait for conflicting locks, in milliseconds.
+        wait_timeout: u64,
+        options: Options,
+    },
     Commit {
         ctx: Context,
         keys: Vec<Key>,
         lock_ts: u64,
         commit_ts: u64,
     },
+    CheckSecondaryLocks {
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+    },
     Cleanup {
         ctx: Context,
         key: Key,
@@ -143,11 +168,27 @@ pub enum Command {
         scan_key: Option<Key>,
         keys: Vec<Key>,
     },
-    RawGet { ctx: Context, key: Key },
+    RawGet {
+        ctx: Context,
+        cf: CfName,
+        key: Key,
+    },
+    RawBatchGet {
+        ctx: Context,
+        cf: CfName,
+        keys: Vec<Key>,
+    },
+    RawGetKeyTTL {
+        ctx: Context,
+        cf: CfName,
+        key: Key,
+    },
     RawScan {
         ctx: Context,
+        cf: CfName,
         start_key: Key,
         limit: usize,
+        reverse: bool,
     },
     DeleteRange {
         ctx: Context,
@@ -206,6 +247,20 @@ impl Display for Command {
                 start_ts,
                 ctx
             ),
+            Command::AcquirePessimisticLock {
+                ref ctx,
+                ref keys,
+                start_ts,
+                for_update_ts,
+                ..
+            } => write!(
+                f,
+                "kv::command::acquire_pessimistic_lock keys({}) @ {} {} | {:?}",
+                keys.len(),
+                start_ts,
+                for_update_ts,
+                ctx
+            ),
             Command::Commit {
                 ref ctx,
                 ref keys,
@@ -220,6 +275,17 @@ impl Display for Command {
                 commit_ts,
                 ctx
             ),
+            Command::CheckSecondaryLocks {
+                ref ctx,
+                ref keys,
+                start_ts,
+            } => write!(
+                f,
+                "kv::command::check_secondary_locks keys({}) @ {} | {:?}",
+                keys.len(),
+                start_ts,
+                ctx
+            ),
             Command::Cleanup {
                 ref ctx,
                 ref key,
@@ -265,18 +331,40 @@ impl Display for Command {
                 safe_point,
                 ctx
             ),
-            Command::RawGet { ref ctx, ref key } => {
-                write!(f, "kv::command::rawget {:?} | {:?}", key, ctx)
-            }
+            Command::RawGet {
+                ref ctx,
+                cf,
+                ref key,
+            } => write!(f, "kv::command::rawget {}:{:?} | {:?}", cf, key, ctx),
+            Command::RawBatchGet {
+                ref ctx,
+                cf,
+                ref keys,
+            } => write!(
+                f,
+                "kv::command::rawbatchget {}:keys({}) | {:?}",
+                cf,
+                keys.len(),
+                ctx
+            ),
+            Command::RawGetKeyTTL {
+                ref ctx,
+                cf,
+                ref key,
+            } => write!(f, "kv::command::rawgetkeyttl {}:{:?} | {:?}", cf, key, ctx),
             Command::RawScan {
                 ref ctx,
+                cf,
                 ref start_key,
                 limit,
+                reverse,
             } => write!(
                 f,
-                "kv::command::rawscan {:?} {} | {:?}",
+                "kv::command::rawscan {}:{:?} {} reverse({}) | {:?}",
+                cf,
                 start_key,
                 limit,
+                reverse,
                 ctx
             ),
             Command::DeleteRange {
@@ -320,6 +408,8 @@ impl Command {
             Command::Scan { .. } |
             Command::ScanLock { .. } |
             Command::RawGet { .. } |
+            Command::RawBatchGet { .. } |
+            Command::RawGetKeyTTL { .. } |
             Command::RawScan { .. } |
             // DeleteRange only called by DDL bg thread after table is dropped and
             // must guarantee that there is no other read or write on these keys, so
@@ -356,13 +446,17 @@ impl Command {
             Command::BatchGet { .. } => "batch_get",
             Command::Scan { .. } => "scan",
             Command::Prewrite { .. } => "prewrite",
+            Command::AcquirePessimisticLock { .. } => "acquire_pessimistic_lock",
             Command::Commit { .. } => "commit",
+            Command::CheckSecondaryLocks { .. } => "check_secondary_locks",
             Command::Cleanup { .. } => "cleanup",
             Command::Rollback { .. } => "rollback",
             Command::ScanLock { .. } => "scan_lock",
             Command::ResolveLock { .. } => "resolve_lock",
             Command::Gc { .. } => CMD_TAG_GC,
             Command::RawGet { .. } => "raw_get",
+            Command::RawBatchGet { .. } => "raw_batch_get",
+            Command::RawGetKeyTTL { .. } => "raw_get_key_ttl",
             Command::RawScan { .. } => "raw_scan",
             Command::DeleteRange { .. } => "delete_range",
             Command::Pause { .. } => "pause",
@@ -377,6 +471,8 @@ impl Command {
             Command::BatchGet { start_ts, .. } |
             Command::Scan { start_ts, .. } |
             Command::Prewrite { start_ts, .. } |
+            Command::AcquirePessimisticLock { start_ts, .. } |
+            Command::CheckSecondaryLocks { start_ts, .. } |
             Command::Cleanup { start_ts, .. } |
             Command::Rollback { start_ts, .. } |
             Command::ResolveLock { start_ts, .. } |
@@ -385,6 +481,8 @@ impl Command {
             Command::ScanLock { max_ts, .. } => max_ts,
             Command::Gc { safe_point, .. } => safe_point,
             Command::RawGet { .. } |
+            Command::RawBatchGet { .. } |
+            Command::RawGetKeyTTL { .. } |
             Command::RawScan { .. } |
             Command::DeleteRange { .. } |
             Command::Pause { .. } |
@@ -398,13 +496,17 @@ impl Command {
             Command::BatchGet { ref ctx, .. } |
             Command::Scan { ref ctx, .. } |
             Command::Prewrite { ref ctx, .. } |
+            Command::AcquirePessimisticLock { ref ctx, .. } |
             Command::Commit { ref ctx, .. } |
+            Command::CheckSecondaryLocks { ref ctx, .. } |
             Command::Cleanup { ref ctx, .. } |
             Command::Rollback { ref ctx, .. } |
             Command::ScanLock { ref ctx, .. } |
             Command::ResolveLock { ref ctx, .. } |
             Command::Gc { ref ctx, .. } |
             Command::RawGet { ref ctx, .. } |
+            Command::RawBatchGet { ref ctx, .. } |
+            Command::RawGetKeyTTL { ref ctx, .. } |
             Command::RawScan { ref ctx, .. } |
             Command::DeleteRange { ref ctx, .. } |
             Command::Pause { ref ctx, .. } |
@@ -419,13 +521,17 @@ impl Command {
             Command::BatchGet { ref mut ctx, .. } |
             Command::Scan { ref mut ctx, .. } |
             Command::Prewrite { ref mut ctx, .. } |
+            Command::AcquirePessimisticLock { ref mut ctx, .. } |
             Command::Commit { ref mut ctx, .. } |
+            Command::CheckSecondaryLocks { ref mut ctx, .. } |
             Command::Cleanup { ref mut ctx, .. } |
             Command::Rollback { ref mut ctx, .. } |
             Command::ScanLock { ref mut ctx, .. } |
             Command::ResolveLock { ref mut ctx, .. } |
             Command::Gc { ref mut ctx, .. } |
             Command::RawGet { ref mut ctx, .. } |
+            Command::RawBatchGet { ref mut ctx, .. } |
+            Command::RawGetKeyTTL { ref mut ctx, .. } |
             Command::RawScan { ref mut ctx, .. } |
             Command::DeleteRange { ref mut ctx, .. } |
             Command::Pause { ref mut ctx, .. } |
@@ -448,7 +554,9 @@ impl Command {
                     }
                 }
             },
+            Command::AcquirePessimisticLock { ref keys, .. } |
             Command::Commit { ref keys, .. } |
+            Command::CheckSecondaryLocks { ref keys, .. } |
             Command::Rollback { ref keys, .. } |
             Command::ResolveLock { ref keys, .. } => for key in keys {
                 bytes += key.encoded().len();
@@ -469,6 +577,17 @@ pub struct Options {
     pub lock_ttl: u64,
     pub skip_constraint_check: bool,
     pub key_only: bool,
+    // Prewrite locks that record the secondaries in the primary lock, so that the transaction
+    // is committed once all the prewrites succeed.
+    pub use_async_commit: bool,
+    // The other keys of an async-commit transaction, recorded in the primary lock.
+    pub secondary_keys: Vec<Vec<u8>>,
+    // Commits the transaction in prewrite if all the mutations are in one region.
+    pub try_one_pc: bool,
+    // Set by the scheduler for async-commit and 1PC prewrites.
+    pub min_commit_ts: u64,
+    // Scans the keys before the start key in descending order.
+    pub reverse_scan: bool,
 }
 
 impl Options {
@@ -477,6 +596,7 @@ impl Options {
             lock_ttl: lock_ttl,
             skip_constraint_check: skip_constraint_check,
             key_only: key_only,
+            ..Default::default()
         }
     }
 }
@@ -494,6 +614,15 @@ pub struct Storage {
     // Storage configurations.
     gc_ratio_threshold: f64,
     max_key_size: usize,
+
+    cm: ConcurrencyManager,
+
+    enable_ttl: bool,
+
+    gc_ctx: GcContext,
+
+    // where the load of the reads is reported to, for load based split
+    pd_scheduler: Option<FutureScheduler<PdTask>>,
 }
 
 impl Storage {
@@ -511,6 +640,10 @@ impl Storage {
             })),
             gc_ratio_threshold: config.gc_ratio_threshold,
             max_key_size: config.max_key_size,
+            cm: ConcurrencyManager::new(),
+            enable_ttl: config.enable_ttl,
+            gc_ctx: GcContext::new(),
+            pd_scheduler: None,
         })
     }
 
@@ -532,6 +665,10 @@ impl Storage {
         let sched_worker_pool_size = config.scheduler_worker_pool_size;
         let sched_pending_write_threshold = config.scheduler_pending_write_threshold.0 as usize;
         let ch = self.sendch.clone();
+        let cm = self.cm.clone();
+        let enable_ttl = self.enable_ttl;
+        let gc_ctx = self.gc_ctx.clone();
+        let pd_scheduler = self.pd_scheduler.clone();
         let h = builder.spawn(move || {
             let mut sched = Scheduler::new(
                 engine,
@@ -539,6 +676,10 @@ impl Storage {
                 sched_concurrency,
                 sched_worker_pool_size,
                 sched_pending_write_threshold,
+                cm,
+                enable_ttl,
+                gc_ctx,
+                pd_scheduler,
             );
             if let Err(e) = sched.run(rx) {
                 panic!("scheduler run err:{:?}", e);
@@ -574,6 +715,24 @@ impl Storage {
         self.engine.clone()
     }
 
+    /// Sets the GC state shared with the compaction filter of the write CF, so that GC is done by
+    /// the compaction filter if it's enabled. It must be called before `start`.
+    pub fn set_gc_context(&mut self, gc_ctx: GcContext) {
+        self.gc_ctx = gc_ctx;
+    }
+
+    /// Sets the PD worker which the keys read by the get and scan commands are reported to, so
+    /// that hot regions can be split by their read load. It must be called before `start`.
+    pub fn set_pd_scheduler(&mut self, pd_scheduler: FutureScheduler<PdTask>) {
+        self.pd_scheduler = Some(pd_scheduler);
+    }
+
+    /// Returns the concurrency manager shared by all the read paths, whose max ts should be
+    /// synced through `MaxTsObserver`.
+    pub fn get_concurrency_manager(&self) -> ConcurrencyManager {
+        self.cm.clone()
+    }
+
     fn send(&self, cmd: Command, cb: StorageCb) -> Result<()> {
         box_try!(self.sendch.try_send(Msg::RawCmd { cmd: cmd, cb: cb }));
         Ok(())
@@ -662,6 +821,57 @@ impl Storage {
                 return Ok(());
             }
         }
+        self.prewrite(
+            ctx,
+            mutations,
+            primary,
+            start_ts,
+            options,
+            StorageCb::Booleans(callback),
+        )
+    }
+
+    /// Prewrites `mutations` and reports the commit ts decided by TiKV.
+    ///
+    /// With `options.try_one_pc`, the transaction is committed at once, so `mutations` should be
+    /// all the mutations of the transaction, and they should be in the same region. With
+    /// `options.use_async_commit`, the transaction is committed when all the prewrites succeed,
+    /// at the max `min_commit_ts` of them.
+    pub fn async_prewrite_with_result(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+        callback: Callback<PrewriteResult>,
+    ) -> Result<()> {
+        for m in &mutations {
+            let size = m.key().encoded().len();
+            if size > self.max_key_size {
+                callback(Err(Error::KeyTooLarge(size, self.max_key_size)));
+                return Ok(());
+            }
+        }
+        self.prewrite(
+            ctx,
+            mutations,
+            primary,
+            start_ts,
+            options,
+            StorageCb::PrewriteResult(callback),
+        )
+    }
+
+    fn prewrite(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+        callback: StorageCb,
+    ) -> Result<()> {
         let cmd = Command::Prewrite {
             ctx: ctx,
             mutations: mutations,
@@ -670,7 +880,67 @@ impl Storage {
             options: options,
         };
         let tag = cmd.tag();
-        self.send(cmd, StorageCb::Booleans(callback))?;
+        self.send(cmd, callback)?;
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Checks the secondary locks of an async-commit transaction whose primary lock is found.
+    ///
+    /// Keys that are not prewritten yet are rolled back, so the transaction can't be committed
+    /// any more if the status is `RolledBack`.
+    pub fn async_check_secondary_locks(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+        callback: Callback<SecondaryLocksStatus>,
+    ) -> Result<()> {
+        let cmd = Command::CheckSecondaryLocks {
+            ctx: ctx,
+            keys: keys,
+            start_ts: start_ts,
+        };
+        let tag = cmd.tag();
+        self.send(cmd, StorageCb::SecondaryLocksStatus(callback))?;
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Locks `keys` for a pessimistic transaction before it is prewritten.
+    ///
+    /// If some keys are locked by other transactions, the command waits up to `wait_timeout`
+    /// milliseconds for them to be committed or rolled back, then fails with the conflicting lock.
+    #[allow(too_many_arguments)]
+    pub fn async_acquire_pessimistic_lock(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        for_update_ts: u64,
+        wait_timeout: u64,
+        options: Options,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        for k in &keys {
+            let size = k.encoded().len();
+            if size > self.max_key_size {
+                callback(Err(Error::KeyTooLarge(size, self.max_key_size)));
+                return Ok(());
+            }
+        }
+        let cmd = Command::AcquirePessimisticLock {
+            ctx: ctx,
+            keys: keys,
+            primary: primary,
+            start_ts: start_ts,
+            for_update_ts: for_update_ts,
+            wait_timeout: wait_timeout,
+            options: options,
+        };
+        let tag = cmd.tag();
+        self.send(cmd, StorageCb::Boolean(callback))?;
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
@@ -814,14 +1084,44 @@ impl Storage {
         Ok(())
     }
 
+    fn rawkv_cf(cf: &str) -> Result<CfName> {
+        if cf.is_empty() {
+            return Ok(CF_DEFAULT);
+        }
+        for c in DATA_CFS {
+            if cf == *c {
+                return Ok(*c);
+            }
+        }
+        Err(Error::InvalidCf(cf.to_owned()))
+    }
+
+    fn raw_write(
+        &self,
+        ctx: Context,
+        modifies: Vec<Modify>,
+        callback: Callback<()>,
+        tag: &'static str,
+    ) -> Result<()> {
+        self.engine.async_write(
+            &ctx,
+            modifies,
+            box |(_, res): (_, engine::Result<_>)| callback(res.map_err(Error::from)),
+        )?;
+        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
     pub fn async_raw_get(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         callback: Callback<Option<Vec<u8>>>,
     ) -> Result<()> {
         let cmd = Command::RawGet {
             ctx: ctx,
+            cf: Storage::rawkv_cf(&cf)?,
             key: Key::from_encoded(key),
         };
         self.send(cmd, StorageCb::SingleValue(callback))?;
@@ -829,30 +1129,111 @@ impl Storage {
         Ok(())
     }
 
+    /// Gets the remaining TTL of `key` in seconds, 0 means it never expires, `None` means it
+    /// is not found or has expired.
+    pub fn async_raw_get_key_ttl(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+        callback: Callback<Option<u64>>,
+    ) -> Result<()> {
+        if !self.enable_ttl {
+            return Err(Error::TTLNotEnabled);
+        }
+        let cmd = Command::RawGetKeyTTL {
+            ctx: ctx,
+            cf: Storage::rawkv_cf(&cf)?,
+            key: Key::from_encoded(key),
+        };
+        self.send(cmd, StorageCb::TTL(callback))?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["get_key_ttl"])
+            .inc();
+        Ok(())
+    }
+
+    /// Gets the values of `keys`, the keys not found are skipped.
+    pub fn async_raw_batch_get(
+        &self,
+        ctx: Context,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+        callback: Callback<Vec<Result<KvPair>>>,
+    ) -> Result<()> {
+        let cmd = Command::RawBatchGet {
+            ctx: ctx,
+            cf: Storage::rawkv_cf(&cf)?,
+            keys: keys.into_iter().map(Key::from_encoded).collect(),
+        };
+        self.send(cmd, StorageCb::KvPairs(callback))?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_get"])
+            .inc();
+        Ok(())
+    }
+
+    /// Encodes a raw value to store, it expires after `ttl` seconds if `ttl` is not 0.
+    fn raw_value(&self, mut value: Vec<u8>, ttl: u64) -> Result<Vec<u8>> {
+        if self.enable_ttl {
+            ttl::append_expire_ts(&mut value, ttl);
+        } else if ttl != 0 {
+            return Err(Error::TTLNotEnabled);
+        }
+        Ok(value)
+    }
+
+    /// Puts `value` which expires after `ttl` seconds, 0 means it never expires.
     pub fn async_raw_put(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         value: Vec<u8>,
+        ttl: u64,
         callback: Callback<()>,
     ) -> Result<()> {
         if key.len() > self.max_key_size {
             callback(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
             return Ok(());
         }
-        try!(self.engine
-            .async_write(&ctx,
-                         vec![Modify::Put(CF_DEFAULT, Key::from_encoded(key), value)],
-                         box |(_, res): (_, engine::Result<_>)| {
-                             callback(res.map_err(Error::from))
-                         }));
-        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["put"]).inc();
-        Ok(())
+        let cf = Storage::rawkv_cf(&cf)?;
+        let value = self.raw_value(value, ttl)?;
+        self.raw_write(
+            ctx,
+            vec![Modify::Put(cf, Key::from_encoded(key), value)],
+            callback,
+            "put",
+        )
+    }
+
+    /// Puts all the `pairs` in one write, so they are applied atomically.
+    pub fn async_raw_batch_put(
+        &self,
+        ctx: Context,
+        cf: String,
+        pairs: Vec<KvPair>,
+        ttl: u64,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cf = Storage::rawkv_cf(&cf)?;
+        for &(ref key, _) in &pairs {
+            if key.len() > self.max_key_size {
+                callback(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
+                return Ok(());
+            }
+        }
+        let mut modifies = Vec::with_capacity(pairs.len());
+        for (k, v) in pairs {
+            modifies.push(Modify::Put(cf, Key::from_encoded(k), self.raw_value(v, ttl)?));
+        }
+        self.raw_write(ctx, modifies, callback, "batch_put")
     }
 
     pub fn async_raw_delete(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         callback: Callback<()>,
     ) -> Result<()> {
@@ -860,31 +1241,82 @@ impl Storage {
             callback(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
             return Ok(());
         }
-        self.engine.async_write(
-            &ctx,
-            vec![Modify::Delete(CF_DEFAULT, Key::from_encoded(key))],
-            box |(_, res): (_, engine::Result<_>)| callback(res.map_err(Error::from)),
-        )?;
-        RAWKV_COMMAND_COUNTER_VEC
-            .with_label_values(&["delete"])
-            .inc();
-        Ok(())
+        let cf = Storage::rawkv_cf(&cf)?;
+        self.raw_write(
+            ctx,
+            vec![Modify::Delete(cf, Key::from_encoded(key))],
+            callback,
+            "delete",
+        )
+    }
+
+    /// Deletes all the `keys` in one write, so they are applied atomically.
+    pub fn async_raw_batch_delete(
+        &self,
+        ctx: Context,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cf = Storage::rawkv_cf(&cf)?;
+        for key in &keys {
+            if key.len() > self.max_key_size {
+                callback(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
+                return Ok(());
+            }
+        }
+        let modifies = keys.into_iter()
+            .map(|k| Modify::Delete(cf, Key::from_encoded(k)))
+            .collect();
+        self.raw_write(ctx, modifies, callback, "batch_delete")
+    }
+
+    /// Deletes the keys in [`start_key`, `end_key`).
+    pub fn async_raw_delete_range(
+        &self,
+        ctx: Context,
+        cf: String,
+        start_key: Vec<u8>,
+        end_key: Vec<u8>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        if start_key.len() > self.max_key_size || end_key.len() > self.max_key_size {
+            let len = cmp::max(start_key.len(), end_key.len());
+            callback(Err(Error::KeyTooLarge(len, self.max_key_size)));
+            return Ok(());
+        }
+        let cf = Storage::rawkv_cf(&cf)?;
+        self.raw_write(
+            ctx,
+            vec![
+                Modify::DeleteRange(cf, Key::from_encoded(start_key), Key::from_encoded(end_key)),
+            ],
+            callback,
+            "delete_range",
+        )
     }
 
+    /// Scans at most `limit` pairs from `key`, or the pairs before `key` in descending order
+    /// if `reverse` is set.
     pub fn async_raw_scan(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         limit: usize,
+        reverse: bool,
         callback: Callback<Vec<Result<KvPair>>>,
     ) -> Result<()> {
         let cmd = Command::RawScan {
             ctx: ctx,
+            cf: Storage::rawkv_cf(&cf)?,
             start_key: Key::from_encoded(key),
             limit: limit,
+            reverse: reverse,
         };
         self.send(cmd, StorageCb::KvPairs(callback))?;
-        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["scan"]).inc();
+        let tag = if reverse { "reverse_scan" } else { "scan" };
+        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
 
@@ -926,6 +1358,10 @@ impl Clone for Storage {
             handle: self.handle.clone(),
             gc_ratio_threshold: self.gc_ratio_threshold,
             max_key_size: self.max_key_size,
+            cm: self.cm.clone(),
+            enable_ttl: self.enable_ttl,
+            gc_ctx: self.gc_ctx.clone(),
+            pd_scheduler: self.pd_scheduler.clone(),
         }
     }
 }
@@ -968,6 +1404,13 @@ quick_error! {
             description("max key size exceeded")
             display("max key size exceeded, size: {}, limit: {}", size, limit)
         }
+        InvalidCf(cf_name: String) {
+            description("invalid cf name")
+            display("invalid cf name: {}", cf_name)
+        }
+        TTLNotEnabled {
+            description("ttl is not enabled")
+        }
     }
 }
 
@@ -993,8 +1436,11 @@ pub fn get_tag_from_header(header: &errorpb::Error) -> &'static str {
 mod tests {
     use super::*;
     use std::sync::mpsc::{channel, Sender};
+    use std::time::{Duration, Instant};
     use kvproto::kvrpcpb::Context;
     use util::config::ReadableSize;
+    use storage::mvcc::{Lock, LockType};
+    use util::codec::number::NumberEncoder;
 
     fn expect_get_none(done: Sender<i32>, id: i32) -> Callback<Option<Value>> {
         Box::new(move |x: Result<Option<Value>>| {
@@ -1035,6 +1481,32 @@ mod tests {
         })
     }
 
+    fn expect_prewrite_result(
+        done: Sender<i32>,
+        min_commit_ts: u64,
+        one_pc_commit_ts: u64,
+        id: i32,
+    ) -> Callback<PrewriteResult> {
+        Box::new(move |x: Result<PrewriteResult>| {
+            let res = x.unwrap();
+            assert!(res.locks.is_empty());
+            assert_eq!(res.min_commit_ts, min_commit_ts);
+            assert_eq!(res.one_pc_commit_ts, one_pc_commit_ts);
+            done.send(id).unwrap();
+        })
+    }
+
+    fn expect_secondary_locks_status(
+        done: Sender<i32>,
+        status: SecondaryLocksStatus,
+        id: i32,
+    ) -> Callback<SecondaryLocksStatus> {
+        Box::new(move |x: Result<SecondaryLocksStatus>| {
+            assert_eq!(x.unwrap(), status);
+            done.send(id).unwrap();
+        })
+    }
+
     fn expect_scan(
         done: Sender<i32>,
         pairs: Vec<Option<KvPair>>,
@@ -1416,6 +1888,573 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_rawkv_cf() {
+        assert_eq!(Storage::rawkv_cf("").unwrap(), CF_DEFAULT);
+        assert_eq!(Storage::rawkv_cf("default").unwrap(), CF_DEFAULT);
+        assert_eq!(Storage::rawkv_cf("lock").unwrap(), CF_LOCK);
+        assert_eq!(Storage::rawkv_cf("write").unwrap(), CF_WRITE);
+        // Raft logs are not exposed.
+        assert!(Storage::rawkv_cf("raft").is_err());
+        assert!(Storage::rawkv_cf("invalid").is_err());
+    }
+
+    fn expect_ttl(done: Sender<i32>, ttl: Option<u64>, id: i32) -> Callback<Option<u64>> {
+        Box::new(move |x: Result<Option<u64>>| {
+            let res = x.unwrap();
+            match (res, ttl) {
+                // The clock may move forward during the test.
+                (Some(res), Some(ttl)) => assert!(res <= ttl && res + 2 >= ttl),
+                (res, ttl) => assert_eq!(res, ttl),
+            }
+            done.send(id).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_raw_ttl() {
+        let mut config = Config::default();
+        let storage = Storage::new(&config).unwrap();
+        let res = storage.async_raw_put(
+            Context::new(),
+            "".to_owned(),
+            b"k1".to_vec(),
+            b"v1".to_vec(),
+            10,
+            expect_ok(channel().0, 0),
+        );
+        match res {
+            Err(Error::TTLNotEnabled) => {}
+            res => panic!("expect ttl not enabled, got {:?}", res),
+        }
+
+        config.enable_ttl = true;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_raw_put(
+                Context::new(),
+                "".to_owned(),
+                b"k1".to_vec(),
+                b"v1".to_vec(),
+                100,
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_put(
+                Context::new(),
+                "".to_owned(),
+                b"k2".to_vec(),
+                b"v2".to_vec(),
+                0,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Writes an expired value and a value written before TTL is enabled directly.
+        let mut expired = b"v3".to_vec();
+        expired.encode_u64(1).unwrap();
+        expired.extend_from_slice(b"TTL\x01");
+        storage
+            .get_engine()
+            .write(
+                &Context::new(),
+                vec![
+                    Modify::Put(CF_DEFAULT, Key::from_encoded(b"k3".to_vec()), expired),
+                    Modify::Put(CF_DEFAULT, Key::from_encoded(b"k0".to_vec()), b"v0".to_vec()),
+                ],
+            )
+            .unwrap();
+
+        storage
+            .async_raw_get(
+                Context::new(),
+                "".to_owned(),
+                b"k1".to_vec(),
+                expect_get_val(tx.clone(), b"v1".to_vec(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get(
+                Context::new(),
+                "".to_owned(),
+                b"k3".to_vec(),
+                expect_get_none(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_batch_get(
+                Context::new(),
+                "".to_owned(),
+                vec![b"k1".to_vec(), b"k2".to_vec(), b"k3".to_vec()],
+                expect_batch_get_vals(
+                    tx.clone(),
+                    vec![
+                        Some((b"k1".to_vec(), b"v1".to_vec())),
+                        Some((b"k2".to_vec(), b"v2".to_vec())),
+                    ],
+                    4,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // The expired value doesn't count in the limit.
+        storage
+            .async_raw_scan(
+                Context::new(),
+                "".to_owned(),
+                b"k2".to_vec(),
+                2,
+                false,
+                expect_scan(tx.clone(), vec![Some((b"k2".to_vec(), b"v2".to_vec()))], 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_scan(
+                Context::new(),
+                "".to_owned(),
+                b"k4".to_vec(),
+                1,
+                true,
+                expect_scan(tx.clone(), vec![Some((b"k2".to_vec(), b"v2".to_vec()))], 6),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+
+        storage
+            .async_raw_get_key_ttl(
+                Context::new(),
+                "".to_owned(),
+                b"k1".to_vec(),
+                expect_ttl(tx.clone(), Some(100), 7),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get_key_ttl(
+                Context::new(),
+                "".to_owned(),
+                b"k2".to_vec(),
+                expect_ttl(tx.clone(), Some(0), 8),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        for (id, key) in vec![b"k3", b"k4"].into_iter().enumerate() {
+            storage
+                .async_raw_get_key_ttl(
+                    Context::new(),
+                    "".to_owned(),
+                    key.to_vec(),
+                    expect_ttl(tx.clone(), None, 9 + id as i32),
+                )
+                .unwrap();
+            rx.recv().unwrap();
+        }
+
+        // The value written before TTL is enabled never expires.
+        storage
+            .async_raw_get(
+                Context::new(),
+                "".to_owned(),
+                b"k0".to_vec(),
+                expect_get_val(tx.clone(), b"v0".to_vec(), 11),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get_key_ttl(
+                Context::new(),
+                "".to_owned(),
+                b"k0".to_vec(),
+                expect_ttl(tx.clone(), Some(0), 12),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+    }
+
+    #[test]
+    fn test_one_pc() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_get(
+                Context::new(),
+                make_key(b"x"),
+                200,
+                expect_get_none(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // The commit ts is larger than the ts of all the reads before.
+        let mut options = Options::default();
+        options.try_one_pc = true;
+        storage
+            .async_prewrite_with_result(
+                Context::new(),
+                vec![
+                    Mutation::Put((make_key(b"x"), b"100".to_vec())),
+                    Mutation::Put((make_key(b"y"), b"101".to_vec())),
+                ],
+                b"x".to_vec(),
+                100,
+                options,
+                expect_prewrite_result(tx.clone(), 0, 201, 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_get(
+                Context::new(),
+                make_key(b"x"),
+                200,
+                expect_get_none(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_batch_get(
+                Context::new(),
+                vec![make_key(b"x"), make_key(b"y")],
+                201,
+                expect_batch_get_vals(
+                    tx.clone(),
+                    vec![
+                        Some((b"x".to_vec(), b"100".to_vec())),
+                        Some((b"y".to_vec(), b"101".to_vec())),
+                    ],
+                    3,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_async_commit() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let mut options = Options::default();
+        options.use_async_commit = true;
+        options.secondary_keys = vec![b"y".to_vec()];
+        storage
+            .async_prewrite_with_result(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                b"x".to_vec(),
+                100,
+                options.clone(),
+                expect_prewrite_result(tx.clone(), 101, 0, 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_get(
+                Context::new(),
+                make_key(b"y"),
+                150,
+                expect_get_none(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_prewrite_with_result(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"y"), b"101".to_vec()))],
+                b"x".to_vec(),
+                100,
+                options,
+                expect_prewrite_result(tx.clone(), 151, 0, 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Readers before the min commit ts are not blocked.
+        storage
+            .async_get(
+                Context::new(),
+                make_key(b"y"),
+                150,
+                expect_get_none(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+
+        // All the keys are prewritten.
+        let lock = Lock::new(LockType::Put, b"x".to_vec(), 100, 0, Some(b"101".to_vec()))
+            .with_min_commit_ts(151)
+            .with_async_commit(vec![]);
+        storage
+            .async_check_secondary_locks(
+                Context::new(),
+                vec![make_key(b"y")],
+                100,
+                expect_secondary_locks_status(
+                    tx.clone(),
+                    SecondaryLocksStatus::Locked(vec![lock]),
+                    4,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"x"), make_key(b"y")],
+                100,
+                151,
+                expect_ok(tx.clone(), 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_check_secondary_locks(
+                Context::new(),
+                vec![make_key(b"y")],
+                100,
+                expect_secondary_locks_status(
+                    tx.clone(),
+                    SecondaryLocksStatus::Committed(151),
+                    6,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+
+        // A transaction with keys not prewritten is rolled back.
+        storage
+            .async_check_secondary_locks(
+                Context::new(),
+                vec![make_key(b"z")],
+                200,
+                expect_secondary_locks_status(tx.clone(), SecondaryLocksStatus::RolledBack, 7),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"z"), b"102".to_vec()))],
+                b"x".to_vec(),
+                200,
+                Options::default(),
+                expect_fail(tx.clone(), 8),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_pessimistic_lock_wait() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(b"x")],
+                b"x".to_vec(),
+                10,
+                10,
+                0,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 0);
+
+        // Waits for the lock of txn 10.
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(b"x")],
+                b"x".to_vec(),
+                20,
+                20,
+                3000,
+                Options::default(),
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        // Woken up after txn 10 commits.
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                b"x".to_vec(),
+                10,
+                Options::default(),
+                expect_ok(tx.clone(), 2),
+            )
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 2);
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"x")],
+                10,
+                15,
+                expect_ok(tx.clone(), 3),
+            )
+            .unwrap();
+        let mut finished = vec![rx.recv().unwrap(), rx.recv().unwrap()];
+        finished.sort();
+        assert_eq!(finished, vec![1, 3]);
+
+        // Fails when the lock of txn 20 is not released in time.
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(b"x")],
+                b"x".to_vec(),
+                30,
+                30,
+                100,
+                Options::default(),
+                expect_fail(tx.clone(), 4),
+            )
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 4);
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_pessimistic_lock_wait_deadline() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(b"x"), make_key(b"y")],
+                b"x".to_vec(),
+                10,
+                10,
+                0,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 0);
+
+        let start = Instant::now();
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(b"x")],
+                b"x".to_vec(),
+                20,
+                20,
+                500,
+                Options::default(),
+                expect_fail(tx.clone(), 1),
+            )
+            .unwrap();
+        thread::sleep(Duration::from_millis(300));
+        // Rolling back y wakes the waiter up, but it's blocked by x again.
+        storage
+            .async_rollback(
+                Context::new(),
+                vec![make_key(b"y")],
+                10,
+                expect_ok(tx.clone(), 2),
+            )
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 2);
+        // The waiter still times out at the deadline of its first wait.
+        assert_eq!(rx.recv().unwrap(), 1);
+        assert!(start.elapsed() < Duration::from_millis(700));
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_pessimistic_lock_deadlock() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        {
+            let (tx, rx) = channel();
+            let acquire = |key: &[u8], start_ts: u64, cb: Callback<()>| {
+                storage
+                    .async_acquire_pessimistic_lock(
+                        Context::new(),
+                        vec![make_key(key)],
+                        key.to_vec(),
+                        start_ts,
+                        start_ts,
+                        3000,
+                        Options::default(),
+                        cb,
+                    )
+                    .unwrap();
+            };
+            acquire(b"a", 10, expect_ok(tx.clone(), 0));
+            assert_eq!(rx.recv().unwrap(), 0);
+            acquire(b"b", 20, expect_ok(tx.clone(), 1));
+            assert_eq!(rx.recv().unwrap(), 1);
+
+            // 10 waits for 20.
+            acquire(b"b", 10, expect_ok(tx.clone(), 2));
+            assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+            // 20 waits for 10, which closes the cycle.
+            let done = tx.clone();
+            acquire(
+                b"a",
+                20,
+                Box::new(move |x: Result<()>| {
+                    match x {
+                        Err(Error::Txn(txn::Error::Deadlock {
+                            start_ts,
+                            lock_ts,
+                            lock_key,
+                            wait_chain,
+                        })) => {
+                            assert_eq!((start_ts, lock_ts), (20, 10));
+                            assert_eq!(lock_key, b"a".to_vec());
+                            let chain: Vec<_> = wait_chain
+                                .into_iter()
+                                .map(|e| (e.txn, e.wait_for_txn, e.key))
+                                .collect();
+                            let expect = vec![(10, 20, b"b".to_vec()), (20, 10, b"a".to_vec())];
+                            assert_eq!(chain, expect);
+                        }
+                        _ => panic!("expect deadlock"),
+                    }
+                    done.send(3).unwrap();
+                }),
+            );
+            assert_eq!(rx.recv().unwrap(), 3);
+
+            // 10 gets the lock after 20 rolls back.
+            storage
+                .async_rollback(
+                    Context::new(),
+                    vec![make_key(b"b")],
+                    20,
+                    expect_ok(tx.clone(), 4),
+                )
+                .unwrap();
+            let mut finished = vec![rx.recv().unwrap(), rx.recv().unwrap()];
+            finished.sort();
+            assert_eq!(finished, vec![2, 4]);
+        }
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_high_priority_get_put() {
         let config = Config::default();
@@ -0,0 +1,525 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::CString;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rocksdb::{new_compaction_filter_raw, CompactionFilter, CompactionFilterContext,
+              CompactionFilterFactory, DBCompactionFilter, SeekKey, Writable, WriteBatch, DB};
+
+use raftstore::store::keys;
+use storage::{Key, CF_DEFAULT, CF_WRITE};
+use storage::types::split_encoded_key_on_ts;
+use util::escape;
+use util::rocksdb::{compact_range, get_cf_handle};
+use util::worker::{Runnable, Scheduler};
+use super::write::{Write, WriteType};
+
+pub const WRITE_COMPACTION_FILTER_NAME: &str = "write_compaction_filter";
+pub const WRITE_COMPACTION_FILTER_FACTORY_NAME: &str = "write_compaction_filter_factory";
+
+// The keys of the obsolete values in the default CF are sent to the worker in batches.
+const DELETE_BATCH_SIZE: usize = 256;
+
+pub enum Task {
+    DeleteDefault { keys: Vec<Vec<u8>> },
+    CompactWrite { start_key: Vec<u8>, end_key: Vec<u8> },
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::DeleteDefault { ref keys } => write!(f, "delete {} default values", keys.len()),
+            Task::CompactWrite {
+                ref start_key,
+                ref end_key,
+            } => write!(
+                f,
+                "compact write cf [{}, {}]",
+                escape(start_key),
+                escape(end_key)
+            ),
+        }
+    }
+}
+
+/// Deletes the values of the obsolete versions found by the compaction filter, and compacts the
+/// ranges worth GC. It runs out of the compaction threads, so that writes never wait for
+/// compactions in the compaction threads.
+pub struct Runner {
+    db: Arc<DB>,
+}
+
+impl Runner {
+    pub fn new(db: Arc<DB>) -> Runner {
+        Runner { db: db }
+    }
+
+    fn delete_default(&self, keys: Vec<Vec<u8>>) -> Result<(), String> {
+        let handle = get_cf_handle(&self.db, CF_DEFAULT)?;
+        let wb = WriteBatch::new();
+        for key in keys {
+            wb.delete_cf(handle, &key)?;
+        }
+        self.db.write(wb)
+    }
+
+    fn compact_write(&self, start_key: &[u8], end_key: &[u8]) -> Result<(), String> {
+        let handle = get_cf_handle(&self.db, CF_WRITE)?;
+        compact_range(&self.db, handle, Some(start_key), Some(end_key), false);
+        Ok(())
+    }
+}
+
+impl Runnable<Task> for Runner {
+    fn run(&mut self, task: Task) {
+        let res = match task {
+            Task::DeleteDefault { keys } => self.delete_default(keys),
+            Task::CompactWrite {
+                ref start_key,
+                ref end_key,
+            } => self.compact_write(start_key, end_key),
+        };
+        if let Err(e) = res {
+            error!("gc by compaction filter failed: {:?}", e);
+        }
+    }
+}
+
+#[derive(Default)]
+struct GcContextInner {
+    // 0 means GC is not started yet, nothing is filtered.
+    safe_point: AtomicU64,
+    // The engine owns the filter factory holding the context, so it's referred weakly.
+    db: Mutex<Option<Weak<DB>>>,
+    scheduler: Mutex<Option<Scheduler<Task>>>,
+}
+
+/// The GC state shared by the write compaction filters of an engine and the storage on it.
+#[derive(Clone, Default)]
+pub struct GcContext {
+    inner: Arc<GcContextInner>,
+}
+
+impl GcContext {
+    pub fn new() -> GcContext {
+        GcContext::default()
+    }
+
+    /// Enables GC by the compaction filter of `db`, the tasks of it are sent to `scheduler`.
+    pub fn init(&self, db: &Arc<DB>, scheduler: Scheduler<Task>) {
+        *self.inner.db.lock().unwrap() = Some(Arc::downgrade(db));
+        *self.inner.scheduler.lock().unwrap() = Some(scheduler);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.scheduler.lock().unwrap().is_some()
+    }
+
+    pub fn safe_point(&self) -> u64 {
+        self.inner.safe_point.load(Ordering::Acquire)
+    }
+
+    /// Pushes the GC safe point down to the compaction filter, it never goes backward.
+    pub fn update_safe_point(&self, safe_point: u64) {
+        let mut current = self.safe_point();
+        while current < safe_point {
+            let prev = self.inner
+                .safe_point
+                .compare_and_swap(current, safe_point, Ordering::AcqRel);
+            if prev == current {
+                break;
+            }
+            current = prev;
+        }
+    }
+
+    /// Compacts the write CF in [`start_key`, `end_key`], which are data keys.
+    pub fn compact_write_cf(&self, start_key: Vec<u8>, end_key: Vec<u8>) {
+        self.schedule(Task::CompactWrite {
+            start_key: start_key,
+            end_key: end_key,
+        });
+    }
+
+    fn db(&self) -> Option<Arc<DB>> {
+        self.inner
+            .db
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|db| db.upgrade())
+    }
+
+    fn schedule(&self, task: Task) {
+        if let Some(ref scheduler) = *self.inner.scheduler.lock().unwrap() {
+            if let Err(e) = scheduler.schedule(task) {
+                error!("failed to schedule gc compaction filter task: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Creates a `WriteCompactionFilter` for each compaction of the write CF.
+pub struct WriteCompactionFilterFactory {
+    ctx: GcContext,
+}
+
+impl WriteCompactionFilterFactory {
+    pub fn new(ctx: GcContext) -> WriteCompactionFilterFactory {
+        WriteCompactionFilterFactory { ctx: ctx }
+    }
+}
+
+impl CompactionFilterFactory for WriteCompactionFilterFactory {
+    fn create_compaction_filter(&self, _: &CompactionFilterContext) -> *mut DBCompactionFilter {
+        let name = CString::new(WRITE_COMPACTION_FILTER_NAME).unwrap();
+        let filter = WriteCompactionFilter::new(self.ctx.clone());
+        unsafe { new_compaction_filter_raw(name, Box::new(filter)) }
+    }
+}
+
+/// Drops the versions in the write CF that no one can read after the GC safe point, and deletes
+/// their values in the default CF.
+///
+/// A version whose value is in the default CF is only dropped after the value is deleted, so
+/// that a value is never left behind. Its value is deleted by the worker, and the version is
+/// dropped in a later compaction.
+///
+/// The latest version before the safe point is kept if it's a put, or a delete hiding older
+/// versions, which may be in other SSTs out of the compaction.
+///
+/// The keys of one compaction are filtered in order by one filter, so it must be created for
+/// each compaction by `WriteCompactionFilterFactory`, and never be shared by compactions.
+pub struct WriteCompactionFilter {
+    ctx: GcContext,
+    // The key being filtered, with the data prefix and without ts.
+    key: Vec<u8>,
+    // Whether a version of `key` before the safe point is kept, the older ones can be dropped.
+    remove_older: bool,
+    // The keys of the values to delete in the default CF, sent in batches.
+    default_keys: Vec<Vec<u8>>,
+}
+
+impl CompactionFilter for WriteCompactionFilter {
+    fn filter(&mut self, _: usize, key: &[u8], value: &[u8]) -> bool {
+        let safe_point = self.ctx.safe_point();
+        if safe_point == 0 || !keys::validate_data_key(key) {
+            return false;
+        }
+        self.do_filter(safe_point, key, value)
+    }
+}
+
+impl Drop for WriteCompactionFilter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl WriteCompactionFilter {
+    fn new(ctx: GcContext) -> WriteCompactionFilter {
+        WriteCompactionFilter {
+            ctx: ctx,
+            key: vec![],
+            remove_older: false,
+            default_keys: vec![],
+        }
+    }
+
+    fn do_filter(&mut self, safe_point: u64, key: &[u8], value: &[u8]) -> bool {
+        let (user_key, commit_ts) = match split_encoded_key_on_ts(key) {
+            Ok(res) => res,
+            Err(_) => return false,
+        };
+        if user_key != self.key.as_slice() {
+            self.key = user_key.to_vec();
+            self.remove_older = false;
+        }
+        if commit_ts > safe_point {
+            return false;
+        }
+        let write = match Write::parse(value) {
+            Ok(write) => write,
+            Err(_) => return false,
+        };
+        if self.remove_older {
+            if write.write_type == WriteType::Put && write.short_value.is_none() {
+                let default_key = Key::from_encoded(user_key.to_vec()).append_ts(write.start_ts);
+                if self.default_value_exists(default_key.encoded()) {
+                    self.delete_default(default_key.encoded().to_owned());
+                    return false;
+                }
+            }
+            return true;
+        }
+        match write.write_type {
+            WriteType::Put => {
+                self.remove_older = true;
+                false
+            }
+            WriteType::Delete => {
+                self.remove_older = true;
+                // Nothing is left to be hidden by the delete.
+                !self.has_older_versions(user_key, commit_ts)
+            }
+            // Rollbacks and locks before the safe point are useless.
+            WriteType::Rollback | WriteType::Lock => true,
+        }
+    }
+
+    // Returns true if it's unknown, so that the version is kept.
+    fn default_value_exists(&self, key: &[u8]) -> bool {
+        let db = match self.ctx.db() {
+            Some(db) => db,
+            None => return true,
+        };
+        let handle = match get_cf_handle(&db, CF_DEFAULT) {
+            Ok(handle) => handle,
+            Err(_) => return true,
+        };
+        match db.get_cf(handle, key) {
+            Ok(value) => value.is_some(),
+            Err(_) => true,
+        }
+    }
+
+    // Returns true if it's unknown, so that the version is kept.
+    fn has_older_versions(&self, user_key: &[u8], commit_ts: u64) -> bool {
+        let db = match self.ctx.db() {
+            Some(db) => db,
+            None => return true,
+        };
+        let handle = match get_cf_handle(&db, CF_WRITE) {
+            Ok(handle) => handle,
+            Err(_) => return true,
+        };
+        let seek_key = Key::from_encoded(user_key.to_vec()).append_ts(commit_ts.saturating_sub(1));
+        let mut iter = db.iter_cf(handle);
+        iter.seek(SeekKey::Key(seek_key.encoded()));
+        if !iter.valid() {
+            return false;
+        }
+        match split_encoded_key_on_ts(iter.key()) {
+            Ok((key, _)) => key == user_key,
+            Err(_) => false,
+        }
+    }
+
+    fn delete_default(&mut self, key: Vec<u8>) {
+        self.default_keys.push(key);
+        if self.default_keys.len() >= DELETE_BATCH_SIZE {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.default_keys.is_empty() {
+            return;
+        }
+        let keys = mem::replace(&mut self.default_keys, vec![]);
+        self.ctx.schedule(Task::DeleteDefault { keys: keys });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use rocksdb::{ColumnFamilyOptions, DBOptions, SeekKey};
+
+    use storage::{make_key, ALL_CFS};
+    use util::rocksdb::{new_engine, new_engine_opt, CFOptions};
+    use util::worker::{dummy_scheduler, Worker};
+    use super::*;
+
+    fn write_key(key: &[u8], commit_ts: u64) -> Vec<u8> {
+        keys::data_key(make_key(key).append_ts(commit_ts).encoded())
+    }
+
+    fn write_value(write_type: WriteType, start_ts: u64, short_value: bool) -> Vec<u8> {
+        let short_value = if short_value {
+            Some(b"v".to_vec())
+        } else {
+            None
+        };
+        Write::new(write_type, start_ts, short_value).to_bytes()
+    }
+
+    #[test]
+    fn test_filter_state() {
+        let path = TempDir::new("_test_filter_state").expect("");
+        let db = Arc::new(new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
+        let default_handle = get_cf_handle(&db, CF_DEFAULT).unwrap();
+        db.put_cf(default_handle, &write_key(b"k1", 3), b"v").unwrap();
+        let write_handle = get_cf_handle(&db, CF_WRITE).unwrap();
+        let value = write_value(WriteType::Put, 8, true);
+        db.put_cf(write_handle, &write_key(b"k2", 10), &value).unwrap();
+
+        let ctx = GcContext::new();
+        ctx.init(&db, dummy_scheduler());
+        let mut filter = WriteCompactionFilter::new(ctx);
+        let cases = vec![
+            // Newer than the safe point.
+            (b"k1", 30, WriteType::Put, 25, false, false),
+            // Useless before the latest version.
+            (b"k1", 20, WriteType::Rollback, 20, false, true),
+            (b"k1", 19, WriteType::Lock, 18, false, true),
+            // The latest version before the safe point.
+            (b"k1", 15, WriteType::Put, 12, false, false),
+            // Older versions.
+            (b"k1", 10, WriteType::Put, 8, true, true),
+            (b"k1", 7, WriteType::Delete, 6, false, true),
+            // The value in the default CF is not deleted yet.
+            (b"k1", 5, WriteType::Put, 3, false, false),
+            (b"k1", 4, WriteType::Put, 2, false, true),
+            // The latest delete is kept if there are older versions.
+            (b"k2", 15, WriteType::Delete, 14, false, false),
+            (b"k2", 10, WriteType::Put, 8, false, true),
+            // Otherwise it's useless.
+            (b"k3", 15, WriteType::Delete, 14, false, true),
+        ];
+        for (key, commit_ts, write_type, start_ts, short_value, filtered) in cases {
+            let res = filter.do_filter(
+                25,
+                &write_key(key, commit_ts),
+                &write_value(write_type, start_ts, short_value),
+            );
+            assert_eq!(res, filtered, "{:?} {}", key, commit_ts);
+        }
+        // Only the values not in the write CF are deleted.
+        assert_eq!(filter.default_keys, vec![write_key(b"k1", 3)]);
+
+        // A new filter doesn't remember the keys filtered by others.
+        let mut filter = WriteCompactionFilter::new(GcContext::new());
+        assert!(!filter.do_filter(
+            25,
+            &write_key(b"k2", 12),
+            &write_value(WriteType::Put, 11, true)
+        ));
+    }
+
+    fn new_engine_with_filter(path: &str, ctx: &GcContext) -> Arc<DB> {
+        let cfs_opts = ALL_CFS
+            .iter()
+            .map(|cf| {
+                let mut cf_opts = CFOptions::new(*cf, ColumnFamilyOptions::new());
+                if *cf == CF_WRITE {
+                    cf_opts
+                        .set_compaction_filter_factory(
+                            WRITE_COMPACTION_FILTER_FACTORY_NAME,
+                            Box::new(WriteCompactionFilterFactory::new(ctx.clone())),
+                        )
+                        .unwrap();
+                }
+                cf_opts
+            })
+            .collect();
+        Arc::new(new_engine_opt(path, DBOptions::new(), cfs_opts).unwrap())
+    }
+
+    fn scan_cf(db: &DB, cf: &str) -> Vec<Vec<u8>> {
+        let handle = get_cf_handle(db, cf).unwrap();
+        let mut iter = db.iter_cf(handle);
+        iter.seek(SeekKey::Start);
+        let mut keys = vec![];
+        while iter.valid() {
+            keys.push(iter.key().to_vec());
+            iter.next();
+        }
+        keys
+    }
+
+    #[test]
+    fn test_write_compaction_filter() {
+        let path = TempDir::new("_test_write_compaction_filter").expect("");
+        let ctx = GcContext::new();
+        let db = new_engine_with_filter(path.path().to_str().unwrap(), &ctx);
+        let mut worker = Worker::new("test-gc-compaction-filter");
+        worker.start(Runner::new(db.clone())).unwrap();
+        ctx.init(&db, worker.scheduler());
+        let handle = get_cf_handle(&db, CF_WRITE).unwrap();
+
+        let writes = vec![
+            (b"k1", 30, WriteType::Put, 25, true),
+            (b"k1", 20, WriteType::Put, 18, true),
+            (b"k1", 10, WriteType::Put, 8, true),
+            (b"k2", 20, WriteType::Rollback, 20, true),
+            (b"k2", 10, WriteType::Delete, 8, true),
+            (b"k3", 20, WriteType::Put, 18, true),
+            (b"k3", 10, WriteType::Put, 8, false),
+        ];
+        for &(key, commit_ts, write_type, start_ts, short_value) in &writes {
+            db.put_cf(
+                handle,
+                &write_key(key, commit_ts),
+                &write_value(write_type, start_ts, short_value),
+            ).unwrap();
+        }
+        let default_handle = get_cf_handle(&db, CF_DEFAULT).unwrap();
+        db.put_cf(default_handle, &write_key(b"k3", 8), b"v").unwrap();
+
+        ctx.update_safe_point(25);
+        // The safe point never goes backward.
+        ctx.update_safe_point(1);
+        assert_eq!(ctx.safe_point(), 25);
+        db.compact_range_cf(handle, None, None);
+
+        // The version of k3 is kept until its value is deleted.
+        assert_eq!(
+            scan_cf(&db, CF_WRITE),
+            vec![
+                write_key(b"k1", 30),
+                write_key(b"k1", 20),
+                write_key(b"k3", 20),
+                write_key(b"k3", 10),
+            ]
+        );
+        worker.stop().unwrap().join().unwrap();
+        assert!(scan_cf(&db, CF_DEFAULT).is_empty());
+        db.compact_range_cf(handle, None, None);
+        assert_eq!(
+            scan_cf(&db, CF_WRITE),
+            vec![
+                write_key(b"k1", 30),
+                write_key(b"k1", 20),
+                write_key(b"k3", 20),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_compaction_filter_per_compaction() {
+        let path = TempDir::new("_test_write_compaction_filter_per_compaction").expect("");
+        let ctx = GcContext::new();
+        let db = new_engine_with_filter(path.path().to_str().unwrap(), &ctx);
+        ctx.init(&db, dummy_scheduler());
+        let handle = get_cf_handle(&db, CF_WRITE).unwrap();
+        ctx.update_safe_point(25);
+
+        // The first compaction ends with a version of k1 before the safe point.
+        let value = write_value(WriteType::Put, 8, true);
+        db.put_cf(handle, &write_key(b"k1", 10), &value).unwrap();
+        db.compact_range_cf(handle, None, None);
+        assert_eq!(scan_cf(&db, CF_WRITE), vec![write_key(b"k1", 10)]);
+
+        // The next compaction on the same thread starts with a newer version of k1, which is
+        // the latest one before the safe point and must be kept.
+        let value = write_value(WriteType::Put, 18, true);
+        db.put_cf(handle, &write_key(b"k1", 20), &value).unwrap();
+        db.compact_range_cf(handle, None, None);
+        assert_eq!(scan_cf(&db, CF_WRITE), vec![write_key(b"k1", 20)]);
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use storage::{is_short_value, Mutation, CF_DEFAULT, CF_WRITE};
+use storage::engine::Modify;
+use util::escape;
+use super::write::{Write, WriteType};
+use super::Result;
+
+/// Converts the mutations of a bulk import into the records that a transaction
+/// committed at `commit_ts` leaves in `CF_DEFAULT` and `CF_WRITE`.
+///
+/// Imported data never holds a lock, so the start ts of every record is the same
+/// as `commit_ts`. Mutations must be sorted by key without duplicated keys.
+pub fn import_modifies(mutations: Vec<Mutation>, commit_ts: u64) -> Result<Vec<Modify>> {
+    let mut modifies = Vec::with_capacity(mutations.len());
+    let mut last_key: Option<Vec<u8>> = None;
+    for m in mutations {
+        if let Some(ref last_key) = last_key {
+            if last_key.as_slice() >= m.key().encoded().as_slice() {
+                return Err(box_err!(
+                    "import keys are not in ascending order: {} >= {}",
+                    escape(last_key),
+                    escape(m.key().encoded())
+                ));
+            }
+        }
+        last_key = Some(m.key().encoded().to_owned());
+
+        let (key, write) = match m {
+            Mutation::Put((key, value)) => if is_short_value(&value) {
+                (key, Write::new(WriteType::Put, commit_ts, Some(value)))
+            } else {
+                modifies.push(Modify::Put(CF_DEFAULT, key.append_ts(commit_ts), value));
+                (key, Write::new(WriteType::Put, commit_ts, None))
+            },
+            Mutation::Delete(key) => (key, Write::new(WriteType::Delete, commit_ts, None)),
+            Mutation::Lock(key) => {
+                return Err(box_err!("can't import lock mutation on key {}", key));
+            }
+        };
+        modifies.push(Modify::Put(
+            CF_WRITE,
+            key.append_ts(commit_ts),
+            write.to_bytes(),
+        ));
+    }
+    Ok(modifies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvproto::kvrpcpb::{Context, IsolationLevel};
+    use storage::{make_key, Statistics, ALL_CFS, SHORT_VALUE_MAX_LEN};
+    use storage::engine::{self, Engine, TEMP_DIR};
+    use storage::mvcc::MvccReader;
+
+    #[test]
+    fn test_import_modifies() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+        let mutations = vec![
+            Mutation::Put((make_key(b"k1"), b"v1".to_vec())),
+            Mutation::Delete(make_key(b"k2")),
+            Mutation::Put((make_key(b"k3"), long_value.clone())),
+        ];
+        let modifies = import_modifies(mutations, 10).unwrap();
+        assert_eq!(modifies.len(), 4);
+        engine.write(&Context::new(), modifies).unwrap();
+
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            None,
+            true,
+            None,
+            IsolationLevel::SI,
+        );
+        assert_eq!(reader.get(&make_key(b"k1"), 9).unwrap(), None);
+        assert_eq!(
+            reader.get(&make_key(b"k1"), 10).unwrap(),
+            Some(b"v1".to_vec())
+        );
+        assert_eq!(reader.get(&make_key(b"k2"), 10).unwrap(), None);
+        assert_eq!(reader.get(&make_key(b"k3"), 10).unwrap(), Some(long_value));
+    }
+
+    #[test]
+    fn test_import_modifies_invalid() {
+        // unsorted keys
+        let mutations = vec![
+            Mutation::Put((make_key(b"k2"), b"v2".to_vec())),
+            Mutation::Put((make_key(b"k1"), b"v1".to_vec())),
+        ];
+        assert!(import_modifies(mutations, 10).is_err());
+
+        // duplicated keys
+        let mutations = vec![
+            Mutation::Put((make_key(b"k1"), b"v1".to_vec())),
+            Mutation::Delete(make_key(b"k1")),
+        ];
+        assert!(import_modifies(mutations, 10).is_err());
+
+        // lock
+        let mutations = vec![Mutation::Lock(make_key(b"k1"))];
+        assert!(import_modifies(mutations, 10).is_err());
+    }
+}
@@ -23,11 +23,13 @@ pub enum LockType {
     Put,
     Delete,
     Lock,
+    Pessimistic,
 }
 
 const FLAG_PUT: u8 = b'P';
 const FLAG_DELETE: u8 = b'D';
 const FLAG_LOCK: u8 = b'L';
+const FLAG_PESSIMISTIC: u8 = b'S';
 
 impl LockType {
     pub fn from_mutation(mutation: &Mutation) -> LockType {
@@ -43,6 +45,7 @@ impl LockType {
             FLAG_PUT => Some(LockType::Put),
             FLAG_DELETE => Some(LockType::Delete),
             FLAG_LOCK => Some(LockType::Lock),
+            FLAG_PESSIMISTIC => Some(LockType::Pessimistic),
             _ => None,
         }
     }
@@ -52,10 +55,14 @@ impl LockType {
             LockType::Put => FLAG_PUT,
             LockType::Delete => FLAG_DELETE,
             LockType::Lock => FLAG_LOCK,
+            LockType::Pessimistic => FLAG_PESSIMISTIC,
         }
     }
 }
 
+const MIN_COMMIT_TS_PREFIX: u8 = b'c';
+const ASYNC_COMMIT_PREFIX: u8 = b'a';
+
 #[derive(PartialEq, Debug)]
 pub struct Lock {
     pub lock_type: LockType,
@@ -63,6 +70,11 @@ pub struct Lock {
     pub ts: u64,
     pub ttl: u64,
     pub short_value: Option<Value>,
+    // The transaction can't be committed with a commit ts smaller than this.
+    pub min_commit_ts: u64,
+    pub use_async_commit: bool,
+    // Only the primary lock of an async-commit transaction records the secondaries.
+    pub secondaries: Vec<Vec<u8>>,
 }
 
 impl Lock {
@@ -79,9 +91,23 @@ impl Lock {
             ts: ts,
             ttl: ttl,
             short_value: short_value,
+            min_commit_ts: 0,
+            use_async_commit: false,
+            secondaries: vec![],
         }
     }
 
+    pub fn with_min_commit_ts(mut self, min_commit_ts: u64) -> Lock {
+        self.min_commit_ts = min_commit_ts;
+        self
+    }
+
+    pub fn with_async_commit(mut self, secondaries: Vec<Vec<u8>>) -> Lock {
+        self.use_async_commit = true;
+        self.secondaries = secondaries;
+        self
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut b = Vec::with_capacity(
             1 + MAX_VAR_U64_LEN + self.primary.len() + MAX_VAR_U64_LEN + SHORT_VALUE_MAX_LEN + 2,
@@ -95,6 +121,17 @@ impl Lock {
             b.push(v.len() as u8);
             b.extend_from_slice(v);
         }
+        if self.min_commit_ts > 0 {
+            b.push(MIN_COMMIT_TS_PREFIX);
+            b.encode_u64(self.min_commit_ts).unwrap();
+        }
+        if self.use_async_commit {
+            b.push(ASYNC_COMMIT_PREFIX);
+            b.encode_var_u64(self.secondaries.len() as u64).unwrap();
+            for k in &self.secondaries {
+                b.encode_compact_bytes(k).unwrap();
+            }
+        }
         b
     }
 
@@ -106,29 +143,31 @@ impl Lock {
         let primary = b.decode_compact_bytes()?;
         let ts = b.decode_var_u64()?;
         let ttl = if b.is_empty() { 0 } else { b.decode_var_u64()? };
+        let mut lock = Lock::new(lock_type, primary, ts, ttl, None);
 
-        if b.is_empty() {
-            return Ok(Lock::new(lock_type, primary, ts, ttl, None));
-        }
-
-        let flag = b.read_u8()?;
-        assert_eq!(
-            flag,
-            SHORT_VALUE_PREFIX,
-            "invalid flag [{:?}] in write",
-            flag
-        );
-
-        let len = b.read_u8()?;
-        if len as usize != b.len() {
-            panic!(
-                "short value len [{}] not equal to content len [{}]",
-                len,
-                b.len()
-            );
+        while !b.is_empty() {
+            match b.read_u8()? {
+                SHORT_VALUE_PREFIX => {
+                    let len = b.read_u8()? as usize;
+                    if len > b.len() {
+                        return Err(Error::BadFormatLock);
+                    }
+                    lock.short_value = Some(b[..len].to_vec());
+                    b = &b[len..];
+                }
+                MIN_COMMIT_TS_PREFIX => lock.min_commit_ts = b.decode_u64()?,
+                ASYNC_COMMIT_PREFIX => {
+                    let count = b.decode_var_u64()?;
+                    let mut secondaries = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        secondaries.push(b.decode_compact_bytes()?);
+                    }
+                    lock = lock.with_async_commit(secondaries);
+                }
+                _ => return Err(Error::BadFormatLock),
+            }
         }
-
-        Ok(Lock::new(lock_type, primary, ts, ttl, Some(b.to_vec())))
+        Ok(lock)
     }
 }
 
@@ -185,6 +224,13 @@ mod tests {
                 lt
             );
         }
+
+        // Pessimistic locks are not created from mutations.
+        assert_eq!(LockType::Pessimistic.to_u8(), FLAG_PESSIMISTIC);
+        assert_eq!(
+            LockType::from_u8(FLAG_PESSIMISTIC),
+            Some(LockType::Pessimistic)
+        );
     }
 
     #[test]
@@ -199,6 +245,14 @@ mod tests {
                 10,
                 Some(b"short_value".to_vec()),
             ),
+            Lock::new(LockType::Pessimistic, b"pk".to_vec(), 1, 10, None),
+            Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None).with_min_commit_ts(5),
+            Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, Some(b"v".to_vec()))
+                .with_min_commit_ts(5)
+                .with_async_commit(vec![b"k1".to_vec(), b"k2".to_vec()]),
+            Lock::new(LockType::Lock, b"k1".to_vec(), 1, 10, None)
+                .with_min_commit_ts(5)
+                .with_async_commit(vec![]),
         ];
         for (i, lock) in locks.drain(..).enumerate() {
             let v = lock.to_bytes();
@@ -218,5 +272,11 @@ mod tests {
         );
         let v = lock.to_bytes();
         assert!(Lock::parse(&v[..4]).is_err());
+        // Truncated short value.
+        assert!(Lock::parse(&v[..v.len() - 1]).is_err());
+        // Unknown flag.
+        let mut v = lock.to_bytes();
+        v.push(b'x');
+        assert!(Lock::parse(&v).is_err());
     }
 }
@@ -15,14 +15,17 @@ mod reader;
 mod txn;
 mod lock;
 mod write;
+mod import;
 mod metrics;
+pub mod compaction_filter;
 
 use std::io;
 use std::error;
-pub use self::txn::{MvccTxn, MAX_TXN_WRITE_SIZE};
+pub use self::txn::{MvccTxn, SecondaryLockStatus, MAX_TXN_WRITE_SIZE};
 pub use self::reader::MvccReader;
 pub use self::lock::{Lock, LockType};
 pub use self::write::{Write, WriteType};
+pub use self::import::import_modifies;
 use util::escape;
 
 quick_error! {
@@ -66,6 +69,20 @@ quick_error! {
             display("write conflict {} with {}, key:{:?}, primary:{:?}",
              start_ts, conflict_ts, key, primary)
         }
+        PessimisticLockNotPrewritten { start_ts: u64, key: Vec<u8> } {
+            description("pessimistic lock not prewritten")
+            display("pessimistic lock of {} not prewritten, key:{:?}", start_ts, key)
+        }
+        CommitTsExpired { start_ts: u64, commit_ts: u64, key: Vec<u8>, min_commit_ts: u64 } {
+            description("commit ts expired")
+            display("commit ts {} of {} is less than min commit ts {}, key:{:?}",
+             commit_ts, start_ts, min_commit_ts, key)
+        }
+        AsyncCommitUndetermined { start_ts: u64, key: Vec<u8> } {
+            description("async-commit txn undetermined")
+            display("async-commit txn {} may be committed, check its secondary locks, key:{:?}",
+             start_ts, key)
+        }
         KeyVersion {description("bad format key(version)")}
         Other(err: Box<error::Error + Sync + Send>) {
             from()
@@ -114,6 +131,29 @@ impl Error {
                 key: key.to_owned(),
                 primary: primary.to_owned(),
             }),
+            Error::PessimisticLockNotPrewritten { start_ts, ref key } => {
+                Some(Error::PessimisticLockNotPrewritten {
+                    start_ts: start_ts,
+                    key: key.to_owned(),
+                })
+            }
+            Error::CommitTsExpired {
+                start_ts,
+                commit_ts,
+                ref key,
+                min_commit_ts,
+            } => Some(Error::CommitTsExpired {
+                start_ts: start_ts,
+                commit_ts: commit_ts,
+                key: key.to_owned(),
+                min_commit_ts: min_commit_ts,
+            }),
+            Error::AsyncCommitUndetermined { start_ts, ref key } => {
+                Some(Error::AsyncCommitUndetermined {
+                    start_ts: start_ts,
+                    key: key.to_owned(),
+                })
+            }
             Error::KeyVersion => Some(Error::KeyVersion),
             Error::Committed { commit_ts } => Some(Error::Committed {
                 commit_ts: commit_ts,
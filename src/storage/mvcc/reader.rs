@@ -14,7 +14,7 @@
 use storage::engine::{Cursor, ScanMode, Snapshot, Statistics};
 use storage::{Key, Value, CF_LOCK, CF_WRITE};
 use super::{Error, Result};
-use super::lock::Lock;
+use super::lock::{Lock, LockType};
 use super::write::{Write, WriteType};
 use raftstore::store::engine::IterOption;
 use std::u64;
@@ -191,7 +191,10 @@ impl<'a> MvccReader<'a> {
 
     fn check_lock(&mut self, key: &Key, mut ts: u64) -> Result<Option<u64>> {
         if let Some(lock) = self.load_lock(key)? {
-            if lock.ts <= ts {
+            // A pessimistic lock carries no data, so it never blocks readers. Neither does a
+            // lock whose transaction must be committed after `ts`.
+            if lock.ts <= ts && lock.lock_type != LockType::Pessimistic && lock.min_commit_ts <= ts
+            {
                 if ts == u64::MAX && key.raw()? == lock.primary {
                     // when ts==u64::MAX(which means to get latest committed version for
                     // primary key),and current key is the primary key, returns the latest
@@ -491,30 +494,32 @@ impl<'a> MvccReader<'a> {
             return true;
         }
 
-        let props = match self.get_mvcc_properties(safe_point) {
-            Some(v) => v,
-            None => return true,
-        };
-
-        // No data older than safe_point to GC.
-        if props.min_ts > safe_point {
-            return false;
+        match self.get_mvcc_properties(safe_point) {
+            Some(props) => check_need_gc(&props, safe_point, ratio_threshold),
+            None => true,
         }
+    }
 
-        // Note: Since the properties are file-based, it can be false positive.
-        // For example, multiple files can have a different version of the same row.
-
-        // A lot of MVCC versions to GC.
-        if props.num_versions as f64 > props.num_rows as f64 * ratio_threshold {
-            return true;
-        }
-        // A lot of non-effective MVCC versions to GC.
-        if props.num_versions as f64 > props.num_puts as f64 * ratio_threshold {
+    // Returns true if any SST of the write CF has enough versions to GC, so it is worth
+    // compacting the range when GC is done by the compaction filter.
+    pub fn need_compaction_gc(&self, safe_point: u64, ratio_threshold: f64) -> bool {
+        if ratio_threshold < 1.0 {
             return true;
         }
 
-        // A lot of MVCC versions of a single row to GC.
-        props.max_row_versions > GC_MAX_ROW_VERSIONS_THRESHOLD
+        let collection = match self.snapshot.get_properties_cf(CF_WRITE) {
+            Ok(v) => v,
+            Err(_) => return true,
+        };
+        for (_, v) in &*collection {
+            match MvccProperties::decode(v.user_collected_properties()) {
+                Ok(props) => if check_need_gc(&props, safe_point, ratio_threshold) {
+                    return true;
+                },
+                Err(_) => return true,
+            }
+        }
+        false
     }
 
     fn get_mvcc_properties(&self, safe_point: u64) -> Option<MvccProperties> {
@@ -542,6 +547,28 @@ impl<'a> MvccReader<'a> {
     }
 }
 
+fn check_need_gc(props: &MvccProperties, safe_point: u64, ratio_threshold: f64) -> bool {
+    // No data older than safe_point to GC.
+    if props.min_ts > safe_point {
+        return false;
+    }
+
+    // Note: Since the properties are file-based, it can be false positive.
+    // For example, multiple files can have a different version of the same row.
+
+    // A lot of MVCC versions to GC.
+    if props.num_versions as f64 > props.num_rows as f64 * ratio_threshold {
+        return true;
+    }
+    // A lot of non-effective MVCC versions to GC.
+    if props.num_versions as f64 > props.num_puts as f64 * ratio_threshold {
+        return true;
+    }
+
+    // A lot of MVCC versions of a single row to GC.
+    props.max_row_versions > GC_MAX_ROW_VERSIONS_THRESHOLD
+}
+
 #[cfg(test)]
 mod tests {
     use std::u64;
@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::fmt;
 use storage::{is_short_value, Key, Mutation, Options, Statistics, Value, CF_DEFAULT, CF_LOCK,
               CF_WRITE};
@@ -24,6 +25,14 @@ use kvproto::kvrpcpb::IsolationLevel;
 
 pub const MAX_TXN_WRITE_SIZE: usize = 32 * 1024;
 
+/// The state of a key written by an async-commit transaction.
+#[derive(Debug, PartialEq)]
+pub enum SecondaryLockStatus {
+    Locked(Lock),
+    Committed(u64),
+    RolledBack,
+}
+
 pub struct MvccTxn<'a> {
     reader: MvccReader<'a>,
     start_ts: u64,
@@ -78,7 +87,12 @@ impl<'a> MvccTxn<'a> {
         ttl: u64,
         short_value: Option<Value>,
     ) {
-        let lock = Lock::new(lock_type, primary, self.start_ts, ttl, short_value).to_bytes();
+        let lock = Lock::new(lock_type, primary, self.start_ts, ttl, short_value);
+        self.put_lock(key, &lock);
+    }
+
+    fn put_lock(&mut self, key: Key, lock: &Lock) {
+        let lock = lock.to_bytes();
         self.write_size += CF_LOCK.len() + key.encoded().len() + lock.len();
         self.writes.push(Modify::Put(CF_LOCK, key, lock));
     }
@@ -123,7 +137,14 @@ impl<'a> MvccTxn<'a> {
         options: &Options,
     ) -> Result<()> {
         let key = mutation.key();
-        if !options.skip_constraint_check {
+        let lock = self.reader.load_lock(key)?;
+        // A pessimistic lock of this transaction has been checked for write conflicts when it
+        // was acquired, so it is simply replaced by the prewrite lock.
+        let pessimistic_locked = match lock {
+            Some(ref lock) => lock.ts == self.start_ts && lock.lock_type == LockType::Pessimistic,
+            None => false,
+        };
+        if !options.skip_constraint_check && !pessimistic_locked {
             if let Some((commit, _)) = self.reader.seek_write(key, u64::max_value())? {
                 // Abort on writes after our start timestamp ...
                 if commit >= self.start_ts {
@@ -140,7 +161,7 @@ impl<'a> MvccTxn<'a> {
             }
         }
         // ... or locks at any timestamp.
-        if let Some(lock) = self.reader.load_lock(key)? {
+        if let Some(lock) = lock {
             if lock.ts != self.start_ts {
                 return Err(Error::KeyIsLocked {
                     key: key.raw()?,
@@ -149,13 +170,15 @@ impl<'a> MvccTxn<'a> {
                     ttl: lock.ttl,
                 });
             }
-            // No need to overwrite the lock and data.
-            // If we use single delete, we can't put a key multiple times.
-            info!(
-                "duplicated prewrite with start_ts {}, ignore it.",
-                self.start_ts
-            );
-            return Ok(());
+            if !pessimistic_locked {
+                // No need to overwrite the lock and data.
+                // If we use single delete, we can't put a key multiple times.
+                info!(
+                    "duplicated prewrite with start_ts {}, ignore it.",
+                    self.start_ts
+                );
+                return Ok(());
+            }
         }
 
         let short_value = if let Mutation::Put((_, ref value)) = mutation {
@@ -168,13 +191,41 @@ impl<'a> MvccTxn<'a> {
             None
         };
 
-        self.lock_key(
-            key.clone(),
-            LockType::from_mutation(&mutation),
-            primary.to_vec(),
-            options.lock_ttl,
-            short_value,
-        );
+        let lock_type = LockType::from_mutation(&mutation);
+        if options.try_one_pc {
+            // All the mutations are prewritten by this command, so the transaction is committed
+            // at `min_commit_ts` directly.
+            let write_type = WriteType::from_lock_type(lock_type).unwrap();
+            let write = Write::new(write_type, self.start_ts, short_value);
+            self.put_write(key, options.min_commit_ts, write.to_bytes());
+            if pessimistic_locked {
+                self.unlock_key(key.clone());
+            }
+        } else if options.use_async_commit {
+            let mut lock = Lock::new(
+                lock_type,
+                primary.to_vec(),
+                self.start_ts,
+                options.lock_ttl,
+                short_value,
+            ).with_min_commit_ts(options.min_commit_ts);
+            // Only the primary lock needs to know the secondaries to resolve the transaction.
+            let secondaries = if key.raw()? == primary {
+                options.secondary_keys.clone()
+            } else {
+                vec![]
+            };
+            lock = lock.with_async_commit(secondaries);
+            self.put_lock(key.clone(), &lock);
+        } else {
+            self.lock_key(
+                key.clone(),
+                lock_type,
+                primary.to_vec(),
+                options.lock_ttl,
+                short_value,
+            );
+        }
 
         if let Mutation::Put((_, ref value)) = mutation {
             if !is_short_value(value) {
@@ -185,9 +236,131 @@ impl<'a> MvccTxn<'a> {
         Ok(())
     }
 
+    /// Locks `key` before the transaction is prewritten, so that other transactions have to wait
+    /// for it instead of failing with write conflicts at commit time.
+    ///
+    /// Writes committed after `for_update_ts` conflict with the lock. The lock is turned into a
+    /// normal one by `prewrite`, or removed by `rollback` and `pessimistic_rollback`.
+    pub fn acquire_pessimistic_lock(
+        &mut self,
+        key: Key,
+        primary: &[u8],
+        for_update_ts: u64,
+        options: &Options,
+    ) -> Result<()> {
+        if let Some(lock) = self.reader.load_lock(&key)? {
+            if lock.ts != self.start_ts {
+                MVCC_CONFLICT_COUNTER
+                    .with_label_values(&["acquire_pessimistic_lock_conflict"])
+                    .inc();
+                return Err(Error::KeyIsLocked {
+                    key: key.raw()?,
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+            // Locked by an earlier request of the same transaction.
+            return Ok(());
+        }
+
+        // Only data changes after `for_update_ts` conflict with the lock.
+        let mut ts = u64::max_value();
+        while let Some((commit, write)) = self.reader.seek_write(&key, ts)? {
+            if commit <= for_update_ts {
+                break;
+            }
+            match write.write_type {
+                WriteType::Put | WriteType::Delete => {
+                    MVCC_CONFLICT_COUNTER
+                        .with_label_values(&["acquire_pessimistic_lock_conflict"])
+                        .inc();
+                    return Err(Error::WriteConflict {
+                        start_ts: self.start_ts,
+                        conflict_ts: commit,
+                        key: key.encoded().to_owned(),
+                        primary: primary.to_vec(),
+                    });
+                }
+                WriteType::Lock | WriteType::Rollback => ts = commit - 1,
+            }
+        }
+        // The transaction may have been rolled back by others while it was waiting.
+        if let Some((commit, write)) = self.reader.seek_write(&key, self.start_ts)? {
+            if commit == self.start_ts && write.write_type == WriteType::Rollback {
+                return Err(Error::WriteConflict {
+                    start_ts: self.start_ts,
+                    conflict_ts: commit,
+                    key: key.encoded().to_owned(),
+                    primary: primary.to_vec(),
+                });
+            }
+        }
+
+        self.lock_key(
+            key,
+            LockType::Pessimistic,
+            primary.to_vec(),
+            options.lock_ttl,
+            None,
+        );
+        Ok(())
+    }
+
+    /// Removes the pessimistic lock of this transaction on `key` without leaving a rollback
+    /// record. Returns false if there is no such lock.
+    pub fn pessimistic_rollback(&mut self, key: &Key) -> Result<bool> {
+        match self.reader.load_lock(key)? {
+            Some(ref lock)
+                if lock.ts == self.start_ts && lock.lock_type == LockType::Pessimistic =>
+            {
+                self.unlock_key(key.clone());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Checks whether `key` has been prewritten by the async-commit transaction.
+    ///
+    /// If the key is neither locked nor committed, a rollback record is written to prevent it
+    /// from being prewritten later, so that the transaction can be safely rolled back.
+    pub fn check_secondary_lock(&mut self, key: &Key) -> Result<SecondaryLockStatus> {
+        if let Some(lock) = self.reader.load_lock(key)? {
+            if lock.ts == self.start_ts {
+                if lock.lock_type != LockType::Pessimistic {
+                    return Ok(SecondaryLockStatus::Locked(lock));
+                }
+                // The key is locked but not prewritten yet.
+                self.unlock_key(key.clone());
+            }
+        }
+        match self.reader.get_txn_commit_info(key, self.start_ts)? {
+            Some((_, WriteType::Rollback)) => Ok(SecondaryLockStatus::RolledBack),
+            Some((commit_ts, _)) => Ok(SecondaryLockStatus::Committed(commit_ts)),
+            None => {
+                let ts = self.start_ts;
+                let write = Write::new(WriteType::Rollback, ts, None);
+                self.put_write(key, ts, write.to_bytes());
+                Ok(SecondaryLockStatus::RolledBack)
+            }
+        }
+    }
+
     pub fn commit(&mut self, key: &Key, commit_ts: u64) -> Result<()> {
         let (lock_type, short_value) = match self.reader.load_lock(key)? {
             Some(ref mut lock) if lock.ts == self.start_ts => {
+                if commit_ts < lock.min_commit_ts {
+                    MVCC_CONFLICT_COUNTER
+                        .with_label_values(&["commit_ts_expired"])
+                        .inc();
+                    return Err(Error::CommitTsExpired {
+                        start_ts: self.start_ts,
+                        commit_ts: commit_ts,
+                        key: key.encoded().to_owned(),
+                        min_commit_ts: lock.min_commit_ts,
+                    });
+                }
                 (lock.lock_type, lock.short_value.take())
             }
             _ => {
@@ -217,11 +390,19 @@ impl<'a> MvccTxn<'a> {
                 };
             }
         };
-        let write = Write::new(
-            WriteType::from_lock_type(lock_type),
-            self.start_ts,
-            short_value,
-        );
+        let write_type = match WriteType::from_lock_type(lock_type) {
+            Some(write_type) => write_type,
+            None => {
+                MVCC_CONFLICT_COUNTER
+                    .with_label_values(&["commit_lock_not_prewritten"])
+                    .inc();
+                return Err(Error::PessimisticLockNotPrewritten {
+                    start_ts: self.start_ts,
+                    key: key.encoded().to_owned(),
+                });
+            }
+        };
+        let write = Write::new(write_type, self.start_ts, short_value);
         self.put_write(key, commit_ts, write.to_bytes());
         self.unlock_key(key.clone());
         Ok(())
@@ -230,6 +411,9 @@ impl<'a> MvccTxn<'a> {
     pub fn rollback(&mut self, key: &Key) -> Result<()> {
         match self.reader.load_lock(key)? {
             Some(ref lock) if lock.ts == self.start_ts => {
+                if lock.use_async_commit && !lock.secondaries.is_empty() {
+                    self.check_async_commit_rollback(key, lock)?;
+                }
                 // If prewrite type is DEL or LOCK, it is no need to delete value.
                 if lock.short_value.is_none() && lock.lock_type == LockType::Put {
                     self.delete_value(key, lock.ts);
@@ -271,6 +455,50 @@ impl<'a> MvccTxn<'a> {
         Ok(())
     }
 
+    /// Checks the secondaries of the async-commit primary `lock` before rolling it back.
+    ///
+    /// The transaction is committed once all its keys are prewritten, so it can only be rolled
+    /// back after one of the secondaries has been rolled back, which is done by
+    /// `check_secondary_lock`.
+    fn check_async_commit_rollback(&mut self, key: &Key, lock: &Lock) -> Result<()> {
+        let mut commit_ts = lock.min_commit_ts;
+        let mut determined = true;
+        for secondary in &lock.secondaries {
+            let secondary = Key::from_raw(secondary);
+            // Secondaries out of the region of the primary can't be read here.
+            let lock = match self.reader.load_lock(&secondary) {
+                Ok(lock) => lock,
+                Err(_) => {
+                    determined = false;
+                    continue;
+                }
+            };
+            if let Some(lock) = lock {
+                if lock.ts == self.start_ts && lock.lock_type != LockType::Pessimistic {
+                    commit_ts = cmp::max(commit_ts, lock.min_commit_ts);
+                    continue;
+                }
+            }
+            match self.reader.get_txn_commit_info(&secondary, self.start_ts) {
+                Ok(Some((_, WriteType::Rollback))) => return Ok(()),
+                Ok(Some((ts, _))) => return Err(Error::Committed { commit_ts: ts }),
+                Ok(None) | Err(_) => determined = false,
+            }
+        }
+        if !determined {
+            return Err(Error::AsyncCommitUndetermined {
+                start_ts: self.start_ts,
+                key: key.encoded().to_owned(),
+            });
+        }
+        MVCC_CONFLICT_COUNTER
+            .with_label_values(&["rollback_async_committed"])
+            .inc();
+        Err(Error::Committed {
+            commit_ts: commit_ts,
+        })
+    }
+
     pub fn gc(&mut self, key: &Key, safe_point: u64) -> Result<()> {
         let mut remove_older = false;
         let mut ts: u64 = u64::max_value();
@@ -337,9 +565,10 @@ impl<'a> MvccTxn<'a> {
 mod tests {
     use tempdir::TempDir;
     use kvproto::kvrpcpb::{Context, IsolationLevel};
-    use super::MvccTxn;
-    use super::super::MvccReader;
+    use super::{MvccTxn, SecondaryLockStatus};
+    use super::super::{Error, MvccReader, Result};
     use super::super::write::{Write, WriteType};
+    use super::super::lock::{Lock, LockType};
     use storage::{make_key, Mutation, Options, ScanMode, Statistics, ALL_CFS, CF_WRITE,
                   SHORT_VALUE_MAX_LEN};
     use storage::engine::{self, Engine, TEMP_DIR};
@@ -850,6 +1079,352 @@ mod tests {
         must_get_rc(engine.as_ref(), key, 20, v1);
     }
 
+    #[test]
+    fn test_pessimistic_lock() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k1", b"v1");
+
+        // Normal: acquire, prewrite, commit.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 1, 1);
+        must_pessimistic_locked(engine.as_ref(), k, 1);
+        // Acquiring again is ok.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 1, 1);
+        // Pessimistic locks don't block readers.
+        must_get_none(engine.as_ref(), k, 3);
+        // Other transactions can't lock or prewrite the key.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 2, 2);
+        must_prewrite_lock_err(engine.as_ref(), k, k, 2);
+        // A pessimistic lock can't be committed before it is prewritten.
+        must_commit_err(engine.as_ref(), k, 1, 2);
+        must_prewrite_put(engine.as_ref(), k, v, k, 1);
+        must_locked(engine.as_ref(), k, 1);
+        must_commit(engine.as_ref(), k, 1, 2);
+        must_unlocked(engine.as_ref(), k);
+        must_get(engine.as_ref(), k, 3, v);
+
+        // Conflicts with writes committed after for_update_ts, but writes committed after
+        // start_ts are ok.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 1, 1);
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 1, 3);
+        // Prewrite skips the write conflict check.
+        must_prewrite_delete(engine.as_ref(), k, k, 1);
+        must_commit(engine.as_ref(), k, 1, 4);
+        must_get_none(engine.as_ref(), k, 5);
+
+        // Rollback removes the pessimistic lock.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 5, 5);
+        must_rollback(engine.as_ref(), k, 5);
+        must_unlocked(engine.as_ref(), k);
+        must_written(engine.as_ref(), k, 5, 5, WriteType::Rollback);
+        // Can't lock the key after the transaction is rolled back.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 5, 6);
+
+        // Pessimistic rollback leaves nothing.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 7, 7);
+        must_pessimistic_rollback(engine.as_ref(), k, 7, true);
+        must_unlocked(engine.as_ref(), k);
+        must_get_commit_ts_none(engine.as_ref(), k, 7);
+        must_pessimistic_rollback(engine.as_ref(), k, 7, false);
+        // A prewrite lock is not removed by pessimistic rollback.
+        must_prewrite_put(engine.as_ref(), k, v, k, 8);
+        must_pessimistic_rollback(engine.as_ref(), k, 8, false);
+        must_locked(engine.as_ref(), k, 8);
+
+        // Rollbacks after for_update_ts don't conflict.
+        must_rollback(engine.as_ref(), k, 8);
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 6, 6);
+        must_pessimistic_locked(engine.as_ref(), k, 6);
+    }
+
+    #[test]
+    fn test_one_pc() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k1, k2, v1) = (b"k1", b"k2", b"v1");
+        let v2 = gen_value(b'v', SHORT_VALUE_MAX_LEN + 1);
+
+        let mut opt = Options::default();
+        opt.try_one_pc = true;
+        opt.min_commit_ts = 11;
+        must_prewrite_put_with_options(engine.as_ref(), k1, v1, k1, 10, &opt);
+        must_prewrite_put_with_options(engine.as_ref(), k2, &v2, k1, 10, &opt);
+        // Committed without locks.
+        must_unlocked(engine.as_ref(), k1);
+        must_unlocked(engine.as_ref(), k2);
+        must_written(engine.as_ref(), k1, 10, 11, WriteType::Put);
+        must_written(engine.as_ref(), k2, 10, 11, WriteType::Put);
+        must_get_none(engine.as_ref(), k1, 10);
+        must_get(engine.as_ref(), k1, 11, v1);
+        must_get(engine.as_ref(), k2, 11, &v2);
+
+        // The pessimistic lock is removed.
+        must_acquire_pessimistic_lock(engine.as_ref(), k1, k1, 20, 20);
+        opt.min_commit_ts = 25;
+        must_prewrite_put_with_options(engine.as_ref(), k1, v1, k1, 20, &opt);
+        must_unlocked(engine.as_ref(), k1);
+        must_written(engine.as_ref(), k1, 20, 25, WriteType::Put);
+    }
+
+    #[test]
+    fn test_async_commit() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (pk, k, v) = (b"pk", b"k", b"v");
+
+        let mut opt = Options::default();
+        opt.use_async_commit = true;
+        opt.secondary_keys = vec![k.to_vec()];
+        opt.min_commit_ts = 15;
+        must_prewrite_put_with_options(engine.as_ref(), pk, v, pk, 10, &opt);
+        must_prewrite_put_with_options(engine.as_ref(), k, v, pk, 10, &opt);
+        // Only the primary lock records the secondaries.
+        let lock = must_load_lock(engine.as_ref(), pk);
+        assert!(lock.use_async_commit);
+        assert_eq!(lock.min_commit_ts, 15);
+        assert_eq!(lock.secondaries, vec![k.to_vec()]);
+        let lock = must_load_lock(engine.as_ref(), k);
+        assert!(lock.use_async_commit);
+        assert_eq!(lock.min_commit_ts, 15);
+        assert!(lock.secondaries.is_empty());
+
+        // Readers before min_commit_ts are not blocked.
+        must_get_none(engine.as_ref(), k, 14);
+        must_get_err(engine.as_ref(), k, 15);
+        // Can't be committed before min_commit_ts.
+        must_commit_err(engine.as_ref(), k, 10, 14);
+
+        match must_check_secondary_lock(engine.as_ref(), k, 10) {
+            SecondaryLockStatus::Locked(lock) => assert_eq!(lock.ts, 10),
+            status => panic!("unexpected status {:?}", status),
+        }
+        must_commit(engine.as_ref(), pk, 10, 15);
+        must_commit(engine.as_ref(), k, 10, 15);
+        assert_eq!(
+            must_check_secondary_lock(engine.as_ref(), k, 10),
+            SecondaryLockStatus::Committed(15)
+        );
+        must_get(engine.as_ref(), k, 15, v);
+
+        // A key not prewritten yet is rolled back, and can't be prewritten any more.
+        let k2 = b"k2";
+        assert_eq!(
+            must_check_secondary_lock(engine.as_ref(), k2, 10),
+            SecondaryLockStatus::RolledBack
+        );
+        must_written(engine.as_ref(), k2, 10, 10, WriteType::Rollback);
+        must_prewrite_lock_err(engine.as_ref(), k2, pk, 10);
+        assert_eq!(
+            must_check_secondary_lock(engine.as_ref(), k2, 10),
+            SecondaryLockStatus::RolledBack
+        );
+
+        // So is a key only locked pessimistically.
+        let k3 = b"k3";
+        must_acquire_pessimistic_lock(engine.as_ref(), k3, pk, 20, 20);
+        assert_eq!(
+            must_check_secondary_lock(engine.as_ref(), k3, 20),
+            SecondaryLockStatus::RolledBack
+        );
+        must_unlocked(engine.as_ref(), k3);
+        must_written(engine.as_ref(), k3, 20, 20, WriteType::Rollback);
+    }
+
+    #[test]
+    fn test_async_commit_rollback() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (pk, k1, k2, v) = (b"pk", b"k1", b"k2", b"v");
+
+        let mut opt = Options::default();
+        opt.use_async_commit = true;
+        opt.secondary_keys = vec![k1.to_vec(), k2.to_vec()];
+        opt.min_commit_ts = 35;
+        must_prewrite_put_with_options(engine.as_ref(), pk, v, pk, 30, &opt);
+        opt.min_commit_ts = 37;
+        must_prewrite_put_with_options(engine.as_ref(), k1, v, pk, 30, &opt);
+
+        // k2 may still be prewritten, the primary can't be rolled back.
+        match try_rollback(engine.as_ref(), pk, 30) {
+            Err(Error::AsyncCommitUndetermined { start_ts, key }) => {
+                assert_eq!(start_ts, 30);
+                assert_eq!(key, make_key(pk).encoded().to_owned());
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        must_locked(engine.as_ref(), pk, 30);
+
+        // All the keys are prewritten, the transaction is committed.
+        opt.min_commit_ts = 40;
+        must_prewrite_put_with_options(engine.as_ref(), k2, v, pk, 30, &opt);
+        match try_rollback(engine.as_ref(), pk, 30) {
+            Err(Error::Committed { commit_ts }) => assert_eq!(commit_ts, 40),
+            res => panic!("unexpected result {:?}", res),
+        }
+        must_locked(engine.as_ref(), pk, 30);
+
+        // The transaction can be rolled back once a secondary is rolled back.
+        let (pk, k) = (b"pk2", b"k3");
+        opt.secondary_keys = vec![k.to_vec()];
+        must_prewrite_put_with_options(engine.as_ref(), pk, v, pk, 50, &opt);
+        assert_eq!(
+            must_check_secondary_lock(engine.as_ref(), k, 50),
+            SecondaryLockStatus::RolledBack
+        );
+        must_rollback(engine.as_ref(), pk, 50);
+        must_unlocked(engine.as_ref(), pk);
+        must_written(engine.as_ref(), pk, 50, 50, WriteType::Rollback);
+    }
+
+    fn try_rollback(engine: &Engine, key: &[u8], start_ts: u64) -> Result<()> {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        txn.rollback(&make_key(key))
+    }
+
+    fn must_prewrite_put_with_options(
+        engine: &Engine,
+        key: &[u8],
+        value: &[u8],
+        pk: &[u8],
+        ts: u64,
+        options: &Options,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        txn.prewrite(Mutation::Put((make_key(key), value.to_vec())), pk, options)
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_check_secondary_lock(
+        engine: &Engine,
+        key: &[u8],
+        start_ts: u64,
+    ) -> SecondaryLockStatus {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let status = txn.check_secondary_lock(&make_key(key)).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+        status
+    }
+
+    fn must_load_lock(engine: &Engine, key: &[u8]) -> Lock {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            None,
+            true,
+            None,
+            IsolationLevel::SI,
+        );
+        reader.load_lock(&make_key(key)).unwrap().unwrap()
+    }
+
+    fn must_acquire_pessimistic_lock(
+        engine: &Engine,
+        key: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        txn.acquire_pessimistic_lock(make_key(key), pk, for_update_ts, &Options::default())
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_acquire_pessimistic_lock_err(
+        engine: &Engine,
+        key: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        assert!(
+            txn.acquire_pessimistic_lock(make_key(key), pk, for_update_ts, &Options::default())
+                .is_err()
+        );
+    }
+
+    fn must_pessimistic_rollback(engine: &Engine, key: &[u8], start_ts: u64, expect: bool) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        assert_eq!(txn.pessimistic_rollback(&make_key(key)).unwrap(), expect);
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_pessimistic_locked(engine: &Engine, key: &[u8], start_ts: u64) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            None,
+            true,
+            None,
+            IsolationLevel::SI,
+        );
+        let lock = reader.load_lock(&make_key(key)).unwrap().unwrap();
+        assert_eq!(lock.ts, start_ts);
+        assert_eq!(lock.lock_type, LockType::Pessimistic);
+    }
+
     fn must_get(engine: &Engine, key: &[u8], ts: u64, expect: &[u8]) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -1352,4 +1927,79 @@ mod tests {
 
         assert_eq!(reader.seek_ts(3).unwrap().unwrap(), make_key(&[2]));
     }
+
+    #[test]
+    fn test_reverse_seek() {
+        let path = TempDir::new("_test_reverse_seek").expect("");
+        let path = path.path().to_str().unwrap();
+        let engine = engine::new_local_engine(path, ALL_CFS).unwrap();
+        let engine = engine.as_ref();
+
+        // k1 is committed.
+        must_prewrite_put(engine, b"k1", b"v1", b"k1", 5);
+        must_commit(engine, b"k1", 5, 10);
+        // k2 is deleted.
+        must_prewrite_put(engine, b"k2", b"v2", b"k2", 5);
+        must_commit(engine, b"k2", 5, 10);
+        must_prewrite_delete(engine, b"k2", b"k2", 15);
+        must_commit(engine, b"k2", 15, 20);
+        // The latest version of k3 is rolled back.
+        must_prewrite_put(engine, b"k3", b"v3", b"k3", 5);
+        must_commit(engine, b"k3", 5, 10);
+        must_prewrite_put(engine, b"k3", b"v33", b"k3", 15);
+        must_rollback(engine, b"k3", 15);
+        // k4 is only rolled back.
+        must_prewrite_put(engine, b"k4", b"v4", b"k4", 5);
+        must_rollback(engine, b"k4", 5);
+        // The latest version of k5 is a lock record.
+        must_prewrite_put(engine, b"k5", b"v5", b"k5", 5);
+        must_commit(engine, b"k5", 5, 10);
+        must_prewrite_lock(engine, b"k5", b"k5", 15);
+        must_commit(engine, b"k5", 15, 20);
+        // k6 is locked after the read ts.
+        must_prewrite_put(engine, b"k6", b"v6", b"k6", 50);
+
+        must_reverse_seek(engine, b"k7", 30, Some((b"k5", b"v5")));
+        must_reverse_seek(engine, b"k5", 30, Some((b"k3", b"v3")));
+        must_reverse_seek(engine, b"k3", 30, Some((b"k1", b"v1")));
+        must_reverse_seek(engine, b"k1", 30, None);
+        // k2 is not deleted yet at ts 12.
+        must_reverse_seek(engine, b"k3", 12, Some((b"k2", b"v2")));
+        // k6 blocks the readers after its lock.
+        must_reverse_seek_err(engine, b"k7", 60);
+        must_commit(engine, b"k6", 50, 60);
+        must_reverse_seek(engine, b"k7", 60, Some((b"k6", b"v6")));
+    }
+
+    fn must_reverse_seek(engine: &Engine, key: &[u8], ts: u64, expect: Option<(&[u8], &[u8])>) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            Some(ScanMode::Backward),
+            true,
+            None,
+            IsolationLevel::SI,
+        );
+        let res = reader.reverse_seek(make_key(key), ts).unwrap();
+        assert_eq!(
+            res,
+            expect.map(|(k, v)| (make_key(k), v.to_vec()))
+        );
+    }
+
+    fn must_reverse_seek_err(engine: &Engine, key: &[u8], ts: u64) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            Some(ScanMode::Backward),
+            true,
+            None,
+            IsolationLevel::SI,
+        );
+        assert!(reader.reverse_seek(make_key(key), ts).is_err());
+    }
 }
@@ -32,11 +32,13 @@ const FLAG_LOCK: u8 = b'L';
 const FLAG_ROLLBACK: u8 = b'R';
 
 impl WriteType {
-    pub fn from_lock_type(tp: LockType) -> WriteType {
+    /// Returns `None` for pessimistic locks, which must be prewritten before commit.
+    pub fn from_lock_type(tp: LockType) -> Option<WriteType> {
         match tp {
-            LockType::Put => WriteType::Put,
-            LockType::Delete => WriteType::Delete,
-            LockType::Lock => WriteType::Lock,
+            LockType::Put => Some(WriteType::Put),
+            LockType::Delete => Some(WriteType::Delete),
+            LockType::Lock => Some(WriteType::Lock),
+            LockType::Pessimistic => None,
         }
     }
 
@@ -134,7 +136,7 @@ mod tests {
         ];
         for (i, (lock_type, write_type, flag)) in tests.drain(..).enumerate() {
             if lock_type.is_some() {
-                let wt = WriteType::from_lock_type(lock_type.unwrap());
+                let wt = WriteType::from_lock_type(lock_type.unwrap()).unwrap();
                 assert_eq!(
                     wt,
                     write_type,
@@ -166,6 +168,7 @@ mod tests {
                 wt
             );
         }
+        assert_eq!(WriteType::from_lock_type(LockType::Pessimistic), None);
     }
 
     #[test]
@@ -0,0 +1,160 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// When TTL is enabled, every raw value is stored as `value | expire_ts | TTL_MAGIC | version`,
+// where `expire_ts` is an u64 of seconds since the unix epoch, and 0 means the value never
+// expires. The trailer tells the values written by TTL from the raw values written before TTL
+// is enabled, which never expire. MVCC values in the default CF may end with the same bytes as
+// the trailer, so `TTLCompactionFilter` skips the MVCC keys.
+
+use rocksdb::CompactionFilter;
+use time;
+
+use raftstore::store::keys;
+use storage::types::is_mvcc_key;
+use util::codec::{Error as CodecError, Result as CodecResult};
+use util::codec::number::{self, NumberDecoder, NumberEncoder};
+
+pub const TTL_COMPACTION_FILTER_NAME: &str = "ttl_compaction_filter";
+
+const TTL_MAGIC: &[u8] = b"TTL";
+const TTL_VERSION: u8 = 1;
+const TTL_TRAILER_SIZE: usize = number::U64_SIZE + 4;
+
+pub fn current_ts() -> u64 {
+    time::get_time().sec as u64
+}
+
+/// Appends the expire ts of a value that lives `ttl` seconds, 0 means forever.
+pub fn append_expire_ts(value: &mut Vec<u8>, ttl: u64) {
+    let expire_ts = if ttl == 0 {
+        0
+    } else {
+        current_ts().saturating_add(ttl)
+    };
+    value.encode_u64(expire_ts).unwrap();
+    value.extend_from_slice(TTL_MAGIC);
+    value.push(TTL_VERSION);
+}
+
+/// Splits a stored raw value into the user value and its expire ts.
+pub fn split_expire_ts(value: &[u8]) -> CodecResult<(&[u8], u64)> {
+    if value.len() < TTL_TRAILER_SIZE {
+        return Err(CodecError::InvalidDataType(
+            format!("raw value {:?} has no expire ts", value),
+        ));
+    }
+    let (value, trailer) = value.split_at(value.len() - TTL_TRAILER_SIZE);
+    let (mut ts, flag) = trailer.split_at(number::U64_SIZE);
+    if &flag[..TTL_MAGIC.len()] != TTL_MAGIC || flag[TTL_MAGIC.len()] != TTL_VERSION {
+        return Err(CodecError::InvalidDataType(
+            format!("raw value {:?} has no expire ts", value),
+        ));
+    }
+    Ok((value, ts.decode_u64()?))
+}
+
+/// Like `split_expire_ts`, but a value without the trailer, which is written before TTL is
+/// enabled, never expires.
+pub fn split_expire_ts_or_forever(value: &[u8]) -> (&[u8], u64) {
+    split_expire_ts(value).unwrap_or((value, 0))
+}
+
+pub fn is_expired(expire_ts: u64, now: u64) -> bool {
+    expire_ts != 0 && expire_ts <= now
+}
+
+/// Returns the seconds a value expiring at `expire_ts` still lives, 0 means forever.
+pub fn remaining_ttl(expire_ts: u64, now: u64) -> u64 {
+    if expire_ts == 0 {
+        0
+    } else {
+        expire_ts.saturating_sub(now)
+    }
+}
+
+/// Drops the expired raw values in compaction. It's only for the default CF, the values of the
+/// lock and write CFs are never dropped by it. Keys encoded by MVCC are always kept, so a raw
+/// key that happens to look like one doesn't expire in compaction, but only on read.
+pub struct TTLCompactionFilter;
+
+impl CompactionFilter for TTLCompactionFilter {
+    fn filter(&mut self, _: usize, key: &[u8], value: &[u8]) -> bool {
+        if !keys::validate_data_key(key) || is_mvcc_key(keys::origin_key(key)) {
+            return false;
+        }
+        match split_expire_ts(value) {
+            Ok((_, expire_ts)) => is_expired(expire_ts, current_ts()),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::make_key;
+    use super::*;
+
+    #[test]
+    fn test_expire_ts() {
+        let now = current_ts();
+        let mut value = b"v".to_vec();
+        append_expire_ts(&mut value, 0);
+        assert_eq!(split_expire_ts(&value).unwrap(), (&b"v"[..], 0));
+        assert!(!is_expired(0, now));
+        assert_eq!(remaining_ttl(0, now), 0);
+
+        let mut value = b"v".to_vec();
+        append_expire_ts(&mut value, 10);
+        let (v, expire_ts) = split_expire_ts(&value).unwrap();
+        assert_eq!(v, b"v");
+        assert!(expire_ts >= now + 10);
+        assert!(!is_expired(expire_ts, now));
+        assert!(is_expired(expire_ts, expire_ts));
+        assert_eq!(remaining_ttl(expire_ts, expire_ts - 3), 3);
+        assert_eq!(remaining_ttl(expire_ts, expire_ts + 1), 0);
+
+        assert!(split_expire_ts(b"v").is_err());
+        assert!(split_expire_ts(b"0123456789abcdef").is_err());
+        assert_eq!(split_expire_ts_or_forever(b"v"), (&b"v"[..], 0));
+        assert_eq!(split_expire_ts_or_forever(&value), (&b"v"[..], expire_ts));
+    }
+
+    #[test]
+    fn test_ttl_compaction_filter() {
+        let mut filter = TTLCompactionFilter;
+        let mut expired = b"v".to_vec();
+        expired.encode_u64(1).unwrap();
+        expired.extend_from_slice(TTL_MAGIC);
+        expired.push(TTL_VERSION);
+        let mut alive = b"v".to_vec();
+        append_expire_ts(&mut alive, 100);
+        let data_key = keys::data_key(b"k");
+
+        assert!(filter.filter(0, &data_key, &expired));
+        assert!(!filter.filter(0, &data_key, &alive));
+        assert!(!filter.filter(0, &data_key, b"v"));
+        assert!(!filter.filter(0, b"k", &expired));
+        // MVCC values ending with the trailer are kept.
+        let mvcc_key = keys::data_key(make_key(b"k").append_ts(10).encoded());
+        assert!(!filter.filter(0, &mvcc_key, &expired));
+
+        // Values without the trailer, or of another version, are kept.
+        let mut value = b"v".to_vec();
+        value.encode_u64(1).unwrap();
+        assert!(!filter.filter(0, &data_key, &value));
+        value.extend_from_slice(TTL_MAGIC);
+        value.push(TTL_VERSION + 1);
+        assert!(!filter.filter(0, &data_key, &value));
+    }
+}
@@ -0,0 +1,391 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The max ts of reads and the memory locks shared by all the read and write paths of a store.
+//!
+//! The commit ts of an async-commit or 1PC transaction is decided by TiKV from the max ts of
+//! the reads it has served, so every read path, the scheduler, the coprocessor and stale reads,
+//! must push the max ts before it takes its snapshot, and must not read the keys of a prewrite
+//! that is being written with a smaller min commit ts.
+//!
+//! A new leader doesn't know the ts of the reads served by the old one, so the max ts is synced
+//! with a fresh ts from PD after a peer becomes the leader, async-commit and 1PC prewrites of the
+//! region are rejected until then.
+
+use std::cmp;
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::u64;
+
+use futures::{future, Future};
+use tokio_core::reactor::Handle;
+use tokio_timer::Timer;
+
+use pd::PdClient;
+use raft::StateRole;
+use raftstore::coprocessor::{Coprocessor, ObserverContext, RegionObserver};
+use storage::Key;
+use storage::mvcc::Result as MvccResult;
+use util::collections::HashMap;
+use util::worker::{FutureRunnable, FutureScheduler};
+
+use super::memory_lock::{MemoryLock, MemoryLocks};
+use super::{Error, Result};
+
+pub const MAX_TS_OBSERVER_PRIORITY: u32 = 100;
+const SYNC_RETRY_INTERVAL_MILLIS: u64 = 500;
+
+struct Inner {
+    max_ts: u64,
+    memory_locks: MemoryLocks,
+    // regions whose max ts is not synced yet -> the sequence of the sync
+    unsynced_regions: HashMap<u64, u64>,
+    sync_seq: u64,
+}
+
+/// `ConcurrencyManager` is cheap to clone, all the clones share the same state.
+#[derive(Clone)]
+pub struct ConcurrencyManager {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for ConcurrencyManager {
+    fn default() -> ConcurrencyManager {
+        ConcurrencyManager::new()
+    }
+}
+
+impl ConcurrencyManager {
+    pub fn new() -> ConcurrencyManager {
+        ConcurrencyManager {
+            inner: Arc::new(Mutex::new(Inner {
+                max_ts: 0,
+                memory_locks: MemoryLocks::new(),
+                unsynced_regions: HashMap::default(),
+                sync_seq: 0,
+            })),
+        }
+    }
+
+    pub fn max_ts(&self) -> u64 {
+        self.inner.lock().unwrap().max_ts
+    }
+
+    /// Pushes the max ts to `ts`. u64::MAX is used to read the latest data, which doesn't need
+    /// to be tracked.
+    pub fn update_max_ts(&self, ts: u64) {
+        update_max_ts(&mut self.inner.lock().unwrap(), ts);
+    }
+
+    /// Pushes the max ts for reading `key` at `ts`, and checks whether the read may miss a
+    /// running prewrite.
+    pub fn read_key_check(&self, key: &Key, ts: u64) -> MvccResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        update_max_ts(&mut inner, ts);
+        inner.memory_locks.check_key(key, ts)
+    }
+
+    /// Like `read_key_check`, but for scanning from `start_key`, backwards if `reverse`.
+    pub fn read_range_check(&self, start_key: &Key, reverse: bool, ts: u64) -> MvccResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        update_max_ts(&mut inner, ts);
+        inner.memory_locks.check_range(start_key, reverse, ts)
+    }
+
+    /// Like `read_key_check`, but for scanning the encoded keys in `[start_key, end_key)`.
+    pub fn read_bounded_range_check(
+        &self,
+        start_key: &[u8],
+        end_key: &[u8],
+        ts: u64,
+    ) -> MvccResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        update_max_ts(&mut inner, ts);
+        inner
+            .memory_locks
+            .check_bounded_range(start_key, end_key, ts)
+    }
+
+    /// Decides the min commit ts of an async-commit or 1PC prewrite of `region_id`, and locks
+    /// the encoded `keys` in memory with it until `unlock_keys` is called.
+    ///
+    /// Fails if the max ts of the region is not synced yet.
+    pub fn lock_keys(
+        &self,
+        region_id: u64,
+        keys: &[Vec<u8>],
+        primary: &[u8],
+        start_ts: u64,
+        min_commit_ts: u64,
+        ttl: u64,
+    ) -> Result<u64> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.unsynced_regions.contains_key(&region_id) {
+            return Err(Error::MaxTsNotSynced {
+                region_id: region_id,
+            });
+        }
+        let min_commit_ts = cmp::max(min_commit_ts, cmp::max(inner.max_ts, start_ts) + 1);
+        for key in keys {
+            let lock = MemoryLock {
+                primary: primary.to_vec(),
+                start_ts: start_ts,
+                min_commit_ts: min_commit_ts,
+                ttl: ttl,
+            };
+            inner.memory_locks.lock(key.clone(), lock);
+        }
+        Ok(min_commit_ts)
+    }
+
+    pub fn unlock_keys(&self, keys: &[Vec<u8>]) {
+        let mut inner = self.inner.lock().unwrap();
+        for key in keys {
+            inner.memory_locks.unlock(key);
+        }
+    }
+
+    /// Pushes the max ts to `ts`, which is a fresh ts from PD, and returns the largest ts
+    /// not larger than `ts` that is less than the commit ts of all the running async-commit
+    /// and 1PC prewrites. The prewrites starting later get a min commit ts larger than `ts`,
+    /// so the returned ts can be used to advance the resolved ts of the regions.
+    pub fn resolve_ts(&self, ts: u64) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        update_max_ts(&mut inner, ts);
+        match inner.memory_locks.min_commit_ts() {
+            Some(min_commit_ts) => cmp::min(ts, min_commit_ts - 1),
+            None => ts,
+        }
+    }
+
+    /// Marks the max ts of `region_id` not synced, returns the sequence to pass to
+    /// `finish_sync`.
+    pub fn start_sync(&self, region_id: u64) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.sync_seq += 1;
+        let seq = inner.sync_seq;
+        inner.unsynced_regions.insert(region_id, seq);
+        seq
+    }
+
+    /// Pushes the max ts to the ts got from PD, and marks the region synced unless another
+    /// sync of it has been started since.
+    pub fn finish_sync(&self, region_id: u64, seq: u64, ts: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        update_max_ts(&mut inner, ts);
+        if inner.unsynced_regions.get(&region_id) == Some(&seq) {
+            inner.unsynced_regions.remove(&region_id);
+        }
+    }
+
+    /// Stops waiting for the sync of `region_id`, the peer is not the leader any more.
+    pub fn cancel_sync(&self, region_id: u64) {
+        self.inner
+            .lock()
+            .unwrap()
+            .unsynced_regions
+            .remove(&region_id);
+    }
+
+    pub fn is_synced(&self, region_id: u64) -> bool {
+        !self.inner
+            .lock()
+            .unwrap()
+            .unsynced_regions
+            .contains_key(&region_id)
+    }
+}
+
+fn update_max_ts(inner: &mut Inner, ts: u64) {
+    if ts != u64::MAX && ts > inner.max_ts {
+        inner.max_ts = ts;
+    }
+}
+
+/// Syncs the max ts of a region with PD.
+pub struct MaxTsSyncTask {
+    region_id: u64,
+    seq: u64,
+}
+
+impl Display for MaxTsSyncTask {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "sync max ts of region {} [{}]", self.region_id, self.seq)
+    }
+}
+
+pub struct MaxTsSyncRunner<C: PdClient> {
+    pd_client: Arc<C>,
+    cm: ConcurrencyManager,
+    scheduler: FutureScheduler<MaxTsSyncTask>,
+    timer: Timer,
+}
+
+impl<C: PdClient> MaxTsSyncRunner<C> {
+    pub fn new(
+        pd_client: Arc<C>,
+        cm: ConcurrencyManager,
+        scheduler: FutureScheduler<MaxTsSyncTask>,
+    ) -> MaxTsSyncRunner<C> {
+        MaxTsSyncRunner {
+            pd_client: pd_client,
+            cm: cm,
+            scheduler: scheduler,
+            timer: Timer::default(),
+        }
+    }
+}
+
+impl<C: PdClient> FutureRunnable<MaxTsSyncTask> for MaxTsSyncRunner<C> {
+    fn run(&mut self, task: MaxTsSyncTask, handle: &Handle) {
+        if self.cm.is_synced(task.region_id) {
+            // The peer is not the leader any more.
+            return;
+        }
+        let cm = self.cm.clone();
+        let scheduler = self.scheduler.clone();
+        let retry = self.timer
+            .sleep(Duration::from_millis(SYNC_RETRY_INTERVAL_MILLIS));
+        let f = self.pd_client.get_tso().then(move |res| match res {
+            Ok(ts) => {
+                cm.finish_sync(task.region_id, task.seq, ts);
+                box future::ok(()) as Box<Future<Item = (), Error = ()> + Send>
+            }
+            Err(e) => {
+                warn!("failed to get tso for {}: {:?}, retry later", task, e);
+                box retry.then(move |_| {
+                    if let Err(e) = scheduler.schedule(task) {
+                        error!("failed to retry syncing max ts: {:?}", e);
+                    }
+                    Ok(())
+                }) as Box<Future<Item = (), Error = ()> + Send>
+            }
+        });
+        handle.spawn(f);
+    }
+}
+
+/// Starts syncing the max ts of a region when the local peer becomes its leader.
+pub struct MaxTsObserver {
+    cm: ConcurrencyManager,
+    scheduler: FutureScheduler<MaxTsSyncTask>,
+}
+
+impl MaxTsObserver {
+    pub fn new(cm: ConcurrencyManager, scheduler: FutureScheduler<MaxTsSyncTask>) -> MaxTsObserver {
+        MaxTsObserver {
+            cm: cm,
+            scheduler: scheduler,
+        }
+    }
+}
+
+impl Coprocessor for MaxTsObserver {}
+
+impl RegionObserver for MaxTsObserver {
+    fn on_role_change(&self, ctx: &mut ObserverContext, role: StateRole) {
+        let region_id = ctx.region().get_id();
+        if role != StateRole::Leader {
+            self.cm.cancel_sync(region_id);
+            return;
+        }
+        let task = MaxTsSyncTask {
+            region_id: region_id,
+            seq: self.cm.start_sync(region_id),
+        };
+        if let Err(e) = self.scheduler.schedule(task) {
+            error!("failed to sync max ts of region {}: {:?}", region_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::make_key;
+    use super::*;
+
+    #[test]
+    fn test_max_ts() {
+        let cm = ConcurrencyManager::new();
+        cm.update_max_ts(10);
+        cm.update_max_ts(u64::MAX);
+        assert_eq!(cm.max_ts(), 10);
+
+        // Reads push the max ts.
+        cm.read_key_check(&make_key(b"k"), 20).unwrap();
+        assert_eq!(cm.max_ts(), 20);
+        cm.read_range_check(&make_key(b"k"), false, 30).unwrap();
+        assert_eq!(cm.max_ts(), 30);
+        cm.read_bounded_range_check(b"", b"", 40).unwrap();
+        assert_eq!(cm.max_ts(), 40);
+
+        // The min commit ts is larger than the max ts, and readers after it are blocked.
+        let keys = vec![make_key(b"k").encoded().to_owned()];
+        assert_eq!(cm.lock_keys(1, &keys, b"k", 5, 0, 100).unwrap(), 41);
+        assert!(cm.read_key_check(&make_key(b"k"), 40).is_ok());
+        assert!(cm.read_key_check(&make_key(b"k"), 41).is_err());
+        cm.unlock_keys(&keys);
+        assert!(cm.read_key_check(&make_key(b"k"), 41).is_ok());
+        assert_eq!(cm.lock_keys(1, &keys, b"k", 50, 0, 100).unwrap(), 51);
+        assert_eq!(cm.lock_keys(1, &keys, b"k", 50, 60, 100).unwrap(), 60);
+    }
+
+    #[test]
+    fn test_resolve_ts() {
+        let cm = ConcurrencyManager::new();
+        assert_eq!(cm.resolve_ts(10), 10);
+        assert_eq!(cm.max_ts(), 10);
+
+        let k1 = vec![make_key(b"k1").encoded().to_owned()];
+        let k2 = vec![make_key(b"k2").encoded().to_owned()];
+        assert_eq!(cm.lock_keys(1, &k1, b"k1", 5, 0, 100).unwrap(), 11);
+        assert_eq!(cm.lock_keys(1, &k2, b"k2", 6, 15, 100).unwrap(), 15);
+        // The running prewrites may be committed at 11.
+        assert_eq!(cm.resolve_ts(20), 10);
+        assert_eq!(cm.max_ts(), 20);
+        cm.unlock_keys(&k1);
+        assert_eq!(cm.resolve_ts(20), 14);
+        cm.unlock_keys(&k2);
+        assert_eq!(cm.resolve_ts(20), 20);
+        // A prewrite starting later is committed after the resolved ts.
+        assert_eq!(cm.lock_keys(1, &k1, b"k1", 5, 0, 100).unwrap(), 21);
+    }
+
+    #[test]
+    fn test_max_ts_sync() {
+        let cm = ConcurrencyManager::new();
+        let keys = vec![make_key(b"k").encoded().to_owned()];
+        let seq = cm.start_sync(1);
+        assert!(!cm.is_synced(1));
+        assert!(cm.is_synced(2));
+        match cm.lock_keys(1, &keys, b"k", 5, 0, 100) {
+            Err(Error::MaxTsNotSynced { region_id }) => assert_eq!(region_id, 1),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(cm.lock_keys(2, &keys, b"k", 5, 0, 100).is_ok());
+
+        // A stale sync doesn't mark the region synced.
+        let new_seq = cm.start_sync(1);
+        cm.finish_sync(1, seq, 100);
+        assert_eq!(cm.max_ts(), 100);
+        assert!(!cm.is_synced(1));
+        cm.finish_sync(1, new_seq, 90);
+        assert_eq!(cm.max_ts(), 100);
+        assert!(cm.is_synced(1));
+
+        cm.start_sync(3);
+        cm.cancel_sync(3);
+        assert!(cm.is_synced(3));
+    }
+}
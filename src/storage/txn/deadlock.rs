@@ -0,0 +1,236 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deadlock detection for transactions waiting for locks.
+//!
+//! Every time a command is about to wait for the lock of another transaction, an edge
+//! `start_ts -> lock_ts` is added to a wait-for graph. The transaction whose edge closes a cycle
+//! is rejected with a deadlock error carrying the wait chain instead of waiting.
+//!
+//! Detection is local only: every store checks the waits of its own scheduler against its own
+//! graph. Cycles formed by waits on different stores are not detected, they are only broken by
+//! the lock wait timeouts.
+
+use util::collections::HashMap;
+
+use super::super::metrics::*;
+
+/// An edge of the wait-for graph: transaction `txn` waits for the lock of `wait_for_txn` on
+/// `key`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaitForEntry {
+    pub txn: u64,
+    pub wait_for_txn: u64,
+    pub key: Vec<u8>,
+}
+
+/// The wait-for graph of transactions.
+#[derive(Default)]
+pub struct DetectTable {
+    // start_ts -> start_ts of the transactions it waits for -> the key it waits for
+    wait_for_map: HashMap<u64, HashMap<u64, Vec<u8>>>,
+}
+
+impl DetectTable {
+    pub fn new() -> DetectTable {
+        DetectTable::default()
+    }
+
+    /// Adds the edge `txn_ts -> wait_for_ts` on `key` to the graph.
+    ///
+    /// If the edge would close a cycle, it is not added, and the wait chain of the cycle is
+    /// returned, which starts from `wait_for_ts` and ends with the new edge.
+    pub fn detect(
+        &mut self,
+        txn_ts: u64,
+        wait_for_ts: u64,
+        key: &[u8],
+    ) -> Option<Vec<WaitForEntry>> {
+        if let Some(mut chain) = self.find_path(wait_for_ts, txn_ts) {
+            chain.push(WaitForEntry {
+                txn: txn_ts,
+                wait_for_txn: wait_for_ts,
+                key: key.to_vec(),
+            });
+            return Some(chain);
+        }
+        self.wait_for_map
+            .entry(txn_ts)
+            .or_insert_with(HashMap::default)
+            .insert(wait_for_ts, key.to_vec());
+        None
+    }
+
+    /// Searches a path from `from` to `to`, returns the edges on it.
+    fn find_path(&self, from: u64, to: u64) -> Option<Vec<WaitForEntry>> {
+        // ts -> the ts it is reached from
+        let mut parents = HashMap::default();
+        let mut stack = vec![from];
+        while let Some(ts) = stack.pop() {
+            let wait_for = match self.wait_for_map.get(&ts) {
+                Some(wait_for) => wait_for,
+                None => continue,
+            };
+            if wait_for.contains_key(&to) {
+                return Some(self.build_chain(&parents, from, ts, to));
+            }
+            for &next in wait_for.keys() {
+                if next != from && !parents.contains_key(&next) {
+                    parents.insert(next, ts);
+                    stack.push(next);
+                }
+            }
+        }
+        None
+    }
+
+    fn build_chain(
+        &self,
+        parents: &HashMap<u64, u64>,
+        from: u64,
+        last: u64,
+        to: u64,
+    ) -> Vec<WaitForEntry> {
+        let mut chain = vec![self.entry(last, to)];
+        let mut ts = last;
+        while ts != from {
+            let parent = parents[&ts];
+            chain.push(self.entry(parent, ts));
+            ts = parent;
+        }
+        chain.reverse();
+        chain
+    }
+
+    fn entry(&self, txn: u64, wait_for_txn: u64) -> WaitForEntry {
+        WaitForEntry {
+            txn: txn,
+            wait_for_txn: wait_for_txn,
+            key: self.wait_for_map[&txn][&wait_for_txn].clone(),
+        }
+    }
+
+    /// Removes the edge `txn_ts -> wait_for_ts`.
+    pub fn clean_up_wait_for(&mut self, txn_ts: u64, wait_for_ts: u64) {
+        let empty = match self.wait_for_map.get_mut(&txn_ts) {
+            Some(wait_for) => {
+                wait_for.remove(&wait_for_ts);
+                wait_for.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.wait_for_map.remove(&txn_ts);
+        }
+    }
+
+    /// Removes all the edges starting from `txn_ts`.
+    pub fn clean_up(&mut self, txn_ts: u64) {
+        self.wait_for_map.remove(&txn_ts);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wait_for_map.is_empty()
+    }
+}
+
+/// Detects deadlocks for the scheduler of the local store.
+#[derive(Default)]
+pub struct Detector {
+    table: DetectTable,
+}
+
+impl Detector {
+    pub fn new() -> Detector {
+        Detector::default()
+    }
+
+    /// Returns the wait chain of the cycle if waiting closes one.
+    pub fn detect(
+        &mut self,
+        txn_ts: u64,
+        wait_for_ts: u64,
+        key: &[u8],
+    ) -> Option<Vec<WaitForEntry>> {
+        DETECTOR_COUNTER_VEC.with_label_values(&["detect"]).inc();
+        let res = self.table.detect(txn_ts, wait_for_ts, key);
+        if res.is_some() {
+            DETECTOR_COUNTER_VEC.with_label_values(&["deadlock"]).inc();
+        }
+        res
+    }
+
+    pub fn clean_up_wait_for(&mut self, txn_ts: u64, wait_for_ts: u64) {
+        self.table.clean_up_wait_for(txn_ts, wait_for_ts);
+    }
+
+    pub fn clean_up(&mut self, txn_ts: u64) {
+        self.table.clean_up(txn_ts);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(edges: &[(u64, u64)]) -> Vec<WaitForEntry> {
+        edges
+            .iter()
+            .map(|&(txn, wait_for_txn)| {
+                WaitForEntry {
+                    txn: txn,
+                    wait_for_txn: wait_for_txn,
+                    key: format!("k{}", wait_for_txn).into_bytes(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_table() {
+        let mut table = DetectTable::new();
+        let mut detect = |txn_ts: u64, wait_for_ts: u64| {
+            table.detect(txn_ts, wait_for_ts, format!("k{}", wait_for_ts).as_bytes())
+        };
+
+        // Deadlock: 1 -> 2 -> 1
+        assert_eq!(detect(1, 2), None);
+        assert_eq!(detect(2, 1), Some(chain(&[(1, 2), (2, 1)])));
+        // Deadlock: 1 -> 2 -> 3 -> 1
+        assert_eq!(detect(2, 3), None);
+        assert_eq!(detect(3, 1), Some(chain(&[(1, 2), (2, 3), (3, 1)])));
+        // Waiting for the same transaction twice is ok.
+        assert_eq!(detect(1, 2), None);
+        // 1 -> 2 -> 3, 4 -> 3, 1 -> 4
+        assert_eq!(detect(4, 3), None);
+        assert_eq!(detect(1, 4), None);
+        assert_eq!(detect(3, 4), Some(chain(&[(4, 3), (3, 4)])));
+    }
+
+    #[test]
+    fn test_detect_table_clean_up() {
+        let mut table = DetectTable::new();
+        assert_eq!(table.detect(1, 2, b"k2"), None);
+        assert_eq!(table.detect(2, 3, b"k3"), None);
+
+        table.clean_up_wait_for(2, 3);
+        assert_eq!(table.detect(3, 2, b"k2"), None);
+        assert_eq!(table.detect(2, 1, b"k1"), Some(chain(&[(1, 2), (2, 1)])));
+
+        table.clean_up(1);
+        assert_eq!(table.detect(2, 1, b"k1"), None);
+        table.clean_up(2);
+        table.clean_up(3);
+        assert!(table.is_empty());
+    }
+}
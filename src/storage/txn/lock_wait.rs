@@ -0,0 +1,161 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Instant;
+
+use storage::{Command, Error, StorageCb};
+use util::collections::HashMap;
+
+/// A command waiting for the lock of another transaction to be released.
+pub struct Waiter {
+    pub lock_ts: u64,
+    pub cmd: Command,
+    pub cb: StorageCb,
+    /// The error to report if the lock is not released before `deadline`.
+    pub err: Error,
+    pub deadline: Instant,
+}
+
+/// Commands blocked by transaction locks, grouped by the start ts of the lock holder.
+///
+/// Waiters are woken up when the holder is committed or rolled back, and are
+/// expected to be re-scheduled, so that they can check the lock again.
+#[derive(Default)]
+pub struct LockWaitQueue {
+    waiters: HashMap<u64, Vec<Waiter>>,
+    size: usize,
+}
+
+impl LockWaitQueue {
+    pub fn new() -> LockWaitQueue {
+        LockWaitQueue::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Parks `cmd` until the lock of `lock_ts` is released or `deadline` is reached.
+    pub fn wait_for(
+        &mut self,
+        lock_ts: u64,
+        cmd: Command,
+        cb: StorageCb,
+        err: Error,
+        deadline: Instant,
+    ) {
+        let waiter = Waiter {
+            lock_ts: lock_ts,
+            cmd: cmd,
+            cb: cb,
+            err: err,
+            deadline: deadline,
+        };
+        self.waiters
+            .entry(lock_ts)
+            .or_insert_with(Vec::new)
+            .push(waiter);
+        self.size += 1;
+    }
+
+    /// Takes all the waiters of the lock held by `lock_ts`.
+    pub fn wake_up(&mut self, lock_ts: u64) -> Vec<Waiter> {
+        match self.waiters.remove(&lock_ts) {
+            Some(waiters) => {
+                self.size -= waiters.len();
+                waiters
+            }
+            None => vec![],
+        }
+    }
+
+    /// Takes all the waiters whose deadlines have passed.
+    pub fn take_timeout(&mut self, now: Instant) -> Vec<Waiter> {
+        let mut timeout = vec![];
+        for waiters in self.waiters.values_mut() {
+            let (expired, waiting): (Vec<_>, Vec<_>) =
+                waiters.drain(..).partition(|w| w.deadline <= now);
+            *waiters = waiting;
+            timeout.extend(expired);
+        }
+        self.waiters.retain(|_, waiters| !waiters.is_empty());
+        self.size -= timeout.len();
+        timeout
+    }
+
+    /// Returns the earliest deadline of all the waiters.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.waiters
+            .values()
+            .flat_map(|waiters| waiters.iter().map(|w| w.deadline))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use kvproto::kvrpcpb::Context;
+    use storage::{make_key, Command, Options, StorageCb};
+    use super::*;
+
+    fn new_waiter_cmd(start_ts: u64) -> (Command, StorageCb) {
+        let cmd = Command::AcquirePessimisticLock {
+            ctx: Context::new(),
+            keys: vec![make_key(b"k")],
+            primary: b"k".to_vec(),
+            start_ts: start_ts,
+            for_update_ts: start_ts,
+            wait_timeout: 0,
+            options: Options::default(),
+        };
+        (cmd, StorageCb::Boolean(box |_| {}))
+    }
+
+    #[test]
+    fn test_lock_wait_queue() {
+        let mut queue = LockWaitQueue::new();
+        assert!(queue.is_empty());
+        assert!(queue.next_deadline().is_none());
+
+        for &(lock_ts, start_ts, timeout) in &[(10, 20, 3600), (10, 30, 0), (40, 50, 3600)] {
+            let (cmd, cb) = new_waiter_cmd(start_ts);
+            let err = Error::Other(box_err!("lock wait timeout"));
+            let deadline = Instant::now() + Duration::from_secs(timeout);
+            queue.wait_for(lock_ts, cmd, cb, err, deadline);
+        }
+        assert_eq!(queue.len(), 3);
+        assert!(queue.next_deadline().unwrap() <= Instant::now());
+
+        thread::sleep(Duration::from_millis(1));
+        let timeout = queue.take_timeout(Instant::now());
+        assert_eq!(timeout.len(), 1);
+        assert_eq!(timeout[0].cmd.ts(), 30);
+        assert_eq!(queue.len(), 2);
+
+        let woken = queue.wake_up(10);
+        assert_eq!(woken.len(), 1);
+        assert_eq!(woken[0].cmd.ts(), 20);
+        assert!(queue.wake_up(10).is_empty());
+        assert_eq!(queue.len(), 1);
+
+        assert_eq!(queue.wake_up(40).len(), 1);
+        assert!(queue.is_empty());
+    }
+}
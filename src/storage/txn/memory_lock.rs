@@ -0,0 +1,158 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use storage::Key;
+use storage::mvcc::{Error as MvccError, Result as MvccResult};
+
+/// The lock of a prewrite that is not written to the engine yet.
+pub struct MemoryLock {
+    pub primary: Vec<u8>,
+    pub start_ts: u64,
+    pub min_commit_ts: u64,
+    pub ttl: u64,
+}
+
+/// Locks of the running async-commit and 1PC prewrites, by encoded keys.
+///
+/// The commit ts of these transactions are decided before their locks or writes reach the
+/// engine, so readers that may be committed before must not read the keys until the
+/// prewrites finish.
+#[derive(Default)]
+pub struct MemoryLocks {
+    locks: BTreeMap<Vec<u8>, MemoryLock>,
+}
+
+impl MemoryLocks {
+    pub fn new() -> MemoryLocks {
+        MemoryLocks::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locks.is_empty()
+    }
+
+    pub fn lock(&mut self, key: Vec<u8>, lock: MemoryLock) {
+        self.locks.insert(key, lock);
+    }
+
+    pub fn unlock(&mut self, key: &[u8]) {
+        self.locks.remove(key);
+    }
+
+    /// Returns the min commit ts of all the locks, the transactions can't be committed
+    /// before it.
+    pub fn min_commit_ts(&self) -> Option<u64> {
+        self.locks.values().map(|lock| lock.min_commit_ts).min()
+    }
+
+    /// Checks whether reading `key` at `ts` may miss a running prewrite.
+    pub fn check_key(&self, key: &Key, ts: u64) -> MvccResult<()> {
+        match self.locks.get(key.encoded()) {
+            Some(lock) => check_lock(key.encoded(), lock, ts),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks whether scanning from `start_key` at `ts` may miss a running prewrite.
+    ///
+    /// A reverse scan reads the keys before `start_key`, otherwise the keys from it.
+    pub fn check_range(&self, start_key: &Key, reverse: bool, ts: u64) -> MvccResult<()> {
+        let start_key = start_key.encoded().to_owned();
+        if reverse {
+            for (key, lock) in self.locks.range(..start_key) {
+                check_lock(key, lock, ts)?;
+            }
+        } else {
+            for (key, lock) in self.locks.range(start_key..) {
+                check_lock(key, lock, ts)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether reading the encoded keys in `[start_key, end_key)` at `ts` may miss a
+    /// running prewrite. An empty `end_key` means no upper bound.
+    pub fn check_bounded_range(&self, start_key: &[u8], end_key: &[u8], ts: u64) -> MvccResult<()> {
+        for (key, lock) in self.locks.range(start_key.to_vec()..) {
+            if !end_key.is_empty() && key.as_slice() >= end_key {
+                break;
+            }
+            check_lock(key, lock, ts)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_lock(key: &[u8], lock: &MemoryLock, ts: u64) -> MvccResult<()> {
+    if lock.min_commit_ts > ts {
+        return Ok(());
+    }
+    Err(MvccError::KeyIsLocked {
+        key: Key::from_encoded(key.to_vec()).raw()?,
+        primary: lock.primary.clone(),
+        ts: lock.start_ts,
+        ttl: lock.ttl,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::make_key;
+    use super::*;
+
+    fn new_lock(start_ts: u64, min_commit_ts: u64) -> MemoryLock {
+        MemoryLock {
+            primary: b"k2".to_vec(),
+            start_ts: start_ts,
+            min_commit_ts: min_commit_ts,
+            ttl: 100,
+        }
+    }
+
+    #[test]
+    fn test_memory_locks() {
+        let mut locks = MemoryLocks::new();
+        assert!(locks.is_empty());
+        locks.lock(make_key(b"k2").encoded().to_owned(), new_lock(10, 20));
+
+        assert!(locks.check_key(&make_key(b"k1"), 30).is_ok());
+        assert!(locks.check_key(&make_key(b"k2"), 19).is_ok());
+        match locks.check_key(&make_key(b"k2"), 20) {
+            Err(MvccError::KeyIsLocked { key, ts, .. }) => {
+                assert_eq!(key, b"k2".to_vec());
+                assert_eq!(ts, 10);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        assert!(locks.check_range(&make_key(b"k1"), false, 19).is_ok());
+        assert!(locks.check_range(&make_key(b"k1"), false, 20).is_err());
+        assert!(locks.check_range(&make_key(b"k3"), false, 20).is_ok());
+        assert!(locks.check_range(&make_key(b"k3"), true, 19).is_ok());
+        assert!(locks.check_range(&make_key(b"k3"), true, 20).is_err());
+        assert!(locks.check_range(&make_key(b"k2"), true, 20).is_ok());
+        assert!(locks.check_range(&make_key(b"k1"), true, 20).is_ok());
+
+        let (k1, k2, k3) = (make_key(b"k1"), make_key(b"k2"), make_key(b"k3"));
+        assert!(locks.check_bounded_range(k1.encoded(), k2.encoded(), 20).is_ok());
+        assert!(locks.check_bounded_range(k1.encoded(), k3.encoded(), 20).is_err());
+        assert!(locks.check_bounded_range(k2.encoded(), b"", 20).is_err());
+        assert!(locks.check_bounded_range(k3.encoded(), b"", 20).is_ok());
+
+        locks.unlock(make_key(b"k2").encoded());
+        assert!(locks.is_empty());
+        assert!(locks.check_key(&make_key(b"k2"), 20).is_ok());
+    }
+}
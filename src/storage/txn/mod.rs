@@ -14,12 +14,19 @@
 mod store;
 mod scheduler;
 mod latch;
+mod lock_wait;
+mod deadlock;
+mod memory_lock;
+mod concurrency_manager;
 
 use std::error;
 use std::io::Error as IoError;
 
 pub use self::scheduler::{Msg, Scheduler, GC_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
 pub use self::store::{SnapshotStore, StoreScanner};
+pub use self::deadlock::WaitForEntry;
+pub use self::concurrency_manager::{ConcurrencyManager, MaxTsObserver, MaxTsSyncRunner,
+                                    MaxTsSyncTask, MAX_TS_OBSERVER_PRIORITY};
 
 quick_error! {
     #[derive(Debug)]
@@ -61,6 +68,18 @@ quick_error! {
                         start_ts,
                         commit_ts)
         }
+        Deadlock { start_ts: u64, lock_ts: u64, lock_key: Vec<u8>, wait_chain: Vec<WaitForEntry> } {
+            description("deadlock")
+            display("deadlock {} waits for {} on key:{:?}, wait chain:{:?}",
+                        start_ts,
+                        lock_ts,
+                        lock_key,
+                        wait_chain)
+        }
+        MaxTsNotSynced { region_id: u64 } {
+            description("max ts not synced")
+            display("max ts of region {} is not synced with PD yet", region_id)
+        }
     }
 }
 
@@ -77,6 +96,20 @@ impl Error {
                 start_ts: start_ts,
                 commit_ts: commit_ts,
             }),
+            Error::Deadlock {
+                start_ts,
+                lock_ts,
+                ref lock_key,
+                ref wait_chain,
+            } => Some(Error::Deadlock {
+                start_ts: start_ts,
+                lock_ts: lock_ts,
+                lock_key: lock_key.clone(),
+                wait_chain: wait_chain.clone(),
+            }),
+            Error::MaxTsNotSynced { region_id } => Some(Error::MaxTsNotSynced {
+                region_id: region_id,
+            }),
             Error::Other(_) | Error::ProtoBuf(_) | Error::Io(_) => None,
         }
     }
@@ -32,25 +32,31 @@
 //! to the scheduler.
 
 use std::fmt::{self, Debug, Formatter};
-use std::sync::mpsc::Receiver;
-use std::time::Duration;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
 use std::thread;
 use std::hash::{Hash, Hasher};
-use std::u64;
+use std::{mem, u64};
 
 use prometheus::HistogramTimer;
-use kvproto::kvrpcpb::{CommandPri, Context, LockInfo};
-
-use storage::{Command, Engine, Error as StorageError, Result as StorageResult, ScanMode, Snapshot,
-              Statistics, StatisticsSummary, StorageCb};
-use storage::mvcc::{Error as MvccError, Lock as MvccLock, MvccReader, MvccTxn, Write, WriteType,
-                    MAX_TXN_WRITE_SIZE};
-use storage::{Key, KvPair, MvccInfo, Value, CMD_TAG_GC};
+use kvproto::kvrpcpb::{CommandPri, Context, IsolationLevel, LockInfo};
+
+use storage::{CfName, Command, Engine, Error as StorageError, Result as StorageResult, ScanMode,
+              Snapshot, Statistics, StatisticsSummary, StorageCb};
+use storage::mvcc::compaction_filter::GcContext;
+use storage::mvcc::{Error as MvccError, Lock as MvccLock, MvccReader, MvccTxn,
+                    SecondaryLockStatus, Write, WriteType, MAX_TXN_WRITE_SIZE};
+use storage::{Key, KvPair, MvccInfo, PrewriteResult, SecondaryLocksStatus, Value, CF_WRITE,
+              CMD_TAG_GC};
+use storage::ttl;
 use storage::engine::{self, Callback as EngineCallback, CbContext, Error as EngineError, Modify,
                       Result as EngineResult};
 use raftstore::store::engine::IterOption;
+use raftstore::store::{keys, LoadStat};
+use pd::PdTask;
 use util::transport::{Error as TransportError, SyncSendCh};
-use util::threadpool::{Context as ThreadContext, ThreadPool, ThreadPoolBuilder};
+use util::threadpool::{Context as ThreadContext, ContextFactory, ThreadPool, ThreadPoolBuilder};
+use util::worker::FutureScheduler;
 use util::time::SlowTimer;
 use util::collections::HashMap;
 
@@ -58,6 +64,9 @@ use super::Result;
 use super::Error;
 use super::store::SnapshotStore;
 use super::latch::{Latches, Lock};
+use super::lock_wait::LockWaitQueue;
+use super::deadlock::Detector;
+use super::concurrency_manager::ConcurrencyManager;
 use super::super::metrics::*;
 
 // TODO: make it configurable.
@@ -68,13 +77,22 @@ pub const RESOLVE_LOCK_BATCH_SIZE: usize = 512;
 /// Process result of a command.
 pub enum ProcessResult {
     Res,
-    MultiRes { results: Vec<StorageResult<()>> },
     MultiKvpairs { pairs: Vec<StorageResult<KvPair>> },
     MvccKey { mvcc: MvccInfo },
     MvccStartTs { mvcc: Option<(Key, MvccInfo)> },
     Value { value: Option<Value> },
     Locks { locks: Vec<LockInfo> },
+    PrewriteResult { result: PrewriteResult },
+    SecondaryLocksStatus { status: SecondaryLocksStatus },
+    TTL { ttl: Option<u64> },
     NextCommand { cmd: Command },
+    // The command is blocked by the lock of transaction `lock_ts` on `key`, `err` is reported if
+    // the lock is not released in time.
+    WaitForLock {
+        lock_ts: u64,
+        key: Vec<u8>,
+        err: StorageError,
+    },
     Failed { err: StorageError },
 }
 
@@ -143,7 +161,7 @@ fn execute_callback(callback: StorageCb, pr: ProcessResult) {
             _ => panic!("process result mismatch"),
         },
         StorageCb::Booleans(cb) => match pr {
-            ProcessResult::MultiRes { results } => cb(Ok(results)),
+            ProcessResult::PrewriteResult { result } => cb(Ok(result.locks)),
             ProcessResult::Failed { err } => cb(Err(err)),
             _ => panic!("process result mismatch"),
         },
@@ -172,6 +190,21 @@ fn execute_callback(callback: StorageCb, pr: ProcessResult) {
             ProcessResult::Failed { err } => cb(Err(err)),
             _ => panic!("process result mismatch"),
         },
+        StorageCb::PrewriteResult(cb) => match pr {
+            ProcessResult::PrewriteResult { result } => cb(Ok(result)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
+        StorageCb::SecondaryLocksStatus(cb) => match pr {
+            ProcessResult::SecondaryLocksStatus { status } => cb(Ok(status)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
+        StorageCb::TTL(cb) => match pr {
+            ProcessResult::TTL { ttl } => cb(Ok(ttl)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
     }
 }
 
@@ -185,6 +218,13 @@ pub struct RunningCtx {
     tag: &'static str,
     ts: u64,
     region_id: u64,
+    // whether the command may release the locks of a transaction.
+    releases_txn_locks: bool,
+    // encoded keys locked in memory by the command.
+    memory_locked_keys: Vec<Vec<u8>>,
+    // the deadline of waiting for locks, which is kept when the command is woken up and
+    // blocked again.
+    lock_wait_deadline: Option<Instant>,
     latch_timer: Option<HistogramTimer>,
     _timer: HistogramTimer,
     slow_timer: SlowTimer,
@@ -197,6 +237,14 @@ impl RunningCtx {
         let ts = cmd.ts();
         let region_id = cmd.get_context().get_region_id();
         let write_bytes = cmd.write_bytes();
+        let releases_txn_locks = match cmd {
+            Command::Commit { .. } |
+            Command::CheckSecondaryLocks { .. } |
+            Command::Cleanup { .. } |
+            Command::Rollback { .. } |
+            Command::ResolveLock { .. } => true,
+            _ => false,
+        };
         RunningCtx {
             cid: cid,
             cmd: Some(cmd),
@@ -206,6 +254,9 @@ impl RunningCtx {
             tag: tag,
             ts: ts,
             region_id: region_id,
+            releases_txn_locks: releases_txn_locks,
+            memory_locked_keys: vec![],
+            lock_wait_deadline: None,
             latch_timer: Some(
                 SCHED_LATCH_HISTOGRAM_VEC
                     .with_label_values(&[tag])
@@ -322,6 +373,21 @@ pub struct Scheduler {
 
     // used to control write flow
     running_write_bytes: usize,
+
+    // commands waiting for the locks of other transactions
+    lock_wait_queue: LockWaitQueue,
+
+    detector: Detector,
+
+    // the max ts of the reads and the memory locks of the async-commit and 1PC prewrites,
+    // shared with the other read paths.
+    cm: ConcurrencyManager,
+
+    // whether raw values are stored with their expire ts
+    enable_ttl: bool,
+
+    // the GC state shared with the compaction filter of the write CF
+    gc_ctx: GcContext,
 }
 
 // Make clippy happy.
@@ -363,13 +429,21 @@ fn find_mvcc_infos_by_key(
 
 impl Scheduler {
     /// Creates a scheduler.
+    #[allow(too_many_arguments)]
     pub fn new(
         engine: Box<Engine>,
         schedch: SyncSendCh<Msg>,
         concurrency: usize,
         worker_pool_size: usize,
         sched_pending_write_threshold: usize,
+        cm: ConcurrencyManager,
+        enable_ttl: bool,
+        gc_ctx: GcContext,
+        pd_scheduler: Option<FutureScheduler<PdTask>>,
     ) -> Scheduler {
+        let factory = ScheContextFactory {
+            pd_scheduler: pd_scheduler,
+        };
         Scheduler {
             engine: engine,
             cmd_ctxs: Default::default(),
@@ -381,14 +455,18 @@ impl Scheduler {
             id_alloc: 0,
             latches: Latches::new(concurrency),
             sched_pending_write_threshold: sched_pending_write_threshold,
-            worker_pool: ThreadPoolBuilder::with_default_factory(thd_name!("sched-worker-pool"))
+            worker_pool: ThreadPoolBuilder::new(thd_name!("sched-worker-pool"), factory.clone())
                 .thread_count(worker_pool_size)
                 .build(),
-            high_priority_pool: ThreadPoolBuilder::with_default_factory(
-                thd_name!("sched-high-pri-pool"),
-            ).build(),
+            high_priority_pool: ThreadPoolBuilder::new(thd_name!("sched-high-pri-pool"), factory)
+                .build(),
             has_gc_command: false,
             running_write_bytes: 0,
+            lock_wait_queue: LockWaitQueue::new(),
+            detector: Detector::new(),
+            cm: cm,
+            enable_ttl: enable_ttl,
+            gc_ctx: gc_ctx,
         }
     }
 }
@@ -400,6 +478,8 @@ fn process_read(
     mut cmd: Command,
     ch: SyncSendCh<Msg>,
     snapshot: Box<Snapshot>,
+    enable_ttl: bool,
+    gc_ctx: GcContext,
 ) -> Statistics {
     debug!("process read cmd(cid={}) in worker pool.", cid);
     SCHED_WORKER_COUNTER_VEC
@@ -482,9 +562,18 @@ fn process_read(
                 ctx.get_isolation_level(),
                 !ctx.get_not_fill_cache(),
             );
+            let mode = if options.reverse_scan {
+                ScanMode::Backward
+            } else {
+                ScanMode::Forward
+            };
             let res = snap_store
-                .scanner(ScanMode::Forward, options.key_only, None, &mut statistics)
-                .and_then(|mut scanner| scanner.scan(start_key.clone(), limit))
+                .scanner(mode, options.key_only, None, &mut statistics)
+                .and_then(|mut scanner| if options.reverse_scan {
+                    scanner.reverse_scan(start_key.clone(), limit)
+                } else {
+                    scanner.scan(start_key.clone(), limit)
+                })
                 .and_then(|mut results| {
                     KV_COMMAND_KEYREAD_HISTOGRAM_VEC
                         .with_label_values(&[tag])
@@ -632,6 +721,25 @@ fn process_read(
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
+        // Collects garbage by the compaction filter of the write CF.
+        Command::Gc {
+            safe_point,
+            ratio_threshold,
+            scan_key: None,
+            ..
+        } if gc_ctx.is_enabled() =>
+        {
+            match gc_by_compaction_filter(
+                &gc_ctx,
+                snapshot.as_ref(),
+                &mut statistics,
+                safe_point,
+                ratio_threshold,
+            ) {
+                Ok(()) => ProcessResult::Res,
+                Err(e) => ProcessResult::Failed { err: e.into() },
+            }
+        }
         // Collects garbage.
         Command::Gc {
             ref ctx,
@@ -690,22 +798,85 @@ fn process_read(
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
-        Command::RawGet { ref key, .. } => {
+        Command::RawGet { cf, ref key, .. } => {
             KV_COMMAND_KEYREAD_HISTOGRAM_VEC
                 .with_label_values(&[tag])
                 .observe(1f64);
-            match snapshot.get(key) {
+            let now = ttl::current_ts();
+            let res = snapshot
+                .get_cf(cf, key)
+                .map_err(Error::from)
+                .and_then(|val| match val {
+                    Some(val) => raw_value(val, enable_ttl, now),
+                    None => Ok(None),
+                });
+            match res {
                 Ok(val) => ProcessResult::Value { value: val },
                 Err(e) => ProcessResult::Failed {
                     err: StorageError::from(e),
                 },
             }
         }
+        Command::RawBatchGet { cf, ref keys, .. } => {
+            KV_COMMAND_KEYREAD_HISTOGRAM_VEC
+                .with_label_values(&[tag])
+                .observe(keys.len() as f64);
+            let now = ttl::current_ts();
+            let mut pairs = vec![];
+            for key in keys {
+                let res = snapshot
+                    .get_cf(cf, key)
+                    .map_err(Error::from)
+                    .and_then(|val| match val {
+                        Some(val) => raw_value(val, enable_ttl, now),
+                        None => Ok(None),
+                    });
+                match res {
+                    Ok(Some(val)) => pairs.push(Ok((key.encoded().to_owned(), val))),
+                    Ok(None) => {}
+                    Err(e) => pairs.push(Err(StorageError::from(e))),
+                }
+            }
+            ProcessResult::MultiKvpairs { pairs: pairs }
+        }
+        Command::RawGetKeyTTL { cf, ref key, .. } => {
+            let now = ttl::current_ts();
+            let res = snapshot
+                .get_cf(cf, key)
+                .map_err(Error::from)
+                .and_then(|val| match val {
+                    Some(val) => {
+                        let (_, expire_ts) = ttl::split_expire_ts_or_forever(&val);
+                        if ttl::is_expired(expire_ts, now) {
+                            Ok(None)
+                        } else {
+                            Ok(Some(ttl::remaining_ttl(expire_ts, now)))
+                        }
+                    }
+                    None => Ok(None),
+                });
+            match res {
+                Ok(ttl) => ProcessResult::TTL { ttl: ttl },
+                Err(e) => ProcessResult::Failed {
+                    err: StorageError::from(e),
+                },
+            }
+        }
         Command::RawScan {
+            cf,
             ref start_key,
             limit,
+            reverse,
             ..
-        } => match process_rawscan(snapshot, start_key, limit, &mut statistics) {
+        } => match process_rawscan(
+            snapshot,
+            cf,
+            start_key,
+            limit,
+            reverse,
+            enable_ttl,
+            &mut statistics,
+        ) {
             Ok(val) => ProcessResult::MultiKvpairs { pairs: val },
             Err(e) => ProcessResult::Failed {
                 err: StorageError::from(e),
@@ -727,22 +898,92 @@ fn process_read(
 
 fn process_rawscan(
     snapshot: Box<Snapshot>,
+    cf: CfName,
     start_key: &Key,
     limit: usize,
+    reverse: bool,
+    enable_ttl: bool,
     stats: &mut Statistics,
 ) -> Result<Vec<StorageResult<KvPair>>> {
-    let mut cursor = snapshot.iter(IterOption::default(), ScanMode::Forward)?;
-    if !cursor.seek(start_key, &mut stats.data)? {
+    let mode = if reverse {
+        ScanMode::Backward
+    } else {
+        ScanMode::Forward
+    };
+    let mut cursor = snapshot.iter_cf(cf, IterOption::default(), mode)?;
+    let statistics = stats.mut_cf_statistics(cf);
+    let ok = if reverse {
+        cursor.reverse_seek(start_key, statistics)?
+    } else {
+        cursor.seek(start_key, statistics)?
+    };
+    if !ok {
         return Ok(vec![]);
     }
+    let now = ttl::current_ts();
     let mut pairs = vec![];
     while cursor.valid() && pairs.len() < limit {
-        pairs.push(Ok((cursor.key().to_owned(), cursor.value().to_owned())));
-        cursor.next(&mut stats.data);
+        // Expired values are skipped, so they don't count in `limit`.
+        match raw_value(cursor.value().to_owned(), enable_ttl, now) {
+            Ok(Some(val)) => pairs.push(Ok((cursor.key().to_owned(), val))),
+            Ok(None) => {}
+            Err(e) => pairs.push(Err(StorageError::from(e))),
+        }
+        if reverse {
+            cursor.prev(statistics);
+        } else {
+            cursor.next(statistics);
+        }
     }
     Ok(pairs)
 }
 
+/// Pushes the safe point down to the compaction filter, and compacts the write CF of the region
+/// if its SSTs have enough versions to GC. The obsolete versions are dropped in the compaction.
+fn gc_by_compaction_filter(
+    gc_ctx: &GcContext,
+    snapshot: &Snapshot,
+    stats: &mut Statistics,
+    safe_point: u64,
+    ratio_threshold: f64,
+) -> Result<()> {
+    gc_ctx.update_safe_point(safe_point);
+    let need_gc = {
+        let reader = MvccReader::new(snapshot, stats, None, false, None, IsolationLevel::SI);
+        reader.need_compaction_gc(safe_point, ratio_threshold)
+    };
+    if !need_gc {
+        KV_COMMAND_GC_SKIPPED_COUNTER.inc();
+        return Ok(());
+    }
+    let mut cursor = snapshot.iter_cf(CF_WRITE, IterOption::default(), ScanMode::Mixed)?;
+    if !cursor.seek_to_first(&mut stats.write) {
+        KV_COMMAND_GC_EMPTY_RANGE_COUNTER.inc();
+        return Ok(());
+    }
+    let start_key = keys::data_key(cursor.key());
+    cursor.seek_to_last(&mut stats.write);
+    let end_key = keys::data_key(cursor.key());
+    gc_ctx.compact_write_cf(start_key, end_key);
+    Ok(())
+}
+
+/// Strips the expire ts from a raw value if TTL is enabled, returns `None` if it has expired.
+fn raw_value(mut value: Value, enable_ttl: bool, now: u64) -> Result<Option<Value>> {
+    if !enable_ttl {
+        return Ok(Some(value));
+    }
+    let (len, expire_ts) = {
+        let (v, expire_ts) = ttl::split_expire_ts_or_forever(&value);
+        (v.len(), expire_ts)
+    };
+    if ttl::is_expired(expire_ts, now) {
+        return Ok(None);
+    }
+    value.truncate(len);
+    Ok(Some(value))
+}
+
 /// Processes a write command within a worker thread, then posts either a `WritePrepareFinished`
 /// message if successful or a `WritePrepareFailed` message back to the event loop.
 fn process_write(
@@ -804,14 +1045,77 @@ fn process_write_impl(
                 }
             }
             if locks.is_empty() {
-                let pr = ProcessResult::MultiRes { results: vec![] };
+                let result = PrewriteResult {
+                    locks: vec![],
+                    min_commit_ts: if options.use_async_commit {
+                        options.min_commit_ts
+                    } else {
+                        0
+                    },
+                    one_pc_commit_ts: if options.try_one_pc {
+                        options.min_commit_ts
+                    } else {
+                        0
+                    },
+                };
+                let pr = ProcessResult::PrewriteResult { result: result };
                 (pr, txn.modifies(), rows)
             } else {
                 // Skip write stage if some keys are locked.
-                let pr = ProcessResult::MultiRes { results: locks };
+                let result = PrewriteResult {
+                    locks: locks,
+                    min_commit_ts: 0,
+                    one_pc_commit_ts: 0,
+                };
+                let pr = ProcessResult::PrewriteResult { result: result };
                 (pr, vec![], 0)
             }
         }
+        Command::AcquirePessimisticLock {
+            ref ctx,
+            ref keys,
+            ref primary,
+            start_ts,
+            for_update_ts,
+            wait_timeout,
+            ref options,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let rows = keys.len();
+            let mut wait_for = None;
+            for k in keys {
+                let res = txn.acquire_pessimistic_lock(k.clone(), primary, for_update_ts, options);
+                if let Err(e) = res {
+                    let (lock_ts, key) = match e {
+                        MvccError::KeyIsLocked { ts, ref key, .. } if wait_timeout > 0 => {
+                            (ts, key.clone())
+                        }
+                        e => return Err(Error::from(e)),
+                    };
+                    wait_for = Some((lock_ts, key, e));
+                    break;
+                }
+            }
+            match wait_for {
+                None => (ProcessResult::Res, txn.modifies(), rows),
+                // Skip write stage and wait for the lock to be released.
+                Some((lock_ts, key, e)) => {
+                    let pr = ProcessResult::WaitForLock {
+                        lock_ts: lock_ts,
+                        key: key,
+                        err: StorageError::from(Error::from(e)),
+                    };
+                    (pr, vec![], 0)
+                }
+            }
+        }
         Command::Commit {
             ref ctx,
             ref keys,
@@ -841,6 +1145,40 @@ fn process_write_impl(
             let pr = ProcessResult::Res;
             (pr, txn.modifies(), rows)
         }
+        Command::CheckSecondaryLocks {
+            ref ctx,
+            ref keys,
+            start_ts,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let rows = keys.len();
+            let mut locks = vec![];
+            let mut status = None;
+            for k in keys {
+                match txn.check_secondary_lock(k)? {
+                    SecondaryLockStatus::Locked(lock) => locks.push(lock),
+                    SecondaryLockStatus::Committed(commit_ts) => {
+                        status = Some(SecondaryLocksStatus::Committed(commit_ts));
+                        break;
+                    }
+                    // The transaction can't be committed any more.
+                    SecondaryLockStatus::RolledBack => {
+                        status = Some(SecondaryLocksStatus::RolledBack);
+                        break;
+                    }
+                }
+            }
+            let status = status.unwrap_or_else(|| SecondaryLocksStatus::Locked(locks));
+            let pr = ProcessResult::SecondaryLocksStatus { status: status };
+            (pr, txn.modifies(), rows)
+        }
         Command::Cleanup {
             ref ctx,
             ref key,
@@ -908,9 +1246,13 @@ fn process_write_impl(
             );
             let rows = keys.len();
             for k in keys {
-                match commit_ts {
-                    Some(ts) => txn.commit(k, ts)?,
-                    None => txn.rollback(k)?,
+                // Pessimistic locks are not prewritten, so they are simply removed
+                // whether the transaction is committed or not.
+                if !txn.pessimistic_rollback(k)? {
+                    match commit_ts {
+                        Some(ts) => txn.commit(k, ts)?,
+                        None => txn.rollback(k)?,
+                    }
                 }
                 if txn.write_size() >= MAX_TXN_WRITE_SIZE {
                     scan_key = Some(k.to_owned());
@@ -985,9 +1327,26 @@ fn process_write_impl(
     Ok(())
 }
 
-#[derive(Default)]
+#[derive(Clone)]
+struct ScheContextFactory {
+    pd_scheduler: Option<FutureScheduler<PdTask>>,
+}
+
+impl ContextFactory<ScheContext> for ScheContextFactory {
+    fn create(&self) -> ScheContext {
+        ScheContext {
+            stats: HashMap::default(),
+            read_load: HashMap::default(),
+            pd_scheduler: self.pd_scheduler.clone(),
+        }
+    }
+}
+
 struct ScheContext {
     stats: HashMap<&'static str, StatisticsSummary>,
+    // The keys read by the commands, sampled by region for load based split.
+    read_load: HashMap<u64, LoadStat>,
+    pd_scheduler: Option<FutureScheduler<PdTask>>,
 }
 
 impl ScheContext {
@@ -995,6 +1354,25 @@ impl ScheContext {
         let entry = self.stats.entry(cmd_tag).or_insert_with(Default::default);
         entry.add_statistics(stat);
     }
+
+    // Records the keys read by `cmd`, a scan is recorded by its start key.
+    fn add_read_load(&mut self, cmd: &Command) {
+        if self.pd_scheduler.is_none() {
+            return;
+        }
+        let keys = match *cmd {
+            Command::Get { ref key, .. } => vec![key],
+            Command::BatchGet { ref keys, .. } => keys.iter().collect(),
+            Command::Scan { ref start_key, .. } => vec![start_key],
+            _ => return,
+        };
+        let load_stat = self.read_load
+            .entry(cmd.get_context().get_region_id())
+            .or_insert_with(LoadStat::default);
+        for key in keys {
+            load_stat.record(key.encoded());
+        }
+    }
 }
 
 impl ThreadContext for ScheContext {
@@ -1009,6 +1387,17 @@ impl ThreadContext for ScheContext {
                 }
             }
         }
+        if self.read_load.is_empty() {
+            return;
+        }
+        let load_stats = mem::replace(&mut self.read_load, HashMap::default());
+        if let Some(ref pd_scheduler) = self.pd_scheduler {
+            if let Err(e) = pd_scheduler.schedule(PdTask::ReadLoad {
+                load_stats: load_stats,
+            }) {
+                error!("send storage read load: {:?}", e);
+            }
+        }
     }
 }
 
@@ -1037,6 +1426,7 @@ impl Scheduler {
     fn remove_ctx(&mut self, cid: u64) -> RunningCtx {
         let ctx = self.cmd_ctxs.remove(&cid).unwrap();
         assert_eq!(ctx.cid, cid);
+        self.cm.unlock_keys(&ctx.memory_locked_keys);
         if ctx.lock.is_write_lock() {
             self.running_write_bytes -= ctx.write_bytes;
         }
@@ -1078,13 +1468,20 @@ impl Scheduler {
         if let Some(term) = cb_ctx.term {
             cmd.mut_context().set_term(term);
         }
+        if let Err(e) = self.lock_in_memory(cid, &mut cmd) {
+            self.finish_with_err(cid, e);
+            return;
+        }
         let ch = self.schedch.clone();
+        let enable_ttl = self.enable_ttl;
+        let gc_ctx = self.gc_ctx.clone();
         let readcmd = cmd.readonly();
         let worker_pool = self.fetch_worker_pool(cmd.priority());
         let tag = cmd.tag();
         if readcmd {
             worker_pool.execute(move |ctx: &mut ScheContext| {
-                let s = process_read(cid, cmd, ch, snapshot);
+                ctx.add_read_load(&cmd);
+                let s = process_read(cid, cmd, ch, snapshot, enable_ttl, gc_ctx);
                 ctx.add_statistics(tag, &s);
             });
         } else {
@@ -1095,6 +1492,61 @@ impl Scheduler {
         }
     }
 
+    /// Decides the min commit ts of an async-commit or 1PC prewrite, and locks its keys in memory
+    /// until the prewrite is written, so that readers which may miss it are blocked.
+    fn lock_in_memory(&mut self, cid: u64, cmd: &mut Command) -> Result<()> {
+        let region_id = cmd.get_context().get_region_id();
+        let keys = match *cmd {
+            Command::Prewrite {
+                ref mutations,
+                ref primary,
+                start_ts,
+                ref mut options,
+                ..
+            } => {
+                if !options.use_async_commit && !options.try_one_pc {
+                    return Ok(());
+                }
+                let keys: Vec<_> = mutations
+                    .iter()
+                    .map(|m| m.key().encoded().to_owned())
+                    .collect();
+                options.min_commit_ts = self.cm.lock_keys(
+                    region_id,
+                    &keys,
+                    primary,
+                    start_ts,
+                    options.min_commit_ts,
+                    options.lock_ttl,
+                )?;
+                keys
+            }
+            _ => return Ok(()),
+        };
+        self.cmd_ctxs.get_mut(&cid).unwrap().memory_locked_keys = keys;
+        Ok(())
+    }
+
+    /// Records the ts of a read, and checks it against the memory locks.
+    fn check_read(&self, cmd: &Command) -> StorageResult<()> {
+        let ts = cmd.ts();
+        let res = match *cmd {
+            Command::Get { ref key, .. } => self.cm.read_key_check(key, ts),
+            Command::BatchGet { ref keys, .. } => keys.iter()
+                .map(|key| self.cm.read_key_check(key, ts))
+                .find(|res| res.is_err())
+                .unwrap_or(Ok(())),
+            Command::Scan {
+                ref start_key,
+                ref options,
+                ..
+            } => self.cm
+                .read_range_check(start_key, options.reverse_scan, ts),
+            _ => return Ok(()),
+        };
+        res.map_err(|e| StorageError::from(Error::from(e)))
+    }
+
     /// Calls the callback with an error.
     fn finish_with_err(&mut self, cid: u64, err: Error) {
         debug!("command cid={}, finished with error", cid);
@@ -1130,6 +1582,16 @@ impl Scheduler {
     /// execution because 1) all the conflicting commands (if any) must be in the waiting queues;
     /// 2) there may be non-conflicitng commands running concurrently, but it doesn't matter.
     fn schedule_command(&mut self, cmd: Command, callback: StorageCb) {
+        self.schedule_command_with_deadline(cmd, callback, None);
+    }
+
+    /// Schedules a command, `lock_wait_deadline` is the deadline of a woken up lock waiter.
+    fn schedule_command_with_deadline(
+        &mut self,
+        cmd: Command,
+        callback: StorageCb,
+        lock_wait_deadline: Option<Instant>,
+    ) {
         SCHED_STAGE_COUNTER_VEC
             .with_label_values(&[cmd.tag(), "new"])
             .inc();
@@ -1139,7 +1601,8 @@ impl Scheduler {
         let cid = self.gen_id();
         debug!("received new command, cid={}, cmd={}", cid, cmd);
         let lock = gen_command_lock(&self.latches, &cmd);
-        let ctx = RunningCtx::new(cid, cmd, lock, callback);
+        let mut ctx = RunningCtx::new(cid, cmd, lock, callback);
+        ctx.lock_wait_deadline = lock_wait_deadline;
         self.insert_ctx(ctx);
         self.lock_and_register_get_snapshot(cid);
     }
@@ -1176,6 +1639,10 @@ impl Scheduler {
             return;
 
         }
+        if let Err(e) = self.check_read(&cmd) {
+            execute_callback(callback, ProcessResult::Failed { err: e });
+            return;
+        }
         self.schedule_command(cmd, callback);
     }
 
@@ -1390,6 +1857,12 @@ impl Scheduler {
         SCHED_STAGE_COUNTER_VEC
             .with_label_values(&[self.get_ctx_tag(cid), "write"])
             .inc();
+        let pr = match pr {
+            ProcessResult::WaitForLock { lock_ts, key, err } => {
+                return self.on_wait_for_lock(cid, cmd, lock_ts, key, err);
+            }
+            pr => pr,
+        };
         if to_be_write.is_empty() {
             return self.on_write_finished(cid, pr, Ok(()));
         }
@@ -1412,6 +1885,7 @@ impl Scheduler {
         debug!("write finished for command, cid={}", cid);
         let mut ctx = self.remove_ctx(cid);
         let cb = ctx.callback.take().unwrap();
+        let write_succeeded = result.is_ok();
         let pr = match result {
             Ok(()) => pr,
             Err(e) => ProcessResult::Failed {
@@ -1428,6 +1902,115 @@ impl Scheduler {
         }
 
         self.release_lock(&ctx.lock, cid);
+        // The locks are still there if the write failed, the waiters keep waiting for them.
+        if ctx.releases_txn_locks && write_succeeded {
+            self.wake_up_lock_waiters(ctx.ts);
+        }
+    }
+
+    /// Event handler for a command blocked by the lock of another transaction.
+    ///
+    /// Releases the latches of the command and parks it in the lock wait queue. It will be
+    /// scheduled again when the lock holder is committed or rolled back, or fails with `err` on
+    /// timeout. The deadline is counted from the first time the command is blocked. If waiting
+    /// for the lock leads to a deadlock, the command fails immediately.
+    fn on_wait_for_lock(
+        &mut self,
+        cid: u64,
+        cmd: Command,
+        lock_ts: u64,
+        key: Vec<u8>,
+        err: StorageError,
+    ) {
+        let mut ctx = self.remove_ctx(cid);
+        let cb = ctx.callback.take().unwrap();
+        let start_ts = cmd.ts();
+        if let Some(wait_chain) = self.detector.detect(start_ts, lock_ts, &key) {
+            SCHED_STAGE_COUNTER_VEC
+                .with_label_values(&[ctx.tag, "deadlock"])
+                .inc();
+            let err = Error::Deadlock {
+                start_ts: start_ts,
+                lock_ts: lock_ts,
+                lock_key: key,
+                wait_chain: wait_chain,
+            };
+            let pr = ProcessResult::Failed {
+                err: StorageError::from(err),
+            };
+            execute_callback(cb, pr);
+            self.release_lock(&ctx.lock, cid);
+            return;
+        }
+
+        let deadline = match ctx.lock_wait_deadline {
+            Some(deadline) => deadline,
+            None => {
+                let timeout = match cmd {
+                    Command::AcquirePessimisticLock { wait_timeout, .. } => wait_timeout,
+                    _ => unreachable!(),
+                };
+                Instant::now() + Duration::from_millis(timeout)
+            }
+        };
+        SCHED_STAGE_COUNTER_VEC
+            .with_label_values(&[ctx.tag, "lock_wait"])
+            .inc();
+        self.lock_wait_queue.wait_for(lock_ts, cmd, cb, err, deadline);
+        SCHED_LOCK_WAIT_GAUGE.set(self.lock_wait_queue.len() as f64);
+
+        self.release_lock(&ctx.lock, cid);
+    }
+
+    /// Schedules the commands waiting for the locks of transaction `lock_ts` again.
+    fn wake_up_lock_waiters(&mut self, lock_ts: u64) {
+        // The transaction is finishing, so it doesn't wait for others any more.
+        self.detector.clean_up(lock_ts);
+        let waiters = self.lock_wait_queue.wake_up(lock_ts);
+        if waiters.is_empty() {
+            return;
+        }
+        SCHED_LOCK_WAIT_GAUGE.set(self.lock_wait_queue.len() as f64);
+        for w in waiters {
+            self.detector.clean_up_wait_for(w.cmd.ts(), lock_ts);
+            SCHED_STAGE_COUNTER_VEC
+                .with_label_values(&[w.cmd.tag(), "lock_wake_up"])
+                .inc();
+            self.schedule_command_with_deadline(w.cmd, w.cb, Some(w.deadline));
+        }
+    }
+
+    /// Fails the commands which have waited for locks too long.
+    fn on_lock_wait_timeout(&mut self) {
+        let waiters = self.lock_wait_queue.take_timeout(Instant::now());
+        if waiters.is_empty() {
+            return;
+        }
+        SCHED_LOCK_WAIT_GAUGE.set(self.lock_wait_queue.len() as f64);
+        for w in waiters {
+            self.detector.clean_up_wait_for(w.cmd.ts(), w.lock_ts);
+            SCHED_STAGE_COUNTER_VEC
+                .with_label_values(&[w.cmd.tag(), "lock_wait_timeout"])
+                .inc();
+            execute_callback(w.cb, ProcessResult::Failed { err: w.err });
+        }
+    }
+
+    /// Receives a message, or returns `None` if a lock waiter times out before one arrives.
+    fn recv_msg(&self, receiver: &Receiver<Msg>) -> Result<Option<Msg>> {
+        let deadline = match self.lock_wait_queue.next_deadline() {
+            Some(deadline) => deadline,
+            None => return Ok(Some(box_try!(receiver.recv()))),
+        };
+        let now = Instant::now();
+        if deadline <= now {
+            return Ok(None);
+        }
+        match receiver.recv_timeout(deadline - now) {
+            Ok(msg) => Ok(Some(msg)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(e) => Err(box_err!(e)),
+        }
     }
 
     /// Releases all the latches held by a command.
@@ -1455,12 +2038,13 @@ impl Scheduler {
     pub fn run(&mut self, receiver: Receiver<Msg>) -> Result<()> {
         let mut msgs = Vec::with_capacity(CMD_BATCH_SIZE);
         loop {
-            let msg = box_try!(receiver.recv());
-            msgs.push(msg);
-            while let Ok(msg) = receiver.try_recv() {
+            if let Some(msg) = self.recv_msg(&receiver)? {
                 msgs.push(msg);
-                if msgs.len() >= CMD_BATCH_SIZE {
-                    break;
+                while let Ok(msg) = receiver.try_recv() {
+                    msgs.push(msg);
+                    if msgs.len() >= CMD_BATCH_SIZE {
+                        break;
+                    }
                 }
             }
 
@@ -1492,6 +2076,10 @@ impl Scheduler {
                 }
             }
 
+            if !self.lock_wait_queue.is_empty() {
+                self.on_lock_wait_timeout();
+            }
+
             if self.grouped_cmds.as_ref().unwrap().is_empty() {
                 continue;
             }
@@ -1535,7 +2123,9 @@ pub fn gen_command_lock(latches: &Latches, cmd: &Command) -> Lock {
             let keys: Vec<&Key> = mutations.iter().map(|x| x.key()).collect();
             latches.gen_lock(&keys)
         }
+        Command::AcquirePessimisticLock { ref keys, .. } |
         Command::Commit { ref keys, .. } |
+        Command::CheckSecondaryLocks { ref keys, .. } |
         Command::Rollback { ref keys, .. } |
         Command::ResolveLock { ref keys, .. } => latches.gen_lock(keys),
         Command::Cleanup { ref key, .. } => latches.gen_lock(&[key]),
@@ -1605,12 +2195,26 @@ mod tests {
                 start_ts: 10,
                 options: Options::default(),
             },
+            Command::AcquirePessimisticLock {
+                ctx: Context::new(),
+                keys: vec![make_key(b"k")],
+                primary: b"k".to_vec(),
+                start_ts: 10,
+                for_update_ts: 10,
+                wait_timeout: 0,
+                options: Options::default(),
+            },
             Command::Commit {
                 ctx: Context::new(),
                 keys: vec![make_key(b"k")],
                 lock_ts: 10,
                 commit_ts: 20,
             },
+            Command::CheckSecondaryLocks {
+                ctx: Context::new(),
+                keys: vec![make_key(b"k")],
+                start_ts: 10,
+            },
             Command::Cleanup {
                 ctx: Context::new(),
                 key: make_key(b"k"),
@@ -43,11 +43,41 @@ pub struct MvccInfo {
     pub values: Vec<(u64, bool, Value)>,
 }
 
+/// The result of a prewrite.
+#[derive(Debug)]
+pub struct PrewriteResult {
+    /// Errors of the keys locked by other transactions.
+    pub locks: Vec<::storage::Result<()>>,
+    /// The min commit ts of an async-commit transaction, 0 otherwise.
+    pub min_commit_ts: u64,
+    /// The commit ts of a transaction committed by 1PC, 0 otherwise.
+    pub one_pc_commit_ts: u64,
+}
+
+/// The state of an async-commit transaction told by its secondary locks.
+#[derive(Debug, PartialEq)]
+pub enum SecondaryLocksStatus {
+    /// All the keys are locked. The transaction is committed if its primary is locked too.
+    Locked(Vec<Lock>),
+    Committed(u64),
+    RolledBack,
+}
+
 /// The caller should ensure the key is a timestamped key.
 pub fn truncate_ts(key: &[u8]) -> &[u8] {
     &key[..key.len() - number::U64_SIZE]
 }
 
+/// Returns whether `key` is an encoded key with a timestamp, which is how MVCC stores the keys
+/// of the default and write CFs.
+pub fn is_mvcc_key(key: &[u8]) -> bool {
+    if key.len() < number::U64_SIZE {
+        return false;
+    }
+    let mut encoded = truncate_ts(key);
+    encoded.decode_bytes(false).is_ok() && encoded.is_empty()
+}
+
 /// Key type.
 ///
 /// Keys have 2 types of binary representation - raw and encoded. The raw
@@ -178,4 +208,13 @@ mod tests {
         let res = split_encoded_key_on_ts(enc.encoded()).unwrap();
         assert_eq!(res, (k.as_ref(), ts));
     }
+
+    #[test]
+    fn test_is_mvcc_key() {
+        assert!(is_mvcc_key(make_key(b"k").append_ts(10).encoded()));
+        assert!(is_mvcc_key(make_key(b"").append_ts(10).encoded()));
+        assert!(!is_mvcc_key(make_key(b"k").encoded()));
+        assert!(!is_mvcc_key(b"k"));
+        assert!(!is_mvcc_key(b"raw key with a ts"));
+    }
 }
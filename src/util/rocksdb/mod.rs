@@ -25,8 +25,9 @@ use std::sync::Arc;
 use std::str::FromStr;
 
 use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK};
-use rocksdb::{ColumnFamilyOptions, CompactOptions, DBCompressionType, DBOptions, ReadOptions,
-              SliceTransform, Writable, WriteBatch, DB};
+use rocksdb::{ColumnFamilyOptions, CompactOptions, CompactionFilter, CompactionFilterFactory,
+              DBCompressionType, DBOptions, EnvOptions, ReadOptions, SliceTransform, SstFileWriter,
+              Writable, WriteBatch, DB};
 use rocksdb::rocksdb::supported_compression;
 use util::rocksdb::engine_metrics::{ROCKSDB_COMPRESSION_RATIO_AT_LEVEL,
                                     ROCKSDB_CUR_SIZE_ALL_MEM_TABLES, ROCKSDB_TOTAL_SST_FILES_SIZE};
@@ -88,6 +89,28 @@ impl<'a> CFOptions<'a> {
             options: options,
         }
     }
+
+    pub fn cf(&self) -> &'a str {
+        self.cf
+    }
+
+    pub fn set_compaction_filter(
+        &mut self,
+        name: &str,
+        filter: Box<CompactionFilter>,
+    ) -> Result<(), String> {
+        self.options.set_compaction_filter(name, false, filter)
+    }
+
+    /// Sets a factory to create a compaction filter for each compaction, which is needed by
+    /// filters with states.
+    pub fn set_compaction_filter_factory(
+        &mut self,
+        name: &str,
+        factory: Box<CompactionFilterFactory>,
+    ) -> Result<(), String> {
+        self.options.set_compaction_filter_factory(name, factory)
+    }
 }
 
 pub fn new_engine(path: &str, cfs: &[&str]) -> Result<DB, String> {
@@ -100,27 +123,24 @@ pub fn new_engine(path: &str, cfs: &[&str]) -> Result<DB, String> {
     new_engine_opt(path, db_opts, cfs_opts)
 }
 
+// Column family options are moved instead of cloned, because the compaction filters set in them
+// can't be cloned.
 fn check_and_open(
     path: &str,
     mut db_opt: DBOptions,
-    cfs_opts: Vec<CFOptions>,
+    mut cfs_opts: Vec<CFOptions>,
 ) -> Result<DB, String> {
     // If db not exist, create it.
     if !db_exist(path) {
         db_opt.create_if_missing(true);
 
-        let mut cfs_v = vec![];
-        let mut cf_opts_v = vec![];
-        if let Some(x) = cfs_opts.iter().find(|x| x.cf == CF_DEFAULT) {
-            cfs_v.push(x.cf);
-            cf_opts_v.push(x.options.clone());
+        let mut cfds = vec![];
+        if let Some(pos) = cfs_opts.iter().position(|x| x.cf == CF_DEFAULT) {
+            let x = cfs_opts.remove(pos);
+            cfds.push((x.cf, x.options));
         }
-        let cfds = cfs_v.into_iter().zip(cf_opts_v).collect();
         let mut db = DB::open_cf(db_opt, path, cfds)?;
         for x in cfs_opts {
-            if x.cf == CF_DEFAULT {
-                continue;
-            }
             db.create_cf((x.cf, x.options))?;
         }
 
@@ -136,32 +156,19 @@ fn check_and_open(
 
     // If all column families are exist, just open db.
     if existed == needed {
-        let mut cfs_v = vec![];
-        let mut cfs_opts_v = vec![];
-        for x in cfs_opts {
-            cfs_v.push(x.cf);
-            cfs_opts_v.push(x.options);
-        }
-
-        let cfds = cfs_v.into_iter().zip(cfs_opts_v).collect();
+        let cfds = cfs_opts.into_iter().map(|x| (x.cf, x.options)).collect();
         return DB::open_cf(db_opt, path, cfds);
     }
 
     // Open db.
-    let mut cfs_v: Vec<&str> = Vec::new();
-    let mut cfs_opts_v: Vec<ColumnFamilyOptions> = Vec::new();
+    let mut cfds = vec![];
     for cf in &existed {
-        cfs_v.push(cf);
-        match cfs_opts.iter().find(|x| x.cf == *cf) {
-            Some(x) => {
-                cfs_opts_v.push(x.options.clone());
-            }
-            None => {
-                cfs_opts_v.push(ColumnFamilyOptions::new());
-            }
-        }
+        let options = match cfs_opts.iter().position(|x| x.cf == *cf) {
+            Some(pos) => cfs_opts.remove(pos).options,
+            None => ColumnFamilyOptions::new(),
+        };
+        cfds.push((*cf, options));
     }
-    let cfds = cfs_v.into_iter().zip(cfs_opts_v).collect();
     let mut db = DB::open_cf(db_opt, path, cfds).unwrap();
 
     // Drop discarded column families.
@@ -173,17 +180,9 @@ fn check_and_open(
         }
     }
 
-    // Create needed column families not existed yet.
-    for cf in cfs_diff(&needed, &existed) {
-        db.create_cf((
-            cf,
-            cfs_opts
-                .iter()
-                .find(|x| x.cf == cf)
-                .unwrap()
-                .options
-                .clone(),
-        ))?;
+    // Create needed column families not existed yet, which are the ones left in `cfs_opts`.
+    for x in cfs_opts {
+        db.create_cf((x.cf, x.options))?;
     }
     Ok(db)
 }
@@ -349,6 +348,22 @@ pub fn roughly_cleanup_range(db: &DB, start_key: &[u8], end_key: &[u8]) -> Resul
     Ok(())
 }
 
+/// Create a `SstFileWriter` for the cf at `path`, the produced file can be
+/// ingested into the same cf later.
+pub fn new_sst_writer(db: &DB, cf: &str, path: &str) -> Result<SstFileWriter, String> {
+    let handle = get_cf_handle(db, cf)?;
+    let mut io_options = db.get_options_cf(handle).clone();
+    io_options.compression(get_fastest_supported_compression_type());
+    // in rocksdb 5.5.1, SstFileWriter will try to use bottommost_compression and
+    // compression_per_level first, so to make sure our specified compression type
+    // being used, we must set them empty or disabled.
+    io_options.compression_per_level(&[]);
+    io_options.bottommost_compression(DBCompressionType::Disable);
+    let mut writer = SstFileWriter::new(EnvOptions::new(), io_options);
+    writer.open(path)?;
+    Ok(writer)
+}
+
 /// Compact the cf in the specified range by manual or not.
 pub fn compact_range(
     db: &DB,
@@ -65,6 +65,9 @@ fn test_serde_custom_tikv_config() {
         end_point_concurrency: 12,
         end_point_max_tasks: 12,
         end_point_stack_size: ReadableSize::mb(12),
+        end_point_stream_batch_row_limit: 12,
+        end_point_aggr_memory_quota: ReadableSize::mb(12),
+        end_point_aggr_spill_dir: "/var/aggr-spill".to_owned(),
     };
     value.metric = MetricConfig {
         interval: ReadableDuration::secs(12),
@@ -74,6 +77,7 @@ fn test_serde_custom_tikv_config() {
     value.raft_store = RaftstoreConfig {
         sync_log: false,
         raftdb_path: "/var".to_owned(),
+        import_dir: "/var/import".to_owned(),
         capacity: ReadableSize(123),
         raft_base_tick_interval: ReadableDuration::secs(12),
         raft_heartbeat_ticks: 1,
@@ -81,12 +85,15 @@ fn test_serde_custom_tikv_config() {
         raft_max_size_per_msg: ReadableSize::mb(12),
         raft_max_inflight_msgs: 123,
         raft_entry_max_size: ReadableSize::mb(12),
+        allow_follower_read: true,
         raft_log_gc_tick_interval: ReadableDuration::secs(12),
         raft_log_gc_threshold: 12,
         raft_log_gc_count_limit: 12,
         raft_log_gc_size_limit: ReadableSize::kb(1),
         split_region_check_tick_interval: ReadableDuration::secs(12),
         region_split_check_diff: ReadableSize::mb(6),
+        region_split_qps_threshold: 1_234,
+        region_split_load_duration: ReadableDuration::secs(12),
         region_compact_check_interval: ReadableDuration::secs(12),
         region_compact_delete_keys_count: 1_234,
         pd_heartbeat_tick_interval: ReadableDuration::minutes(12),
@@ -101,6 +108,7 @@ fn test_serde_custom_tikv_config() {
         lock_cf_compact_interval: ReadableDuration::minutes(12),
         lock_cf_compact_bytes_threshold: ReadableSize::mb(123),
         consistency_check_interval: ReadableDuration::secs(12),
+        advance_resolved_ts_interval: ReadableDuration::secs(12),
         report_region_flow_interval: ReadableDuration::minutes(12),
         raft_store_max_leader_lease: ReadableDuration::secs(12),
         right_derive_when_split: false,
@@ -317,10 +325,15 @@ fn test_serde_custom_tikv_config() {
         scheduler_concurrency: 123,
         scheduler_worker_pool_size: 1,
         scheduler_pending_write_threshold: ReadableSize::kb(123),
+        enable_ttl: true,
+        enable_compaction_filter_gc: false,
     };
     value.coprocessor = CopConfig {
+        split_region_on_table: false,
         region_max_size: ReadableSize::mb(12),
         region_split_size: ReadableSize::mb(12),
+        region_max_keys: 100000,
+        region_split_keys: 100000,
     };
 
     let custom = read_file_in_project_dir("tests/config/test-custom.toml");
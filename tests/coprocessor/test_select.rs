@@ -25,16 +25,18 @@ use kvproto::kvrpcpb::Context;
 use tikv::coprocessor::codec::{datum, table, Datum};
 use tikv::coprocessor::codec::datum::DatumDecoder;
 use tikv::util::codec::number::*;
-use tikv::storage::{Key, Mutation, ALL_CFS};
+use tikv::storage::{ConcurrencyManager, Key, Mutation, ALL_CFS};
 use tikv::server::Config;
 use tikv::storage::engine::{self, Engine, TEMP_DIR};
 use tikv::util::worker::{FutureWorker, Worker};
 use kvproto::coprocessor::{KeyRange, Request, Response};
 use tipb::select::{Chunk, DAGRequest, SelectRequest, SelectResponse};
-use tipb::executor::{Aggregation, ExecType, Executor, IndexScan, Limit, Selection, TableScan, TopN};
+use tipb::executor::{Aggregation, ExecType, Executor, IndexScan, Limit, Projection, Selection,
+                     TableScan, TopN};
 use tipb::schema::{self, ColumnInfo};
 use tipb::expression::{ByItem, Expr, ExprType, ScalarFuncSig};
 use protobuf::{Message, RepeatedField};
+use futures::{self, Stream};
 
 use raftstore::util::MAX_LEADER_LEASE;
 use storage::sync_storage::SyncStorage;
@@ -688,6 +690,7 @@ fn init_data_with_engine_and_commit(
         end_point.scheduler(),
         &cfg,
         pd_worker.scheduler(),
+        ConcurrencyManager::new(),
     );
     end_point.start_batch(runner, 5).unwrap();
 
@@ -870,6 +873,30 @@ impl DAGSelect {
         self
     }
 
+    // looks up the rows of the table by the handles from the index.
+    fn lookup(mut self, table: &Table) -> DAGSelect {
+        let mut exec = Executor::new();
+        exec.set_tp(ExecType::TypeTableScan);
+        let mut tbl_scan = TableScan::new();
+        let mut table_info = table.get_table_info();
+        tbl_scan.set_table_id(table_info.get_table_id());
+        tbl_scan.set_columns(table_info.take_columns());
+        exec.set_tbl_scan(tbl_scan);
+        self.execs.push(exec);
+        self.cols = table.get_table_columns();
+        self
+    }
+
+    fn project(mut self, exprs: Vec<Expr>) -> DAGSelect {
+        let mut exec = Executor::new();
+        exec.set_tp(ExecType::TypeProjection);
+        let mut projection = Projection::new();
+        projection.set_exprs(RepeatedField::from_vec(exprs));
+        exec.set_projection(projection);
+        self.execs.push(exec);
+        self
+    }
+
     fn build(self) -> Request {
         self.build_with(&[0])
     }
@@ -970,6 +997,97 @@ fn test_select() {
     end_point.stop().unwrap().join().unwrap();
 }
 
+#[test]
+fn test_select_streaming() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:4"), 3),
+        (4, Some("name:3"), 1),
+        (5, Some("name:1"), 4),
+        (6, Some("name:2"), 5),
+    ];
+
+    let product = ProductTable::new();
+    let (store, mut end_point) = init_with_data(&product, &data);
+    end_point.stop().unwrap().join().unwrap();
+    let mut end_point = Worker::new("test select streaming worker");
+    let mut cfg = Config::default();
+    cfg.end_point_concurrency = 1;
+    cfg.end_point_stream_batch_row_limit = 2;
+    let pd_worker = FutureWorker::new("test pd worker");
+    let runner = EndPointHost::new(
+        store.get_engine(),
+        end_point.scheduler(),
+        &cfg,
+        pd_worker.scheduler(),
+        ConcurrencyManager::new(),
+    );
+    end_point.start_batch(runner, 5).unwrap();
+
+    let req = DAGSelect::from(&product.table).build();
+    let ranges = req.get_ranges().to_vec();
+    let resps = handle_streaming_request(&end_point, req);
+    assert_eq!(resps.len(), 3);
+    let mut rows = vec![];
+    for (i, resp) in resps.into_iter().enumerate() {
+        let mut sel_resp = SelectResponse::new();
+        sel_resp.merge_from_bytes(resp.resp.get_data()).unwrap();
+        let spliter = DAGChunkSpliter::new(sel_resp.take_chunks().into_vec(), 3);
+        rows.extend(spliter);
+        let last_key = if i < 2 {
+            Some(build_row_key(product.table.id, data[i * 2 + 1].0))
+        } else {
+            None
+        };
+        assert_eq!(resp.last_key, last_key);
+    }
+    assert_eq!(rows.len(), data.len());
+    for (row, &(id, name, cnt)) in rows.iter().zip(&data) {
+        let name_datum = name.map(|s| s.as_bytes()).into();
+        let expected_encoded =
+            datum::encode_value(&[Datum::I64(id), name_datum, cnt.into()]).unwrap();
+        assert_eq!(datum::encode_value(row).unwrap(), expected_encoded);
+    }
+
+    // resume after the second row.
+    let mut req = DAGSelect::from(&product.table).build();
+    let last_key = build_row_key(product.table.id, data[1].0);
+    let resumed = resume_ranges(&ranges, &last_key, false);
+    req.set_ranges(RepeatedField::from_vec(resumed));
+    let resps = handle_streaming_request(&end_point, req);
+    let mut rows = 0;
+    for resp in resps {
+        let mut sel_resp = SelectResponse::new();
+        sel_resp.merge_from_bytes(resp.resp.get_data()).unwrap();
+        let spliter = DAGChunkSpliter::new(sel_resp.take_chunks().into_vec(), 3);
+        for (row, &(id, _, _)) in spliter.zip(&data[2..]) {
+            assert_eq!(row[0], Datum::I64(id));
+            rows += 1;
+        }
+    }
+    assert_eq!(rows, 3);
+
+    // aggregated rows can't be resumed.
+    let req = DAGSelect::from(&product.table).count().build();
+    let resps = handle_streaming_request(&end_point, req);
+    assert_eq!(resps.len(), 1);
+    assert!(resps[0].last_key.is_none());
+
+    // The stream yields instead of blocking the only thread of the pool while its responses
+    // are not consumed.
+    let (tx, rx) = futures::sync::mpsc::channel(0);
+    let req = RequestTask::new_stream(DAGSelect::from(&product.table).build(), tx);
+    end_point.schedule(EndPointTask::Request(req)).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let mut resp = handle_select(&end_point, DAGSelect::from(&product.table).build());
+    let spliter = DAGChunkSpliter::new(resp.take_chunks().into_vec(), 3);
+    assert_eq!(spliter.count(), data.len());
+    let resps: Vec<_> = rx.wait().map(|resp| resp.unwrap()).collect();
+    assert_eq!(resps.len(), 3);
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
 #[test]
 fn test_select_after_lease() {
     let data = vec![
@@ -1054,6 +1172,56 @@ fn test_group_by() {
     }
     assert_eq!(row_count, 3);
 
+    // the output of projection can't be reordered by the output offsets.
+    let req = DAGSelect::from(&product.table)
+        .project(vec![plus, col_ref(product.name.id)])
+        .output_offsets(Some(vec![1]))
+        .build();
+    let resp = handle_request(&end_point, req);
+    assert!(!resp.get_other_error().is_empty(), "{:?}", resp);
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
+#[test]
+fn test_index_lookup_out_of_region() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:4"), 3),
+        (4, Some("name:3"), 1),
+        (5, Some("name:1"), 4),
+    ];
+
+    let product = ProductTable::new();
+    let (mut cluster, raft_engine, ctx) = new_raft_engine(1, "");
+    let (_, mut end_point) =
+        init_data_with_engine_and_commit(ctx, raft_engine, &product, &data, true);
+
+    // the index and the rows of the table are split into two regions.
+    let row_prefix = Key::from_raw(&table::encode_row_key(product.table.id, &[]));
+    let region = cluster.get_region(b"");
+    cluster.must_split(&region, row_prefix.encoded());
+    let region = cluster.get_region(b"");
+    assert_eq!(region.get_end_key(), row_prefix.encoded().as_slice());
+    let mut ctx = Context::new();
+    ctx.set_region_id(region.get_id());
+    ctx.set_region_epoch(region.get_region_epoch().clone());
+    ctx.set_peer(cluster.leader_of_region(region.get_id()).unwrap());
+
+    let mut req = DAGSelect::from_index(&product.table, product.name)
+        .lookup(&product.table)
+        .build();
+    req.set_context(ctx);
+    let resp = handle_request(&end_point, req);
+    // It's not a region error, or the client would retry it on the region forever.
+    assert!(!resp.has_region_error(), "{:?}", resp);
+    assert!(resp.get_data().is_empty(), "{:?}", resp);
+    assert!(
+        resp.get_other_error().contains("is not supported"),
+        "{:?}",
+        resp
+    );
+
     end_point.stop().unwrap().join().unwrap();
 }
 
@@ -1666,6 +1834,14 @@ pub fn handle_request(end_point: &Worker<EndPointTask>, req: Request) -> Respons
     rx.recv().unwrap()
 }
 
+fn handle_streaming_request(end_point: &Worker<EndPointTask>, req: Request) -> Vec<StreamResponse> {
+    // Small enough to block the endpoint before the responses are consumed.
+    let (tx, rx) = futures::sync::mpsc::channel(1);
+    let req = RequestTask::new_stream(req, tx);
+    end_point.schedule(EndPointTask::Request(req)).unwrap();
+    rx.wait().map(|resp| resp.unwrap()).collect()
+}
+
 fn handle_select(end_point: &Worker<EndPointTask>, req: Request) -> SelectResponse {
     let resp = handle_request(end_point, req);
     assert!(!resp.get_data().is_empty(), "{:?}", resp);
@@ -2662,3 +2838,82 @@ fn test_key_is_locked_for_index() {
     assert!(resp.has_locked(), "{:?}", resp);
     end_point.stop().unwrap().join().unwrap();
 }
+
+#[test]
+fn test_index_lookup_for_dag() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:4"), 3),
+        (4, Some("name:3"), 1),
+        (5, Some("name:1"), 4),
+    ];
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    let req = DAGSelect::from_index(&product.table, product.name)
+        .lookup(&product.table)
+        .build();
+    let mut resp = handle_select(&end_point, req);
+    let spliter = DAGChunkSpliter::new(resp.take_chunks().into_vec(), 3);
+    // the rows are in the order of the index on name.
+    let expect = vec![data[0], data[3], data[2], data[1]];
+    let mut row_count = 0;
+    for (row, (id, name, cnt)) in spliter.zip(expect) {
+        let name_datum = name.map(|s| s.as_bytes()).into();
+        let expected_encoded =
+            datum::encode_value(&[Datum::I64(id), name_datum, cnt.into()]).unwrap();
+        let result_encoded = datum::encode_value(&row).unwrap();
+        assert_eq!(&*result_encoded, &*expected_encoded);
+        row_count += 1;
+    }
+    assert_eq!(row_count, data.len());
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
+#[test]
+fn test_projection_for_dag() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:4"), 3),
+        (4, Some("name:3"), 1),
+        (5, Some("name:1"), 4),
+    ];
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    let cols = product.table.get_table_columns();
+    let col_ref = |id| {
+        let mut expr = Expr::new();
+        expr.set_tp(ExprType::ColumnRef);
+        expr.mut_val()
+            .encode_i64(offset_for_column(&cols, id))
+            .unwrap();
+        expr
+    };
+    let mut plus = Expr::new();
+    plus.set_tp(ExprType::ScalarFunc);
+    plus.set_sig(ScalarFuncSig::PlusInt);
+    plus.mut_children().push(col_ref(product.id.id));
+    plus.mut_children().push(col_ref(product.count.id));
+    let req = DAGSelect::from(&product.table)
+        .project(vec![plus.clone(), col_ref(product.name.id)])
+        .limit(3)
+        .output_offsets(Some(vec![0, 1]))
+        .build();
+    let mut resp = handle_select(&end_point, req);
+    let spliter = DAGChunkSpliter::new(resp.take_chunks().into_vec(), 2);
+    let mut row_count = 0;
+    for (row, (id, name, cnt)) in spliter.zip(data) {
+        let name_datum = name.map(|s| s.as_bytes()).into();
+        let expected_encoded = datum::encode_value(&[Datum::I64(id + cnt), name_datum]).unwrap();
+        let result_encoded = datum::encode_value(&row).unwrap();
+        assert_eq!(&*result_encoded, &*expected_encoded);
+        row_count += 1;
+    }
+    assert_eq!(row_count, 3);
+
+    end_point.stop().unwrap().join().unwrap();
+}
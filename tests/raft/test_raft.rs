@@ -111,6 +111,19 @@ pub fn new_test_raft_with_config(config: &Config, storage: MemStorage) -> Interf
     Interface::new(Raft::new(config, storage))
 }
 
+pub fn new_test_learner_raft(
+    id: u64,
+    peers: Vec<u64>,
+    learners: Vec<u64>,
+    election: usize,
+    heartbeat: usize,
+    storage: MemStorage,
+) -> Interface {
+    let mut config = new_test_config(id, peers, election, heartbeat);
+    config.learners = learners;
+    new_test_raft_with_config(&config, storage)
+}
+
 
 fn read_messages<T: Storage>(raft: &mut Raft<T>) -> Vec<Message> {
     raft.msgs.drain(..).collect()
@@ -192,11 +205,13 @@ impl Interface {
     fn initial(&mut self, id: u64, ids: &[u64]) {
         if self.raft.is_some() {
             self.id = id;
+            let learners = self.learner_nodes();
             self.prs = RaftFlatMap::with_capacity(ids.len());
             for id in ids {
                 self.prs.insert(
                     *id,
                     Progress {
+                        is_learner: learners.contains(id),
                         ..Default::default()
                     },
                 );
@@ -2983,6 +2998,361 @@ fn test_remove_node() {
     assert!(r.nodes().is_empty());
 }
 
+// test_add_learner tests that add_learner could update pending_conf and learner nodes correctly.
+#[test]
+fn test_add_learner() {
+    let mut r = new_test_raft(1, vec![1], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.add_learner(2);
+    assert!(!r.pending_conf);
+    assert_eq!(r.nodes(), vec![1]);
+    assert_eq!(r.learner_nodes(), vec![2]);
+    assert!(r.prs[&2].is_learner);
+
+    // A voter can't be demoted.
+    r.add_learner(1);
+    assert_eq!(r.nodes(), vec![1]);
+    assert_eq!(r.learner_nodes(), vec![2]);
+}
+
+// test_remove_learner tests that remove_node could remove learners.
+#[test]
+fn test_remove_learner() {
+    let mut r = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.remove_node(2);
+    assert!(!r.pending_conf);
+    assert_eq!(r.nodes(), vec![1]);
+    assert!(r.learner_nodes().is_empty());
+}
+
+// test_learner_promotion tests that a learner can't campaign until it's promoted by add_node.
+#[test]
+fn test_learner_promotion() {
+    let mut n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    n1.become_follower(1, INVALID_ID);
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    n2.become_follower(1, INVALID_ID);
+    assert!(!n2.promotable());
+    assert!(n2.is_learner());
+
+    let mut nt = Network::new(vec![Some(n1), Some(n2)]);
+    assert_ne!(nt.peers[&1].state, StateRole::Leader);
+
+    // n1 should become leader.
+    let timeout = nt.peers[&1].get_randomized_election_timeout();
+    for _ in 0..timeout {
+        nt.peers.get_mut(&1).unwrap().tick();
+    }
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert_eq!(nt.peers[&2].state, StateRole::Follower);
+
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+
+    nt.peers.get_mut(&1).unwrap().add_node(2);
+    nt.peers.get_mut(&2).unwrap().add_node(2);
+    assert!(nt.peers[&2].promotable());
+    assert!(!nt.peers[&2].is_learner());
+    assert_eq!(nt.peers[&1].nodes(), vec![1, 2]);
+
+    // n2 starts an election and should become leader.
+    nt.send(vec![new_message(2, 2, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Follower);
+    assert_eq!(nt.peers[&2].state, StateRole::Leader);
+}
+
+// test_learner_election_timeout verifies that a learner never starts an election.
+#[test]
+fn test_learner_election_timeout() {
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    n2.become_follower(1, INVALID_ID);
+
+    let timeout = n2.get_election_timeout();
+    n2.set_randomized_election_timeout(timeout);
+    for _ in 0..timeout * 2 {
+        n2.tick();
+    }
+    assert_eq!(n2.state, StateRole::Follower);
+    assert!(n2.read_messages().is_empty());
+
+    // A learner ignores MsgHup and MsgTimeoutNow too.
+    n2.step(new_message(2, 2, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(n2.state, StateRole::Follower);
+    n2.step(new_message(1, 2, MessageType::MsgTimeoutNow, 0))
+        .expect("");
+    assert_eq!(n2.state, StateRole::Follower);
+}
+
+// test_learner_log_replication tests that a learner receives entries from the leader, but the
+// entries are committed without it.
+#[test]
+fn test_learner_log_replication() {
+    let n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    let mut nt = Network::new(vec![Some(n1), Some(n2)]);
+
+    nt.peers.get_mut(&1).unwrap().become_candidate();
+    nt.peers.get_mut(&1).unwrap().become_leader();
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    let committed = nt.peers[&1].raft_log.committed;
+    assert_eq!(nt.peers[&2].raft_log.committed, committed);
+
+    // The learner is isolated, but the proposal is still committed.
+    nt.isolate(2);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&1].raft_log.committed, committed + 1);
+    assert_eq!(nt.peers[&2].raft_log.committed, committed);
+
+    nt.recover();
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&1].raft_log.committed, committed + 2);
+    assert_eq!(nt.peers[&2].raft_log.committed, committed + 2);
+    assert_eq!(
+        nt.peers[&2].raft_log.last_index(),
+        nt.peers[&1].raft_log.last_index()
+    );
+    assert_eq!(nt.peers[&1].prs[&2].matched, committed + 2);
+}
+
+// test_learner_receives_no_vote_request tests that vote requests are only sent to voters.
+#[test]
+fn test_learner_receives_no_vote_request() {
+    let mut r = new_test_learner_raft(1, vec![1, 2], vec![3], 10, 1, new_storage());
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(r.state, StateRole::Candidate);
+    let msgs = r.read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_to(), 2);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgRequestVote);
+
+    // The vote of node 2 makes a quorum.
+    let mut m = new_message(2, 1, MessageType::MsgRequestVoteResponse, 0);
+    m.set_term(r.term);
+    r.step(m).expect("");
+    assert_eq!(r.state, StateRole::Leader);
+}
+
+// test_restore_learners_from_storage tests that the learners persisted in the storage are
+// restored when raft restarts.
+#[test]
+fn test_restore_learners_from_storage() {
+    let store = new_storage();
+    store
+        .wl()
+        .apply_snapshot(new_snapshot(11, 11, vec![1, 2]))
+        .expect("");
+    store.wl().set_learners(vec![3]);
+
+    let mut r = new_test_raft(3, vec![], 10, 1, store);
+    assert_eq!(r.nodes(), vec![1, 2]);
+    assert_eq!(r.learner_nodes(), vec![3]);
+    assert!(r.is_learner());
+    assert!(!r.promotable());
+
+    // A restarted learner still never starts an election.
+    let timeout = r.get_election_timeout();
+    for _ in 0..timeout * 2 {
+        r.tick();
+    }
+    assert_eq!(r.state, StateRole::Follower);
+    assert!(r.read_messages().is_empty());
+}
+
+// test_restore_learners_conflict tests that learners can't be specified by both the config and
+// the storage.
+#[test]
+#[should_panic]
+fn test_restore_learners_conflict() {
+    let store = new_storage();
+    store
+        .wl()
+        .apply_snapshot(new_snapshot(11, 11, vec![1, 2]))
+        .expect("");
+    store.wl().set_learners(vec![3]);
+    new_test_learner_raft(1, vec![], vec![3], 10, 1, store);
+}
+
+fn new_conf_change(t: ConfChangeType, node_id: u64) -> ConfChange {
+    let mut cc = ConfChange::new();
+    cc.set_change_type(t);
+    cc.set_node_id(node_id);
+    cc
+}
+
+// swap_voters_changes replaces voters 2 and 3 with 4 and 5.
+fn swap_voters_changes() -> Vec<ConfChange> {
+    vec![
+        new_conf_change(ConfChangeType::RemoveNode, 2),
+        new_conf_change(ConfChangeType::RemoveNode, 3),
+        new_conf_change(ConfChangeType::AddNode, 4),
+        new_conf_change(ConfChangeType::AddNode, 5),
+    ]
+}
+
+// test_joint_config tests that enter_joint and leave_joint update the voters correctly.
+#[test]
+fn test_joint_config() {
+    let mut r = new_test_learner_raft(1, vec![1, 2, 3], vec![5, 6], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.enter_joint(&[
+        new_conf_change(ConfChangeType::RemoveNode, 3),
+        new_conf_change(ConfChangeType::AddNode, 4),
+        new_conf_change(ConfChangeType::AddNode, 5),
+        new_conf_change(ConfChangeType::RemoveNode, 6),
+    ]);
+    assert!(!r.pending_conf);
+    {
+        let joint = r.joint.as_ref().unwrap();
+        let mut incoming: Vec<_> = joint.incoming.iter().cloned().collect();
+        incoming.sort();
+        assert_eq!(incoming, vec![1, 2, 4, 5]);
+        let mut outgoing: Vec<_> = joint.outgoing.iter().cloned().collect();
+        outgoing.sort();
+        assert_eq!(outgoing, vec![1, 2, 3]);
+    }
+    // The voters of both sets are in progress, learner 5 is promoted and learner 6 is removed.
+    assert_eq!(r.nodes(), vec![1, 2, 3, 4, 5]);
+    assert!(r.learner_nodes().is_empty());
+
+    // Entering again is ignored.
+    r.enter_joint(&[new_conf_change(ConfChangeType::RemoveNode, 1)]);
+    assert_eq!(r.nodes(), vec![1, 2, 3, 4, 5]);
+
+    r.pending_conf = true;
+    r.leave_joint();
+    assert!(!r.pending_conf);
+    assert!(r.joint.is_none());
+    assert_eq!(r.nodes(), vec![1, 2, 4, 5]);
+}
+
+// test_joint_commit tests that an entry is committed in the joint configuration only when
+// it's committed by both voter sets.
+#[test]
+fn test_joint_commit() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    r.enter_joint(&swap_voters_changes());
+
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+        .expect("");
+    let index = r.raft_log.last_index();
+    let committed = r.raft_log.committed;
+
+    // The new voters accept it, but the old voters don't.
+    for id in 4..6 {
+        let mut m = new_message(id, 1, MessageType::MsgAppendResponse, 0);
+        m.set_index(index);
+        r.step(m).expect("");
+    }
+    assert_eq!(r.raft_log.committed, committed);
+
+    // Now a quorum of the old voters accepts it too.
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.set_index(index);
+    r.step(m).expect("");
+    assert_eq!(r.raft_log.committed, index);
+
+    r.leave_joint();
+    assert_eq!(r.nodes(), vec![1, 4, 5]);
+}
+
+// test_joint_election tests that a candidate needs the votes of a quorum of both voter sets
+// in the joint configuration.
+#[test]
+fn test_joint_election() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.enter_joint(&swap_voters_changes());
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(r.state, StateRole::Candidate);
+    // Vote requests are sent to the voters of both sets.
+    let mut tos: Vec<_> = r.read_messages().iter().map(|m| m.get_to()).collect();
+    tos.sort();
+    assert_eq!(tos, vec![2, 3, 4, 5]);
+
+    // A quorum of the new voters isn't enough.
+    let mut m = new_message(4, 1, MessageType::MsgRequestVoteResponse, 0);
+    m.set_term(r.term);
+    r.step(m).expect("");
+    assert_eq!(r.state, StateRole::Candidate);
+
+    let mut m = new_message(2, 1, MessageType::MsgRequestVoteResponse, 0);
+    m.set_term(r.term);
+    r.step(m).expect("");
+    assert_eq!(r.state, StateRole::Leader);
+
+    // The election is lost once a quorum of either set rejects.
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.enter_joint(&swap_voters_changes());
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    for id in 2..4 {
+        let mut m = new_message(id, 1, MessageType::MsgRequestVoteResponse, 0);
+        m.set_term(r.term);
+        m.set_reject(true);
+        r.step(m).expect("");
+    }
+    assert_eq!(r.state, StateRole::Follower);
+}
+
+// test_restore_joint_from_storage tests that the joint configuration persisted in the storage
+// is restored when raft restarts, and elections still need a quorum of both voter sets.
+#[test]
+fn test_restore_joint_from_storage() {
+    let store = new_storage();
+    store
+        .wl()
+        .apply_snapshot(new_snapshot(11, 11, vec![1, 2, 3, 4, 5]))
+        .expect("");
+    let joint = JointConfig {
+        incoming: vec![1, 4, 5].into_iter().collect(),
+        outgoing: vec![1, 2, 3].into_iter().collect(),
+    };
+    store.wl().set_joint(Some(joint.clone()));
+
+    let mut r = new_test_raft(1, vec![], 10, 1, store);
+    assert_eq!(r.joint, Some(joint));
+    assert_eq!(r.nodes(), vec![1, 2, 3, 4, 5]);
+
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(r.state, StateRole::Candidate);
+    // A quorum of the new voters isn't enough.
+    let mut m = new_message(4, 1, MessageType::MsgRequestVoteResponse, 0);
+    m.set_term(r.term);
+    r.step(m).expect("");
+    assert_eq!(r.state, StateRole::Candidate);
+
+    let mut m = new_message(2, 1, MessageType::MsgRequestVoteResponse, 0);
+    m.set_term(r.term);
+    r.step(m).expect("");
+    assert_eq!(r.state, StateRole::Leader);
+
+    r.leave_joint();
+    assert!(r.joint.is_none());
+    assert_eq!(r.nodes(), vec![1, 4, 5]);
+}
+
+// test_leave_joint_removes_leader tests that the leader steps down when it's removed by
+// leave_joint.
+#[test]
+fn test_leave_joint_removes_leader() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    let term = r.term;
+    r.enter_joint(&[
+        new_conf_change(ConfChangeType::RemoveNode, 1),
+        new_conf_change(ConfChangeType::AddNode, 4),
+    ]);
+    assert_eq!(r.state, StateRole::Leader);
+
+    r.leave_joint();
+    assert_eq!(r.state, StateRole::Follower);
+    assert_eq!(r.term, term);
+    assert_eq!(r.leader_id, INVALID_ID);
+    assert_eq!(r.nodes(), vec![2, 3, 4]);
+    assert!(!r.promotable());
+}
+
 #[test]
 fn test_promotable() {
     let id = 1u64;
@@ -270,6 +270,25 @@ fn test_raw_node_propose_add_duplicate_node() {
     assert_eq!(entries[2].take_data(), ccdata2);
 }
 
+// test_raw_node_apply_learner_conf_change ensures that a learner can be added and then
+// promoted by conf changes.
+#[test]
+fn test_raw_node_apply_learner_conf_change() {
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![], 10, 1, s.clone(), vec![new_peer(1)]);
+    let rd = raw_node.ready();
+    s.wl().append(&rd.entries).expect("");
+    raw_node.advance(rd);
+
+    let cs = raw_node.apply_learner_conf_change(&conf_change(ConfChangeType::AddNode, 2));
+    assert_eq!(cs.get_nodes(), &[1]);
+    assert_eq!(raw_node.raft.learner_nodes(), vec![2]);
+
+    let cs = raw_node.apply_conf_change(&conf_change(ConfChangeType::AddNode, 2));
+    assert_eq!(cs.get_nodes(), &[1, 2]);
+    assert!(raw_node.raft.learner_nodes().is_empty());
+}
+
 // test_raw_node_read_index ensures that RawNode.read_index sends the MsgReadIndex message
 // to the underlying raft. It also ensures that ReadState can be read out.
 #[test]
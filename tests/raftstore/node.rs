@@ -30,6 +30,7 @@ use kvproto::eraftpb::MessageType;
 use tikv::config::TiKvConfig;
 use tikv::raftstore::{Error, Result};
 use tikv::raftstore::coprocessor::CoprocessorHost;
+use tikv::storage::ConcurrencyManager;
 use tikv::util::HandyRwLock;
 use tikv::util::worker::FutureWorker;
 use tikv::util::transport::SendCh;
@@ -181,6 +182,7 @@ impl Simulator for NodeCluster {
 
         // Create coprocessor.
         let coprocessor_host = CoprocessorHost::new(cfg.coprocessor, node.get_sendch());
+        let local_reader = LocalReader::new();
 
         node.start(
             event_loop,
@@ -190,6 +192,8 @@ impl Simulator for NodeCluster {
             snap_status_receiver,
             pd_worker,
             coprocessor_host,
+            local_reader.clone(),
+            ConcurrencyManager::new(),
         ).unwrap();
         assert!(
             engines
@@ -213,7 +217,11 @@ impl Simulator for NodeCluster {
         }
 
         let node_id = node.id();
-        let router = ServerRaftStoreRouter::new(node.get_sendch(), snap_status_sender.clone());
+        let router = ServerRaftStoreRouter::new(
+            node.get_sendch(),
+            snap_status_sender.clone(),
+            local_reader,
+        );
         self.trans
             .wl()
             .routers
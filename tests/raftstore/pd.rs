@@ -75,6 +75,7 @@ struct Cluster {
     down_peers: HashMap<u64, pdpb::PeerStats>,
     pending_peers: HashMap<u64, metapb::Peer>,
     is_bootstraped: bool,
+    tso: u64,
 }
 
 impl Cluster {
@@ -95,6 +96,7 @@ impl Cluster {
             down_peers: HashMap::new(),
             pending_peers: HashMap::new(),
             is_bootstraped: false,
+            tso: 0,
         }
     }
 
@@ -742,4 +744,10 @@ impl PdClient for TestPdClient {
         self.cluster.wl().split_count += 1;
         Box::new(ok(()))
     }
+
+    fn get_tso(&self) -> PdFuture<u64> {
+        let mut cluster = self.cluster.wl();
+        cluster.tso += 1;
+        Box::new(ok(cluster.tso))
+    }
 }
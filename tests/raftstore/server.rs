@@ -28,7 +28,7 @@ use tikv::server::resolve::{self, Task as ResolveTask};
 use tikv::server::transport::ServerRaftStoreRouter;
 use tikv::server::transport::RaftStoreRouter;
 use tikv::raftstore::{store, Error, Result};
-use tikv::raftstore::store::{Engines, Msg as StoreMsg, SnapManager};
+use tikv::raftstore::store::{Engines, LocalReader, Msg as StoreMsg, SnapManager};
 use tikv::raftstore::coprocessor::CoprocessorHost;
 use tikv::util::transport::SendCh;
 use tikv::util::worker::{FutureWorker, Worker};
@@ -109,7 +109,12 @@ impl Simulator for ServerCluster {
         let mut event_loop = store::create_event_loop(&cfg.raft_store).unwrap();
         let store_sendch = SendCh::new(event_loop.channel(), "raftstore");
         let (snap_status_sender, snap_status_receiver) = mpsc::channel();
-        let raft_router = ServerRaftStoreRouter::new(store_sendch.clone(), snap_status_sender);
+        let local_reader = LocalReader::new();
+        let raft_router = ServerRaftStoreRouter::new(
+            store_sendch.clone(),
+            snap_status_sender,
+            local_reader.clone(),
+        );
         let sim_router = SimulateTransport::new(raft_router);
 
         // Create storage.
@@ -157,6 +162,8 @@ impl Simulator for ServerCluster {
             snap_status_receiver,
             pd_worker,
             coprocessor_host,
+            local_reader,
+            store.get_concurrency_manager(),
         ).unwrap();
         assert!(node_id == 0 || node_id == node.id());
         let node_id = node.id();
@@ -13,8 +13,8 @@
 
 use std::sync::{mpsc, Arc};
 use std::path::Path;
-use tikv::raftstore::store::{bootstrap_store, create_event_loop, keys, Engines, Peekable,
-                             SnapManager};
+use tikv::raftstore::store::{bootstrap_store, create_event_loop, keys, Engines, LocalReader,
+                             Peekable, SnapManager};
 use tikv::server::Node;
 use tikv::storage::{ALL_CFS, CF_RAFT};
 use tikv::raftstore::coprocessor::CoprocessorHost;
@@ -107,6 +107,7 @@ fn test_node_bootstrap_with_prepared_data() {
         snapshot_status_receiver,
         pd_worker,
         coprocessor_host,
+        LocalReader::new(),
     ).unwrap();
     assert!(
         engine
@@ -0,0 +1,195 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A module contains test cases for linearizable read on Raft followers.
+
+use std::time::Duration;
+
+use kvproto::eraftpb::MessageType;
+use kvproto::metapb::{Peer, Region};
+use kvproto::raft_cmdpb::CmdType;
+use tikv::raftstore::{Error, Result};
+
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+use super::transport_simulate::*;
+use super::util::*;
+
+fn read_on_peer<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    peer: Peer,
+    region: &Region,
+    key: &[u8],
+    read_quorum: bool,
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let mut request = new_request(
+        region.get_id(),
+        region.get_region_epoch().clone(),
+        vec![new_get_cmd(key)],
+        read_quorum,
+    );
+    request.mut_header().set_peer(peer);
+    let mut resp = cluster.call_command(request, timeout)?;
+    if resp.get_header().has_error() {
+        return Err(Error::Other(
+            box_err!(resp.mut_header().take_error().take_message()),
+        ));
+    }
+    assert_eq!(resp.get_responses().len(), 1);
+    assert_eq!(resp.get_responses()[0].get_cmd_type(), CmdType::Get);
+    let mut get = resp.mut_responses()[0].take_get();
+    assert!(get.has_value());
+    Ok(get.take_value())
+}
+
+fn test_follower_read<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.raft_store.allow_follower_read = true;
+    cluster.run();
+
+    let (key, value) = (b"k1", b"v1");
+    cluster.must_put(key, value);
+    let region = cluster.get_region(key);
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let follower = region
+        .get_peers()
+        .iter()
+        .find(|p| p.get_id() != leader.get_id())
+        .unwrap()
+        .clone();
+    let timeout = Duration::from_secs(5);
+
+    // A follower only serves the reads opted in by `read_quorum`.
+    assert!(read_on_peer(cluster, follower.clone(), &region, key, false, timeout).is_err());
+    let v = read_on_peer(cluster, follower.clone(), &region, key, true, timeout).unwrap();
+    assert_eq!(v, value);
+
+    // The follower doesn't receive the new value, so it can't serve the read.
+    cluster.add_send_filter(CloneFilterFactory(
+        RegionPacketFilter::new(region.get_id(), follower.get_store_id())
+            .direction(Direction::Recv)
+            .msg_type(MessageType::MsgAppend),
+    ));
+    let new_value = b"v2";
+    cluster.must_put(key, new_value);
+    let res = read_on_peer(
+        cluster,
+        follower.clone(),
+        &region,
+        key,
+        true,
+        Duration::from_millis(500),
+    );
+    assert!(res.is_err(), "{:?}", res);
+
+    // The follower serves the latest value after it catches up.
+    cluster.clear_send_filters();
+    let v = read_on_peer(cluster, follower, &region, key, true, timeout).unwrap();
+    assert_eq!(v, new_value);
+}
+
+// Tests that a follower read fails with a retriable error if its read index response is lost,
+// instead of waiting forever.
+fn test_follower_read_timeout<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.raft_store.allow_follower_read = true;
+    cluster.run();
+
+    let key = b"k1";
+    cluster.must_put(key, b"v1");
+    let region = cluster.get_region(key);
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let follower = region
+        .get_peers()
+        .iter()
+        .find(|p| p.get_id() != leader.get_id())
+        .unwrap()
+        .clone();
+
+    cluster.add_send_filter(CloneFilterFactory(
+        RegionPacketFilter::new(region.get_id(), follower.get_store_id())
+            .direction(Direction::Recv)
+            .msg_type(MessageType::MsgReadIndexResp),
+    ));
+    let mut request = new_request(
+        region.get_id(),
+        region.get_region_epoch().clone(),
+        vec![new_get_cmd(key)],
+        true,
+    );
+    request.mut_header().set_peer(follower.clone());
+    let resp = cluster
+        .call_command(request, Duration::from_secs(5))
+        .unwrap();
+    assert!(
+        resp.get_header().get_error().has_stale_command(),
+        "{:?}",
+        resp
+    );
+
+    // The follower serves reads again once the responses are delivered.
+    cluster.clear_send_filters();
+    let timeout = Duration::from_secs(5);
+    let v = read_on_peer(cluster, follower, &region, key, true, timeout).unwrap();
+    assert_eq!(v, b"v1");
+}
+
+#[test]
+fn test_node_follower_read() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_follower_read(&mut cluster);
+}
+
+#[test]
+fn test_server_follower_read() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_follower_read(&mut cluster);
+}
+
+// Tests that followers don't serve reads unless `allow_follower_read` is on.
+fn test_follower_read_not_allowed<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    let key = b"k1";
+    cluster.must_put(key, b"v1");
+    let region = cluster.get_region(key);
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let follower = region
+        .get_peers()
+        .iter()
+        .find(|p| p.get_id() != leader.get_id())
+        .unwrap()
+        .clone();
+    let timeout = Duration::from_secs(5);
+    assert!(read_on_peer(cluster, follower, &region, key, true, timeout).is_err());
+    let v = read_on_peer(cluster, leader, &region, key, true, timeout).unwrap();
+    assert_eq!(v, b"v1");
+}
+
+#[test]
+fn test_node_follower_read_not_allowed() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_follower_read_not_allowed(&mut cluster);
+}
+
+#[test]
+fn test_node_follower_read_timeout() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_follower_read_timeout(&mut cluster);
+}
+
+#[test]
+fn test_server_follower_read_timeout() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_follower_read_timeout(&mut cluster);
+}
@@ -300,6 +300,49 @@ fn test_server_auto_split_region() {
     test_auto_split_region(&mut cluster);
 }
 
+fn test_load_split_region<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.raft_store.split_region_check_tick_interval = ReadableDuration::millis(100);
+    cluster.cfg.raft_store.region_split_qps_threshold = 1;
+    cluster.cfg.raft_store.region_split_load_duration = ReadableDuration::millis(100);
+    cluster.run();
+
+    let pd_client = cluster.pd_client.clone();
+    let region = pd_client.get_region(b"").unwrap();
+
+    // A single hot key can't be split.
+    for _ in 0..100 {
+        cluster.must_put(b"k1", b"v1");
+    }
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(pd_client.get_region(b"").unwrap(), region);
+
+    for i in 0..100 {
+        let key = format!("k{:03}", i);
+        cluster.must_put(key.as_bytes(), b"v1");
+    }
+    // it should be finished in millis if split.
+    thread::sleep(Duration::from_secs(1));
+
+    let left = pd_client.get_region(b"").unwrap();
+    let right = pd_client.get_region(b"k099").unwrap();
+    assert_ne!(left, right);
+    assert_eq!(region.get_start_key(), left.get_start_key());
+    assert_eq!(region.get_end_key(), right.get_end_key());
+    assert!(left.get_end_key() > &b"k000"[..]);
+}
+
+#[test]
+fn test_node_load_split_region() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_load_split_region(&mut cluster);
+}
+
+#[test]
+fn test_server_load_split_region() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_load_split_region(&mut cluster);
+}
+
 fn test_delay_split_region<T: Simulator>(cluster: &mut Cluster<T>) {
     // We use three nodes for this test.
     cluster.run();
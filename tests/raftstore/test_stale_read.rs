@@ -0,0 +1,176 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A module contains test cases for reads at a timestamp on any peer.
+
+use std::boxed::FnBox;
+use std::thread;
+use std::time::Duration;
+
+use futures::Future;
+
+use kvproto::metapb::{Peer, Region};
+use kvproto::raft_cmdpb::{CmdType, RaftCmdResponse};
+use tikv::pd::PdClient;
+use tikv::raftstore::{Error, Result};
+use tikv::raftstore::store::Msg;
+use tikv::storage::{make_key, CF_LOCK, CF_WRITE};
+use tikv::storage::mvcc::{Lock, LockType, Write, WriteType};
+use tikv::util::HandyRwLock;
+
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+use super::util::*;
+
+fn stale_read<T: Simulator>(
+    cluster: &Cluster<T>,
+    peer: &Peer,
+    region: &Region,
+    key: &[u8],
+    read_ts: u64,
+) -> Result<Vec<u8>> {
+    let mut request = new_request(
+        region.get_id(),
+        region.get_region_epoch().clone(),
+        vec![new_get_cmd(key)],
+        false,
+    );
+    request.mut_header().set_peer(peer.clone());
+    let ch = cluster
+        .sim
+        .rl()
+        .get_store_sendch(peer.get_store_id())
+        .unwrap();
+    let timeout = Duration::from_secs(3);
+    let mut resp = wait_op!(
+        |cb: Box<FnBox(RaftCmdResponse) + 'static + Send>| {
+            ch.try_send(Msg::StaleRead {
+                read_ts: read_ts,
+                request: request,
+                callback: cb,
+            }).unwrap()
+        },
+        timeout
+    ).ok_or_else(|| Error::Timeout(format!("request timeout for {:?}", timeout)))?;
+    if resp.get_header().has_error() {
+        return Err(Error::Other(
+            box_err!(resp.mut_header().take_error().take_message()),
+        ));
+    }
+    assert_eq!(resp.get_responses()[0].get_cmd_type(), CmdType::Get);
+    Ok(resp.mut_responses()[0].mut_get().take_value())
+}
+
+fn must_stale_read<T: Simulator>(
+    cluster: &Cluster<T>,
+    peer: &Peer,
+    region: &Region,
+    key: &[u8],
+    read_ts: u64,
+) -> Vec<u8> {
+    // The resolved ts of the peer may not have been advanced yet.
+    for _ in 0..100 {
+        if let Ok(v) = stale_read(cluster, peer, region, key, read_ts) {
+            return v;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("failed to read {:?} at {} on {:?}", key, read_ts, peer);
+}
+
+fn test_stale_read<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    let (key, value) = (b"k0", b"v0");
+    cluster.must_put(key, value);
+    let region = cluster.get_region(key);
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let follower = region
+        .get_peers()
+        .iter()
+        .find(|p| p.get_id() != leader.get_id())
+        .unwrap()
+        .clone();
+
+    // A transaction prewrites at the latest ts, it blocks the resolved ts.
+    let start_ts = cluster.pd_client.get_tso().wait().unwrap();
+    let lock_key = make_key(b"k2").encoded().to_owned();
+    let lock = Lock::new(LockType::Put, b"k2".to_vec(), start_ts, 0, None).to_bytes();
+    cluster.must_put_cf(CF_LOCK, &lock_key, &lock);
+    assert_eq!(
+        must_stale_read(cluster, &leader, &region, key, start_ts - 1),
+        value
+    );
+    assert!(stale_read(cluster, &leader, &region, key, start_ts).is_err());
+    // The follower serves the read with the safe ts sent by the leader.
+    assert_eq!(
+        must_stale_read(cluster, &follower, &region, key, start_ts - 1),
+        value
+    );
+    assert!(stale_read(cluster, &follower, &region, key, start_ts).is_err());
+
+    // The resolved ts advances with PD after the transaction commits, even if
+    // nothing is written to the region.
+    cluster.must_delete_cf(CF_LOCK, &lock_key);
+    let write_key = make_key(b"k2").append_ts(start_ts + 1).encoded().to_owned();
+    let write = Write::new(WriteType::Put, start_ts, None).to_bytes();
+    cluster.must_put_cf(CF_WRITE, &write_key, &write);
+    assert_eq!(
+        must_stale_read(cluster, &leader, &region, key, start_ts + 10),
+        value
+    );
+    assert_eq!(
+        must_stale_read(cluster, &follower, &region, key, start_ts + 10),
+        value
+    );
+
+    // An async-commit lock blocks the resolved ts at its min commit ts.
+    let start_ts = cluster.pd_client.get_tso().wait().unwrap();
+    let lock = Lock::new(LockType::Put, b"k2".to_vec(), start_ts, 0, None)
+        .with_min_commit_ts(start_ts + 5)
+        .with_async_commit(vec![])
+        .to_bytes();
+    cluster.must_put_cf(CF_LOCK, &lock_key, &lock);
+    assert_eq!(
+        must_stale_read(cluster, &leader, &region, key, start_ts + 4),
+        value
+    );
+    assert!(stale_read(cluster, &leader, &region, key, start_ts + 5).is_err());
+    cluster.must_delete_cf(CF_LOCK, &lock_key);
+
+    // The locks are tracked by the new regions after split.
+    cluster.must_split(&region, b"k1");
+    let left = cluster.get_region(b"k0");
+    let leader = cluster.leader_of_region(left.get_id()).unwrap();
+    let start_ts = cluster.pd_client.get_tso().wait().unwrap();
+    let lock = Lock::new(LockType::Put, b"k0".to_vec(), start_ts, 0, None).to_bytes();
+    cluster.must_put_cf(CF_LOCK, &make_key(b"k0").encoded().to_owned(), &lock);
+    assert_eq!(
+        must_stale_read(cluster, &leader, &left, key, start_ts - 1),
+        value
+    );
+    assert!(stale_read(cluster, &leader, &left, key, start_ts).is_err());
+}
+
+#[test]
+fn test_node_stale_read() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_stale_read(&mut cluster);
+}
+
+#[test]
+fn test_server_stale_read() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_stale_read(&mut cluster);
+}
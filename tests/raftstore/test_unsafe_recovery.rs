@@ -0,0 +1,84 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use tikv::raftstore::store::unsafe_recovery;
+use tikv::util::collections::HashSet;
+use tikv::util::HandyRwLock;
+
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+use super::util::*;
+
+fn test_unsafe_recovery<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+    cluster.must_put(b"k1", b"v1");
+    let region = cluster.get_region(b"k1");
+    let peer_on_store1 = region
+        .get_peers()
+        .iter()
+        .find(|p| p.get_store_id() == 1)
+        .unwrap()
+        .clone();
+    cluster.must_transfer_leader(region.get_id(), peer_on_store1.clone());
+    cluster.stop_node(2);
+    cluster.stop_node(3);
+
+    let ch = cluster.sim.rl().get_store_sendch(1).unwrap();
+    let timeout = Duration::from_secs(3);
+    let reports = unsafe_recovery::report(&ch, timeout).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].region, region);
+    assert_eq!(reports[0].peer_id, peer_on_store1.get_id());
+    let regions: Vec<_> = reports.into_iter().map(|r| r.region).collect();
+    assert!(unsafe_recovery::find_lost_ranges(&regions).is_empty());
+
+    let mut failed_stores = HashSet::default();
+    failed_stores.insert(2);
+    failed_stores.insert(3);
+    let mut res = unsafe_recovery::force_remove_peers(&ch, failed_stores.clone(), timeout)
+        .unwrap();
+    assert_eq!(res.len(), 1);
+    let (region_id, new_region) = res.pop().unwrap();
+    assert_eq!(region_id, region.get_id());
+    let new_region = new_region.unwrap();
+    assert_eq!(new_region.get_peers(), &[peer_on_store1]);
+    assert_eq!(
+        new_region.get_region_epoch().get_conf_ver(),
+        region.get_region_epoch().get_conf_ver() + 2
+    );
+
+    // The surviving peer becomes the leader and serves writes alone.
+    cluster.reset_leader_of_region(region_id);
+    cluster.must_put(b"k2", b"v2");
+    assert_eq!(cluster.must_get(b"k1"), Some(b"v1".to_vec()));
+    must_get_equal(&cluster.get_engine(1), b"k2", b"v2");
+
+    // The region doesn't lose the quorum any more.
+    let res = unsafe_recovery::force_remove_peers(&ch, failed_stores, timeout).unwrap();
+    assert!(res.is_empty());
+}
+
+#[test]
+fn test_node_unsafe_recovery() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_unsafe_recovery(&mut cluster);
+}
+
+#[test]
+fn test_server_unsafe_recovery() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_unsafe_recovery(&mut cluster);
+}
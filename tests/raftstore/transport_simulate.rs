@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use kvproto::raft_serverpb::RaftMessage;
+use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse};
 use kvproto::eraftpb::MessageType;
 use tikv::raftstore::{Error, Result};
 use tikv::raftstore::store::{Msg as StoreMsg, SignificantMsg, Transport};
@@ -24,7 +25,7 @@ use std::collections::HashMap;
 use std::sync::{Arc, Mutex, RwLock};
 use std::sync::mpsc::Sender;
 use std::marker::PhantomData;
-use std::{thread, time, usize};
+use std::{result, thread, time, usize};
 use std::sync::atomic::*;
 
 pub trait Channel<M>: Send + Clone {
@@ -32,6 +33,9 @@ pub trait Channel<M>: Send + Clone {
     fn significant_send(&self, _: SignificantMsg) -> Result<()> {
         unimplemented!()
     }
+    fn local_read(&self, req: RaftCmdRequest) -> result::Result<RaftCmdResponse, RaftCmdRequest> {
+        Err(req)
+    }
     fn flush(&mut self) {}
 }
 
@@ -57,6 +61,10 @@ impl Channel<StoreMsg> for ServerRaftStoreRouter {
     fn significant_send(&self, msg: SignificantMsg) -> Result<()> {
         RaftStoreRouter::significant_send(self, msg)
     }
+
+    fn local_read(&self, req: RaftCmdRequest) -> result::Result<RaftCmdResponse, RaftCmdRequest> {
+        RaftStoreRouter::local_read(self, req)
+    }
 }
 
 pub fn check_messages<M>(msgs: &[M]) -> Result<()> {
@@ -200,6 +208,10 @@ impl<C: Channel<StoreMsg>> RaftStoreRouter for SimulateTransport<StoreMsg, C> {
     fn significant_send(&self, m: SignificantMsg) -> Result<()> {
         self.ch.lock().unwrap().significant_send(m)
     }
+
+    fn local_read(&self, req: RaftCmdRequest) -> result::Result<RaftCmdResponse, RaftCmdRequest> {
+        self.ch.lock().unwrap().local_read(req)
+    }
 }
 
 pub trait FilterFactory {
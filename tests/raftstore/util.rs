@@ -95,6 +95,7 @@ pub fn new_store_cfg() -> Config {
         report_region_flow_interval: ReadableDuration::millis(100),
         raft_store_max_leader_lease: ReadableDuration::millis(MAX_LEADER_LEASE),
         allow_remove_leader: true,
+        advance_resolved_ts_interval: ReadableDuration::millis(50),
         ..Config::default()
     }
 }
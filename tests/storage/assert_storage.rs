@@ -275,6 +275,25 @@ impl AssertionStorage {
         assert_eq!(result, expect);
     }
 
+    pub fn reverse_scan_ok(
+        &self,
+        start_key: &[u8],
+        limit: usize,
+        ts: u64,
+        expect: Vec<Option<(&[u8], &[u8])>>,
+    ) {
+        let key_address = make_key(start_key);
+        let result = self.store
+            .reverse_scan(self.ctx.clone(), key_address, limit, false, ts)
+            .unwrap();
+        let result: Vec<Option<KvPair>> = result.into_iter().map(Result::ok).collect();
+        let expect: Vec<Option<KvPair>> = expect
+            .into_iter()
+            .map(|x| x.map(|(k, v)| (k.to_vec(), v.to_vec())))
+            .collect();
+        assert_eq!(result, expect);
+    }
+
     pub fn scan_key_only_ok(
         &self,
         start_key: &[u8],
@@ -424,30 +443,124 @@ impl AssertionStorage {
     }
 
     pub fn raw_get_ok(&self, key: Vec<u8>, value: Option<Vec<u8>>) {
-        assert_eq!(self.store.raw_get(self.ctx.clone(), key).unwrap(), value);
+        self.raw_get_cf_ok("", key, value);
+    }
+
+    pub fn raw_get_cf_ok(&self, cf: &str, key: Vec<u8>, value: Option<Vec<u8>>) {
+        assert_eq!(
+            self.store
+                .raw_get(self.ctx.clone(), cf.to_owned(), key)
+                .unwrap(),
+            value
+        );
+    }
+
+    pub fn raw_batch_get_ok(&self, cf: &str, keys: Vec<&[u8]>, expect: Vec<(&[u8], &[u8])>) {
+        let keys = keys.into_iter().map(|k| k.to_vec()).collect();
+        let result: Vec<KvPair> = self.store
+            .raw_batch_get(self.ctx.clone(), cf.to_owned(), keys)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.unwrap())
+            .collect();
+        let expect: Vec<KvPair> = expect
+            .into_iter()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect();
+        assert_eq!(result, expect);
     }
 
     pub fn raw_put_ok(&self, key: Vec<u8>, value: Vec<u8>) {
-        self.store.raw_put(self.ctx.clone(), key, value).unwrap();
+        self.store
+            .raw_put(self.ctx.clone(), String::new(), key, value, 0)
+            .unwrap();
+    }
+
+    pub fn raw_put_cf_ok(&self, cf: &str, key: Vec<u8>, value: Vec<u8>) {
+        self.store
+            .raw_put(self.ctx.clone(), cf.to_owned(), key, value, 0)
+            .unwrap();
     }
 
     pub fn raw_put_err(&self, key: Vec<u8>, value: Vec<u8>) {
         self.store
-            .raw_put(self.ctx.clone(), key, value)
+            .raw_put(self.ctx.clone(), String::new(), key, value, 0)
             .unwrap_err();
     }
 
+    pub fn raw_batch_put_ok(&self, cf: &str, pairs: Vec<(&[u8], &[u8])>) {
+        let pairs = pairs
+            .into_iter()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect();
+        self.store
+            .raw_batch_put(self.ctx.clone(), cf.to_owned(), pairs, 0)
+            .unwrap();
+    }
+
     pub fn raw_delete_ok(&self, key: Vec<u8>) {
-        self.store.raw_delete(self.ctx.clone(), key).unwrap()
+        self.store
+            .raw_delete(self.ctx.clone(), String::new(), key)
+            .unwrap()
     }
 
     pub fn raw_delete_err(&self, key: Vec<u8>) {
-        self.store.raw_delete(self.ctx.clone(), key).unwrap_err();
+        self.store
+            .raw_delete(self.ctx.clone(), String::new(), key)
+            .unwrap_err();
+    }
+
+    pub fn raw_batch_delete_ok(&self, cf: &str, keys: Vec<&[u8]>) {
+        let keys = keys.into_iter().map(|k| k.to_vec()).collect();
+        self.store
+            .raw_batch_delete(self.ctx.clone(), cf.to_owned(), keys)
+            .unwrap();
+    }
+
+    pub fn raw_delete_range_ok(&self, cf: &str, start_key: &[u8], end_key: &[u8]) {
+        self.store
+            .raw_delete_range(
+                self.ctx.clone(),
+                cf.to_owned(),
+                start_key.to_vec(),
+                end_key.to_vec(),
+            )
+            .unwrap();
     }
 
     pub fn raw_scan_ok(&self, start_key: Vec<u8>, limit: usize, expect: Vec<(&[u8], &[u8])>) {
+        self.raw_scan_cf_ok("", start_key, limit, expect);
+    }
+
+    pub fn raw_scan_cf_ok(
+        &self,
+        cf: &str,
+        start_key: Vec<u8>,
+        limit: usize,
+        expect: Vec<(&[u8], &[u8])>,
+    ) {
+        self.raw_scan_impl(cf, start_key, limit, false, expect);
+    }
+
+    pub fn raw_reverse_scan_ok(
+        &self,
+        start_key: Vec<u8>,
+        limit: usize,
+        expect: Vec<(&[u8], &[u8])>,
+    ) {
+        self.raw_scan_impl("", start_key, limit, true, expect);
+    }
+
+    fn raw_scan_impl(
+        &self,
+        cf: &str,
+        start_key: Vec<u8>,
+        limit: usize,
+        reverse: bool,
+        expect: Vec<(&[u8], &[u8])>,
+    ) {
         let result: Vec<KvPair> = self.store
-            .raw_scan(self.ctx.clone(), start_key, limit)
+            .raw_scan(self.ctx.clone(), cf.to_owned(), start_key, limit, reverse)
             .unwrap()
             .into_iter()
             .map(|x| x.unwrap())
@@ -105,6 +105,23 @@ impl SyncStorage {
         }).unwrap()
     }
 
+    pub fn reverse_scan(
+        &self,
+        ctx: Context,
+        key: Key,
+        limit: usize,
+        key_only: bool,
+        start_ts: u64,
+    ) -> Result<Vec<Result<KvPair>>> {
+        let mut options = Options::new(0, false, key_only);
+        options.reverse_scan = true;
+        wait_op!(|cb| {
+            self.store
+                .async_scan(ctx, key, limit, start_ts, options, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
     pub fn prewrite(
         &self,
         ctx: Context,
@@ -161,27 +178,89 @@ impl SyncStorage {
         wait_op!(|cb| self.store.async_gc(ctx, safe_point, cb).unwrap()).unwrap()
     }
 
-    pub fn raw_get(&self, ctx: Context, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        wait_op!(|cb| self.store.async_raw_get(ctx, key, cb).unwrap()).unwrap()
+    pub fn raw_get(&self, ctx: Context, cf: String, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        wait_op!(|cb| self.store.async_raw_get(ctx, cf, key, cb).unwrap()).unwrap()
+    }
+
+    pub fn raw_batch_get(
+        &self,
+        ctx: Context,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+    ) -> Result<Vec<Result<KvPair>>> {
+        wait_op!(|cb| self.store.async_raw_batch_get(ctx, cf, keys, cb).unwrap()).unwrap()
+    }
+
+    pub fn raw_put(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: u64,
+    ) -> Result<()> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_put(ctx, cf, key, value, ttl, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_batch_put(
+        &self,
+        ctx: Context,
+        cf: String,
+        pairs: Vec<KvPair>,
+        ttl: u64,
+    ) -> Result<()> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_batch_put(ctx, cf, pairs, ttl, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_get_key_ttl(&self, ctx: Context, cf: String, key: Vec<u8>) -> Result<Option<u64>> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_get_key_ttl(ctx, cf, key, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_delete(&self, ctx: Context, cf: String, key: Vec<u8>) -> Result<()> {
+        wait_op!(|cb| self.store.async_raw_delete(ctx, cf, key, cb).unwrap()).unwrap()
     }
 
-    pub fn raw_put(&self, ctx: Context, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        wait_op!(|cb| self.store.async_raw_put(ctx, key, value, cb).unwrap()).unwrap()
+    pub fn raw_batch_delete(&self, ctx: Context, cf: String, keys: Vec<Vec<u8>>) -> Result<()> {
+        wait_op!(|cb| self.store.async_raw_batch_delete(ctx, cf, keys, cb).unwrap()).unwrap()
     }
 
-    pub fn raw_delete(&self, ctx: Context, key: Vec<u8>) -> Result<()> {
-        wait_op!(|cb| self.store.async_raw_delete(ctx, key, cb).unwrap()).unwrap()
+    pub fn raw_delete_range(
+        &self,
+        ctx: Context,
+        cf: String,
+        start_key: Vec<u8>,
+        end_key: Vec<u8>,
+    ) -> Result<()> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_delete_range(ctx, cf, start_key, end_key, cb)
+                .unwrap()
+        }).unwrap()
     }
 
     pub fn raw_scan(
         &self,
         ctx: Context,
+        cf: String,
         start_key: Vec<u8>,
         limit: usize,
+        reverse: bool,
     ) -> Result<Vec<Result<KvPair>>> {
         wait_op!(|cb| {
             self.store
-                .async_raw_scan(ctx, start_key, limit, cb)
+                .async_raw_scan(ctx, cf, start_key, limit, reverse, cb)
                 .unwrap()
         }).unwrap()
     }
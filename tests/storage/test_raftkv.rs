@@ -8,8 +8,9 @@ use tikv::storage::{CFStatistics, CfName, Key, CF_DEFAULT};
 use tikv::util::codec::bytes;
 use tikv::util::escape;
 use kvproto::kvrpcpb::Context;
-use raftstore::transport_simulate::IsolationFilterFactory;
+use raftstore::transport_simulate::{DropPacketFilter, IsolationFilterFactory};
 use raftstore::server::new_server_cluster_with_cfs;
+use raftstore::cluster::Simulator;
 use tikv::raftstore::store::engine::IterOption;
 
 use raftstore::util::MAX_LEADER_LEASE;
@@ -74,6 +75,42 @@ fn test_read_leader_in_lease() {
     assert_eq!(can_read(&ctx, storage.as_ref(), k2, v2), true);
 }
 
+#[test]
+fn test_local_read() {
+    let count = 3;
+    let mut cluster = new_server_cluster_with_cfs(0, count, &["cf"]);
+    cluster.run();
+
+    let (k, v) = (b"k1", b"v1");
+    // make sure leader has been elected.
+    assert_eq!(cluster.must_get(k), None);
+
+    let region = cluster.get_region(b"");
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let storage = cluster.sim.rl().storages[&leader.get_id()].clone();
+
+    let mut ctx = Context::new();
+    ctx.set_region_id(region.get_id());
+    ctx.set_region_epoch(region.get_region_epoch().clone());
+    ctx.set_peer(leader.clone());
+    must_put(&ctx, storage.as_ref(), k, v);
+
+    // Drop all the messages to the raftstore of the leader.
+    cluster
+        .sim
+        .wl()
+        .add_recv_filter(leader.get_store_id(), box DropPacketFilter::new(100));
+
+    // The snapshot is served without the raftstore as the leader is in lease.
+    assert!(can_read(&ctx, storage.as_ref(), k, v));
+
+    // sleep util leader lease is expired, it falls back to the raftstore.
+    thread::sleep(Duration::from_millis(MAX_LEADER_LEASE));
+    assert!(!can_read(&ctx, storage.as_ref(), k, v));
+
+    cluster.sim.wl().clear_recv_filters(leader.get_store_id());
+}
+
 #[test]
 fn test_batch_snapshot() {
     let count = 3;
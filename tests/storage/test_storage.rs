@@ -279,6 +279,48 @@ fn test_txn_store_scan() {
     check_v40();
 }
 
+#[test]
+fn test_txn_store_reverse_scan() {
+    let store = AssertionStorage::default();
+
+    // ver10: A(10) - B(_) - C(10) - D(_) - E(10)
+    store.put_ok(b"A", b"A10", 5, 10);
+    store.put_ok(b"C", b"C10", 5, 10);
+    store.put_ok(b"E", b"E10", 5, 10);
+    // ver20: A(10) - B(_) - C(_) - D(20) - E(10)
+    store.delete_ok(b"C", 15, 20);
+    store.put_ok(b"D", b"D20", 15, 20);
+    // A rolled back prewrite of B is not visible.
+    store.prewrite_ok(vec![Mutation::Put((make_key(b"B"), b"B30".to_vec()))], b"B", 25);
+    store.rollback_ok(vec![b"B"], 25);
+
+    store.reverse_scan_ok(b"F", 0, 10, vec![]);
+    store.reverse_scan_ok(b"F", 1, 10, vec![Some((b"E", b"E10"))]);
+    store.reverse_scan_ok(
+        b"F",
+        5,
+        10,
+        vec![
+            Some((b"E", b"E10")),
+            Some((b"C", b"C10")),
+            Some((b"A", b"A10")),
+        ],
+    );
+    store.reverse_scan_ok(b"C", 5, 10, vec![Some((b"A", b"A10"))]);
+    store.reverse_scan_ok(b"A", 5, 10, vec![]);
+    store.reverse_scan_ok(
+        b"F",
+        5,
+        30,
+        vec![
+            Some((b"E", b"E10")),
+            Some((b"D", b"D20")),
+            Some((b"A", b"A10")),
+        ],
+    );
+    store.reverse_scan_ok(b"E", 1, 30, vec![Some((b"D", b"D20"))]);
+}
+
 #[test]
 fn test_txn_store_scan_key_only() {
     let store = AssertionStorage::default();
@@ -543,6 +585,95 @@ fn test_txn_store_rawkv() {
     );
     store.raw_scan_ok(b"".to_vec(), 0, vec![]);
     store.raw_scan_ok(b"k5".to_vec(), 1, vec![]);
+
+    store.raw_reverse_scan_ok(b"k5".to_vec(), 1, vec![(b"k3", b"v3")]);
+    store.raw_reverse_scan_ok(b"k3".to_vec(), 1, vec![(b"k2", b"v2")]);
+    store.raw_reverse_scan_ok(b"k20".to_vec(), 5, vec![(b"k2", b"v2"), (b"k1", b"v1")]);
+    store.raw_reverse_scan_ok(b"k1".to_vec(), 1, vec![]);
+}
+
+#[test]
+fn test_txn_store_rawkv_ttl() {
+    let mut config = Config::default();
+    config.enable_ttl = true;
+    let store = SyncStorage::new(&config);
+    let ctx = Context::new();
+    store
+        .raw_put(ctx.clone(), String::new(), b"k1".to_vec(), b"v1".to_vec(), 100)
+        .unwrap();
+    store
+        .raw_put(ctx.clone(), String::new(), b"k2".to_vec(), b"v2".to_vec(), 1)
+        .unwrap();
+    let ttl = store
+        .raw_get_key_ttl(ctx.clone(), String::new(), b"k1".to_vec())
+        .unwrap()
+        .unwrap();
+    assert!(ttl > 0 && ttl <= 100);
+    assert_eq!(
+        store
+            .raw_get(ctx.clone(), String::new(), b"k2".to_vec())
+            .unwrap(),
+        Some(b"v2".to_vec())
+    );
+
+    thread::sleep(Duration::from_millis(2100));
+    assert_eq!(
+        store
+            .raw_get(ctx.clone(), String::new(), b"k2".to_vec())
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        store
+            .raw_get_key_ttl(ctx.clone(), String::new(), b"k2".to_vec())
+            .unwrap(),
+        None
+    );
+    let pairs: Vec<_> = store
+        .raw_scan(ctx.clone(), String::new(), b"".to_vec(), 10, false)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(pairs, vec![(b"k1".to_vec(), b"v1".to_vec())]);
+}
+
+#[test]
+fn test_txn_store_rawkv_batch() {
+    let store = AssertionStorage::default();
+    store.raw_batch_put_ok(
+        "",
+        vec![(b"k1", b"v1"), (b"k2", b"v2"), (b"k3", b"v3"), (b"k4", b"v4")],
+    );
+    store.raw_batch_get_ok(
+        "",
+        vec![b"k1", b"k2", b"k5"],
+        vec![(b"k1", b"v1"), (b"k2", b"v2")],
+    );
+    store.raw_batch_delete_ok("", vec![b"k1", b"k5"]);
+    store.raw_scan_ok(
+        b"".to_vec(),
+        5,
+        vec![(b"k2", b"v2"), (b"k3", b"v3"), (b"k4", b"v4")],
+    );
+    store.raw_delete_range_ok("", b"k2", b"k4");
+    store.raw_scan_ok(b"".to_vec(), 5, vec![(b"k4", b"v4")]);
+
+    // Column families are separated.
+    store.raw_batch_put_ok("lock", vec![(b"k1", b"l1"), (b"k4", b"l4")]);
+    store.raw_get_cf_ok("lock", b"k4".to_vec(), Some(b"l4".to_vec()));
+    store.raw_get_cf_ok("default", b"k4".to_vec(), Some(b"v4".to_vec()));
+    store.raw_get_cf_ok("", b"k1".to_vec(), None);
+    store.raw_put_cf_ok("lock", b"k2".to_vec(), b"l2".to_vec());
+    store.raw_scan_cf_ok(
+        "lock",
+        b"".to_vec(),
+        5,
+        vec![(b"k1", b"l1"), (b"k2", b"l2"), (b"k4", b"l4")],
+    );
+    store.raw_delete_range_ok("lock", b"k1", b"k3");
+    store.raw_batch_get_ok("lock", vec![b"k1", b"k2", b"k4"], vec![(b"k4", b"l4")]);
+    store.raw_get_ok(b"k4".to_vec(), Some(b"v4".to_vec()));
 }
 
 #[test]